@@ -0,0 +1,108 @@
+//! Matching the video to an aspect ratio, for vertical clips and ultrawide renders.
+//!
+//! The resolution keeps the length of its shorter side and gets a longer side which matches the
+//! aspect ratio, so `--aspect 9:16` turns 1920x1080 into 1080x1920. Rendered videos are scaled to
+//! fit the matched resolution and padded with the background color, so they have exactly that size
+//! even if the gource arguments set another one.
+
+use clap::ValueEnum;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Aspect {
+    /// Widescreen, like most monitors.
+    #[value(name = "16:9")]
+    Widescreen,
+    /// Ultrawide monitors.
+    #[value(name = "21:9")]
+    Ultrawide,
+    /// Vertical clips for phones and social media.
+    #[value(name = "9:16")]
+    Vertical,
+    #[value(name = "1:1")]
+    Square,
+}
+
+impl Aspect {
+    /// The width and height of the ratio.
+    #[must_use]
+    pub fn ratio(self) -> (u32, u32) {
+        match self {
+            Self::Widescreen => (16, 9),
+            Self::Ultrawide => (21, 9),
+            Self::Vertical => (9, 16),
+            Self::Square => (1, 1),
+        }
+    }
+
+    /// The resolution with this aspect ratio and the same shorter side as `resolution`, or `None`
+    /// if `resolution` isn't like `1920x1080`. The longer side is rounded to an even number of
+    /// pixels, which most encoders need.
+    #[must_use]
+    pub fn fit(self, resolution: &str) -> Option<String> {
+        let (width, height) = resolution.split_once('x')?;
+        let short = width.parse::<u32>().ok()?.min(height.parse().ok()?);
+
+        let (ratio_width, ratio_height) = self.ratio();
+        let long = |long_ratio: u32, short_ratio: u32| {
+            (u64::from(short) * u64::from(long_ratio) / u64::from(short_ratio)).div_ceil(2) * 2
+        };
+        let (width, height) = if ratio_width >= ratio_height {
+            (long(ratio_width, ratio_height), u64::from(short))
+        } else {
+            (u64::from(short), long(ratio_height, ratio_width))
+        };
+
+        Some(format!("{width}x{height}"))
+    }
+}
+
+/// An ffmpeg filter which scales a video to fit `resolution` without stretching it, and pads the
+/// rest with `background`, a hex color.
+#[must_use]
+pub fn fit_filter(resolution: &str, background: &str) -> String {
+    let (width, height) = resolution
+        .split_once('x')
+        .unwrap_or((resolution, resolution));
+
+    format!(
+        "scale={width}:{height}:force_original_aspect_ratio=decrease,\
+         pad={width}:{height}:(ow-iw)/2:(oh-ih)/2:color=0x{background},setsar=1"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(
+            Aspect::Widescreen.fit("1080x1920").as_deref(),
+            Some("1920x1080")
+        );
+        assert_eq!(
+            Aspect::Ultrawide.fit("1920x1080").as_deref(),
+            Some("2520x1080")
+        );
+        assert_eq!(
+            Aspect::Vertical.fit("1920x1080").as_deref(),
+            Some("1080x1920")
+        );
+        assert_eq!(Aspect::Square.fit("1280x720").as_deref(), Some("720x720"));
+        // 721 * 16 / 9 is about 1281.8, which is rounded to an even width
+        assert_eq!(
+            Aspect::Widescreen.fit("1280x721").as_deref(),
+            Some("1282x721")
+        );
+        assert_eq!(Aspect::Square.fit("big"), None);
+    }
+
+    #[test]
+    fn test_fit_filter() {
+        assert_eq!(
+            fit_filter("1080x1920", "0D1117"),
+            "scale=1080:1920:force_original_aspect_ratio=decrease,\
+             pad=1080:1920:(ow-iw)/2:(oh-ih)/2:color=0x0D1117,setsar=1"
+        );
+    }
+}
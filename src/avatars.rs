@@ -0,0 +1,289 @@
+//! Avatars for each author's bubble with `--avatars`.
+//!
+//! Gource shows the image named after an author from the directory given with `--user-image-dir`.
+//! Authors who were named after their GitHub login with `--use-github-logins` get their GitHub
+//! avatar, which is kept in `avatars/` in the data directory until it is older than
+//! `--avatar-max-age`. Every other author gets an identicon generated from their name, so every
+//! bubble has a face.
+
+use std::{
+    collections::BTreeSet,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{GourcersError, Result},
+    github,
+    log::LogEntry,
+    logins::LoginCache,
+    network::NetworkOptions,
+    Context,
+};
+
+/// The width and height of generated identicons.
+const IDENTICON_SIZE: u32 = 80;
+/// The number of cells in each row and column of an identicon.
+const IDENTICON_CELLS: usize = 5;
+
+#[derive(Debug, Deserialize)]
+struct User {
+    avatar_url: String,
+}
+
+/// Make sure there is an avatar for every author in the sorted log.
+pub async fn prepare(cx: &Context) -> Result<()> {
+    let dir = cx.data_dir.avatars_dir();
+    if !dir.exists() {
+        trace!("creating avatar directory: {}", dir.display());
+        std::fs::create_dir_all(&dir)
+            .map_err(GourcersError::io("failed to create avatar directory"))?;
+    }
+
+    let logins = LoginCache::read(&cx.data_dir.logins())?
+        .unwrap_or_default()
+        .emails
+        .into_values()
+        .flatten()
+        .collect::<BTreeSet<_>>();
+    let api = github::client(cx)?;
+    let images = image_client(&cx.network)?;
+
+    for author in authors(&cx.sorted_log())? {
+        // gource looks images up by file name, so these authors can't have one
+        if author.contains(['/', '\\']) || author.starts_with('.') {
+            continue;
+        }
+
+        let path = dir.join(format!("{author}.png"));
+
+        if cx.use_github_logins && logins.contains(&author) {
+            if is_fresh(&path, cx.avatar_max_age.0) {
+                continue;
+            }
+
+            match download(&api, &images, &cx.api_url, &author, &path).await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                // an outdated avatar is better than an identicon
+                Err(e) if path.exists() => {
+                    warn!("failed to refresh the avatar of {author}: {e:#}");
+                    continue;
+                }
+                Err(e) => warn!("failed to download the avatar of {author}: {e:#}"),
+            }
+        } else if path.exists() {
+            continue;
+        }
+
+        write_identicon(&author, &path)?;
+    }
+
+    Ok(())
+}
+
+/// The authors in a log.
+fn authors(log: &Path) -> Result<BTreeSet<String>> {
+    let reader =
+        BufReader::new(File::open(log).map_err(GourcersError::io("failed to open sorted log"))?);
+
+    let mut authors = BTreeSet::new();
+    for line in reader.lines() {
+        let line = line.map_err(GourcersError::io("failed to read sorted log"))?;
+        if let Some(entry) = LogEntry::parse(&line) {
+            if !authors.contains(entry.author) {
+                authors.insert(entry.author.to_string());
+            }
+        }
+    }
+
+    Ok(authors)
+}
+
+/// Whether the file at `path` exists and was written less than `max_age` ago.
+fn is_fresh(path: &Path, max_age: Duration) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age < max_age)
+}
+
+/// A client for downloading avatar images. It doesn't send the token, since the images are served
+/// from another host than the API.
+fn image_client(network: &NetworkOptions) -> Result<Client> {
+    network
+        .client_builder(Client::builder())?
+        .user_agent("gourcers-ng")
+        .build()
+        .map_err(GourcersError::network("failed to build reqwest client"))
+}
+
+/// Download the avatar of a GitHub user to `path`, looking the user up with the `api` client and
+/// downloading the image with the `images` client. Returns `false` if there is no such user.
+async fn download(
+    api: &Client,
+    images: &Client,
+    api_url: &str,
+    login: &str,
+    path: &Path,
+) -> Result<bool> {
+    let url = format!("{api_url}/users/{login}");
+    debug!(url = url, "looking up avatar");
+
+    let response = api
+        .get(&url)
+        .send()
+        .await
+        .map_err(GourcersError::network("failed to execute request"))?;
+
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(false);
+    }
+
+    let user: User = response
+        .error_for_status()
+        .map_err(GourcersError::network("request failed"))?
+        .json()
+        .await
+        .map_err(GourcersError::network("failed to read response"))?;
+
+    // avatars can be JPEGs too, but gource detects the format from the contents
+    let image = images
+        .get(&user.avatar_url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(GourcersError::network("failed to download avatar"))?
+        .bytes()
+        .await
+        .map_err(GourcersError::network("failed to download avatar"))?;
+
+    std::fs::write(path, image).map_err(GourcersError::io(format!(
+        "failed to write {}",
+        path.display()
+    )))?;
+
+    Ok(true)
+}
+
+/// The filled cells of the identicon for a name, which is mirrored horizontally.
+fn identicon_cells(hash: &[u8]) -> [[bool; IDENTICON_CELLS]; IDENTICON_CELLS] {
+    let mut cells = [[false; IDENTICON_CELLS]; IDENTICON_CELLS];
+
+    for (y, row) in cells.iter_mut().enumerate() {
+        for x in 0..IDENTICON_CELLS.div_ceil(2) {
+            let filled = hash[3 + y * IDENTICON_CELLS + x] & 1 == 1;
+            row[x] = filled;
+            row[IDENTICON_CELLS - 1 - x] = filled;
+        }
+    }
+
+    cells
+}
+
+/// Write an identicon for `name` to `path` as a PNG.
+fn write_identicon(name: &str, path: &Path) -> Result<()> {
+    let hash = Sha256::digest(name.as_bytes());
+    let cells = identicon_cells(&hash);
+    let color = [hash[0], hash[1], hash[2]];
+    let background = [240; 3];
+
+    let cell_size = IDENTICON_SIZE as usize / IDENTICON_CELLS;
+    let mut pixels = Vec::with_capacity((IDENTICON_SIZE * IDENTICON_SIZE * 3) as usize);
+    for y in 0..IDENTICON_SIZE as usize {
+        for x in 0..IDENTICON_SIZE as usize {
+            let filled = cells[y / cell_size][x / cell_size];
+            pixels.extend(if filled { color } else { background });
+        }
+    }
+
+    let io_err = || GourcersError::io(format!("failed to write {}", path.display()));
+    let file = File::create(path).map_err(io_err())?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), IDENTICON_SIZE, IDENTICON_SIZE);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .map_err(std::io::Error::from)
+        .map_err(io_err())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use temp_dir::TempDir;
+
+    use super::*;
+    use crate::mock_server::{MockResponse, MockServer};
+
+    #[test]
+    fn test_identicon() {
+        let hash = Sha256::digest(b"Jane Doe");
+        let cells = identicon_cells(&hash);
+        for row in cells {
+            let mut mirrored = row;
+            mirrored.reverse();
+            assert_eq!(row, mirrored);
+        }
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("Jane Doe.png");
+        write_identicon("Jane Doe", &path).unwrap();
+
+        assert!(std::fs::read(&path).unwrap().starts_with(b"\x89PNG"));
+        assert!(is_fresh(&path, Duration::from_mins(1)));
+        assert!(!is_fresh(&temp.path().join("missing.png"), Duration::MAX));
+    }
+
+    #[tokio::test]
+    async fn test_download() {
+        let server = MockServer::start(vec![
+            (
+                "/users/octocat",
+                vec![MockResponse::json(
+                    r#"{"avatar_url": "{url}/avatars/octocat.png"}"#,
+                )],
+            ),
+            ("/avatars/octocat.png", vec![MockResponse::json("image")]),
+        ]);
+        let api = Client::builder()
+            .no_proxy()
+            .default_headers(HeaderMap::from_iter([(
+                reqwest::header::AUTHORIZATION,
+                HeaderValue::from_static("Bearer secret"),
+            )]))
+            .build()
+            .unwrap();
+        let images = image_client(&NetworkOptions {
+            no_proxy: true,
+            ..NetworkOptions::default()
+        })
+        .unwrap();
+        let temp = TempDir::new().unwrap();
+        let path = temp.child("octocat.png");
+
+        assert!(download(&api, &images, server.url(), "octocat", &path)
+            .await
+            .unwrap());
+        assert!(!download(&api, &images, server.url(), "ghost", &path)
+            .await
+            .unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "image");
+
+        // the token is only sent to the API
+        let requests = server.requests();
+        assert_eq!(requests[1].path, "/avatars/octocat.png");
+        assert!(requests[0].headers.contains_key("authorization"));
+        assert!(!requests[1].headers.contains_key("authorization"));
+    }
+}
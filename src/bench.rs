@@ -0,0 +1,189 @@
+//! Timing each step of a run for `gourcers bench`.
+//!
+//! Every run records how long each phase took, and how long cloning and generating the log took
+//! for each repo. `bench` prints them as a report once the run is done.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Write},
+    sync::Mutex,
+    time::Duration,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct BenchArgs {
+    /// How many of the slowest repos to list for each phase.
+    #[clap(long, default_value_t = 5)]
+    pub slowest: usize,
+    /// Stop after sorting the logs instead of rendering them.
+    #[clap(long)]
+    pub skip_render: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Api,
+    Clone,
+    LogGen,
+    Sort,
+    Render,
+}
+
+impl Phase {
+    const ALL: [Self; 5] = [
+        Self::Api,
+        Self::Clone,
+        Self::LogGen,
+        Self::Sort,
+        Self::Render,
+    ];
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Api => "GitHub API",
+            Self::Clone => "clone/pull",
+            Self::LogGen => "log generation",
+            Self::Sort => "sorting",
+            Self::Render => "rendering",
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Timings {
+    inner: Mutex<Recorded>,
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    phases: BTreeMap<Phase, Duration>,
+    repos: BTreeMap<Phase, BTreeMap<String, Duration>>,
+}
+
+impl Timings {
+    /// Add the time spent in a phase.
+    pub fn phase(&self, phase: Phase, elapsed: Duration) {
+        *self.inner.lock().unwrap().phases.entry(phase).or_default() += elapsed;
+    }
+
+    /// Add the time spent on a repo during a phase.
+    pub fn repo(&self, phase: Phase, repo: String, elapsed: Duration) {
+        *self
+            .inner
+            .lock()
+            .unwrap()
+            .repos
+            .entry(phase)
+            .or_default()
+            .entry(repo)
+            .or_default() += elapsed;
+    }
+
+    /// A report of the time spent in each phase, and the `slowest` repos of each phase which
+    /// timed repos.
+    #[must_use]
+    pub fn report(&self, slowest: usize) -> String {
+        let recorded = self.inner.lock().unwrap();
+        let total = recorded.phases.values().sum::<Duration>();
+
+        let mut report = String::new();
+        let mut line = |s: String| {
+            writeln!(report, "{s}").expect("writing to a String cannot fail");
+        };
+
+        line(format!("{:<16}{:>10}{:>8}", "phase", "time", "share"));
+        for phase in Phase::ALL {
+            let Some(elapsed) = recorded.phases.get(&phase) else {
+                continue;
+            };
+            line(format!(
+                "{:<16}{:>10}{:>7.0}%",
+                phase.to_string(),
+                seconds(*elapsed),
+                share(*elapsed, total)
+            ));
+        }
+        line(format!("{:<16}{:>10}", "total", seconds(total)));
+
+        for (phase, repos) in &recorded.repos {
+            let mut repos = repos.iter().collect::<Vec<_>>();
+            repos.sort_by_key(|(name, elapsed)| (std::cmp::Reverse(**elapsed), *name));
+
+            line(String::new());
+            line(format!("slowest repos for {phase}:"));
+            for (name, elapsed) in repos.into_iter().take(slowest) {
+                line(format!("  {name:<40}{:>10}", seconds(*elapsed)));
+            }
+        }
+
+        if let Some(slowest) = recorded
+            .phases
+            .iter()
+            .max_by_key(|(_, elapsed)| **elapsed)
+            .map(|(phase, _)| *phase)
+        {
+            line(String::new());
+            line(format!(
+                "most time was spent on {slowest}. {}",
+                tip(slowest)
+            ));
+        }
+
+        report
+    }
+}
+
+/// A suggestion for speeding up a phase.
+fn tip(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Api => "Responses are cached, so later runs should be faster.",
+        Phase::Clone => {
+            "Try a higher --jobs, or --update-strategy fetch-only for repos which are already cloned."
+        }
+        Phase::LogGen => "Try a higher --jobs, or fewer repos with --min-commits or --top.",
+        Phase::Sort => "Try another --sort-backend.",
+        Phase::Render => {
+            "Try a faster ffmpeg_preset in the render profile, or --split-by with --split-jobs."
+        }
+    }
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+fn share(part: Duration, total: Duration) -> f64 {
+    if total.is_zero() {
+        0.0
+    } else {
+        part.as_secs_f64() / total.as_secs_f64() * 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let timings = Timings::default();
+        timings.phase(Phase::Api, Duration::from_secs(1));
+        timings.phase(Phase::Clone, Duration::from_secs(3));
+        timings.repo(Phase::Clone, "me/big".into(), Duration::from_secs(2));
+        timings.repo(Phase::Clone, "me/small".into(), Duration::from_secs(1));
+        timings.repo(Phase::Clone, "me/tiny".into(), Duration::from_millis(10));
+
+        let report = timings.report(2);
+
+        assert!(report.contains("GitHub API           1.00s     25%"));
+        assert!(report.contains("clone/pull           3.00s     75%"));
+        assert!(report.contains("total                4.00s"));
+        let big = report.find("me/big").unwrap();
+        let small = report.find("me/small").unwrap();
+        assert!(big < small);
+        assert!(!report.contains("me/tiny"));
+        assert!(report.contains("most time was spent on clone/pull"));
+    }
+}
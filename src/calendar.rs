@@ -0,0 +1,80 @@
+//! Converting between Unix timestamps and dates in UTC, for splitting logs into calendar periods
+//! and formatting dates.
+
+/// The number of seconds in a day.
+pub const DAY: i64 = 86_400;
+
+/// The year, month and day in UTC of the timestamp.
+#[must_use]
+pub fn date(timestamp: i64) -> (i64, i64, i64) {
+    // Howard Hinnant's civil_from_days
+    let z = timestamp.div_euclid(DAY) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// The timestamp at midnight UTC on the given day, the inverse of [`date`].
+#[must_use]
+pub fn from_date(year: i64, month: i64, day: i64) -> i64 {
+    month_start((year - 1970) * 12 + month - 1) + (day - 1) * DAY
+}
+
+/// The number of months from January 1970 to the month the timestamp is in.
+#[must_use]
+pub fn month_index(timestamp: i64) -> i64 {
+    let (year, month, _) = date(timestamp);
+
+    (year - 1970) * 12 + month - 1
+}
+
+/// The timestamp at the start of the month with the given index.
+#[must_use]
+pub fn month_start(index: i64) -> i64 {
+    // Howard Hinnant's days_from_civil
+    let month = index.rem_euclid(12) + 1;
+    let year = 1970 + index.div_euclid(12) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    (era * 146_097 + doe - 719_468) * DAY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2023-01-01, 2023-04-01, 2023-05-01, 2024-01-01 and 2024-03-01 at midnight UTC
+    const JAN_2023: i64 = 1_672_531_200;
+    const APR_2023: i64 = 1_680_307_200;
+    const MAY_2023: i64 = 1_682_899_200;
+    const JAN_2024: i64 = 1_704_067_200;
+    const MAR_2024: i64 = 1_709_251_200;
+
+    #[test]
+    fn test_month_boundaries() {
+        for start in [0, JAN_2023, APR_2023, MAY_2023, JAN_2024, MAR_2024] {
+            assert_eq!(month_start(month_index(start)), start);
+            assert_eq!(month_start(month_index(start + 20 * DAY)), start);
+        }
+        assert_eq!(month_index(-1), -1);
+        assert_eq!(date(MAR_2024 - 1), (2024, 2, 29));
+        assert_eq!(date(MAY_2023 + 20 * DAY), (2023, 5, 21));
+    }
+
+    #[test]
+    fn test_from_date() {
+        assert_eq!(from_date(2024, 3, 1), MAR_2024);
+        assert_eq!(date(from_date(2000, 2, 29)), (2000, 2, 29));
+    }
+}
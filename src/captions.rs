@@ -0,0 +1,151 @@
+//! Captions shown at points in the history, e.g. for releases.
+//!
+//! Captions are read from a gource caption file, where each line is `timestamp|text`. Gource draws
+//! them onto the video, and every rendered video also gets an SRT subtitle file next to it with the
+//! same captions, so platforms which support subtitles can show them as selectable text.
+
+use std::{fmt::Write, path::Path};
+
+use crate::{
+    config::RenderProfile,
+    error::{GourcersError, Result},
+    ffmpeg::Format,
+    timeline::{self, Timeline},
+    Context,
+};
+
+/// Gource's default `--caption-duration`.
+const DEFAULT_DURATION: f64 = 10.0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caption {
+    pub timestamp: i64,
+    pub text: String,
+}
+
+/// Parse a gource caption file, skipping malformed lines like gource does.
+#[must_use]
+pub fn parse(contents: &str) -> Vec<Caption> {
+    let mut captions = contents
+        .lines()
+        .filter_map(|line| {
+            let (timestamp, text) = line.split_once('|')?;
+            Some(Caption {
+                timestamp: timestamp.trim().parse().ok()?,
+                text: text.trim().to_string(),
+            })
+        })
+        .filter(|caption| !caption.text.is_empty())
+        .collect::<Vec<_>>();
+    captions.sort_by_key(|caption| caption.timestamp);
+
+    captions
+}
+
+/// The captions as a gource caption file.
+#[must_use]
+pub fn to_file(captions: &[Caption]) -> String {
+    let mut file = String::new();
+    for caption in captions {
+        writeln!(file, "{}|{}", caption.timestamp, caption.text).unwrap();
+    }
+
+    file
+}
+
+/// The captions as SRT subtitles, each shown for `duration` seconds from the time gource shows it.
+#[must_use]
+pub fn to_srt(captions: &[Caption], timeline: &Timeline, duration: f64) -> String {
+    let mut srt = String::new();
+
+    for (x, caption) in captions.iter().enumerate() {
+        let start = timeline.video_time(caption.timestamp);
+        writeln!(
+            srt,
+            "{}\n{} --> {}\n{}\n",
+            x + 1,
+            srt_time(start),
+            srt_time(start + duration),
+            caption.text
+        )
+        .unwrap();
+    }
+
+    srt
+}
+
+/// Write the captions for the video rendered from `log` with `profile` next to the video, with the
+/// same name and an `.srt` extension.
+pub fn write_srt(cx: &Context, profile: &RenderProfile, log: &Path, captions: &Path) -> Result<()> {
+    if cx.auto_speed {
+        warn!("subtitles can't be timed with --auto-speed, so none are written");
+        return Ok(());
+    }
+    // animations can't have subtitles
+    if matches!(
+        profile.format.unwrap_or(cx.format),
+        Format::Gif | Format::Apng
+    ) {
+        return Ok(());
+    }
+
+    let read = |path: &Path| {
+        std::fs::read_to_string(path).map_err(GourcersError::io(format!(
+            "failed to read {}",
+            path.display()
+        )))
+    };
+
+    let gource_args = cx.gource_args_for(Some(profile));
+    let timeline = Timeline::from_log(&read(log)?, &gource_args);
+    let duration =
+        timeline::last_value(&gource_args, &["--caption-duration"]).unwrap_or(DEFAULT_DURATION);
+
+    let srt = to_srt(&parse(&read(captions)?), &timeline, duration);
+
+    let path = profile.output.with_extension("srt");
+    debug!(profile = %profile.name, "writing subtitles to {}", path.display());
+    std::fs::write(&path, srt).map_err(GourcersError::io(format!(
+        "failed to write {}",
+        path.display()
+    )))
+}
+
+/// Format seconds as `HH:MM:SS,mmm`.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn srt_time(seconds: f64) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_srt() {
+        let captions = parse("172800|v1.0\nnot a caption\n0|First commit\n86400|\n");
+        assert_eq!(captions.len(), 2);
+        assert_eq!(captions[0].text, "First commit");
+        assert_eq!(to_file(&captions), "0|First commit\n172800|v1.0\n");
+
+        let timeline = Timeline::new(&[0, 172_800], 1.5, None);
+        assert_eq!(
+            to_srt(&captions, &timeline, 10.0),
+            "1\n00:00:00,000 --> 00:00:10,000\nFirst commit\n\n\
+             2\n00:00:03,000 --> 00:00:13,000\nv1.0\n\n"
+        );
+    }
+
+    #[test]
+    fn test_srt_time() {
+        assert_eq!(srt_time(3_723.456), "01:02:03,456");
+        assert_eq!(srt_time(-1.0), "00:00:00,000");
+    }
+}
@@ -0,0 +1,199 @@
+//! Chapter markers for rendered videos, so viewers can jump between years, months or repos.
+//!
+//! The chapters are timed with the log's [`Timeline`] and added to the finished video by remuxing
+//! it with an ffmpeg metadata file, which works for MP4 and `WebM` alike.
+
+use std::{collections::HashSet, fmt::Write, path::Path};
+
+use clap::ValueEnum;
+
+use crate::{
+    calendar,
+    config::RenderProfile,
+    error::{GourcersError, Result},
+    ffmpeg::{self, Format},
+    log::LogEntry,
+    timeline::Timeline,
+    Context,
+};
+
+/// Where chapters start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChapterBy {
+    /// At the start of every year, in UTC.
+    Year,
+    /// At the start of every month, in UTC.
+    Month,
+    /// Where each repo's first commit appears.
+    Repo,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    /// The number of seconds into the video the chapter starts at.
+    pub start: f64,
+    pub title: String,
+}
+
+/// The chapters of a sorted log's contents. Chapters which would start at the same time, e.g.
+/// repos created on the same day, are merged into one.
+#[must_use]
+pub fn plan(contents: &str, timeline: &Timeline, by: ChapterBy) -> Vec<Chapter> {
+    let mut chapters = Vec::<Chapter>::new();
+    let mut seen = HashSet::new();
+
+    for entry in contents.lines().filter_map(LogEntry::parse) {
+        let Ok(timestamp) = i64::try_from(entry.timestamp) else {
+            continue;
+        };
+
+        let title = match by {
+            ChapterBy::Year => calendar::date(timestamp).0.to_string(),
+            ChapterBy::Month => {
+                let (year, month, _) = calendar::date(timestamp);
+                format!("{year}-{month:02}")
+            }
+            ChapterBy::Repo => entry.repo().to_string(),
+        };
+        if !seen.insert(title.clone()) {
+            continue;
+        }
+
+        let start = timeline.video_time(timestamp);
+        match chapters.last_mut() {
+            Some(last) if (last.start - start).abs() < 0.001 => {
+                write!(last.title, ", {title}").unwrap();
+            }
+            _ => chapters.push(Chapter { start, title }),
+        }
+    }
+
+    chapters
+}
+
+/// The chapters as an ffmpeg metadata file, with the last chapter ending at `duration`.
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn ffmetadata(chapters: &[Chapter], duration: f64) -> String {
+    let millis = |seconds: f64| (seconds * 1000.0).round() as i64;
+
+    let mut metadata = ";FFMETADATA1\n".to_string();
+    for (x, chapter) in chapters.iter().enumerate() {
+        let end = chapters.get(x + 1).map_or(duration, |next| next.start);
+        write!(
+            metadata,
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            millis(chapter.start),
+            millis(end.max(chapter.start)),
+            escape(&chapter.title)
+        )
+        .unwrap();
+    }
+
+    metadata
+}
+
+/// Add chapters to the video rendered from `log` with `profile`.
+pub fn write(cx: &Context, profile: &RenderProfile, log: &Path, by: ChapterBy) -> Result<()> {
+    // animations can't have chapters
+    if matches!(
+        profile.format.unwrap_or(cx.format),
+        Format::Gif | Format::Apng
+    ) {
+        return Ok(());
+    }
+
+    let contents = std::fs::read_to_string(log).map_err(GourcersError::io(format!(
+        "failed to read {}",
+        log.display()
+    )))?;
+    let timeline = Timeline::from_log(&contents, &cx.gource_args_for(Some(profile)));
+    let chapters = plan(&contents, &timeline, by);
+    if chapters.is_empty() {
+        return Ok(());
+    }
+
+    let video = &profile.output;
+    let metadata = video.with_extension("chapters.txt");
+    std::fs::write(&metadata, ffmetadata(&chapters, ffmpeg::duration(video)?)).map_err(
+        GourcersError::io(format!("failed to write {}", metadata.display())),
+    )?;
+
+    debug!(profile = %profile.name, "adding {} chapters to {}", chapters.len(), video.display());
+    let result = ffmpeg::add_metadata(video, &metadata);
+    std::fs::remove_file(&metadata).ok();
+
+    result
+}
+
+/// Escape the characters which are special in ffmpeg metadata files.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2022-12-31 and 2023-01-01 at midnight UTC
+    const DEC_2022: i64 = 1_672_444_800;
+    const JAN_2023: i64 = 1_672_531_200;
+
+    #[test]
+    fn test_plan() {
+        let log = format!(
+            "{DEC_2022}|a|A|/one/x\n{DEC_2022}|a|A|/two/x\n{JAN_2023}|a|M|/one/x\n{}|a|A|/three/x\n",
+            JAN_2023 + 86_400
+        );
+        let timeline = Timeline::new(&[DEC_2022, JAN_2023, JAN_2023 + 86_400], 2.0, None);
+
+        assert_eq!(
+            plan(&log, &timeline, ChapterBy::Year),
+            [
+                Chapter {
+                    start: 0.0,
+                    title: "2022".into()
+                },
+                Chapter {
+                    start: 2.0,
+                    title: "2023".into()
+                }
+            ]
+        );
+        assert_eq!(plan(&log, &timeline, ChapterBy::Month)[1].title, "2023-01");
+
+        let repos = plan(&log, &timeline, ChapterBy::Repo);
+        assert_eq!(repos[0].title, "one, two");
+        assert_eq!(repos[1].title, "three");
+        assert!((repos[1].start - 4.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_ffmetadata() {
+        let chapters = [
+            Chapter {
+                start: 0.0,
+                title: "a=b".into(),
+            },
+            Chapter {
+                start: 1.5,
+                title: "c".into(),
+            },
+        ];
+
+        assert_eq!(
+            ffmetadata(&chapters, 10.0),
+            ";FFMETADATA1\n\
+             \n[CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=1500\ntitle=a\\=b\n\
+             \n[CHAPTER]\nTIMEBASE=1/1000\nSTART=1500\nEND=10000\ntitle=c\n"
+        );
+    }
+}
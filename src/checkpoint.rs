@@ -0,0 +1,196 @@
+//! Checkpoints for renders done in segments.
+//!
+//! Each segment video is written under a temporary name and only moved into place once gource and
+//! ffmpeg both finished, next to a digest of everything which went into it. When a render is run
+//! again, e.g. after a crash, segments whose video and digest are still there are reused instead
+//! of rendered again.
+
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::RenderProfile,
+    error::{GourcersError, Result},
+    gource, Context,
+};
+
+/// Render a segment's log to `profile.output`, unless it was already rendered from the same log
+/// with the same settings. Returns whether the segment was rendered.
+pub fn encode(
+    cx: &Context,
+    profile: &RenderProfile,
+    log: &Path,
+    extra_args: &[String],
+) -> Result<bool> {
+    let contents = std::fs::read(log).map_err(GourcersError::io(format!(
+        "failed to read {}",
+        log.display()
+    )))?;
+    let key = key(cx, profile, &contents, extra_args);
+
+    let output = &profile.output;
+    let key_path = output.with_extension("sha256");
+
+    if output.exists()
+        && std::fs::read_to_string(&key_path).is_ok_and(|previous| previous.trim() == key)
+    {
+        debug!(profile = %profile.name, "reusing {}", output.display());
+        return Ok(false);
+    }
+
+    // the extension is kept so ffmpeg still knows which container to write
+    let partial = output.with_extension(format!(
+        "part.{}",
+        output
+            .extension()
+            .map(|ext| ext.to_string_lossy())
+            .unwrap_or_default()
+    ));
+    let partial_profile = RenderProfile {
+        output: partial.clone(),
+        ..profile.clone()
+    };
+
+    gource::encode(cx, &partial_profile, log, extra_args)?;
+
+    std::fs::rename(&partial, output).map_err(GourcersError::io(format!(
+        "failed to move {} into place",
+        output.display()
+    )))?;
+    std::fs::write(&key_path, key).map_err(GourcersError::io(format!(
+        "failed to write {}",
+        key_path.display()
+    )))?;
+
+    Ok(true)
+}
+
+/// A digest of the segment's log and every setting which changes how it is rendered.
+fn key(cx: &Context, profile: &RenderProfile, log: &[u8], extra_args: &[String]) -> String {
+    let settings = [
+        profile
+            .resolution
+            .clone()
+            .unwrap_or_else(|| cx.resolution.clone()),
+        profile.framerate.unwrap_or(cx.framerate).to_string(),
+        format!("{:?}", profile.format.unwrap_or(cx.format)),
+        profile.ffmpeg_preset.clone().unwrap_or_default(),
+        shell_words::join(cx.gource_args_for(Some(profile))),
+        shell_words::join(cx.ffmpeg_args_for(Some(profile))),
+        shell_words::join(extra_args),
+    ];
+
+    let mut hasher = Sha256::new();
+    hasher.update(log);
+    for setting in settings {
+        hasher.update(b"\0");
+        hasher.update(setting.as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use temp_dir::TempDir;
+
+    use super::*;
+    use crate::{ffmpeg::Format, Cli};
+
+    fn context(data_dir: &TempDir) -> Context {
+        let data_dir = data_dir.path().display().to_string();
+        let cli = Cli::parse_from([
+            "gourcers",
+            "--data-dir",
+            &data_dir,
+            "--local-repos",
+            &data_dir,
+            "--non-interactive",
+        ]);
+        Context::from_cli(cli).unwrap()
+    }
+
+    fn profile(output: &Path) -> RenderProfile {
+        RenderProfile {
+            name: "default".into(),
+            resolution: None,
+            framerate: None,
+            gource_args: None,
+            ffmpeg_preset: None,
+            ffmpeg_args: None,
+            format: None,
+            output: output.to_path_buf(),
+        }
+    }
+
+    #[test]
+    fn test_key() {
+        let temp = TempDir::new().unwrap();
+        let cx = context(&temp);
+        let profile = profile(&temp.child("segment.mp4"));
+        let log = b"1|Alice|A|/repo/file";
+        let original = key(&cx, &profile, log, &[]);
+
+        assert_eq!(key(&cx, &profile, log, &[]), original);
+
+        let changed = [
+            RenderProfile {
+                resolution: Some("640x360".into()),
+                ..profile.clone()
+            },
+            RenderProfile {
+                format: Some(Format::Webm),
+                ..profile.clone()
+            },
+            RenderProfile {
+                gource_args: Some(vec!["--hide".into(), "date".into()]),
+                ..profile.clone()
+            },
+            RenderProfile {
+                ffmpeg_args: Some(vec!["-crf".into(), "30".into()]),
+                ..profile.clone()
+            },
+        ];
+        for changed in changed {
+            assert_ne!(key(&cx, &changed, log, &[]), original);
+        }
+        assert_ne!(key(&cx, &profile, b"2|Bob|M|/repo/file", &[]), original);
+        assert_ne!(
+            key(
+                &cx,
+                &profile,
+                log,
+                &["--start-position".into(), "0.5".into()]
+            ),
+            original
+        );
+    }
+
+    #[test]
+    fn test_reuse() {
+        let temp = TempDir::new().unwrap();
+        let cx = context(&temp);
+        let log = temp.child("segment.txt");
+        std::fs::write(&log, "1|Alice|A|/repo/file\n").unwrap();
+        let output = temp.child("segment.mp4");
+        let profile = profile(&output);
+
+        // a finished segment with the same digest isn't rendered again, which would need gource
+        std::fs::write(&output, "video").unwrap();
+        std::fs::write(
+            output.with_extension("sha256"),
+            key(&cx, &profile, &std::fs::read(&log).unwrap(), &[]),
+        )
+        .unwrap();
+        assert!(!encode(&cx, &profile, &log, &[]).unwrap());
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "video");
+
+        let partial = std::fs::read_dir(temp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains(".part."))
+            .collect::<Vec<_>>();
+        assert!(partial.is_empty(), "{partial:?}");
+    }
+}
@@ -0,0 +1,92 @@
+//! Rendering the repos selected by two rule files side by side.
+
+use std::path::PathBuf;
+
+use crate::{
+    config::RenderProfile,
+    error::{GourcersError, Result},
+    ffmpeg::{self, Format},
+    gource,
+    progress::Progress,
+    Context,
+};
+
+/// The names of the two halves of the video, in the order the rule files are given.
+pub const SIDES: [&str; 2] = ["left", "right"];
+
+#[derive(Debug, clap::Args)]
+pub struct CompareArgs {
+    /// A rule file selecting the repos for one side. Must be given twice; the first is shown on
+    /// the left.
+    #[clap(
+        short = 'f',
+        long = "include-file",
+        value_name = "FILE",
+        required = true
+    )]
+    pub include_files: Vec<PathBuf>,
+    /// The resolution of each half of the video.
+    #[clap(long, default_value = "1280x720")]
+    pub resolution: String,
+    /// The path of the video file to write.
+    ///
+    /// Defaults to `compare` with the extension of `--format`.
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+impl CompareArgs {
+    /// The path of the video file to write.
+    #[must_use]
+    pub fn output(&self, format: Format) -> PathBuf {
+        self.output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("compare.{}", format.extension())))
+    }
+}
+
+/// Render the sorted log of each side to its own video, then place them next to each other.
+///
+/// Returns the path of the video file written.
+pub fn render(cx: &Context, args: &CompareArgs, progress: &Progress) -> Result<PathBuf> {
+    for side in SIDES {
+        progress.set_message(side);
+
+        let profile = RenderProfile {
+            name: side.to_string(),
+            resolution: Some(args.resolution.clone()),
+            framerate: None,
+            gource_args: None,
+            ffmpeg_preset: None,
+            // the extra ffmpeg arguments are only used for the composited video
+            ffmpeg_args: Some(Vec::new()),
+            // the halves are re-encoded when they are placed next to each other
+            format: Some(Format::Mp4),
+            output: cx.data_dir.compare_video(side),
+        };
+
+        debug!("rendering {side} side");
+        gource::render_log(cx, &profile, &cx.data_dir.compare_log(side)).map_err(|source| {
+            GourcersError::Render {
+                profile: side.to_string(),
+                source: Box::new(source),
+            }
+        })?;
+
+        progress.inc(1);
+    }
+
+    progress.set_message("compositing");
+    let output = args.output(cx.format);
+
+    ffmpeg::hstack(
+        &cx.data_dir.compare_video(SIDES[0]),
+        &cx.data_dir.compare_video(SIDES[1]),
+        cx.format,
+        &cx.ffmpeg_output_args_for(None),
+        &output,
+    )?;
+    progress.inc(1);
+
+    Ok(output)
+}
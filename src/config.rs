@@ -0,0 +1,280 @@
+//! The optional TOML configuration file.
+//!
+//! The config file describes settings which are too verbose to pass on the command line, such as
+//! render profiles and overrides for particular repos.
+//!
+//! Example:
+//! ```toml
+//! gource_args = "--hide root,mouse --title 'Our year' --key"
+//! compress_logs = true
+//!
+//! [[profile]]
+//! name = "master"
+//! resolution = "3840x2160"
+//! ffmpeg_preset = "slow"
+//! output = "gource-4k.mp4"
+//!
+//! [[profile]]
+//! name = "web"
+//! resolution = "1920x1080"
+//! output = "gource-1080p.mp4"
+//!
+//! [[profile]]
+//! name = "readme"
+//! resolution = "640x360"
+//! format = "gif"
+//! output = "gource.gif"
+//!
+//! [repo."acme/monorepo"]
+//! max_depth = 3
+//! skip_extensions = ["json", "snap"]
+//!
+//! [colors]
+//! rs = "#dea584"
+//! ts = "#3178c6"
+//!
+//! [theme.brand]
+//! background = "1a1b26"
+//! highlight = "ff9e64"
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::Error as _, Deserialize, Deserializer};
+
+use crate::{
+    error::{GourcersError, Result},
+    ffmpeg::Format,
+    path_filter::FilterPreset,
+    theme::Theme,
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Arguments to pass to gource when `--gource-args` isn't given, split like a shell would.
+    #[serde(deserialize_with = "deserialize_shell_words")]
+    pub gource_args: Option<Vec<String>>,
+    /// Arguments to pass to ffmpeg when `--ffmpeg-args` isn't given, split like a shell would.
+    #[serde(deserialize_with = "deserialize_shell_words")]
+    pub ffmpeg_args: Option<Vec<String>>,
+    /// Store the gource logs of the repos gzip-compressed, which makes them several times smaller.
+    pub compress_logs: bool,
+    /// Render profiles to execute from the same sorted log.
+    #[serde(rename = "profile")]
+    pub profiles: Vec<RenderProfile>,
+    /// Changes to how the logs of particular repos are processed, keyed by full name.
+    #[serde(rename = "repo")]
+    pub repos: BTreeMap<String, RepoOverrides>,
+    /// The colors of files with particular extensions, keyed by extension without the leading dot.
+    #[serde(deserialize_with = "deserialize_colors")]
+    pub colors: BTreeMap<String, String>,
+    /// Color themes for `--theme`, keyed by name.
+    #[serde(rename = "theme")]
+    pub themes: BTreeMap<String, Theme>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(GourcersError::io(format!(
+            "failed to read config file {}",
+            path.display()
+        )))?;
+
+        let config: Self =
+            toml::from_str(&contents).map_err(|source| GourcersError::ConfigParse {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        for (x, profile) in config.profiles.iter().enumerate() {
+            if config.profiles[..x].iter().any(|p| p.name == profile.name) {
+                return Err(GourcersError::DuplicateProfile(profile.name.clone()));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// The overrides for the repo with the given full name, which is compared ignoring case like
+    /// GitHub does.
+    #[must_use]
+    pub fn overrides_for(&self, full_name: &str) -> Option<&RepoOverrides> {
+        self.repos
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(full_name))
+            .map(|(_, overrides)| overrides)
+    }
+}
+
+fn deserialize_shell_words<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<String>>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|args| shell_words::split(&args).map_err(D::Error::custom))
+        .transpose()
+}
+
+fn deserialize_colors<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<BTreeMap<String, String>, D::Error> {
+    BTreeMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(extension, color)| {
+            let color = crate::gource::parse_color(&color).map_err(D::Error::custom)?;
+            Ok((extension.trim_start_matches('.').to_string(), color))
+        })
+        .collect()
+}
+
+/// A single video to render from the sorted log.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderProfile {
+    /// The name of the profile, used in progress messages.
+    pub name: String,
+    /// The resolution to render at, e.g. `1920x1080`, instead of `--resolution`.
+    pub resolution: Option<String>,
+    /// The framerate to render at, instead of `--framerate`.
+    pub framerate: Option<u32>,
+    /// Arguments to pass to gource instead of `--gource-args`, split like a shell would.
+    #[serde(default, deserialize_with = "deserialize_shell_words")]
+    pub gource_args: Option<Vec<String>>,
+    /// The x264 preset passed to ffmpeg. Defaults to `medium`.
+    pub ffmpeg_preset: Option<String>,
+    /// Arguments to pass to ffmpeg instead of `--ffmpeg-args`, split like a shell would.
+    #[serde(default, deserialize_with = "deserialize_shell_words")]
+    pub ffmpeg_args: Option<Vec<String>>,
+    /// The format of the video file, instead of `--format`.
+    pub format: Option<Format>,
+    /// The path of the video file to write, which can contain the placeholders in [`template`].
+    ///
+    /// [`template`]: crate::template
+    pub output: PathBuf,
+}
+
+/// Changes to how the log of a single repo is processed.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoOverrides {
+    /// Only show files with these extensions, instead of `--only-extensions`.
+    pub only_extensions: Option<Vec<String>>,
+    /// Hide files with these extensions, instead of `--skip-extensions`.
+    pub skip_extensions: Option<Vec<String>>,
+    /// Hide the files matched by these presets, instead of `--filter-preset`.
+    pub filter_presets: Option<Vec<FilterPreset>>,
+    /// Move files in directories nested deeper than this into their ancestor at this depth, to
+    /// keep huge trees readable.
+    pub max_depth: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_profiles() {
+        const CONTENTS: &str = r#"
+gource_args = "--hide root"
+
+[[profile]]
+name = "master"
+resolution = "3840x2160"
+ffmpeg_preset = "slow"
+output = "gource-4k.mp4"
+
+[[profile]]
+name = "web"
+gource_args = "--hide root --title 'My Video'"
+ffmpeg_args = "-metadata title=\"My Video\""
+format = "webm"
+output = "gource-1080p.webm"
+        "#;
+
+        let config: Config = toml::from_str(CONTENTS).unwrap();
+
+        assert_eq!(
+            config.gource_args.as_deref(),
+            Some(&["--hide", "root"].map(String::from)[..])
+        );
+        assert_eq!(config.ffmpeg_args, None);
+        assert_eq!(config.profiles.len(), 2);
+        assert_eq!(config.profiles[0].name, "master");
+        assert_eq!(config.profiles[0].resolution.as_deref(), Some("3840x2160"));
+        assert_eq!(config.profiles[0].ffmpeg_preset.as_deref(), Some("slow"));
+        assert_eq!(
+            config.profiles[1].gource_args.as_deref(),
+            Some(&["--hide", "root", "--title", "My Video"].map(String::from)[..])
+        );
+        assert_eq!(
+            config.profiles[1].ffmpeg_args.as_deref(),
+            Some(&["-metadata", "title=My Video"].map(String::from)[..])
+        );
+        assert_eq!(config.profiles[0].format, None);
+        assert_eq!(config.profiles[1].format, Some(Format::Webm));
+        assert_eq!(
+            config.profiles[1].output,
+            PathBuf::from("gource-1080p.webm")
+        );
+    }
+
+    #[test]
+    fn test_duplicate_profile() {
+        let temp = temp_dir::TempDir::new().unwrap();
+        let path = temp.child("gourcers.toml");
+        std::fs::write(
+            &path,
+            "[[profile]]\nname = \"web\"\noutput = \"a.mp4\"\n\n[[profile]]\nname = \"web\"\noutput = \"b.mp4\"\n",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            Config::load(&path),
+            Err(GourcersError::DuplicateProfile(name)) if name == "web"
+        ));
+    }
+
+    #[test]
+    fn test_parse_repo_overrides() {
+        const CONTENTS: &str = r#"
+[repo."Acme/Monorepo"]
+max_depth = 2
+filter_presets = ["codegen"]
+        "#;
+
+        let config: Config = toml::from_str(CONTENTS).unwrap();
+
+        let overrides = config.overrides_for("acme/monorepo").unwrap();
+        assert_eq!(overrides.max_depth, Some(2));
+        assert_eq!(
+            overrides.filter_presets.as_deref(),
+            Some(&[FilterPreset::Codegen][..])
+        );
+        assert!(overrides.skip_extensions.is_none());
+        assert!(config.overrides_for("acme/other").is_none());
+    }
+
+    #[test]
+    fn test_empty_config() {
+        let config: Config = toml::from_str("").unwrap();
+        assert!(config.profiles.is_empty());
+    }
+
+    #[test]
+    fn test_parse_colors() {
+        let config: Config =
+            toml::from_str("[colors]\nrs = \"#dea584\"\n\".ts\" = \"3178c6\"\n").unwrap();
+        assert_eq!(
+            config.colors,
+            BTreeMap::from([
+                ("rs".to_string(), "DEA584".to_string()),
+                ("ts".to_string(), "3178C6".to_string())
+            ])
+        );
+
+        assert!(toml::from_str::<Config>("[colors]\nrs = \"orange\"\n").is_err());
+    }
+}
@@ -0,0 +1,360 @@
+//! The checks run by `doctor`, which find problems with the environment before a long run does.
+
+use std::{
+    collections::BTreeSet,
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::process::Command;
+
+use crate::{
+    ffmpeg::Format,
+    github::{self, DEFAULT_API_URL},
+    headless::{self, Headless},
+    sorted_log::SortedLogState,
+    Context,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// Something which may cause problems, but doesn't stop gourcers from working.
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Run every check, in the order they should be printed.
+pub async fn run(cx: &Context) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    checks.extend(tools(cx).await);
+    checks.push(token(cx).await);
+    checks.push(ssh(cx).await);
+    checks.extend(encoders(cx).await);
+    checks.push(display(cx));
+    checks.extend(data_dir(cx));
+
+    checks
+}
+
+/// Whether each external program is installed, and its version.
+async fn tools(cx: &Context) -> Vec<Check> {
+    let mut tools = vec![
+        ("git", "--version"),
+        ("gource", "--help"),
+        ("ffmpeg", "-version"),
+        ("ffprobe", "-version"),
+    ];
+    if cx.network.clone_bandwidth_limit.is_some() {
+        tools.push(("trickle", "-V"));
+    }
+
+    let mut checks = Vec::with_capacity(tools.len());
+    for (tool, version_arg) in tools {
+        if headless::find_executable(tool).is_none() {
+            checks.push(Check::new(tool, Status::Fail, "not found in PATH"));
+            continue;
+        }
+
+        let version = Command::new(tool)
+            .arg(version_arg)
+            .output()
+            .await
+            .ok()
+            .and_then(|output| first_line(&output.stdout).or_else(|| first_line(&output.stderr)))
+            .unwrap_or_else(|| "installed, but its version is unknown".to_string());
+        checks.push(Check::new(tool, Status::Pass, version));
+    }
+
+    checks
+}
+
+/// Whether the API accepts the token, whether it can list private repos, and whether it expires
+/// soon.
+async fn token(cx: &Context) -> Check {
+    const NAME: &str = "token";
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    match github::token_info(cx).await {
+        Ok(info) if info.has_repo_scope() => {
+            if let Some(warning) = info.expiry_warning(i64::try_from(now).unwrap_or_default()) {
+                return Check::new(NAME, Status::Warn, warning);
+            }
+            match &info.scopes {
+                Some(scopes) => Check::new(
+                    NAME,
+                    Status::Pass,
+                    format!(
+                        "authenticated as {} with the scopes {}",
+                        info.login,
+                        scopes.join(", ")
+                    ),
+                ),
+                None => Check::new(
+                    NAME,
+                    Status::Pass,
+                    format!("authenticated as {}", info.login),
+                ),
+            }
+        }
+        Ok(info) => Check::new(
+            NAME,
+            Status::Fail,
+            format!(
+                "authenticated as {}, but without the repo scope, so private repos can't be listed",
+                info.login
+            ),
+        ),
+        Err(e) => Check::new(NAME, Status::Fail, error_chain(&e)),
+    }
+}
+
+/// Whether git can connect to the server over SSH, which repos are cloned with.
+async fn ssh(cx: &Context) -> Check {
+    const NAME: &str = "ssh";
+
+    let host = if cx.api_url == DEFAULT_API_URL {
+        "github.com".to_string()
+    } else {
+        match reqwest::Url::parse(&cx.api_url)
+            .ok()
+            .and_then(|url| url.host_str().map(ToString::to_string))
+        {
+            Some(host) => host,
+            None => return Check::new(NAME, Status::Fail, "the API URL has no host"),
+        }
+    };
+
+    let ssh_command = cx
+        .network
+        .git_ssh_command()
+        .unwrap_or_else(|| "ssh".to_string());
+    let Some((program, args)) = shell_words::split(&ssh_command)
+        .ok()
+        .and_then(|words| words.split_first().map(|(p, a)| (p.clone(), a.to_vec())))
+    else {
+        return Check::new(NAME, Status::Fail, "the SSH command is empty or invalid");
+    };
+
+    let output = Command::new(program)
+        .args(args)
+        .args(["-T", "-o", "BatchMode=yes", "-o", "ConnectTimeout=10"])
+        .arg(format!("git@{host}"))
+        .output()
+        .await;
+
+    match output {
+        // the server ends the session with an error after greeting the user
+        Ok(output)
+            if String::from_utf8_lossy(&output.stderr).contains("successfully authenticated") =>
+        {
+            Check::new(NAME, Status::Pass, format!("authenticated with {host}"))
+        }
+        Ok(output) => Check::new(
+            NAME,
+            Status::Fail,
+            last_line(&output.stderr)
+                .unwrap_or_else(|| format!("failed to authenticate with {host}")),
+        ),
+        Err(e) => Check::new(NAME, Status::Fail, format!("failed to run ssh: {e}")),
+    }
+}
+
+/// Whether ffmpeg has the encoder of every format which would be rendered.
+async fn encoders(cx: &Context) -> Vec<Check> {
+    let formats = cx
+        .config
+        .profiles
+        .iter()
+        .map(|profile| profile.format.unwrap_or(cx.format))
+        .chain([cx.format])
+        .map(Format::encoder)
+        .collect::<BTreeSet<_>>();
+
+    let Ok(output) = Command::new("ffmpeg")
+        .args(["-hide_banner", "-encoders"])
+        .output()
+        .await
+    else {
+        // the tool check already reports that ffmpeg is missing
+        return Vec::new();
+    };
+    let output = String::from_utf8_lossy(&output.stdout);
+
+    formats
+        .into_iter()
+        .map(|encoder| {
+            let name = format!("encoder {encoder}");
+            if has_encoder(&output, encoder) {
+                Check::new(name, Status::Pass, "available")
+            } else {
+                Check::new(name, Status::Fail, "ffmpeg was built without it")
+            }
+        })
+        .collect()
+}
+
+/// Whether gource can get an OpenGL context, from a display or a headless backend.
+fn display(cx: &Context) -> Check {
+    const NAME: &str = "display";
+
+    if let Some(headless) = cx.headless {
+        return Check::new(
+            NAME,
+            Status::Pass,
+            format!("rendering with {}", backend_name(headless)),
+        );
+    }
+    if env::var_os("DISPLAY").is_some() || env::var_os("WAYLAND_DISPLAY").is_some() {
+        return Check::new(NAME, Status::Pass, "a display is available");
+    }
+
+    match Headless::detect() {
+        Ok(headless) => Check::new(
+            NAME,
+            Status::Warn,
+            format!(
+                "no display, but {} is available with --headless",
+                backend_name(headless)
+            ),
+        ),
+        Err(_) => Check::new(
+            NAME,
+            Status::Fail,
+            "no display, and neither xvfb-run nor EGL is installed",
+        ),
+    }
+}
+
+/// Whether the data directory can be written to, and whether the sorted log is intact.
+fn data_dir(cx: &Context) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let probe = cx.data_dir.path().join(".doctor");
+    let writable = std::fs::write(&probe, "").and_then(|()| std::fs::remove_file(&probe));
+    checks.push(match writable {
+        Ok(()) => Check::new(
+            "data directory",
+            Status::Pass,
+            format!("{} is writable", cx.data_dir.path().display()),
+        ),
+        Err(e) => Check::new(
+            "data directory",
+            Status::Fail,
+            format!("{} isn't writable: {e}", cx.data_dir.path().display()),
+        ),
+    });
+
+    let sorted_log = cx.data_dir.sorted_log();
+    if let Ok(Some(state)) = SortedLogState::read(&cx.data_dir.sorted_log_state()) {
+        if sorted_log.exists() {
+            checks.push(match state.matches(&sorted_log) {
+                Ok(true) => Check::new("sorted log", Status::Pass, "intact"),
+                Ok(false) => Check::new(
+                    "sorted log",
+                    Status::Warn,
+                    "changed since it was written, so it will be rebuilt",
+                ),
+                Err(e) => Check::new("sorted log", Status::Fail, e.to_string()),
+            });
+        }
+    }
+
+    checks
+}
+
+fn backend_name(headless: Headless) -> &'static str {
+    match headless {
+        Headless::Xvfb => "xvfb-run",
+        Headless::Egl => "EGL",
+    }
+}
+
+/// The error followed by each of its causes which it doesn't already mention.
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let cause_message = cause.to_string();
+        if !message.contains(&cause_message) {
+            message.push_str(": ");
+            message.push_str(&cause_message);
+        }
+        source = cause.source();
+    }
+
+    message
+}
+
+/// Whether the output of `ffmpeg -encoders` lists the encoder.
+fn has_encoder(output: &str, encoder: &str) -> bool {
+    output
+        .lines()
+        .any(|line| line.split_whitespace().nth(1) == Some(encoder))
+}
+
+fn first_line(output: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(ToString::to_string)
+}
+
+fn last_line(output: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(output)
+        .lines()
+        .map(str::trim)
+        .rev()
+        .find(|line| !line.is_empty())
+        .map(ToString::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_has_encoder() {
+        let output = "Encoders:\n V..... = Video\n ------\n V....D libx264              libx264 H.264\n V....D gif                  GIF (Graphics Interchange Format)\n";
+
+        assert!(has_encoder(output, "libx264"));
+        assert!(has_encoder(output, "gif"));
+        assert!(!has_encoder(output, "libvpx-vp9"));
+        assert!(!has_encoder(output, "Video"));
+    }
+
+    #[test]
+    fn test_first_and_last_line() {
+        assert_eq!(
+            first_line(b"\ngit version 2.43.0\nmore\n").as_deref(),
+            Some("git version 2.43.0")
+        );
+        assert_eq!(
+            last_line(b"warning\ngit@github.com: Permission denied (publickey).\n\n").as_deref(),
+            Some("git@github.com: Permission denied (publickey).")
+        );
+        assert_eq!(first_line(b""), None);
+    }
+}
@@ -0,0 +1,238 @@
+//! End-to-end tests of the log pipeline against small synthetic git repos.
+//!
+//! Each test scripts commits into local repos and runs the same steps as a render up to the sorted
+//! log: listing the repos with `--local-repos`, cloning them, generating and rewriting their gource
+//! logs, and combining and sorting them. Gource writes the logs, so the tests are skipped when it
+//! isn't installed.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use clap::Parser;
+use temp_dir::TempDir;
+
+use crate::{combine_and_sort, log_file, prepare, Cli, Context};
+
+/// A git repo to script commits into.
+pub struct SyntheticRepo {
+    dir: PathBuf,
+}
+
+impl SyntheticRepo {
+    /// Create an empty repo named `name` in `parent`.
+    #[must_use]
+    pub fn init(parent: &Path, name: &str) -> Self {
+        let dir = parent.join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let repo = Self { dir };
+        repo.git(&["init", "--quiet", "--initial-branch=main"], &[]);
+        repo
+    }
+
+    /// Commit the changes as `author` at the Unix time `timestamp`. Each change writes a file, or
+    /// deletes it if there are no contents.
+    pub fn commit(&self, author: &str, timestamp: i64, changes: &[(&str, Option<&str>)]) {
+        for (path, contents) in changes {
+            let path = self.dir.join(path);
+            match contents {
+                Some(contents) => {
+                    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    std::fs::write(path, contents).unwrap();
+                }
+                None => std::fs::remove_file(path).unwrap(),
+            }
+        }
+
+        let email = format!("{}@example.com", author.to_lowercase().replace(' ', "."));
+        let date = format!("@{timestamp} +0000");
+        let env = [
+            ("GIT_AUTHOR_NAME", author),
+            ("GIT_AUTHOR_EMAIL", &email),
+            ("GIT_AUTHOR_DATE", &date),
+            ("GIT_COMMITTER_NAME", author),
+            ("GIT_COMMITTER_EMAIL", &email),
+            ("GIT_COMMITTER_DATE", &date),
+        ];
+        self.git(&["add", "--all"], &[]);
+        self.git(
+            &[
+                "-c",
+                "commit.gpgsign=false",
+                "commit",
+                "--quiet",
+                "-m",
+                "commit",
+            ],
+            &env,
+        );
+    }
+
+    fn git(&self, args: &[&str], env: &[(&str, &str)]) {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            .args(args)
+            .envs(env.iter().copied())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .output()
+            .unwrap();
+
+        assert!(
+            output.status.success(),
+            "git {args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+/// Whether gource can be run, printing why the test is skipped if it can't.
+#[must_use]
+pub fn has_gource() -> bool {
+    let found = Command::new("gource").arg("--help").output().is_ok();
+    if !found {
+        eprintln!("skipping: gource is not installed");
+    }
+    found
+}
+
+/// Run the pipeline on the repos in `repos_dir` with the extra command line `args`, and return the
+/// sorted log.
+pub async fn sorted_log(repos_dir: &Path, args: &[&str]) -> String {
+    let data_dir = TempDir::new().unwrap();
+    let data_path = data_dir.path().display().to_string();
+    let repos_path = repos_dir.display().to_string();
+
+    let cli = Cli::parse_from(
+        [
+            "gourcers",
+            "--data-dir",
+            &data_path,
+            "--local-repos",
+            &repos_path,
+            "--include",
+            "*:*",
+            "--non-interactive",
+            "--no-progress",
+        ]
+        .iter()
+        .chain(args),
+    );
+    let cx = Context::from_cli(cli).unwrap();
+
+    let repos = prepare(&cx, |_| true).await.unwrap();
+    combine_and_sort(&cx, &repos).unwrap();
+
+    log_file::read(&cx.data_dir.sorted_log()).unwrap()
+}
+
+mod tests {
+    use super::*;
+
+    // 2024-01-01 at midnight UTC
+    const JAN_2024: i64 = 1_704_067_200;
+    const HOUR: i64 = 3600;
+
+    /// Two repos whose histories interleave, with a file deleted in each.
+    fn interleaved() -> TempDir {
+        let temp = TempDir::new().unwrap();
+
+        let alpha = SyntheticRepo::init(temp.path(), "alpha");
+        alpha.commit(
+            "Alice",
+            JAN_2024,
+            &[
+                ("src/main.rs", Some("fn main() {}")),
+                ("README.md", Some("# alpha")),
+            ],
+        );
+        alpha.commit(
+            "Bob",
+            JAN_2024 + 2 * HOUR,
+            &[("src/main.rs", Some("fn main() { run() }"))],
+        );
+        alpha.commit("Alice", JAN_2024 + 4 * HOUR, &[("README.md", None)]);
+
+        let beta = SyntheticRepo::init(temp.path(), "beta");
+        beta.commit(
+            "Carol",
+            JAN_2024 + HOUR,
+            &[("lib.rs", Some("pub fn run() {}"))],
+        );
+        beta.commit(
+            "Carol",
+            JAN_2024 + 3 * HOUR,
+            &[("Cargo.lock", Some("# generated")), ("lib.rs", None)],
+        );
+
+        temp
+    }
+
+    #[tokio::test]
+    async fn test_sorted_log() {
+        if !has_gource() {
+            return;
+        }
+        let repos = interleaved();
+
+        assert_eq!(
+            sorted_log(repos.path(), &[]).await,
+            format!(
+                "{JAN_2024}|Alice|A|/alpha/README.md\n\
+                 {JAN_2024}|Alice|A|/alpha/src/main.rs\n\
+                 {}|Carol|A|/beta/lib.rs\n\
+                 {}|Bob|M|/alpha/src/main.rs\n\
+                 {}|Carol|A|/beta/Cargo.lock\n\
+                 {}|Carol|D|/beta/lib.rs\n\
+                 {}|Alice|D|/alpha/README.md\n",
+                JAN_2024 + HOUR,
+                JAN_2024 + 2 * HOUR,
+                JAN_2024 + 3 * HOUR,
+                JAN_2024 + 3 * HOUR,
+                JAN_2024 + 4 * HOUR,
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rewritten_log() {
+        if !has_gource() {
+            return;
+        }
+        let repos = interleaved();
+        let owner = repos.path().file_name().unwrap().to_string_lossy();
+
+        let log = sorted_log(
+            repos.path(),
+            &[
+                "--tree-layout",
+                "owner/repo",
+                "--skip-extensions",
+                "lock",
+                "--coalesce-window",
+                "10800",
+            ],
+        )
+        .await;
+
+        // Carol's second commit is within the window of her first, so it's moved back to it, and
+        // the lockfile it added is hidden
+        assert_eq!(
+            log,
+            format!(
+                "{JAN_2024}|Alice|A|/{owner}/alpha/README.md\n\
+                 {JAN_2024}|Alice|A|/{owner}/alpha/src/main.rs\n\
+                 {}|Carol|A|/{owner}/beta/lib.rs\n\
+                 {}|Carol|D|/{owner}/beta/lib.rs\n\
+                 {}|Bob|M|/{owner}/alpha/src/main.rs\n\
+                 {}|Alice|D|/{owner}/alpha/README.md\n",
+                JAN_2024 + HOUR,
+                JAN_2024 + HOUR,
+                JAN_2024 + 2 * HOUR,
+                JAN_2024 + 4 * HOUR,
+            )
+        );
+    }
+}
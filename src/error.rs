@@ -0,0 +1,123 @@
+//! The error type returned by every module.
+//!
+//! Modules return [`GourcersError`] so callers can match on what went wrong. Errors are only
+//! turned into `eyre` reports, with suggestions attached, in `main`.
+
+use std::{io, path::PathBuf};
+
+use thiserror::Error;
+
+use crate::{groups, rules};
+
+pub type Result<T, E = GourcersError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum GourcersError {
+    #[error("{context}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{context}")]
+    Network {
+        context: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("{context}")]
+    Json {
+        context: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("the API rate limit was exceeded, and resets in {}", indicatif::HumanDuration(*wait))]
+    RateLimited { wait: std::time::Duration },
+    #[error("the token cannot be used in an HTTP header")]
+    InvalidToken(#[source] reqwest::header::InvalidHeaderValue),
+    #[error("git {subcommand} failed for {repo}: {stderr}")]
+    Git {
+        repo: String,
+        subcommand: String,
+        stderr: String,
+    },
+    #[cfg(feature = "libgit2")]
+    #[error("libgit2 failed for {repo}")]
+    Libgit2 {
+        repo: String,
+        #[source]
+        source: crate::libgit2::Error,
+    },
+    #[error("gource failed: {0}")]
+    Gource(String),
+    #[error("ffmpeg failed: {0}")]
+    Ffmpeg(String),
+    #[error("{path} is not a valid video: {reason}")]
+    InvalidVideo { path: PathBuf, reason: String },
+    #[error("qsv failed: {0}")]
+    Qsv(String),
+    #[error("failed to render profile {profile}")]
+    Render {
+        profile: String,
+        #[source]
+        source: Box<GourcersError>,
+    },
+    #[error("failed to parse config file {path}")]
+    ConfigParse {
+        path: PathBuf,
+        #[source]
+        source: toml::de::Error,
+    },
+    #[error("duplicate render profile name: {0}")]
+    DuplicateProfile(String),
+    #[error("failed to parse groups from {path}")]
+    GroupsParse {
+        path: PathBuf,
+        #[source]
+        source: groups::Error,
+    },
+    #[error("failed to parse rules from {origin}")]
+    RuleParse {
+        origin: String,
+        #[source]
+        source: rules::Error,
+    },
+    #[error("{0} must be set to upload")]
+    MissingCredential(&'static str),
+    #[error("failed to publish video: {0}")]
+    Publish(String),
+    #[error("no headless rendering backend is available")]
+    NoHeadlessBackend,
+    #[error("{path} was written by a newer gourcers (layout version {version}, this version supports up to {supported})")]
+    LayoutTooNew {
+        path: PathBuf,
+        version: u32,
+        supported: u32,
+    },
+    #[error("{path} doesn't contain a layout version: {contents:?}")]
+    InvalidLayoutVersion { path: PathBuf, contents: String },
+    #[error("{path} is in use by another gourcers process{}", pid.map(|pid| format!(" (PID {pid})")).unwrap_or_default())]
+    Locked { path: PathBuf, pid: Option<u32> },
+    #[error("a background task panicked")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+impl GourcersError {
+    /// Wrap an I/O error with a description of what was being done, for use with `map_err`.
+    pub(crate) fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let context = context.into();
+        move |source| Self::Io { context, source }
+    }
+
+    /// Wrap an HTTP error with a description of what was being done, for use with `map_err`.
+    pub(crate) fn network(context: impl Into<String>) -> impl FnOnce(reqwest::Error) -> Self {
+        let context = context.into();
+        move |source| Self::Network { context, source }
+    }
+
+    /// Wrap a JSON error with a description of what was being done, for use with `map_err`.
+    pub(crate) fn json(context: impl Into<String>) -> impl FnOnce(serde_json::Error) -> Self {
+        let context = context.into();
+        move |source| Self::Json { context, source }
+    }
+}
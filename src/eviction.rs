@@ -0,0 +1,331 @@
+//! Keeping the clones in the data directory under `--max-cache-size`.
+//!
+//! When each repo was last used is recorded in `usage.json` in the data directory. After the logs
+//! are generated, the least recently used clones are deleted until the clones fit in the limit
+//! again. Their gource logs are kept, unless `--evict-logs` is given, so the sorted log can still
+//! be rebuilt without them. The repos used by the current run are never evicted.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{GourcersError, Result},
+    github::Repo,
+    log_file, Context,
+};
+
+/// A number of bytes, written with an optional binary unit: `K`, `M`, `G` or `T`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+const UNITS: [(char, u64); 4] = [
+    ('T', 1 << 40),
+    ('G', 1 << 30),
+    ('M', 1 << 20),
+    ('K', 1 << 10),
+];
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("expected a size like 50G, 500M or 1024K: {s}");
+
+        // 50G, 50GB and 50GiB all mean the same
+        let trimmed = s.trim();
+        let trimmed = trimmed.strip_suffix("iB").unwrap_or(trimmed);
+        let trimmed = trimmed
+            .strip_suffix(['B', 'b'])
+            .filter(|rest| !rest.is_empty())
+            .unwrap_or(trimmed);
+
+        let (number, multiplier) = match trimmed.chars().next_back() {
+            Some(unit) if unit.is_ascii_alphabetic() => {
+                let (_, multiplier) = UNITS
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&unit))
+                    .ok_or_else(err)?;
+                (&trimmed[..trimmed.len() - 1], *multiplier)
+            }
+            _ => (trimmed, 1),
+        };
+
+        let number = number.trim().parse::<f64>().map_err(|_| err())?;
+        if !number.is_finite() || number < 0.0 {
+            return Err(err());
+        }
+
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        Ok(Self((number * multiplier as f64) as u64))
+    }
+}
+
+impl Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match UNITS.iter().find(|(_, multiplier)| self.0 >= *multiplier) {
+            #[allow(clippy::cast_precision_loss)]
+            Some((unit, multiplier)) => {
+                write!(f, "{:.1}{unit}", self.0 as f64 / *multiplier as f64)
+            }
+            None => write!(f, "{}B", self.0),
+        }
+    }
+}
+
+/// When each clone was last used, as a Unix timestamp, keyed by the name of its directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub repos: BTreeMap<String, u64>,
+}
+
+impl Usage {
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = std::fs::read_to_string(path).map_err(GourcersError::io(format!(
+            "failed to read {}",
+            path.display()
+        )))?;
+        let usage = serde_json::from_str(&contents).map_err(GourcersError::json(format!(
+            "failed to parse {}",
+            path.display()
+        )))?;
+
+        Ok(Some(usage))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(GourcersError::json("failed to serialize repo usage"))?;
+        std::fs::write(path, contents).map_err(GourcersError::io(format!(
+            "failed to write {}",
+            path.display()
+        )))
+    }
+}
+
+/// What was evicted from the cache.
+#[derive(Debug, Default)]
+pub struct Evicted {
+    /// The directory names of the evicted clones.
+    pub repos: Vec<String>,
+    pub freed: ByteSize,
+    /// The size of the clones which are left.
+    pub remaining: ByteSize,
+}
+
+/// Record that the repos were used now.
+pub fn record(cx: &Context, repos: &[Repo]) -> Result<()> {
+    let path = cx.data_dir.usage();
+    let mut usage = Usage::read(&path)?.unwrap_or_default();
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    for repo in repos {
+        usage.repos.insert(repo.full_name_path_friendly(), now);
+    }
+
+    usage.write(&path)
+}
+
+/// Delete the least recently used clones, other than those of `in_use`, until the clones take up
+/// at most `max` bytes. With `evict_logs`, their gource logs are deleted too.
+pub fn evict(cx: &Context, in_use: &[Repo], max: ByteSize, evict_logs: bool) -> Result<Evicted> {
+    let repos_dir = cx.data_dir.repos_dir();
+    if !repos_dir.exists() {
+        return Ok(Evicted::default());
+    }
+
+    let path = cx.data_dir.usage();
+    let mut usage = Usage::read(&path)?.unwrap_or_default();
+
+    let mut clones = Vec::new();
+    for entry in std::fs::read_dir(&repos_dir).map_err(GourcersError::io(format!(
+        "failed to read {}",
+        repos_dir.display()
+    )))? {
+        let entry = entry.map_err(GourcersError::io(format!(
+            "failed to read {}",
+            repos_dir.display()
+        )))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let size = dir_size(&entry.path())?;
+        let last_used = usage.repos.get(&name).copied().unwrap_or_default();
+        clones.push(CachedClone {
+            name,
+            size,
+            last_used,
+        });
+    }
+
+    let in_use = in_use
+        .iter()
+        .map(Repo::full_name_path_friendly)
+        .collect::<HashSet<_>>();
+
+    let total = clones.iter().map(|clone| clone.size).sum::<u64>();
+    let evicted = plan(clones, max.0, &in_use);
+
+    let mut freed = 0;
+    for clone in &evicted {
+        debug!("evicting {} ({})", clone.name, ByteSize(clone.size));
+
+        let dir = repos_dir.join(&clone.name);
+        std::fs::remove_dir_all(&dir).map_err(GourcersError::io(format!(
+            "failed to delete {}",
+            dir.display()
+        )))?;
+        freed += clone.size;
+
+        if evict_logs {
+            let log = cx.data_dir.gource_log_by_name(&clone.name);
+            log_file::remove(&log).map_err(GourcersError::io(format!(
+                "failed to delete {}",
+                log.display()
+            )))?;
+        }
+
+        usage.repos.remove(&clone.name);
+    }
+
+    usage.write(&path)?;
+
+    Ok(Evicted {
+        repos: evicted.into_iter().map(|clone| clone.name).collect(),
+        freed: ByteSize(freed),
+        remaining: ByteSize(total - freed),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CachedClone {
+    name: String,
+    size: u64,
+    last_used: u64,
+}
+
+/// The clones to evict, least recently used first, so the rest take up at most `max` bytes.
+fn plan(mut clones: Vec<CachedClone>, max: u64, in_use: &HashSet<String>) -> Vec<CachedClone> {
+    let mut total = clones.iter().map(|clone| clone.size).sum::<u64>();
+
+    clones.retain(|clone| !in_use.contains(&clone.name));
+    clones.sort_by(|a, b| a.last_used.cmp(&b.last_used).then(a.name.cmp(&b.name)));
+
+    let mut evicted = Vec::new();
+    for clone in clones {
+        if total <= max {
+            break;
+        }
+        total -= clone.size;
+        evicted.push(clone);
+    }
+
+    evicted
+}
+
+/// The size of the files in a directory and all of its subdirectories. Symlinks aren't followed.
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    let mut dirs = vec![path.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).map_err(GourcersError::io(format!(
+            "failed to read {}",
+            dir.display()
+        )))? {
+            let entry = entry.map_err(GourcersError::io(format!(
+                "failed to read {}",
+                dir.display()
+            )))?;
+            let metadata = entry.metadata().map_err(GourcersError::io(format!(
+                "failed to read {}",
+                entry.path().display()
+            )))?;
+
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!("50G".parse(), Ok(ByteSize(50 << 30)));
+        assert_eq!("50GB".parse(), Ok(ByteSize(50 << 30)));
+        assert_eq!("50GiB".parse(), Ok(ByteSize(50 << 30)));
+        assert_eq!("1.5k".parse(), Ok(ByteSize(1536)));
+        assert_eq!("100".parse(), Ok(ByteSize(100)));
+        assert_eq!("100B".parse(), Ok(ByteSize(100)));
+        assert!("lots".parse::<ByteSize>().is_err());
+        assert!("-1G".parse::<ByteSize>().is_err());
+        assert!("5X".parse::<ByteSize>().is_err());
+
+        assert_eq!(ByteSize(3 << 29).to_string(), "1.5G");
+        assert_eq!(ByteSize(100).to_string(), "100B");
+    }
+
+    #[test]
+    fn test_plan_evicts_least_recently_used() {
+        let clone = |name: &str, size, last_used| CachedClone {
+            name: name.into(),
+            size,
+            last_used,
+        };
+        let clones = vec![
+            clone("new", 40, 300),
+            clone("old", 30, 100),
+            clone("current", 50, 0),
+            clone("middle", 20, 200),
+        ];
+        let in_use = HashSet::from(["current".to_string()]);
+
+        let names = |clones: Vec<CachedClone>| {
+            clones
+                .into_iter()
+                .map(|clone| clone.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(plan(clones.clone(), 140, &in_use).is_empty());
+        assert_eq!(names(plan(clones.clone(), 110, &in_use)), ["old"]);
+        assert_eq!(names(plan(clones.clone(), 90, &in_use)), ["old", "middle"]);
+        // the clones in use are kept even if they don't fit
+        assert_eq!(names(plan(clones, 10, &in_use)), ["old", "middle", "new"]);
+    }
+
+    #[test]
+    fn test_dir_size() {
+        let temp = TempDir::new().unwrap();
+        std::fs::create_dir_all(temp.path().join("a/b")).unwrap();
+        std::fs::write(temp.path().join("a/one"), [0; 10]).unwrap();
+        std::fs::write(temp.path().join("a/b/two"), [0; 5]).unwrap();
+
+        assert_eq!(dir_size(temp.path()).unwrap(), 15);
+    }
+}
@@ -0,0 +1,724 @@
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    process::{Child, ChildStdout, Command, Stdio},
+    str::FromStr,
+};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    calendar,
+    config::RenderProfile,
+    error::{GourcersError, Result},
+};
+
+pub const DEFAULT_PRESET: &str = "medium";
+
+/// The container and codec of a rendered video.
+#[allow(clippy::doc_markdown)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// H.264 in an MP4 container.
+    #[default]
+    Mp4,
+    /// VP9 in a WebM container.
+    Webm,
+    /// An animated GIF at 15 frames per second.
+    Gif,
+    /// An animated PNG at 15 frames per second.
+    Apng,
+}
+
+impl Format {
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Webm => "webm",
+            Self::Gif => "gif",
+            Self::Apng => "png",
+        }
+    }
+
+    /// The name of the ffmpeg encoder the format is encoded with.
+    #[must_use]
+    pub fn encoder(self) -> &'static str {
+        match self {
+            Self::Mp4 => "libx264",
+            Self::Webm => "libvpx-vp9",
+            Self::Gif => "gif",
+            Self::Apng => "apng",
+        }
+    }
+
+    /// The filter applied to the video before encoding, if any.
+    fn filter(self) -> Option<&'static str> {
+        match self {
+            Self::Mp4 | Self::Webm => None,
+            // GIFs can only hold 256 colors, so generate a palette from the video itself
+            Self::Gif => Some("fps=15,split[a][b];[a]palettegen[p];[b][p]paletteuse"),
+            Self::Apng => Some("fps=15"),
+        }
+    }
+
+    /// The codec and muxer arguments. The muxer is always given so the output file's extension
+    /// doesn't need to match the format.
+    fn codec_args(self, preset: &str) -> Vec<&str> {
+        match self {
+            Self::Mp4 => vec![
+                "-c:v", "libx264", "-pix_fmt", "yuv420p", "-preset", preset, "-f", "mp4",
+            ],
+            Self::Webm => vec![
+                "-c:v",
+                "libvpx-vp9",
+                "-pix_fmt",
+                "yuv420p",
+                "-crf",
+                "32",
+                "-b:v",
+                "0",
+                "-row-mt",
+                "1",
+                "-f",
+                "webm",
+            ],
+            Self::Gif => vec!["-loop", "0", "-f", "gif"],
+            Self::Apng => vec!["-plays", "0", "-f", "apng"],
+        }
+    }
+}
+
+/// A point in a video, either relative to its duration or absolute.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FramePosition {
+    /// A percentage of the video's duration.
+    Percent(f64),
+    Seconds(f64),
+}
+
+impl FromStr for FramePosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (value, percent) = match s.strip_suffix('%') {
+            Some(value) => (value, true),
+            None => (s, false),
+        };
+
+        let value = value
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite() && *value >= 0.0)
+            .ok_or_else(|| format!("expected a percentage like 40% or a time in seconds: {s}"))?;
+
+        if percent {
+            if value > 100.0 {
+                return Err(format!("percentage must be at most 100%: {s}"));
+            }
+            Ok(Self::Percent(value))
+        } else {
+            Ok(Self::Seconds(value))
+        }
+    }
+}
+
+/// Spawn ffmpeg to encode the PPM stream gource writes to `input` into the profile's output file.
+/// `extra_args` are placed just before the output file.
+///
+/// `overlay` is an image and a filter graph which places it, as the second input, on the video.
+/// `filter` is applied to the video after the overlay.
+pub fn spawn_encoder(
+    profile: &RenderProfile,
+    format: Format,
+    framerate: u32,
+    extra_args: &[String],
+    overlay: Option<(&Path, &str)>,
+    filter: Option<&str>,
+    input: ChildStdout,
+) -> Result<Child> {
+    let mut cmd = Command::new("ffmpeg");
+
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y"])
+        .arg("-r")
+        .arg(framerate.to_string())
+        .args(["-f", "image2pipe", "-c:v", "ppm", "-i", "-"]);
+
+    let filters = [filter, format.filter()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    if let Some((image, graph)) = overlay {
+        let graph = [&[graph][..], &filters].concat().join(",");
+        cmd.arg("-i").arg(image).arg("-filter_complex").arg(graph);
+    } else if !filters.is_empty() {
+        cmd.arg("-vf").arg(filters.join(","));
+    }
+
+    cmd.args(format.codec_args(profile.ffmpeg_preset.as_deref().unwrap_or(DEFAULT_PRESET)))
+        .args(extra_args)
+        .arg(&profile.output);
+
+    cmd.stdin(input)
+        .stdout(Stdio::null())
+        .stderr(Stdio::inherit());
+
+    trace!(command = ?cmd, profile = %profile.name, "spawning ffmpeg");
+
+    cmd.spawn()
+        .map_err(GourcersError::io("failed to spawn ffmpeg"))
+}
+
+/// Place two videos of the same height next to each other, with `left` on the left.
+pub fn hstack(
+    left: &Path,
+    right: &Path,
+    format: Format,
+    extra_args: &[String],
+    output: &Path,
+) -> Result<()> {
+    let mut cmd = Command::new("ffmpeg");
+
+    let filter = match format.filter() {
+        Some(filter) => format!("hstack=inputs=2,{filter}"),
+        None => "hstack=inputs=2".to_string(),
+    };
+
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y"])
+        .arg("-i")
+        .arg(left)
+        .arg("-i")
+        .arg(right)
+        .arg("-filter_complex")
+        .arg(filter)
+        .args(format.codec_args(DEFAULT_PRESET))
+        .args(extra_args)
+        .arg(output);
+
+    cmd.stdout(Stdio::null()).stderr(Stdio::inherit());
+
+    trace!(command = ?cmd, "compositing videos with ffmpeg");
+
+    let status = cmd
+        .status()
+        .map_err(GourcersError::io("failed to run ffmpeg"))?;
+
+    if !status.success() {
+        return Err(GourcersError::Ffmpeg("see logs above".into()));
+    }
+
+    Ok(())
+}
+
+/// A card of text on a plain background, such as the outro leaderboard.
+#[derive(Debug, Clone, Copy)]
+pub struct TextCard<'a> {
+    /// The file with the text. It is passed to ffmpeg relative to its directory, so its name
+    /// doesn't need to be escaped for the filter graph, and it shouldn't contain special
+    /// characters.
+    pub text_file: &'a Path,
+    pub resolution: &'a str,
+    pub framerate: u32,
+    /// The background color as a hex code, e.g. `1A1B26`.
+    pub background: &'a str,
+    pub seconds: f64,
+}
+
+impl TextCard<'_> {
+    /// The filter which draws the text centered in a monospace font, in black or white, whichever
+    /// stands out more from the background.
+    fn filter(&self) -> String {
+        let luma = u32::from_str_radix(self.background, 16).map_or(0, |rgb| {
+            (rgb >> 16) * 299 + (rgb >> 8 & 0xFF) * 587 + (rgb & 0xFF) * 114
+        });
+        let color = if luma > 128_000 { "black" } else { "white" };
+        let name = self
+            .text_file
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+
+        format!(
+            "drawtext=textfile={name}:expansion=none:font=monospace:fontcolor={color}:\
+             fontsize=h/28:line_spacing=h/108:x=(w-text_w)/2:y=(h-text_h)/2"
+        )
+    }
+}
+
+/// A vertical 9:16 clip cut from a rendered video, cropped around its center.
+#[derive(Debug, Clone, Copy)]
+pub struct Clip<'a> {
+    /// Where the clip starts in the video, in seconds.
+    pub start: f64,
+    pub seconds: f64,
+    /// A file with a title to draw at the top of the clip. Like [`TextCard::text_file`], it is
+    /// passed to ffmpeg relative to its directory.
+    pub title_file: Option<&'a Path>,
+}
+
+impl Clip<'_> {
+    /// The filter which crops the video to 9:16, or as close as the video allows, and draws the
+    /// title in white on a translucent box so it can be read on any background.
+    fn filter(&self) -> String {
+        let crop = r"crop=min(iw\,trunc(ih*9/32)*2):min(ih\,trunc(iw*16/18)*2)";
+
+        match self.title_file.and_then(Path::file_name) {
+            Some(name) => format!(
+                "{crop},drawtext=textfile={}:expansion=none:fontcolor=white:fontsize=w/16:\
+                 box=1:boxcolor=black@0.5:boxborderw=16:x=(w-text_w)/2:y=h/10",
+                name.to_string_lossy()
+            ),
+            None => crop.to_string(),
+        }
+    }
+}
+
+/// Cut a clip from `video` and crop it to 9:16. `extra_args` are placed just before the output
+/// file.
+pub fn render_clip(
+    video: &Path,
+    clip: &Clip,
+    format: Format,
+    extra_args: &[String],
+    output: &Path,
+) -> Result<()> {
+    // ffmpeg runs in the title file's directory
+    let absolute = |path: &Path| {
+        std::path::absolute(path).map_err(GourcersError::io(format!(
+            "failed to resolve {}",
+            path.display()
+        )))
+    };
+    let video = absolute(video)?;
+    let output = absolute(output)?;
+
+    let filter = match format.filter() {
+        Some(filter) => format!("{},{filter}", clip.filter()),
+        None => clip.filter(),
+    };
+
+    let mut cmd = Command::new("ffmpeg");
+
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y"])
+        .arg("-ss")
+        .arg(format!("{:.3}", clip.start))
+        .arg("-t")
+        .arg(format!("{:.3}", clip.seconds))
+        .arg("-i")
+        .arg(video)
+        .arg("-vf")
+        .arg(filter)
+        .args(format.codec_args(DEFAULT_PRESET))
+        .args(extra_args)
+        .arg(output);
+
+    if let Some(dir) = clip.title_file.and_then(Path::parent) {
+        cmd.current_dir(dir);
+    }
+
+    cmd.stdout(Stdio::null()).stderr(Stdio::inherit());
+
+    trace!(command = ?cmd, "cutting clip with ffmpeg");
+
+    let status = cmd
+        .status()
+        .map_err(GourcersError::io("failed to run ffmpeg"))?;
+
+    if !status.success() {
+        return Err(GourcersError::Ffmpeg("see logs above".into()));
+    }
+
+    Ok(())
+}
+
+/// Render a text card to a video, encoded like a profile's video so the two can be joined.
+/// `extra_args` are placed just before the output file.
+pub fn render_card(
+    card: &TextCard,
+    format: Format,
+    preset: &str,
+    extra_args: &[String],
+    output: &Path,
+) -> Result<()> {
+    // ffmpeg runs in the text file's directory
+    let output = std::path::absolute(output).map_err(GourcersError::io(format!(
+        "failed to resolve {}",
+        output.display()
+    )))?;
+
+    let filter = match format.filter() {
+        Some(filter) => format!("{},{filter}", card.filter()),
+        None => card.filter(),
+    };
+
+    let mut cmd = Command::new("ffmpeg");
+
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y"])
+        .args(["-f", "lavfi", "-i"])
+        .arg(format!(
+            "color=c=0x{}:s={}:r={}:d={}",
+            card.background, card.resolution, card.framerate, card.seconds
+        ))
+        .arg("-vf")
+        .arg(filter)
+        .args(format.codec_args(preset))
+        .args(extra_args)
+        .arg(output);
+
+    if let Some(dir) = card.text_file.parent() {
+        cmd.current_dir(dir);
+    }
+
+    cmd.stdout(Stdio::null()).stderr(Stdio::inherit());
+
+    trace!(command = ?cmd, "rendering text card with ffmpeg");
+
+    let status = cmd
+        .status()
+        .map_err(GourcersError::io("failed to run ffmpeg"))?;
+
+    if !status.success() {
+        return Err(GourcersError::Ffmpeg("see logs above".into()));
+    }
+
+    Ok(())
+}
+
+/// Join MP4 videos with identical encoding settings end to end. They are only re-encoded if
+/// `format` is not MP4.
+///
+/// ffmpeg reads the inputs from a list file, which is written to `list`. `extra_args` are placed
+/// just before the output file.
+pub fn concat(
+    inputs: &[PathBuf],
+    list: &Path,
+    format: Format,
+    extra_args: &[String],
+    output: &Path,
+) -> Result<()> {
+    let mut contents = String::new();
+    for input in inputs {
+        // quotes are escaped by closing the string, adding an escaped quote and reopening it
+        let input = input.display().to_string().replace('\'', r"'\''");
+        writeln!(contents, "file '{input}'").expect("writing to a String cannot fail");
+    }
+    std::fs::write(list, contents).map_err(GourcersError::io("failed to write concat list"))?;
+
+    let mut cmd = Command::new("ffmpeg");
+
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y"])
+        .args(["-f", "concat", "-safe", "0", "-i"])
+        .arg(list);
+
+    if format == Format::Mp4 {
+        cmd.args(["-c", "copy"]);
+    } else {
+        if let Some(filter) = format.filter() {
+            cmd.arg("-vf").arg(filter);
+        }
+        cmd.args(format.codec_args(DEFAULT_PRESET));
+    }
+
+    cmd.args(extra_args).arg(output);
+
+    cmd.stdout(Stdio::null()).stderr(Stdio::inherit());
+
+    trace!(command = ?cmd, "concatenating videos with ffmpeg");
+
+    let status = cmd
+        .status()
+        .map_err(GourcersError::io("failed to run ffmpeg"))?;
+
+    if !status.success() {
+        return Err(GourcersError::Ffmpeg("see logs above".into()));
+    }
+
+    Ok(())
+}
+
+/// Read the duration of a video in seconds with ffprobe.
+pub fn duration(video: &Path) -> Result<f64> {
+    let mut cmd = Command::new("ffprobe");
+
+    cmd.args(["-v", "error", "-show_entries", "format=duration"])
+        .args(["-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(video);
+
+    trace!(command = ?cmd, "reading video duration with ffprobe");
+
+    let output = cmd
+        .output()
+        .map_err(GourcersError::io("failed to run ffprobe"))?;
+
+    if !output.status.success() {
+        return Err(GourcersError::Ffmpeg(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.trim().parse().map_err(|_| {
+        GourcersError::Ffmpeg(format!("ffprobe reported an invalid duration: {stdout}"))
+    })
+}
+
+/// The `-metadata` arguments which tag a video with the given keys and values.
+#[must_use]
+pub fn metadata_args(tags: &[(&str, String)]) -> Vec<String> {
+    tags.iter()
+        .flat_map(|(key, value)| ["-metadata".to_string(), format!("{key}={value}")])
+        .collect()
+}
+
+/// Format a Unix timestamp the way the `creation_time` tag expects, e.g. `2024-03-01T12:00:00Z`.
+#[must_use]
+pub fn creation_time(timestamp: i64) -> String {
+    let (year, month, day) = calendar::date(timestamp);
+    let seconds = timestamp.rem_euclid(86_400);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Add the chapters in an ffmpeg metadata file to `video`, without re-encoding it.
+pub fn add_metadata(video: &Path, metadata: &Path) -> Result<()> {
+    // the extension is kept so ffmpeg still knows which container to write
+    let remuxed = video.with_extension(format!(
+        "remux.{}",
+        video
+            .extension()
+            .map(|ext| ext.to_string_lossy())
+            .unwrap_or_default()
+    ));
+
+    let mut cmd = Command::new("ffmpeg");
+
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+        .arg(video)
+        .arg("-i")
+        .arg(metadata)
+        .args(["-map", "0", "-map_chapters", "1", "-c", "copy"])
+        .arg(&remuxed);
+
+    cmd.stdout(Stdio::null()).stderr(Stdio::inherit());
+
+    trace!(command = ?cmd, "adding metadata with ffmpeg");
+
+    let status = cmd
+        .status()
+        .map_err(GourcersError::io("failed to run ffmpeg"))?;
+
+    if !status.success() {
+        std::fs::remove_file(&remuxed).ok();
+        return Err(GourcersError::Ffmpeg("see logs above".into()));
+    }
+
+    std::fs::rename(&remuxed, video).map_err(GourcersError::io(format!(
+        "failed to move {} into place",
+        video.display()
+    )))
+}
+
+/// What ffprobe reports about a video.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Probe {
+    /// The length of the video in seconds.
+    pub duration: f64,
+    pub video_streams: usize,
+}
+
+impl Probe {
+    /// Parse the output of `ffprobe -show_entries format=duration:stream=codec_type`.
+    fn parse(output: &str) -> Option<Self> {
+        let mut duration = None;
+        let mut video_streams = 0;
+
+        for line in output.lines() {
+            match line.trim().split_once('=') {
+                Some(("codec_type", "video")) => video_streams += 1,
+                Some(("duration", value)) => duration = value.parse().ok(),
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            duration: duration?,
+            video_streams,
+        })
+    }
+}
+
+/// Read the duration and streams of a video with ffprobe.
+pub fn probe(video: &Path) -> Result<Probe> {
+    let mut cmd = Command::new("ffprobe");
+
+    cmd.args([
+        "-v",
+        "error",
+        "-show_entries",
+        "format=duration:stream=codec_type",
+    ])
+    .args(["-of", "default=noprint_wrappers=1"])
+    .arg(video);
+
+    trace!(command = ?cmd, "probing video with ffprobe");
+
+    let output = cmd
+        .output()
+        .map_err(GourcersError::io("failed to run ffprobe"))?;
+
+    let invalid = |reason: String| GourcersError::InvalidVideo {
+        path: video.to_path_buf(),
+        reason,
+    };
+
+    // ffprobe reports damaged files on stderr even when it can read some of them
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() || !stderr.trim().is_empty() {
+        return Err(invalid(stderr.trim().to_string()));
+    }
+
+    Probe::parse(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| invalid("ffprobe didn't report a duration".into()))
+}
+
+/// Check that `video` was written completely, and that it plays for at least 90% of `expected`
+/// seconds if given, so a render which stopped early fails instead of leaving a short video.
+pub fn verify(video: &Path, expected: Option<f64>) -> Result<()> {
+    let invalid = |reason: String| GourcersError::InvalidVideo {
+        path: video.to_path_buf(),
+        reason,
+    };
+
+    let len = std::fs::metadata(video)
+        .map_err(GourcersError::io(format!(
+            "failed to read {}",
+            video.display()
+        )))?
+        .len();
+    if len == 0 {
+        return Err(invalid("the file is empty".into()));
+    }
+
+    let probe = probe(video)?;
+    if probe.video_streams == 0 {
+        return Err(invalid("it has no video stream".into()));
+    }
+    if probe.duration <= 0.0 {
+        return Err(invalid("it has no frames".into()));
+    }
+    if let Some(expected) = expected.filter(|expected| probe.duration < expected * 0.9) {
+        return Err(invalid(format!(
+            "it is {:.1} seconds long, but the log should play for {expected:.1} seconds",
+            probe.duration
+        )));
+    }
+
+    Ok(())
+}
+
+/// Write the frame at the given position in `video` to an image file.
+pub fn extract_frame(video: &Path, at: FramePosition, output: &Path) -> Result<()> {
+    let seconds = match at {
+        FramePosition::Percent(percent) => duration(video)? * percent / 100.0,
+        FramePosition::Seconds(seconds) => seconds,
+    };
+
+    let mut cmd = Command::new("ffmpeg");
+
+    cmd.args(["-hide_banner", "-loglevel", "error", "-y"])
+        .arg("-ss")
+        .arg(format!("{seconds:.3}"))
+        .arg("-i")
+        .arg(video)
+        .args(["-frames:v", "1", "-update", "1"])
+        .arg(output);
+
+    cmd.stdout(Stdio::null()).stderr(Stdio::inherit());
+
+    trace!(command = ?cmd, "extracting frame with ffmpeg");
+
+    let status = cmd
+        .status()
+        .map_err(GourcersError::io("failed to run ffmpeg"))?;
+
+    if !status.success() {
+        return Err(GourcersError::Ffmpeg("see logs above".into()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_card_filter() {
+        let card = |background| TextCard {
+            text_file: Path::new("/data/outro/default/leaderboard.txt"),
+            resolution: "1920x1080",
+            framerate: 60,
+            background,
+            seconds: 6.0,
+        };
+
+        let dark = card("1A1B26").filter();
+        assert!(dark.starts_with("drawtext=textfile=leaderboard.txt:"));
+        assert!(dark.contains("fontcolor=white"));
+        assert!(card("FDF6E3").filter().contains("fontcolor=black"));
+    }
+
+    #[test]
+    fn test_clip_filter() {
+        let clip = |title_file| Clip {
+            start: 12.0,
+            seconds: 60.0,
+            title_file,
+        };
+
+        assert_eq!(
+            clip(None).filter(),
+            r"crop=min(iw\,trunc(ih*9/32)*2):min(ih\,trunc(iw*16/18)*2)"
+        );
+        let titled = clip(Some(Path::new("/data/shorts/default/title.txt"))).filter();
+        assert!(titled.contains(",drawtext=textfile=title.txt:"));
+    }
+
+    #[test]
+    fn test_parse_frame_position() {
+        assert_eq!("40%".parse(), Ok(FramePosition::Percent(40.0)));
+        assert_eq!("12.5".parse(), Ok(FramePosition::Seconds(12.5)));
+        assert!("150%".parse::<FramePosition>().is_err());
+        assert!("-1".parse::<FramePosition>().is_err());
+        assert!("middle".parse::<FramePosition>().is_err());
+    }
+
+    #[test]
+    fn test_parse_probe() {
+        assert_eq!(
+            Probe::parse("codec_type=video\ncodec_type=audio\nduration=12.500000\n"),
+            Some(Probe {
+                duration: 12.5,
+                video_streams: 1
+            })
+        );
+        assert_eq!(Probe::parse("codec_type=video\nduration=N/A\n"), None);
+    }
+
+    #[test]
+    fn test_metadata_args() {
+        assert_eq!(
+            metadata_args(&[("title", "Our year".into())]),
+            ["-metadata", "title=Our year"]
+        );
+        assert_eq!(creation_time(1_709_294_400), "2024-03-01T12:00:00Z");
+    }
+}
@@ -1,25 +1,192 @@
-use std::process::{Command, Stdio};
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    io,
+    path::Path,
+    process::{ExitStatus, Stdio},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use color_eyre::eyre::{bail, Result, WrapErr};
-use indicatif::ProgressBar;
+use clap::ValueEnum;
+use lazy_regex::regex;
 use reqwest::{
-    blocking::{Client, Request},
-    header::HeaderMap,
-    Method,
+    header::{HeaderMap, HeaderValue, ETAG, IF_NONE_MATCH, LINK, RETRY_AFTER},
+    Client, StatusCode,
 };
-use serde::Deserialize;
-use tap::Tap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{io::AsyncReadExt, process::Command};
 
-use crate::Context;
+use crate::{
+    bench::Phase,
+    error::{GourcersError, Result},
+    http_cache::{CachedResponse, ResponseCache},
+    parallel,
+    progress::Progress,
+    rules::RuleSet,
+    Context,
+};
 
-#[derive(Debug, Deserialize)]
+/// The implementation used to clone and pull repos.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GitBackend {
+    /// Shell out to the `git` command.
+    #[default]
+    Cli,
+    /// Use libgit2, which reports per-object progress.
+    #[cfg(feature = "libgit2")]
+    Libgit2,
+}
+
+/// How existing clones are brought up to date.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UpdateStrategy {
+    /// Run `git pull`, which fails if the local branch has diverged.
+    Pull,
+    /// Fetch and hard reset to the remote's default branch, discarding any local changes.
+    #[default]
+    Reset,
+    /// Only fetch, leaving the checked out commit unchanged.
+    FetchOnly,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Repo {
+    pub id: u64,
     pub name: String,
     pub full_name: Option<String>,
+    /// The URL or path the repo is cloned from, which is the SSH URL for repos on GitHub. See
+    /// [`RepoSource::clone_url`](crate::source::RepoSource::clone_url).
     pub ssh_url: String,
     pub owner: Owner,
     pub fork: bool,
     pub private: bool,
+    /// The size of the repo in kilobytes, which is 0 for repos without any commits.
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub stargazers_count: u64,
+    /// When the repo was last pushed to, as an ISO 8601 timestamp.
+    #[serde(default)]
+    pub pushed_at: Option<String>,
+    #[serde(default)]
+    pub has_wiki: bool,
+    #[serde(skip_deserializing)]
+    pub kind: RepoKind,
+    /// The teams with access to the repo as `org/slug`, if they were fetched.
+    #[serde(skip_deserializing)]
+    pub teams: Vec<String>,
+}
+
+/// What kind of git repo a [`Repo`] is. Wikis and gists are listed separately from repos, but are
+/// cloned and visualized the same way.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepoKind {
+    #[default]
+    Repo,
+    /// A repo's wiki, named after the repo with `.wiki` added.
+    Wiki,
+    /// A gist, named after its ID.
+    Gist,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Gist {
+    id: String,
+    git_pull_url: String,
+    public: bool,
+    owner: Option<Owner>,
+}
+
+impl Gist {
+    fn into_repo(self) -> Repo {
+        let owner = self.owner.unwrap_or_default();
+
+        Repo {
+            full_name: Some(format!("{}/gists/{}", owner.login, self.id)),
+            name: self.id,
+            // secret gists can be cloned by anyone with the URL, so there is no SSH URL to prefer
+            ssh_url: self.git_pull_url,
+            owner,
+            private: !self.public,
+            // gists don't report a size, so leave it to the check of the gource log
+            size: 1,
+            kind: RepoKind::Gist,
+            ..Repo::default()
+        }
+    }
+}
+
+/// A repo as printed by `gh repo list --json`, with whichever fields were asked for.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GhRepo {
+    /// The GraphQL node ID.
+    id: Option<String>,
+    name_with_owner: String,
+    ssh_url: Option<String>,
+    url: Option<String>,
+    #[serde(default)]
+    is_fork: bool,
+    #[serde(default)]
+    is_private: bool,
+    /// In kilobytes.
+    disk_usage: Option<u64>,
+    is_empty: Option<bool>,
+    #[serde(default)]
+    stargazer_count: u64,
+    pushed_at: Option<String>,
+    #[serde(default)]
+    has_wiki_enabled: bool,
+}
+
+impl GhRepo {
+    fn into_repo(self) -> Repo {
+        let (owner, name) = self
+            .name_with_owner
+            .split_once('/')
+            .unwrap_or(("", &self.name_with_owner));
+
+        let ssh_url = self.ssh_url.unwrap_or_else(|| {
+            let host = self
+                .url
+                .as_deref()
+                .and_then(|url| url.split("://").nth(1))
+                .and_then(|rest| rest.split('/').next())
+                .unwrap_or("github.com");
+            format!("git@{host}:{}.git", self.name_with_owner)
+        });
+
+        // only the node ID is available, which is hashed so renames can still be detected
+        let id = self.id.as_deref().unwrap_or(&self.name_with_owner);
+        let id = u64::from_be_bytes(Sha256::digest(id)[..8].try_into().unwrap());
+
+        let size = match (self.is_empty, self.disk_usage) {
+            (Some(true), _) => 0,
+            (_, Some(disk_usage)) => disk_usage.max(1),
+            // without either, leave it to the check of the gource log
+            (_, None) => 1,
+        };
+
+        Repo {
+            id,
+            name: name.to_string(),
+            owner: Owner {
+                login: owner.to_string(),
+                account_type: String::new(),
+            },
+            ssh_url,
+            fork: self.is_fork,
+            private: self.is_private,
+            size,
+            stargazers_count: self.stargazer_count,
+            pushed_at: self.pushed_at,
+            has_wiki: self.has_wiki_enabled,
+            full_name: Some(self.name_with_owner),
+            ..Repo::default()
+        }
+    }
 }
 
 impl Repo {
@@ -30,116 +197,1103 @@ impl Repo {
             .unwrap_or_else(|| format!("{}/{}", self.owner.login, self.name))
     }
 
+    /// The repo's wiki, which is only a git repo once its first page is written.
+    #[must_use]
+    pub fn wiki(&self) -> Self {
+        let ssh_url = self.ssh_url.strip_suffix(".git").unwrap_or(&self.ssh_url);
+
+        Self {
+            name: format!("{}.wiki", self.name),
+            full_name: Some(format!("{}.wiki", self.full_name())),
+            ssh_url: format!("{ssh_url}.wiki.git"),
+            has_wiki: false,
+            kind: RepoKind::Wiki,
+            ..self.clone()
+        }
+    }
+
     #[must_use]
     pub fn full_name_path_friendly(&self) -> String {
-        self.full_name().replace('/', "__")
+        path_friendly(&self.full_name())
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// Convert a repo's full name into a name which can be used as a single path component.
+#[must_use]
+pub fn path_friendly(full_name: &str) -> String {
+    full_name.replace('/', "__")
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Owner {
     pub login: String,
+    /// `User` or `Organization`.
+    #[serde(default, rename = "type")]
+    pub account_type: String,
 }
 
-pub(crate) fn list_repos(cx: &Context, progress: &ProgressBar) -> Result<Vec<Repo>> {
+/// Write the repos to `path` as a JSON array, with the same fields as the API returns and their
+/// kind and teams.
+pub fn write_repos(path: &Path, repos: &[Repo]) -> Result<()> {
+    let contents = serde_json::to_string_pretty(repos)
+        .map_err(GourcersError::json("failed to serialize repos"))?;
+    std::fs::write(path, contents).map_err(GourcersError::io(format!(
+        "failed to write {}",
+        path.display()
+    )))
+}
+
+/// The API used when `--api-url` is not given.
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// Build an API client which authenticates with the token.
+pub(crate) fn client(cx: &Context) -> Result<Client> {
     let mut headers = HeaderMap::new();
 
-    headers.append(
-        "Authorization",
-        format!("Bearer {}", &cx.token)
-            .parse()
-            .wrap_err("failed to parse token into header")?,
-    );
+    let mut authorization: HeaderValue = format!("Bearer {}", cx.token.expose())
+        .parse()
+        .map_err(GourcersError::InvalidToken)?;
+    authorization.set_sensitive(true);
+
+    headers.append("Authorization", authorization);
     headers.append("User-Agent", "gourcers-ng".parse().unwrap());
-    headers.append("X-GitHub-Api-Version", "2022-11-28".parse().unwrap());
     headers.append("Accept", "application/vnd.github+json".parse().unwrap());
 
+    // GitHub Enterprise Server only understands this header from 3.9 onwards, and older releases
+    // reject versions they don't know, so let enterprise servers use their default version
+    if cx.api_url == DEFAULT_API_URL {
+        headers.append("X-GitHub-Api-Version", "2022-11-28".parse().unwrap());
+    }
+
     trace!("headers: {:?}", headers);
 
-    let client = Client::builder()
+    cx.network
+        .client_builder(Client::builder())?
         .default_headers(headers)
         .build()
-        .wrap_err("failed to build reqwest client")?;
+        .map_err(GourcersError::network("failed to build reqwest client"))
+}
+
+/// What the API reports about the token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenInfo {
+    /// The login of the account the token belongs to.
+    pub login: String,
+    /// The scopes of a classic token, or `None` for tokens without scopes, such as fine-grained
+    /// tokens.
+    pub scopes: Option<Vec<String>>,
+    /// When the token expires, as a Unix timestamp, or `None` if it never does.
+    pub expires_at: Option<i64>,
+}
+
+/// How long before the token expires to start warning about it.
+const EXPIRY_WARNING: i64 = 7 * 86_400;
+
+impl TokenInfo {
+    /// Whether the token can list private repos. Tokens without scopes are assumed to be able to,
+    /// since their permissions can't be inspected.
+    #[must_use]
+    pub fn has_repo_scope(&self) -> bool {
+        self.scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.iter().any(|scope| scope == "repo"))
+    }
+
+    /// A warning if the token expires within a week of `now`, since runs which take longer would
+    /// fail partway through.
+    #[must_use]
+    pub fn expiry_warning(&self, now: i64) -> Option<String> {
+        let expires_at = self.expires_at?;
+        let remaining = expires_at - now;
+        if remaining > EXPIRY_WARNING {
+            return None;
+        }
+
+        let (year, month, day) = crate::calendar::date(expires_at);
+        Some(format!(
+            "the token expires in {} (on {year:04}-{month:02}-{day:02}), which may be before the \
+             run finishes",
+            indicatif::HumanDuration(Duration::from_secs(remaining.max(0).unsigned_abs()))
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+/// Look up the account and scopes of the token with `/user`.
+pub(crate) async fn token_info(cx: &Context) -> Result<TokenInfo> {
+    let response = client(cx)?
+        .get(format!("{}/user", cx.api_url))
+        .send()
+        .await
+        .map_err(GourcersError::network("failed to execute request"))?
+        .error_for_status()
+        .map_err(GourcersError::network("the token was rejected"))?;
+
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+
+    // fine-grained tokens have permissions instead of scopes, which the API doesn't report
+    let scopes = if cx.token.expose().starts_with("github_pat_") {
+        None
+    } else {
+        header("X-OAuth-Scopes").map(parse_scopes)
+    };
+    let expires_at = header("GitHub-Authentication-Token-Expiration").and_then(parse_expiration);
+
+    let user: User = response
+        .json()
+        .await
+        .map_err(GourcersError::network("failed to parse the user"))?;
+
+    Ok(TokenInfo {
+        login: user.login,
+        scopes,
+        expires_at,
+    })
+}
+
+/// Parse the `GitHub-Authentication-Token-Expiration` header, which looks like
+/// `2024-03-01 12:00:00 UTC` or `2024-03-01 04:00:00 -0800`.
+fn parse_expiration(header: &str) -> Option<i64> {
+    let captures =
+        regex!(r"^(\d{4})-(\d{2})-(\d{2}) (\d{2}):(\d{2}):(\d{2}) (UTC|([+-])(\d{2})(\d{2}))$")
+            .captures(header.trim())?;
+    let number = |x: usize| {
+        captures
+            .get(x)
+            .map_or(Some(0), |m| m.as_str().parse::<i64>().ok())
+    };
+
+    let timestamp = crate::calendar::from_date(number(1)?, number(2)?, number(3)?)
+        + number(4)? * 3600
+        + number(5)? * 60
+        + number(6)?;
+    let offset = number(9)? * 3600 + number(10)? * 60;
+
+    Some(match captures.get(8).map(|m| m.as_str()) {
+        Some("-") => timestamp + offset,
+        _ => timestamp - offset,
+    })
+}
 
+/// Parse the comma separated scopes in the `X-OAuth-Scopes` header.
+fn parse_scopes(header: &str) -> Vec<String> {
+    header
+        .split(',')
+        .map(str::trim)
+        .filter(|scope| !scope.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// List the repos the token has access to, and the public repos of any owners included with
+/// `owner:<login>/*`.
+pub(crate) async fn list_repos(cx: &Context, progress: &Progress) -> Result<Vec<Repo>> {
+    let owners = cx
+        .includes
+        .as_ref()
+        .map(RuleSet::listed_owners)
+        .unwrap_or_default();
+
+    list_repos_from(
+        &client(cx)?,
+        &ResponseCache::new(cx.data_dir.api_cache_dir()),
+        &cx.api_url,
+        &owners,
+        progress,
+    )
+    .await
+}
+
+/// List the repos the client's token has access to, and the public repos of `owners`, from the
+/// API at `api_url`.
+async fn list_repos_from(
+    client: &Client,
+    cache: &ResponseCache,
+    api_url: &str,
+    owners: &[&str],
+    progress: &Progress,
+) -> Result<Vec<Repo>> {
+    let mut repos: Vec<Repo> = list_pages(
+        client,
+        cache,
+        format!("{api_url}/user/repos?per_page=100"),
+        "",
+        progress,
+    )
+    .await?;
+
+    for owner in owners {
+        debug!(owner = owner, "listing owner's repos");
+        let url = format!("{api_url}/users/{owner}/repos?per_page=100");
+        let owner_repos: Vec<Repo> =
+            list_pages(client, cache, url, &format!("{owner}'s repos"), progress).await?;
+
+        let known = repos.iter().map(|repo| repo.id).collect::<HashSet<_>>();
+        repos.extend(
+            owner_repos
+                .into_iter()
+                .filter(|repo| !known.contains(&repo.id)),
+        );
+    }
+
+    Ok(repos)
+}
+
+/// Read a list of repos printed by `gh repo list --json`, from a file or from stdin if `path` is
+/// `-`. `nameWithOwner` is the only field which must be included, and `sshUrl`, `isFork`,
+/// `isPrivate`, `diskUsage`, `isEmpty`, `stargazerCount`, `pushedAt` and `hasWikiEnabled` are
+/// used if they are.
+pub(crate) fn read_gh_json(path: &Path) -> Result<Vec<Repo>> {
+    let contents = if path == Path::new("-") {
+        io::read_to_string(io::stdin())
+    } else {
+        std::fs::read_to_string(path)
+    }
+    .map_err(GourcersError::io(format!(
+        "failed to read {}",
+        path.display()
+    )))?;
+
+    let repos: Vec<GhRepo> = serde_json::from_str(&contents).map_err(GourcersError::json(
+        format!("failed to parse the repos in {}", path.display()),
+    ))?;
+
+    Ok(repos.into_iter().map(GhRepo::into_repo).collect())
+}
+
+/// List the authenticated user's gists, which are cloned like repos.
+pub(crate) async fn list_gists(cx: &Context, progress: &Progress) -> Result<Vec<Repo>> {
+    let client = client(cx)?;
+    let cache = ResponseCache::new(cx.data_dir.api_cache_dir());
+
+    let url = format!("{}/gists?per_page=100", cx.api_url);
+    let gists: Vec<Gist> = list_pages(&client, &cache, url, "gists", progress).await?;
+
+    Ok(gists.into_iter().map(Gist::into_repo).collect())
+}
+
+/// Fetch every page of a listing starting at `url`. `what` is shown in the progress message,
+/// unless it is empty.
+pub(crate) async fn list_pages<T: DeserializeOwned>(
+    client: &Client,
+    cache: &ResponseCache,
+    url: String,
+    what: &str,
+    progress: &Progress,
+) -> Result<Vec<T>> {
     let mut repos = Vec::new();
     let mut page = 1;
+    let mut next = Some(url);
 
-    loop {
+    while let Some(url) = next {
         debug!(page = page, "fetching page of repos");
-        progress.set_message(format!("Fetching page {page}"));
+        if what.is_empty() {
+            progress.set_message(format!("Fetching page {page}"));
+        } else {
+            progress.set_message(format!("Fetching page {page} of {what}"));
+        }
 
-        let request = Request::new(
-            Method::GET,
-            format!("https://api.github.com/user/repos?per_page=100&page={page}")
-                .parse()
-                .unwrap(),
-        );
+        let (link, body) = get_cached(client, cache, &url).await?;
+
+        let links = link.as_deref().map(Links::parse).unwrap_or_default();
+
+        if let Some(last) = links.last.as_deref().and_then(page_number) {
+            progress.set_length(last);
+        }
+
+        let page_repos: Vec<T> =
+            serde_json::from_str(&body).map_err(GourcersError::json("failed to parse response"))?;
+
+        trace!(len = page_repos.len(), page = page, "fetched page of repos");
+
+        repos.extend(page_repos);
+        progress.inc(1);
+        next = links.next;
+        page += 1;
+    }
+
+    Ok(repos)
+}
+
+/// How many times a rate limited request is retried.
+const RATE_LIMIT_RETRIES: u32 = 3;
+
+/// The longest to wait for a rate limit to reset before giving up.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_mins(1);
+
+/// How long to wait after a secondary rate limit which doesn't say how long to wait for, as
+/// GitHub's documentation recommends.
+const DEFAULT_RATE_LIMIT_WAIT: Duration = Duration::from_mins(1);
+
+/// Send a conditional GET request, using the cached response if the server reports it is
+/// unchanged. Rate limited requests are retried once the limit resets, if that is soon enough.
+///
+/// Returns the `Link` header and the body of the response.
+async fn get_cached(
+    client: &Client,
+    cache: &ResponseCache,
+    url: &str,
+) -> Result<(Option<String>, String)> {
+    let cached = cache.get(url);
+    let mut attempt = 0;
+
+    let response = loop {
+        let mut request = client
+            .get(url)
+            .build()
+            .map_err(GourcersError::network("failed to build request"))?;
+
+        if let Some(cached) = &cached {
+            if let Ok(etag) = cached.etag.parse() {
+                request.headers_mut().insert(IF_NONE_MATCH, etag);
+            }
+        }
 
         let response = client
             .execute(request)
-            .wrap_err("failed to execute request")?;
+            .await
+            .map_err(GourcersError::network("failed to execute request"))?;
 
         trace!("response: {:?}", response);
 
-        let response = response.error_for_status().wrap_err("request failed")?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let Some(wait) = rate_limit_wait(response.status(), response.headers(), now) else {
+            break response;
+        };
 
-        let page_repos: Vec<Repo> = response.json().wrap_err("failed to parse response")?;
+        attempt += 1;
+        if attempt > RATE_LIMIT_RETRIES || wait > MAX_RATE_LIMIT_WAIT {
+            return Err(GourcersError::RateLimited { wait });
+        }
+        warn!(url = url, "rate limited, retrying in {}s", wait.as_secs());
+        tokio::time::sleep(wait).await;
+    };
 
-        trace!(len = page_repos.len(), page = page, "fetched page of repos");
+    if response.status() == StatusCode::NOT_MODIFIED {
+        if let Some(cached) = cached {
+            debug!(url = url, "using cached response");
+            return Ok((cached.link, cached.body));
+        }
+    }
 
-        if page_repos.is_empty() {
-            break;
+    let response = response
+        .error_for_status()
+        .map_err(GourcersError::network("request failed"))?;
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string)
+    };
+    let etag = header(ETAG);
+    let link = header(LINK);
+
+    let body = response
+        .text()
+        .await
+        .map_err(GourcersError::network("failed to read response"))?;
+
+    if let Some(etag) = etag {
+        let entry = CachedResponse {
+            etag,
+            link: link.clone(),
+            body: body.clone(),
+        };
+        if let Err(e) = cache.put(url, &entry) {
+            warn!("failed to cache response for {url}: {e:#}");
         }
+    }
 
-        repos.extend(page_repos);
-        page += 1;
+    Ok((link, body))
+}
+
+/// How long to wait before retrying a response, at the Unix time `now`, or `None` if it wasn't
+/// rate limited.
+///
+/// GitHub answers with `429 Too Many Requests` or `403 Forbidden` when a rate limit is exceeded.
+/// The primary limit says when it resets in `X-RateLimit-Reset`, and secondary limits may say how
+/// long to wait in `Retry-After`.
+fn rate_limit_wait(status: StatusCode, headers: &HeaderMap, now: u64) -> Option<Duration> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+    let retry_after = header(RETRY_AFTER.as_str());
+    let remaining = header("x-ratelimit-remaining");
+
+    let limited = status == StatusCode::TOO_MANY_REQUESTS
+        || (status == StatusCode::FORBIDDEN && (remaining == Some(0) || retry_after.is_some()));
+    if !limited {
+        return None;
     }
 
-    Ok(repos)
+    if let Some(seconds) = retry_after {
+        return Some(Duration::from_secs(seconds));
+    }
+    match header("x-ratelimit-reset") {
+        Some(reset) if remaining == Some(0) => Some(Duration::from_secs(reset.saturating_sub(now))),
+        _ => Some(DEFAULT_RATE_LIMIT_WAIT),
+    }
 }
 
-/// Clone or pull the given repo into the repos directory.
-pub(crate) fn fetch_repo(cx: &Context, repo: &Repo) -> Result<()> {
-    let repo_dir = cx.data_dir.repo_dir(repo);
+/// The pagination links from a `Link` header.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Links {
+    next: Option<String>,
+    last: Option<String>,
+}
 
-    let mut cmd = Command::new("git");
+impl Links {
+    /// Parse a header such as `<https://api.github.com/user/repos?page=2>; rel="next", ...`.
+    fn parse(header: &str) -> Self {
+        let mut links = Self::default();
 
-    cmd.stderr(Stdio::piped()).stdout(Stdio::piped());
+        for link in header.split(',') {
+            let mut parts = link.split(';').map(str::trim);
 
-    if repo_dir.exists() {
-        let output = cmd
-            .arg("pull")
-            .current_dir(&repo_dir)
-            .tap(|cmd| {
-                trace!(command = ?cmd, repo = %repo.name, "running git pull");
-            })
-            .output()
-            .wrap_err("failed to run git pull")?;
+            let Some(url) = parts
+                .next()
+                .and_then(|url| url.strip_prefix('<'))
+                .and_then(|url| url.strip_suffix('>'))
+            else {
+                continue;
+            };
 
-        if !output.status.success() {
-            bail!(
-                "git pull failed: {}",
-                String::from_utf8_lossy(&output.stderr).trim()
-            );
+            for param in parts {
+                match param {
+                    r#"rel="next""# => links.next = Some(url.to_string()),
+                    r#"rel="last""# => links.last = Some(url.to_string()),
+                    _ => {}
+                }
+            }
         }
-    } else {
-        let output = cmd
-            .arg("clone")
-            .arg(&repo.ssh_url)
-            .arg(&repo_dir)
-            .tap(|cmd| {
-                trace!(command = ?cmd, repo = %repo.name, "running git clone");
+
+        links
+    }
+}
+
+/// Extract the `page` query parameter from a pagination URL.
+fn page_number(url: &str) -> Option<u64> {
+    reqwest::Url::parse(url)
+        .ok()?
+        .query_pairs()
+        .find(|(key, _)| key == "page")
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+/// Clone or pull every repo, retrying failures after the first pass completes.
+///
+/// Returns the repos which still failed after all retries, along with their last error.
+pub(crate) async fn fetch_repos<'a>(
+    cx: &Context,
+    repos: &'a [Repo],
+) -> Vec<(&'a Repo, GourcersError)> {
+    let fetch = async |repo: &Repo, worker: &Progress| {
+        let started = Instant::now();
+        let result = fetch_repo(cx, repo, worker).await;
+        cx.timings
+            .repo(Phase::Clone, repo.full_name(), started.elapsed());
+        result
+    };
+
+    let progress = Progress::bar(repos.len() as u64, !cx.progress_bars);
+    let mut failures =
+        parallel::for_each_collect(repos, cx.jobs, &progress, Repo::full_name, fetch).await;
+    progress.finish();
+
+    for attempt in 1..=cx.clone_retries {
+        if failures.is_empty() {
+            break;
+        }
+
+        let failed = failures
+            .into_iter()
+            .map(|(repo, _)| repo)
+            .collect::<Vec<_>>();
+
+        let progress = Progress::bar(failed.len() as u64, !cx.progress_bars);
+        progress.println(format!(
+            "Retrying {} failed repos (attempt {attempt}/{})",
+            failed.len(),
+            cx.clone_retries
+        ));
+
+        failures = parallel::for_each_collect(
+            &failed,
+            cx.jobs,
+            &progress,
+            |repo| repo.full_name(),
+            async |repo, worker| fetch(repo, worker).await,
+        )
+        .await
+        .into_iter()
+        .map(|(repo, e)| (*repo, e))
+        .collect();
+        progress.finish();
+    }
+
+    failures
+}
+
+/// Clone or pull the given repo into the repos directory using the configured backend.
+#[instrument(skip_all, fields(repo = %repo.full_name()))]
+pub(crate) async fn fetch_repo(cx: &Context, repo: &Repo, progress: &Progress) -> Result<()> {
+    match cx.git_backend {
+        GitBackend::Cli => fetch_repo_cli(cx, repo, progress).await,
+        #[cfg(feature = "libgit2")]
+        GitBackend::Libgit2 => {
+            let repo_dir = cx.data_dir.repo_dir(repo);
+            let full_name = repo.full_name();
+            let url = repo.ssh_url.clone();
+            let strategy = cx.update_strategy;
+            let network = cx.network.clone();
+            let progress = progress.clone();
+
+            tokio::task::spawn_blocking(move || {
+                crate::libgit2::fetch_repo(&repo_dir, &url, strategy, &network, &progress)
+            })
+            .await?
+            .map_err(|source| GourcersError::Libgit2 {
+                repo: full_name,
+                source,
             })
-            .output()
-            .wrap_err("failed to run git clone")?;
+        }
+    }
+}
+
+/// Clone or update the given repo into the repos directory using the `git` command.
+async fn fetch_repo_cli(cx: &Context, repo: &Repo, progress: &Progress) -> Result<()> {
+    const FETCH: &[&str] = &["fetch", "--prune", "--progress", "origin"];
 
-        if !output.status.success() {
-            bail!(
-                "git clone failed: {}",
-                String::from_utf8_lossy(&output.stderr).trim()
-            );
+    let repo_dir = cx.data_dir.repo_dir(repo);
+    let shallow_since = cx
+        .history_since
+        .map(|since| format!("--shallow-since={since}"));
+
+    if !repo_dir.exists() {
+        let mut args = vec![OsStr::new("clone"), OsStr::new("--progress")];
+        args.extend(shallow_since.as_deref().map(OsStr::new));
+        args.extend([OsStr::new(&repo.ssh_url), repo_dir.as_os_str()]);
+        return run_git(cx, repo, None, &args, progress).await;
+    }
+
+    let dir = Some(repo_dir.as_path());
+
+    // move the start of a shallow clone to the current --history-since, or fetch the rest of the
+    // history without it
+    if repo_dir.join(".git").join("shallow").exists() {
+        let boundary = shallow_since.as_deref().unwrap_or("--unshallow");
+        run_git(
+            cx,
+            repo,
+            dir,
+            &["fetch", "--progress", boundary, "origin"],
+            progress,
+        )
+        .await?;
+    }
+
+    match cx.update_strategy {
+        UpdateStrategy::Pull => run_git(cx, repo, dir, &["pull", "--progress"], progress).await,
+        UpdateStrategy::Reset => {
+            run_git(cx, repo, dir, FETCH, progress).await?;
+            // follow the default branch if it was renamed upstream
+            run_git(
+                cx,
+                repo,
+                dir,
+                &["remote", "set-head", "origin", "--auto"],
+                progress,
+            )
+            .await?;
+            run_git(cx, repo, dir, &["reset", "--hard", "origin/HEAD"], progress).await
         }
+        UpdateStrategy::FetchOnly => run_git(cx, repo, dir, FETCH, progress).await,
+    }
+}
+
+/// Run a git subcommand, failing with its stderr if it exits unsuccessfully.
+async fn run_git<S: AsRef<OsStr>>(
+    cx: &Context,
+    repo: &Repo,
+    current_dir: Option<&Path>,
+    args: &[S],
+    progress: &Progress,
+) -> Result<()> {
+    let subcommand = args[0].as_ref().to_string_lossy();
+
+    let mut cmd = cx.network.git_command(cx.jobs);
+
+    cmd.args(args)
+        .stderr(Stdio::piped())
+        .stdout(Stdio::null())
+        .kill_on_drop(true);
+
+    if let Some(dir) = current_dir {
+        cmd.current_dir(dir);
+    }
+
+    trace!(command = ?cmd, repo = %repo.name, "running git {subcommand}");
+
+    let (status, stderr) =
+        run_with_progress(&mut cmd, progress)
+            .await
+            .map_err(GourcersError::io(format!(
+                "failed to run git {subcommand} for {}",
+                repo.full_name()
+            )))?;
+
+    if !status.success() {
+        return Err(GourcersError::Git {
+            repo: repo.full_name(),
+            subcommand: subcommand.into_owned(),
+            stderr: stderr.trim().to_string(),
+        });
     }
 
     Ok(())
 }
+
+/// Run a git command with `--progress`, showing its transfer progress in a sub-bar.
+///
+/// Returns the exit status and every line of stderr which was not a progress update.
+async fn run_with_progress(
+    cmd: &mut Command,
+    progress: &Progress,
+) -> io::Result<(ExitStatus, String)> {
+    let mut child = cmd.spawn()?;
+    let mut stderr = child.stderr.take().unwrap();
+
+    let sub_bar = progress.sub_bar();
+    let mut messages = String::new();
+    let mut pending = Vec::new();
+    let mut buf = [0; 4096];
+
+    // git separates progress updates with \r and everything else with \n
+    let mut handle_line = |line: &[u8]| {
+        let line = String::from_utf8_lossy(line);
+        if let Some((phase, percent)) = parse_git_progress(&line) {
+            sub_bar.set_message(phase.to_string());
+            sub_bar.set_position(percent);
+        } else if !line.trim().is_empty() {
+            messages.push_str(&line);
+            messages.push('\n');
+        }
+    };
+
+    loop {
+        let n = stderr.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+
+        pending.extend_from_slice(&buf[..n]);
+        while let Some(idx) = pending.iter().position(|b| matches!(b, b'\r' | b'\n')) {
+            let line = pending.drain(..=idx).collect::<Vec<_>>();
+            handle_line(&line[..idx]);
+        }
+    }
+    handle_line(&pending);
+
+    sub_bar.finish_and_clear();
+
+    let status = child.wait().await?;
+
+    Ok((status, messages))
+}
+
+/// Parse a progress line such as `Receiving objects:  45% (123/273)` into its phase and percent.
+fn parse_git_progress(line: &str) -> Option<(&str, u64)> {
+    let captures = regex!(r"^(?:remote: )?([A-Za-z ]+):\s+(\d{1,3})%").captures(line)?;
+
+    let phase = captures.get(1)?.as_str();
+    let percent = captures.get(2)?.as_str().parse().ok()?;
+
+    Some((phase, percent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        log::TreeLayout,
+        mock_server::{MockResponse, MockServer},
+    };
+
+    const USER_REPOS_1: &str = include_str!("../tests/fixtures/github/user_repos_1.json");
+    const USER_REPOS_2: &str = include_str!("../tests/fixtures/github/user_repos_2.json");
+    const OWNER_REPOS: &str = include_str!("../tests/fixtures/github/owner_repos.json");
+
+    fn test_client() -> Client {
+        Client::builder().no_proxy().build().unwrap()
+    }
+
+    #[test]
+    fn test_parse_links() {
+        let header = r#"<https://api.github.com/user/repos?per_page=100&page=2>; rel="next", <https://api.github.com/user/repos?per_page=100&page=5>; rel="last""#;
+
+        let links = Links::parse(header);
+
+        assert_eq!(
+            links.next.as_deref(),
+            Some("https://api.github.com/user/repos?per_page=100&page=2")
+        );
+        assert_eq!(links.last.as_deref().and_then(page_number), Some(5));
+
+        let links = Links::parse(
+            r#"<https://api.github.com/user/repos?page=1>; rel="prev", <https://api.github.com/user/repos?page=1>; rel="first""#,
+        );
+        assert_eq!(links, Links::default());
+    }
+
+    #[tokio::test]
+    async fn test_list_repos() {
+        let server = MockServer::start(vec![
+            (
+                "/user/repos?per_page=100",
+                vec![MockResponse::json(USER_REPOS_1).header(
+                    "Link",
+                    r#"<{url}/user/repos?per_page=100&page=2>; rel="next", <{url}/user/repos?per_page=100&page=2>; rel="last""#,
+                )],
+            ),
+            (
+                "/user/repos?per_page=100&page=2",
+                vec![MockResponse::json(USER_REPOS_2)],
+            ),
+            (
+                "/users/rust-lang/repos?per_page=100",
+                vec![MockResponse::json(OWNER_REPOS)],
+            ),
+        ]);
+        let temp = temp_dir::TempDir::new().unwrap();
+
+        let repos = list_repos_from(
+            &test_client(),
+            &ResponseCache::new(temp.child("cache")),
+            server.url(),
+            &["rust-lang"],
+            &Progress::spinner(true),
+        )
+        .await
+        .unwrap();
+
+        // rustlings is listed for the user and for rust-lang, but only kept once
+        assert_eq!(
+            repos.iter().map(Repo::full_name).collect::<Vec<_>>(),
+            [
+                "campbellcole/gourcers",
+                "campbellcole/dotfiles",
+                "campbellcole/rust",
+                "rust-lang/rustlings",
+                "rust-lang/cargo"
+            ]
+        );
+        assert!(repos[1].private);
+        assert_eq!(repos[1].size, 0);
+        assert!(repos[2].fork);
+        assert_eq!(repos[4].owner.account_type, "Organization");
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_cached() {
+        let server = MockServer::start(vec![(
+            "/user/repos",
+            vec![
+                MockResponse::json("[]").header("ETag", r#""abc""#),
+                MockResponse::status(304),
+            ],
+        )]);
+        let temp = temp_dir::TempDir::new().unwrap();
+        let cache = ResponseCache::new(temp.child("cache"));
+        let url = format!("{}/user/repos", server.url());
+
+        let first = get_cached(&test_client(), &cache, &url).await.unwrap();
+        let second = get_cached(&test_client(), &cache, &url).await.unwrap();
+
+        assert_eq!(first, (None, "[]".to_string()));
+        assert_eq!(second, first);
+        let requests = server.requests();
+        assert_eq!(requests[0].headers.get("if-none-match"), None);
+        assert_eq!(
+            requests[1].headers.get("if-none-match").map(String::as_str),
+            Some(r#""abc""#)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_cached_rate_limited() {
+        let server = MockServer::start(vec![
+            (
+                "/retry",
+                vec![
+                    MockResponse::status(403)
+                        .header("X-RateLimit-Remaining", "0")
+                        .header("Retry-After", "0"),
+                    MockResponse::json("[1]"),
+                ],
+            ),
+            (
+                "/wait",
+                vec![MockResponse::status(429).header("Retry-After", "3600")],
+            ),
+            ("/forbidden", vec![MockResponse::status(403)]),
+        ]);
+        let temp = temp_dir::TempDir::new().unwrap();
+        let cache = ResponseCache::new(temp.child("cache"));
+        let get = |path: &str| {
+            let url = format!("{}{path}", server.url());
+            let cache = &cache;
+            async move { get_cached(&test_client(), cache, &url).await }
+        };
+
+        assert_eq!(get("/retry").await.unwrap().1, "[1]");
+        assert!(matches!(
+            get("/wait").await,
+            Err(GourcersError::RateLimited { wait }) if wait == Duration::from_hours(1)
+        ));
+        assert!(matches!(
+            get("/forbidden").await,
+            Err(GourcersError::Network { .. })
+        ));
+        // the long wait isn't retried
+        assert_eq!(server.requests().len(), 4);
+    }
+
+    #[test]
+    fn test_rate_limit_wait() {
+        let headers = |pairs: &[(&'static str, &str)]| {
+            pairs
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        reqwest::header::HeaderName::from_static(name),
+                        value.parse().unwrap(),
+                    )
+                })
+                .collect::<HeaderMap>()
+        };
+        let now = 1_700_000_000;
+
+        assert_eq!(
+            rate_limit_wait(
+                StatusCode::FORBIDDEN,
+                &headers(&[
+                    ("x-ratelimit-remaining", "0"),
+                    ("x-ratelimit-reset", "1700000030")
+                ]),
+                now
+            ),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            rate_limit_wait(
+                StatusCode::TOO_MANY_REQUESTS,
+                &headers(&[("retry-after", "5")]),
+                now
+            ),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            rate_limit_wait(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), now),
+            Some(DEFAULT_RATE_LIMIT_WAIT)
+        );
+        // a token without access, not a rate limit
+        assert_eq!(
+            rate_limit_wait(
+                StatusCode::FORBIDDEN,
+                &headers(&[("x-ratelimit-remaining", "4999")]),
+                now
+            ),
+            None
+        );
+        assert_eq!(
+            rate_limit_wait(StatusCode::OK, &HeaderMap::new(), now),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_scopes() {
+        assert_eq!(parse_scopes("repo, read:org"), ["repo", "read:org"]);
+        assert!(parse_scopes("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_expiration() {
+        // 2024-03-01 at noon UTC
+        assert_eq!(
+            parse_expiration("2024-03-01 12:00:00 UTC"),
+            Some(1_709_294_400)
+        );
+        assert_eq!(
+            parse_expiration("2024-03-01 04:00:00 -0800"),
+            Some(1_709_294_400)
+        );
+        assert_eq!(
+            parse_expiration("2024-03-01 13:30:00 +0130"),
+            Some(1_709_294_400)
+        );
+        assert_eq!(parse_expiration("next week"), None);
+    }
+
+    #[test]
+    fn test_expiry_warning() {
+        let info = TokenInfo {
+            login: "octocat".into(),
+            scopes: None,
+            expires_at: Some(1_709_294_400),
+        };
+
+        assert_eq!(info.expiry_warning(1_709_294_400 - 8 * 86_400), None);
+        assert_eq!(
+            info.expiry_warning(1_709_294_400 - 3 * 86_400).as_deref(),
+            Some(
+                "the token expires in 3 days (on 2024-03-01), which may be before the run finishes"
+            )
+        );
+        assert_eq!(
+            TokenInfo {
+                expires_at: None,
+                ..info
+            }
+            .expiry_warning(0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_has_repo_scope() {
+        let info = |scopes: Option<&str>| TokenInfo {
+            login: "octocat".into(),
+            scopes: scopes.map(parse_scopes),
+            expires_at: None,
+        };
+
+        assert!(info(Some("read:org, repo")).has_repo_scope());
+        assert!(!info(Some("public_repo")).has_repo_scope());
+        assert!(!info(Some("")).has_repo_scope());
+        assert!(info(None).has_repo_scope());
+    }
+
+    #[test]
+    fn test_parse_git_progress() {
+        assert_eq!(
+            parse_git_progress("Receiving objects:  45% (123/273), 1.20 MiB | 2.40 MiB/s"),
+            Some(("Receiving objects", 45))
+        );
+        assert_eq!(
+            parse_git_progress("remote: Counting objects: 100% (12/12), done."),
+            Some(("Counting objects", 100))
+        );
+        assert_eq!(
+            parse_git_progress("Resolving deltas:   3% (1/25)"),
+            Some(("Resolving deltas", 3))
+        );
+        assert_eq!(parse_git_progress("Cloning into 'gourcers'..."), None);
+        assert_eq!(parse_git_progress("fatal: repository not found"), None);
+    }
+
+    #[test]
+    fn test_gh_json() {
+        let repos: Vec<GhRepo> = serde_json::from_str(
+            r#"[
+                {
+                    "id": "R_kgDOLOx7Vw",
+                    "nameWithOwner": "campbellcole/gourcers",
+                    "sshUrl": "git@github.com:campbellcole/gourcers.git",
+                    "isFork": false,
+                    "diskUsage": 512,
+                    "stargazerCount": 7
+                },
+                { "nameWithOwner": "acme/empty", "url": "https://ghe.acme.dev/acme/empty", "isEmpty": true }
+            ]"#,
+        )
+        .unwrap();
+        let repos = repos.into_iter().map(GhRepo::into_repo).collect::<Vec<_>>();
+
+        assert_eq!(repos[0].name, "gourcers");
+        assert_eq!(repos[0].owner.login, "campbellcole");
+        assert_eq!(repos[0].full_name(), "campbellcole/gourcers");
+        assert_eq!(repos[0].size, 512);
+        assert_eq!(repos[0].stargazers_count, 7);
+        assert_ne!(repos[0].id, repos[1].id);
+
+        assert_eq!(repos[1].ssh_url, "git@ghe.acme.dev:acme/empty.git");
+        assert_eq!(repos[1].size, 0);
+    }
+
+    #[test]
+    fn test_write_repos() {
+        let temp = temp_dir::TempDir::new().unwrap();
+        let path = temp.child("repos.json");
+        let repo = Repo {
+            full_name: Some("me/notes.wiki".into()),
+            kind: RepoKind::Wiki,
+            teams: vec!["acme/docs".into()],
+            ..Repo::default()
+        };
+
+        write_repos(&path, &[repo]).unwrap();
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written[0]["full_name"], "me/notes.wiki");
+        assert_eq!(written[0]["kind"], "wiki");
+        assert_eq!(written[0]["teams"][0], "acme/docs");
+    }
+
+    #[test]
+    fn test_wikis_and_gists() {
+        let repo = Repo {
+            name: "gourcers".into(),
+            full_name: Some("campbellcole/gourcers".into()),
+            ssh_url: "git@github.com:campbellcole/gourcers.git".into(),
+            has_wiki: true,
+            ..Repo::default()
+        };
+
+        let wiki = repo.wiki();
+        assert_eq!(wiki.full_name(), "campbellcole/gourcers.wiki");
+        assert_eq!(
+            wiki.ssh_url,
+            "git@github.com:campbellcole/gourcers.wiki.git"
+        );
+        assert_eq!(TreeLayout::Repo.prefix(&wiki), "/gourcers.wiki");
+
+        let gist = Gist {
+            id: "aa5a315d".into(),
+            git_pull_url: "https://gist.github.com/aa5a315d.git".into(),
+            public: false,
+            owner: Some(Owner {
+                login: "campbellcole".into(),
+                ..Owner::default()
+            }),
+        }
+        .into_repo();
+        assert_eq!(
+            gist.full_name_path_friendly(),
+            "campbellcole__gists__aa5a315d"
+        );
+        assert_eq!(TreeLayout::Repo.prefix(&gist), "/gists/aa5a315d");
+        assert_eq!(
+            TreeLayout::OwnerRepo.prefix(&gist),
+            "/campbellcole/gists/aa5a315d"
+        );
+    }
+}
@@ -1,101 +1,737 @@
 use std::{
-    fs::File,
-    io::Write,
-    process::{Command, Stdio},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
 };
 
-use color_eyre::eyre::{bail, Result, WrapErr};
-use lazy_regex::{lazy_regex, Lazy, Regex};
+use tokio::io::AsyncWriteExt;
 
-use crate::{github::Repo, Context};
+use clap::ValueEnum;
 
-static REPLACE_REGEX: Lazy<Regex> = lazy_regex!(r"(.*\|.{1}\|)(.*)");
-static DEQUOTE_REGEX: Lazy<Regex> = lazy_regex!(r#"['"`]"#);
+use crate::{
+    aspect, captions, chapters,
+    config::RenderProfile,
+    error::{GourcersError, Result},
+    ffmpeg,
+    github::Repo,
+    leaderboard, log, log_file,
+    log_stats::LogStats,
+    progress::Progress,
+    shorts, speed, split,
+    timeline::Timeline,
+    Context,
+};
 
-#[instrument(skip(cx))]
-pub fn generate_gource_log(cx: &Context, repo: &Repo) -> Result<()> {
-    let repo_dir = cx.data_dir.repo_dir(repo);
+/// The arguments gource passes to `git log` when it reads a repo itself.
+const GIT_LOG_ARGS: &[&str] = &[
+    "log",
+    "--pretty=format:user:%aN%n%ct",
+    "--reverse",
+    "--raw",
+    "--encoding=UTF-8",
+    "--no-renames",
+    "--no-show-signature",
+];
 
-    let mut cmd = Command::new("gource");
+#[instrument(skip_all, fields(repo = %repo.full_name()))]
+pub async fn generate_gource_log(cx: &Context, repo: &Repo) -> Result<()> {
+    let output = if cx.no_merges {
+        gource_without_merges(cx, repo).await?
+    } else {
+        let mut cmd = tokio::process::Command::new("gource");
 
-    cmd.arg("--output-custom-log").arg("-").arg(&repo_dir);
+        cmd.arg("--output-custom-log")
+            .arg("-")
+            .arg(cx.data_dir.repo_dir(repo))
+            .kill_on_drop(true);
 
-    trace!(command = ?cmd, repo = %repo.name, "running gource");
+        trace!(command = ?cmd, repo = %repo.name, "running gource");
 
-    let output = cmd.output().wrap_err("failed to generate gource log")?;
+        cmd.output()
+            .await
+            .map_err(GourcersError::io("failed to run gource"))?
+    };
 
     if !output.status.success() {
-        bail!("gource failed: {}", String::from_utf8_lossy(&output.stderr));
+        return Err(GourcersError::Gource(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
     }
 
-    let gource_log = String::from_utf8(output.stdout).wrap_err("gource log was not valid utf-8")?;
-
-    let substitution = format!("$1/{}$2", repo.name);
-    let gource_log = REPLACE_REGEX.replace_all(&gource_log, &substitution);
-    let gource_log = diacritics::remove_diacritics(&gource_log);
-    let gource_log = DEQUOTE_REGEX.replace_all(&gource_log, "");
+    let gource_log = String::from_utf8(output.stdout)
+        .map_err(|_| GourcersError::Gource("gource log was not valid utf-8".into()))?;
 
+    let prefix = cx.groups.prefix(repo) + &cx.tree_layout.prefix(repo);
     let gource_log_path = cx.data_dir.gource_log(repo);
-    let mut gource_log_file =
-        File::create(gource_log_path).wrap_err("failed to create gource log file")?;
+    let sanitize = cx.sanitize;
+    let coalesce_window = cx.coalesce_window;
+    let path_filter = cx.path_filter_for(repo);
+    let history_since = cx.history_since;
+    let colors = cx.config.colors.clone();
+    let max_depth = cx
+        .config
+        .overrides_for(&repo.full_name())
+        .and_then(|overrides| overrides.max_depth);
+    let compress = cx.config.compress_logs;
+    let repo_dir = cx.data_dir.repo_dir(repo);
 
-    gource_log_file
-        .write_all(gource_log.as_bytes())
-        .wrap_err("failed to write gource log")?;
+    // rewriting large logs is CPU-bound, so keep it off the async workers
+    tokio::task::spawn_blocking(move || {
+        let gource_log = match history_since {
+            Some(since) => since.trim(&gource_log),
+            None => gource_log,
+        };
+        let gource_log = path_filter.apply(&gource_log);
+        let gource_log = match max_depth {
+            Some(max_depth) => log::limit_depth(&gource_log, max_depth),
+            None => gource_log,
+        };
+        let gource_log = log::prefix_paths(&gource_log, &prefix);
+        let gource_log = sanitize.apply(&gource_log);
+        let gource_log = match coalesce_window {
+            Some(window) => log::coalesce(&gource_log, window),
+            None => gource_log,
+        };
+        let gource_log = if colors.is_empty() {
+            gource_log
+        } else {
+            log::color_extensions(&gource_log, &colors)
+        };
 
-    Ok(())
+        let head = clone_head(&repo_dir);
+        log_file::write(&gource_log_path, &gource_log, compress, head.as_deref())
+            .map_err(GourcersError::io("failed to write gource log"))
+    })
+    .await?
 }
 
-pub fn combine_and_sort_logs(cx: &Context, repos: &Vec<Repo>) -> Result<()> {
-    let mut combined = String::new();
+/// The commit checked out in the clone at `repo_dir`, or `None` if it can't be read, e.g. because
+/// the repo has no commits.
+#[must_use]
+pub fn clone_head(repo_dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo_dir)
+        .args(["rev-parse", "HEAD"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
 
-    trace!("reading gource logs into memory");
-    for repo in repos {
-        let gource_log_path = cx.data_dir.gource_log(repo);
-        let gource_log = std::fs::read_to_string(gource_log_path)
-            .wrap_err_with(|| format!("failed to read gource log for {}", repo.full_name()))?;
+/// Run gource on the repo's git log without merge commits, which gource can't be asked to skip.
+async fn gource_without_merges(cx: &Context, repo: &Repo) -> Result<Output> {
+    let git_log = tokio::process::Command::new("git")
+        .arg("-C")
+        .arg(cx.data_dir.repo_dir(repo))
+        .args(GIT_LOG_ARGS)
+        .arg("--no-merges")
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(GourcersError::io(format!(
+            "failed to run git log for {}",
+            repo.full_name()
+        )))?;
 
-        combined.push_str(&gource_log);
+    if !git_log.status.success() {
+        return Err(GourcersError::Git {
+            repo: repo.full_name(),
+            subcommand: "log".into(),
+            stderr: String::from_utf8_lossy(&git_log.stderr).trim().to_string(),
+        });
     }
 
-    trace!("sorting combined logs");
-    let mut lines = combined.lines().collect::<Vec<_>>();
+    let mut cmd = tokio::process::Command::new("gource");
 
-    lines.sort_by(|a, b| {
-        let a = a.split('|').next().unwrap();
-        let b = b.split('|').next().unwrap();
-        a.cmp(b)
-    });
+    cmd.args(["--log-format", "git", "--output-custom-log", "-", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
 
-    let sorted_path = cx.data_dir.sorted_log();
-    trace!(sorted_path = ?sorted_path, "writing sorted log to disk");
+    trace!(command = ?cmd, repo = %repo.name, "running gource on git log without merges");
 
-    let mut sorted_file = File::create(sorted_path).wrap_err("failed to create sorted log file")?;
+    let mut child = cmd
+        .spawn()
+        .map_err(GourcersError::io("failed to run gource"))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
 
-    for line in lines {
-        writeln!(sorted_file, "{line}").wrap_err("failed to write sorted log")?;
+    // stdin is closed once the log is written, so gource sees the end of it
+    let (written, output) = tokio::join!(
+        async move { stdin.write_all(&git_log.stdout).await },
+        child.wait_with_output()
+    );
+    let output = output.map_err(GourcersError::io("failed to run gource"))?;
+    if output.status.success() {
+        written.map_err(GourcersError::io("failed to pass git log to gource"))?;
     }
 
-    Ok(())
+    Ok(output)
+}
+
+/// The number of commits in the repo's gource log, from its stats if they were written.
+pub fn count_commits(cx: &Context, repo: &Repo) -> Result<usize> {
+    if let Some(stats) = LogStats::read(&cx.data_dir.gource_log(repo))? {
+        return Ok(stats.commits);
+    }
+
+    let log = log_file::read(&cx.data_dir.gource_log(repo)).map_err(GourcersError::io(format!(
+        "failed to read gource log for {}",
+        repo.full_name()
+    )))?;
+
+    Ok(log::count_commits(&log))
+}
+
+/// Combine the logs of the given repos into a single log sorted by timestamp, written to `output`.
+pub fn combine_and_sort_logs(cx: &Context, repos: &[Repo], output: &Path) -> Result<()> {
+    let inputs = repos
+        .iter()
+        .map(|repo| cx.data_dir.gource_log(repo))
+        .collect::<Vec<_>>();
+
+    combine_and_sort(cx, &inputs, output)
+}
+
+/// Combine the logs at `inputs` into a single log sorted by timestamp, written to `output`.
+pub fn combine_and_sort(cx: &Context, inputs: &[PathBuf], output: &Path) -> Result<()> {
+    cx.sort_backend
+        .backend(cx.data_dir.path())
+        .sort(inputs, output)
+}
+
+/// The logs from `{data_dir}/gource/extra` and `--extra-log`, which are merged into the sorted log
+/// as they are.
+pub fn extra_logs(cx: &Context) -> Result<Vec<PathBuf>> {
+    let extra_dir = cx.data_dir.extra_logs_dir();
+    let mut logs = Vec::new();
+
+    if extra_dir.is_dir() {
+        for entry in std::fs::read_dir(&extra_dir).map_err(GourcersError::io(format!(
+            "failed to read {}",
+            extra_dir.display()
+        )))? {
+            let path = entry
+                .map_err(GourcersError::io(format!(
+                    "failed to read {}",
+                    extra_dir.display()
+                )))?
+                .path();
+            if path.is_file() {
+                logs.push(path);
+            }
+        }
+        // the order the directory is read in isn't stable
+        logs.sort();
+    }
+
+    logs.extend(cx.extra_logs.iter().cloned());
+
+    Ok(logs)
+}
+
+/// The arguments passed to gource when neither `--gource-args` nor the config file set any.
+pub const DEFAULT_ARGS: &str = "--hide root -a 1 -s 1 -c 4 --key --multi-sampling";
+
+/// Gource's default background color.
+pub const DEFAULT_BACKGROUND: &str = "000000";
+
+/// The framerates gource can write video at.
+pub const FRAMERATES: [u32; 3] = [25, 30, 60];
+
+/// Common gource options, which are passed to gource before `--gource-args`.
+#[derive(Debug, Clone, Default, clap::Args)]
+#[clap(next_help_heading = "Gource options")]
+pub struct GourceOptions {
+    /// A title to show at the bottom of the visualization, which is also stored in the metadata of
+    /// rendered videos.
+    #[clap(long)]
+    pub title: Option<String>,
+    /// Elements to hide, separated by commas.
+    #[clap(long, value_enum, value_delimiter = ',')]
+    pub hide: Vec<HideElement>,
+    /// How many seconds each day of history lasts.
+    #[clap(long, value_parser = parse_seconds_per_day)]
+    pub seconds_per_day: Option<f64>,
+    /// Show the key of file extensions.
+    #[clap(long)]
+    pub key: bool,
+    /// The background color as a hex code, e.g. `1a1b26`.
+    #[clap(long, value_parser = parse_color)]
+    pub background_color: Option<String>,
+    /// A gource caption file, where each line is `timestamp|text`, to show captions from.
+    ///
+    /// Rendered MP4 and `WebM` videos also get an SRT subtitle file next to them with the same
+    /// captions.
+    #[clap(long, value_name = "FILE")]
+    pub captions: Option<PathBuf>,
+    /// Draw all text in this TrueType or OpenType font instead of gource's own.
+    #[clap(long, value_name = "FILE", value_parser = parse_font_file)]
+    pub font_file: Option<PathBuf>,
+    /// Scale all text by this factor, e.g. `2` for 4K renders, where the default sizes are hard to
+    /// read.
+    #[clap(long, value_name = "SCALE", value_parser = parse_font_scale)]
+    pub font_scale: Option<f64>,
+}
+
+/// The elements which can be passed to gource's `--hide`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HideElement {
+    Bloom,
+    Date,
+    Dirnames,
+    Files,
+    Filenames,
+    Mouse,
+    Progress,
+    Root,
+    Tree,
+    Users,
+    Usernames,
+}
+
+impl GourceOptions {
+    /// The gource arguments for the options which were given.
+    #[must_use]
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(title) = &self.title {
+            args.extend(["--title".to_string(), title.clone()]);
+        }
+
+        if !self.hide.is_empty() {
+            let hide = self
+                .hide
+                .iter()
+                .filter_map(ValueEnum::to_possible_value)
+                .map(|value| value.get_name().to_string())
+                .collect::<Vec<_>>();
+            args.extend(["--hide".to_string(), hide.join(",")]);
+        }
+
+        if let Some(seconds_per_day) = self.seconds_per_day {
+            args.extend(["--seconds-per-day".to_string(), seconds_per_day.to_string()]);
+        }
+
+        if self.key {
+            args.push("--key".to_string());
+        }
+
+        if let Some(color) = &self.background_color {
+            args.extend(["--background-colour".to_string(), color.clone()]);
+        }
+
+        if let Some(captions) = &self.captions {
+            args.extend(["--caption-file".to_string(), captions.display().to_string()]);
+        }
+
+        if let Some(font_file) = &self.font_file {
+            args.extend(["--font-file".to_string(), font_file.display().to_string()]);
+        }
+
+        if let Some(font_scale) = self.font_scale {
+            args.extend(["--font-scale".to_string(), font_scale.to_string()]);
+        }
+
+        args
+    }
+}
+
+fn parse_seconds_per_day(s: &str) -> std::result::Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+        .ok_or_else(|| format!("expected a positive number of seconds: {s}"))
+}
+
+fn parse_font_file(s: &str) -> std::result::Result<PathBuf, String> {
+    let path = PathBuf::from(s);
+
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err(format!("font file doesn't exist: {s}"))
+    }
+}
+
+fn parse_font_scale(s: &str) -> std::result::Result<f64, String> {
+    s.parse::<f64>()
+        .ok()
+        .filter(|scale| scale.is_finite() && *scale > 0.0)
+        .ok_or_else(|| format!("expected a positive scale: {s}"))
+}
+
+/// Parse a hex color with or without a leading `#` into the form gource expects.
+pub(crate) fn parse_color(s: &str) -> std::result::Result<String, String> {
+    let color = s.strip_prefix('#').unwrap_or(s);
+
+    if color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(color.to_ascii_uppercase())
+    } else {
+        Err(format!("expected a hex color like 1a1b26: {s}"))
+    }
+}
+
+/// Create a command which runs gource with a display, going through the headless backend if one
+/// is configured.
+fn render_command(cx: &Context) -> Command {
+    cx.headless.map_or_else(
+        || Command::new("gource"),
+        |headless| headless.command("gource"),
+    )
 }
 
 pub fn generate_gource_video(cx: &Context) -> Result<()> {
+    let mut cmd = render_command(cx);
+
+    cmd.arg(format!("-{}", cx.resolution))
+        .args(window_logo_args(cx))
+        .args(cx.gource_args_for(None))
+        .arg(cx.render_log());
+
+    run_gource(cmd)
+}
+
+/// The arguments which make gource draw the logo in its window, if it can.
+fn window_logo_args(cx: &Context) -> Vec<String> {
+    let Some(logo) = &cx.logo else {
+        return Vec::new();
+    };
+
+    logo.gource_args(&cx.resolution).unwrap_or_else(|| {
+        warn!("the logo is too large or not a PNG, so it is only shown when rendering profiles");
+        Vec::new()
+    })
+}
+
+/// Open the sorted log in an interactive gource window with the configured arguments, leaving out
+/// any which would write frames instead, so the settings can be tuned before a long render.
+pub fn preview(cx: &Context) -> Result<()> {
     let mut cmd = Command::new("gource");
 
-    cmd.args(&cx.gource_args).arg(cx.data_dir.sorted_log());
+    cmd.arg(format!("-{}", cx.resolution))
+        .args(window_logo_args(cx))
+        .args(without_output_args(cx.gource_args_for(None)))
+        .arg(cx.render_log());
+
+    run_gource(cmd)
+}
+
+/// The arguments without the options which make gource write frames to a file or stdout.
+fn without_output_args(args: Vec<String>) -> Vec<String> {
+    const OUTPUT_ARGS: &[&str] = &["-o", "--output-ppm-stream", "--output-framerate"];
 
+    let mut args = args.into_iter();
+    let mut kept = Vec::new();
+    while let Some(arg) = args.next() {
+        if OUTPUT_ARGS.contains(&arg.as_str()) {
+            // skip the value too
+            args.next();
+        } else {
+            kept.push(arg);
+        }
+    }
+
+    kept
+}
+
+fn run_gource(mut cmd: Command) -> Result<()> {
     cmd.stdout(Stdio::inherit()).stderr(Stdio::inherit());
 
     trace!(command = ?cmd, "spawning gource");
 
-    let mut gource = cmd.spawn().wrap_err("failed to spawn gource")?;
+    let mut gource = cmd
+        .spawn()
+        .map_err(GourcersError::io("failed to spawn gource"))?;
 
     trace!("waiting for gource to finish");
-    let gource_status = gource.wait().wrap_err("gource failed")?;
+    let gource_status = gource
+        .wait()
+        .map_err(GourcersError::io("failed to wait for gource"))?;
+
+    if !gource_status.success() {
+        return Err(GourcersError::Gource("see logs above".into()));
+    }
+
+    Ok(())
+}
+
+/// Render the profiles, either sequentially or all at once.
+pub fn render_profiles(
+    cx: &Context,
+    profiles: &[RenderProfile],
+    progress: &Progress,
+) -> Result<()> {
+    let render = |profile: &RenderProfile| -> Result<()> {
+        debug!(profile = %profile.name, "rendering profile");
+        render_profile(cx, profile).map_err(|source| GourcersError::Render {
+            profile: profile.name.clone(),
+            source: Box::new(source),
+        })?;
+        progress.inc(1);
+        Ok(())
+    };
+
+    if cx.parallel_renders {
+        progress.set_message("all profiles");
+        std::thread::scope(|s| {
+            let handles = profiles
+                .iter()
+                .map(|profile| s.spawn(|| render(profile)))
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .try_for_each(|handle| handle.join().expect("render thread panicked"))
+        })?;
+    } else {
+        for profile in profiles {
+            progress.set_message(profile.name.clone());
+            render(profile)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Render the sorted log to a video file using the settings in the given profile.
+pub fn render_profile(cx: &Context, profile: &RenderProfile) -> Result<()> {
+    render_log(cx, profile, &cx.render_log())
+}
+
+/// Render the given sorted log to a video file using the settings in the given profile.
+///
+/// With `--auto-speed`, the log is rendered in segments of varying speed, with `--split-by` in
+/// chunks of calendar time, and with `--checkpoint-every` in chunks of a fixed length.
+pub fn render_log(cx: &Context, profile: &RenderProfile, log: &Path) -> Result<()> {
+    let mut expected = None;
+
+    if cx.auto_speed {
+        speed::render(cx, profile, log)?;
+    } else if let Some(by) = cx.split_by {
+        split::render(cx, profile, log, |timestamps| by.chunks(timestamps))?;
+    } else if let Some(every) = cx.checkpoint_every {
+        split::render(cx, profile, log, |timestamps| {
+            split::every(timestamps, every.0)
+        })?;
+    } else {
+        encode(cx, profile, log, &[])?;
+        expected = expected_duration(cx, profile, log)?;
+    }
+
+    ffmpeg::verify(&profile.output, expected)?;
+
+    // the clip is cut before the leaderboard is appended, so it can't include it
+    if let Some(length) = cx.shorts_clip {
+        shorts::write(cx, profile, log, length.0.as_secs_f64())?;
+    }
+
+    if let Some(n) = cx.outro_leaderboard {
+        leaderboard::append(cx, profile, log, n)?;
+    }
+
+    if let Some(captions) = &cx.captions {
+        captions::write_srt(cx, profile, log, captions)?;
+    }
+
+    if let Some(by) = cx.chapters {
+        chapters::write(cx, profile, log, by)?;
+    }
+
+    // the leaderboard and chapters rewrite the video, so check the file which is kept too
+    if cx.outro_leaderboard.is_some() || cx.chapters.is_some() {
+        ffmpeg::verify(&profile.output, expected)?;
+    }
+
+    Ok(())
+}
+
+/// How long the video rendered in one piece from `log` should be, if it can be worked out from the
+/// gource arguments.
+fn expected_duration(cx: &Context, profile: &RenderProfile, log: &Path) -> Result<Option<f64>> {
+    // these make gource render only part of the log
+    const PARTIAL_ARGS: &[&str] = &[
+        "--start-date",
+        "--stop-date",
+        "--start-position",
+        "--stop-position",
+        "--stop-at-time",
+        "-p",
+        "-t",
+    ];
+
+    let gource_args = cx.gource_args_for(Some(profile));
+    if gource_args
+        .iter()
+        .any(|arg| PARTIAL_ARGS.contains(&arg.as_str()))
+    {
+        return Ok(None);
+    }
+
+    let contents = std::fs::read_to_string(log).map_err(GourcersError::io(format!(
+        "failed to read {}",
+        log.display()
+    )))?;
+
+    Ok(Some(Timeline::from_log(&contents, &gource_args).duration()))
+}
+
+/// Pipe gource's output for the given log into ffmpeg, passing `extra_args` to gource after every
+/// other argument.
+pub(crate) fn encode(
+    cx: &Context,
+    profile: &RenderProfile,
+    log: &Path,
+    extra_args: &[String],
+) -> Result<()> {
+    let framerate = profile.framerate.unwrap_or(cx.framerate);
+
+    let mut cmd = render_command(cx);
+
+    // explicit gource args can still override the global resolution, but not a profile's
+    cmd.arg(format!("-{}", cx.resolution))
+        .args(cx.gource_args_for(Some(profile)));
+
+    if let Some(resolution) = &profile.resolution {
+        cmd.arg(format!("-{resolution}"));
+    }
+
+    // logos which gource can't draw are overlaid by ffmpeg
+    let resolution = profile.resolution.as_deref().unwrap_or(&cx.resolution);
+    let mut overlay = None;
+    if let Some(logo) = &cx.logo {
+        match logo.gource_args(resolution) {
+            Some(args) => {
+                cmd.args(args);
+            }
+            None => overlay = logo.ffmpeg_filter(resolution),
+        }
+    }
+
+    // ffmpeg can't tell the framerate from the PPM stream, so both must be given the same value
+    cmd.arg("--output-framerate")
+        .arg(framerate.to_string())
+        .args(extra_args)
+        .arg("-o")
+        .arg("-")
+        .arg(log);
+
+    cmd.stdout(Stdio::piped()).stderr(Stdio::inherit());
+
+    trace!(command = ?cmd, profile = %profile.name, "spawning gource");
+
+    let mut gource = cmd
+        .spawn()
+        .map_err(GourcersError::io("failed to spawn gource"))?;
+
+    let stdout = gource.stdout.take().unwrap();
+    let format = profile.format.unwrap_or(cx.format);
+    let fit = cx
+        .aspect
+        .map(|_| aspect::fit_filter(resolution, &cx.background_for(Some(profile))));
+    let ffmpeg_args = cx.ffmpeg_output_args_for(Some(profile));
+    let overlay = cx
+        .logo
+        .as_ref()
+        .zip(overlay.as_deref())
+        .map(|(logo, graph)| (logo.path.as_path(), graph));
+    let mut ffmpeg = match ffmpeg::spawn_encoder(
+        profile,
+        format,
+        framerate,
+        &ffmpeg_args,
+        overlay,
+        fit.as_deref(),
+        stdout,
+    ) {
+        Ok(ffmpeg) => ffmpeg,
+        Err(e) => {
+            gource.kill().ok();
+            return Err(e);
+        }
+    };
+
+    trace!(profile = %profile.name, "waiting for gource and ffmpeg to finish");
+    let gource_status = gource
+        .wait()
+        .map_err(GourcersError::io("failed to wait for gource"))?;
+    let ffmpeg_status = ffmpeg
+        .wait()
+        .map_err(GourcersError::io("failed to wait for ffmpeg"))?;
 
     if !gource_status.success() {
-        bail!("gource failed. see logs above");
+        return Err(GourcersError::Gource("see logs above".into()));
+    }
+
+    if !ffmpeg_status.success() {
+        return Err(GourcersError::Ffmpeg("see logs above".into()));
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gource_options_args() {
+        let options = GourceOptions {
+            title: Some("My Video".into()),
+            hide: vec![HideElement::Root, HideElement::Mouse],
+            seconds_per_day: Some(0.5),
+            key: true,
+            background_color: Some(parse_color("#1a1b26").unwrap()),
+            captions: Some("captions.txt".into()),
+            font_file: Some("fonts/Inter.ttf".into()),
+            font_scale: Some(2.0),
+        };
+
+        assert_eq!(
+            options.args(),
+            [
+                "--title",
+                "My Video",
+                "--hide",
+                "root,mouse",
+                "--seconds-per-day",
+                "0.5",
+                "--key",
+                "--background-colour",
+                "1A1B26",
+                "--caption-file",
+                "captions.txt",
+                "--font-file",
+                "fonts/Inter.ttf",
+                "--font-scale",
+                "2",
+            ]
+        );
+        assert!(parse_color("blue").is_err());
+        assert!(parse_seconds_per_day("0").is_err());
+        assert!(parse_font_scale("0").is_err());
+        assert!(parse_font_file("Cargo.toml").is_ok());
+        assert!(parse_font_file("missing.ttf").is_err());
+    }
+
+    #[test]
+    fn test_without_output_args() {
+        let args = [
+            "--hide",
+            "root",
+            "-o",
+            "-",
+            "--key",
+            "--output-framerate",
+            "60",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(without_output_args(args), ["--hide", "root", "--key"]);
+    }
+}
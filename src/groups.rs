@@ -0,0 +1,151 @@
+//! A file format which places repos in named folders at the top of the tree, so the video is
+//! organized by project area instead of being a flat list of repos.
+//!
+//! Each line is `group: <name> => <patterns>`, where the patterns are full names separated by
+//! commas, in which `*` matches anything. Full names are compared ignoring case, like GitHub does.
+//! A repo is placed in the first group with a pattern which matches it, and repos which match no
+//! group stay at the top of the tree. Empty lines and lines starting with `#` are ignored.
+//!
+//! Example:
+//! ```text
+//! group: work => acme/*, acme-labs/*
+//! group: oss => rust-lang/*, me/gourcers
+//! ```
+
+use std::fmt::Display;
+
+use thiserror::Error;
+
+use crate::{github::Repo, path_filter};
+
+#[derive(Debug, Error)]
+pub struct Error {
+    pub message: String,
+    pub line: usize,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Group {
+    name: String,
+    /// In lowercase.
+    patterns: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Groups {
+    groups: Vec<Group>,
+}
+
+impl Groups {
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let mut groups = Vec::new();
+
+        for (x, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let err = |message: &str| Error {
+                message: format!("{message}: {line}"),
+                line: x + 1,
+            };
+
+            let rest = line
+                .strip_prefix("group:")
+                .ok_or_else(|| err("expected `group: <name> => <patterns>`"))?;
+            let (name, patterns) = rest
+                .split_once("=>")
+                .ok_or_else(|| err("expected `=>` after the group name"))?;
+
+            let name = name.trim();
+            if name.is_empty() || name.contains('/') {
+                return Err(err("group names must be non-empty and can't contain `/`"));
+            }
+
+            let patterns = patterns
+                .split(',')
+                .map(|pattern| pattern.trim().to_lowercase())
+                .filter(|pattern| !pattern.is_empty())
+                .collect::<Vec<_>>();
+            if patterns.is_empty() {
+                return Err(err("expected at least one pattern"));
+            }
+
+            groups.push(Group {
+                name: name.to_string(),
+                patterns,
+            });
+        }
+
+        Ok(Self { groups })
+    }
+
+    /// The name of the group the repo is placed in, if any.
+    #[must_use]
+    pub fn group_of(&self, repo: &Repo) -> Option<&str> {
+        let full_name = repo.full_name().to_lowercase();
+
+        self.groups
+            .iter()
+            .find(|group| {
+                group
+                    .patterns
+                    .iter()
+                    .any(|pattern| path_filter::glob_match(pattern, &full_name))
+            })
+            .map(|group| group.name.as_str())
+    }
+
+    /// The directory the repo's group places it under, which is empty if it isn't in a group.
+    #[must_use]
+    pub fn prefix(&self, repo: &Repo) -> String {
+        self.group_of(repo)
+            .map(|group| format!("/{group}"))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo(full_name: &str) -> Repo {
+        Repo {
+            full_name: Some(full_name.into()),
+            ..Repo::default()
+        }
+    }
+
+    #[test]
+    fn test_groups() {
+        let groups = Groups::parse(
+            "# areas\ngroup: work => acme/*, Acme-Labs/*\n\ngroup: oss => rust-lang/*, acme/oss\n",
+        )
+        .unwrap();
+
+        assert_eq!(groups.group_of(&repo("acme/api")), Some("work"));
+        assert_eq!(groups.group_of(&repo("acme-labs/ml")), Some("work"));
+        // the first matching group wins
+        assert_eq!(groups.group_of(&repo("acme/oss")), Some("work"));
+        assert_eq!(groups.group_of(&repo("rust-lang/rust")), Some("oss"));
+        assert_eq!(groups.group_of(&repo("me/dotfiles")), None);
+
+        assert_eq!(groups.prefix(&repo("rust-lang/rust")), "/oss");
+        assert_eq!(groups.prefix(&repo("me/dotfiles")), "");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Groups::parse("work => acme/*").unwrap_err().line, 1);
+        assert_eq!(Groups::parse("\ngroup: work acme/*").unwrap_err().line, 2);
+        assert!(Groups::parse("group: a/b => acme/*").is_err());
+        assert!(Groups::parse("group: work =>").is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! Running gource on machines without a display.
+//!
+//! Gource needs an OpenGL context even when it is only writing frames to stdout. On a headless
+//! server this is provided either by a virtual X server (`xvfb-run`) or by SDL's offscreen video
+//! driver, which renders through EGL.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::error::{GourcersError, Result};
+
+/// The screen `xvfb-run` creates. It must be at least as large as the largest render resolution,
+/// including vertical ones.
+const XVFB_SCREEN: &str = "-screen 0 3840x3840x24";
+
+/// Directories searched for `libEGL` in addition to `LD_LIBRARY_PATH`.
+const LIBRARY_DIRS: &[&str] = &[
+    "/usr/lib",
+    "/usr/lib64",
+    "/usr/local/lib",
+    "/usr/lib/x86_64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Headless {
+    /// Wrap gource in `xvfb-run`.
+    Xvfb,
+    /// Use SDL's offscreen driver, backed by EGL.
+    Egl,
+}
+
+impl Headless {
+    /// Find a way to run gource without a display, preferring `xvfb-run`.
+    pub fn detect() -> Result<Self> {
+        if find_executable("xvfb-run").is_some() {
+            debug!("using xvfb-run for headless rendering");
+            return Ok(Self::Xvfb);
+        }
+
+        if find_egl() {
+            debug!("using SDL offscreen driver for headless rendering");
+            return Ok(Self::Egl);
+        }
+
+        Err(GourcersError::NoHeadlessBackend)
+    }
+
+    /// Create a command which runs `program` using this backend.
+    #[must_use]
+    pub fn command(self, program: &str) -> Command {
+        match self {
+            Self::Xvfb => {
+                let mut cmd = Command::new("xvfb-run");
+                cmd.args(["-a", "-s", XVFB_SCREEN, program]);
+                cmd
+            }
+            Self::Egl => {
+                let mut cmd = Command::new(program);
+                cmd.env("SDL_VIDEODRIVER", "offscreen");
+                cmd
+            }
+        }
+    }
+}
+
+/// Search `PATH` for an executable with the given name.
+#[must_use]
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH")?;
+    env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+fn find_egl() -> bool {
+    let ld_library_path = env::var_os("LD_LIBRARY_PATH").unwrap_or_default();
+
+    env::split_paths(&ld_library_path)
+        .chain(LIBRARY_DIRS.iter().map(PathBuf::from))
+        .any(|dir| has_egl(&dir))
+}
+
+fn has_egl(dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return false;
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .any(|entry| entry.file_name().to_string_lossy().starts_with("libEGL.so"))
+}
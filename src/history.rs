@@ -0,0 +1,127 @@
+//! Limiting the visualized history to what happened since a date.
+//!
+//! Repos which haven't been pushed to since the date are left out, new clones only fetch commits
+//! made since the date, and earlier entries are trimmed from the logs, so a video of recent
+//! activity doesn't need the whole history of every repo.
+
+use std::{fmt::Display, str::FromStr};
+
+use crate::{calendar, github::Repo, log::LogEntry};
+
+/// A day written as `YYYY-MM-DD`, meaning midnight UTC at its start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Since(pub i64);
+
+impl FromStr for Since {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("expected a date like 2020-01-01: {s}");
+
+        let mut parts = s.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+        let (Some(Some(year)), Some(Some(month)), Some(Some(day))) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(err());
+        };
+
+        let timestamp = calendar::from_date(year, month, day);
+        // reject days which don't exist, like 2023-02-30
+        if calendar::date(timestamp) != (year, month, day) {
+            return Err(err());
+        }
+
+        Ok(Self(timestamp))
+    }
+}
+
+impl Display for Since {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (year, month, day) = calendar::date(self.0);
+        write!(f, "{year:04}-{month:02}-{day:02}")
+    }
+}
+
+impl Since {
+    /// Whether the repo was pushed to on or after the day. Repos the API reports no push time for
+    /// are kept.
+    #[must_use]
+    pub fn pushed_since(self, repo: &Repo) -> bool {
+        // the API's timestamps are in UTC, so they sort like the times they stand for
+        repo.pushed_at
+            .as_deref()
+            .is_none_or(|pushed_at| pushed_at >= format!("{self}T00:00:00Z").as_str())
+    }
+
+    /// The log without the entries from before the day. Malformed lines are dropped.
+    #[must_use]
+    pub fn trim(self, log: &str) -> String {
+        let mut trimmed = String::with_capacity(log.len());
+
+        for line in log.lines() {
+            let Some(entry) = LogEntry::parse(line) else {
+                warn!("dropping malformed log line: {line}");
+                continue;
+            };
+            if i64::try_from(entry.timestamp).is_ok_and(|timestamp| timestamp >= self.0) {
+                trimmed.push_str(line);
+                trimmed.push('\n');
+            }
+        }
+
+        trimmed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2020-01-01 at midnight UTC
+    const JAN_2020: i64 = 1_577_836_800;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("2020-01-01".parse(), Ok(Since(JAN_2020)));
+        assert_eq!(Since(JAN_2020).to_string(), "2020-01-01");
+        assert!("2023-02-30".parse::<Since>().is_err());
+        assert!("2020-01".parse::<Since>().is_err());
+        assert!("yesterday".parse::<Since>().is_err());
+    }
+
+    #[test]
+    fn test_pushed_since() {
+        let repo = |pushed_at: Option<&str>| -> Repo {
+            serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "name": "x",
+                "ssh_url": "git@github.com:o/x.git",
+                "owner": { "login": "o" },
+                "fork": false,
+                "private": false,
+                "pushed_at": pushed_at,
+            }))
+            .unwrap()
+        };
+        let since = Since(JAN_2020);
+
+        assert!(since.pushed_since(&repo(Some("2020-01-01T00:00:00Z"))));
+        assert!(!since.pushed_since(&repo(Some("2019-12-31T23:59:59Z"))));
+        assert!(since.pushed_since(&repo(None)));
+    }
+
+    #[test]
+    fn test_trim() {
+        let since = Since(JAN_2020);
+        let log = format!(
+            "{}|a|A|/x\n{JAN_2020}|a|M|/x\nnot a log line\n{}|b|D|/x\n",
+            JAN_2020 - 1,
+            JAN_2020 + 1
+        );
+
+        assert_eq!(
+            since.trim(&log),
+            format!("{JAN_2020}|a|M|/x\n{}|b|D|/x\n", JAN_2020 + 1)
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! A cache of API responses for making conditional requests.
+//!
+//! GitHub doesn't count `304 Not Modified` responses against the rate limit, so sending the `ETag`
+//! of the last response in `If-None-Match` makes reruns nearly free when nothing has changed.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{GourcersError, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub etag: String,
+    /// The `Link` header, which is needed to continue paginating from a cached page.
+    pub link: Option<String>,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub struct ResponseCache {
+    dir: PathBuf,
+}
+
+impl ResponseCache {
+    #[must_use]
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Look up the cached response for the given URL. Unreadable entries are treated as missing.
+    #[must_use]
+    pub fn get(&self, url: &str) -> Option<CachedResponse> {
+        let contents = std::fs::read_to_string(self.path(url)).ok()?;
+        serde_json::from_str(&contents)
+            .inspect_err(|e| debug!("ignoring corrupt cache entry for {url}: {e}"))
+            .ok()
+    }
+
+    pub fn put(&self, url: &str, response: &CachedResponse) -> Result<()> {
+        if !self.dir.exists() {
+            trace!("creating API cache directory: {}", self.dir.display());
+            std::fs::create_dir_all(&self.dir)
+                .map_err(GourcersError::io("failed to create API cache directory"))?;
+        }
+
+        let contents = serde_json::to_string(response)
+            .map_err(GourcersError::json("failed to serialize response"))?;
+        std::fs::write(self.path(url), contents)
+            .map_err(GourcersError::io("failed to write API cache entry"))
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        let name = url
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        self.dir.join(format!("{name}.json"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let temp = TempDir::new().unwrap();
+        let cache = ResponseCache::new(temp.path().join("api-cache"));
+
+        let url = "https://api.github.com/user/repos?per_page=100&page=2";
+        assert_eq!(cache.get(url), None);
+
+        let response = CachedResponse {
+            etag: r#"W/"abc""#.into(),
+            link: Some(r#"<https://api.github.com/user/repos?page=3>; rel="next""#.into()),
+            body: "[]".into(),
+        };
+        cache.put(url, &response).unwrap();
+
+        assert_eq!(cache.get(url), Some(response));
+        assert_eq!(cache.get("https://api.github.com/user/repos?page=1"), None);
+    }
+}
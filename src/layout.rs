@@ -0,0 +1,168 @@
+//! Versioning of the data directory layout.
+//!
+//! The data directory is stamped with the version of its layout in a `VERSION` file. When a
+//! newer gourcers changes the layout, it migrates data directories written by older versions
+//! before using them, and refuses to use data directories written by versions newer than itself.
+//! Data directories from before the layout was versioned are treated as version 1.
+
+use std::path::Path;
+
+use crate::{
+    error::{GourcersError, Result},
+    lock,
+};
+
+/// The version of the layout this build writes.
+pub const VERSION: u32 = 1;
+
+/// Moves a data directory from one layout version to the next.
+type Migration = fn(&Path) -> Result<()>;
+
+/// The migrations to each version after the first, in order: the first migrates from version 1
+/// to 2, and so on. There must be one for every version up to [`VERSION`].
+const MIGRATIONS: &[Migration] = &[];
+
+/// The version written by builds from before versioning.
+const UNVERSIONED: u32 = 1;
+
+/// Check the version of the data directory at `dir`, migrating it to the current layout if it is
+/// older, and stamp it with the current version.
+pub fn check(dir: &Path) -> Result<()> {
+    check_with(dir, VERSION, MIGRATIONS)
+}
+
+fn check_with(dir: &Path, current: u32, migrations: &[Migration]) -> Result<()> {
+    debug_assert_eq!(migrations.len(), (current - UNVERSIONED) as usize);
+
+    let path = dir.join("VERSION");
+
+    let version = if path.exists() {
+        let contents = std::fs::read_to_string(&path).map_err(GourcersError::io(format!(
+            "failed to read {}",
+            path.display()
+        )))?;
+        contents
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| GourcersError::InvalidLayoutVersion {
+                path: path.clone(),
+                contents: contents.trim().to_string(),
+            })?
+    } else if is_empty(dir)? {
+        current
+    } else {
+        UNVERSIONED
+    };
+
+    if version > current {
+        return Err(GourcersError::LayoutTooNew {
+            path: dir.to_path_buf(),
+            version,
+            supported: current,
+        });
+    }
+
+    for from in version..current {
+        debug!(
+            "migrating data directory from version {from} to {}",
+            from + 1
+        );
+        migrations[(from - UNVERSIONED) as usize](dir)?;
+        write_version(&path, from + 1)?;
+    }
+
+    if version == current && !path.exists() {
+        write_version(&path, current)?;
+    }
+
+    Ok(())
+}
+
+fn write_version(path: &Path, version: u32) -> Result<()> {
+    std::fs::write(path, format!("{version}\n")).map_err(GourcersError::io(format!(
+        "failed to write {}",
+        path.display()
+    )))
+}
+
+/// Whether the directory has nothing in it apart from the lock file, which is created first.
+fn is_empty(dir: &Path) -> Result<bool> {
+    Ok(std::fs::read_dir(dir)
+        .map_err(GourcersError::io(format!(
+            "failed to read {}",
+            dir.display()
+        )))?
+        .filter_map(std::result::Result::ok)
+        .all(|entry| entry.file_name() == lock::FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    fn version(dir: &Path) -> String {
+        std::fs::read_to_string(dir.join("VERSION")).unwrap()
+    }
+
+    #[test]
+    fn test_stamps_new_and_unversioned_dirs() {
+        let temp = TempDir::new().unwrap();
+        check_with(temp.path(), 1, &[]).unwrap();
+        assert_eq!(version(temp.path()), "1\n");
+
+        let temp = TempDir::new().unwrap();
+        std::fs::write(temp.path().join("sorted.txt"), "").unwrap();
+        check_with(temp.path(), 1, &[]).unwrap();
+        assert_eq!(version(temp.path()), "1\n");
+    }
+
+    #[test]
+    fn test_migrates_old_dirs() {
+        let temp = TempDir::new().unwrap();
+        std::fs::write(temp.path().join("sorted.txt"), "").unwrap();
+
+        let migrations: [Migration; 2] = [
+            |dir| {
+                std::fs::rename(dir.join("sorted.txt"), dir.join("sorted.log")).unwrap();
+                Ok(())
+            },
+            |dir| {
+                assert!(dir.join("sorted.log").exists());
+                std::fs::write(dir.join("migrated"), "").unwrap();
+                Ok(())
+            },
+        ];
+
+        check_with(temp.path(), 3, &migrations).unwrap();
+        assert_eq!(version(temp.path()), "3\n");
+        assert!(temp.path().join("migrated").exists());
+
+        // only the migrations after the stamped version run
+        std::fs::remove_file(temp.path().join("migrated")).unwrap();
+        write_version(&temp.path().join("VERSION"), 2).unwrap();
+        check_with(temp.path(), 3, &migrations).unwrap();
+        assert!(temp.path().join("migrated").exists());
+    }
+
+    #[test]
+    fn test_rejects_newer_dirs() {
+        let temp = TempDir::new().unwrap();
+        write_version(&temp.path().join("VERSION"), 2).unwrap();
+        assert!(matches!(
+            check_with(temp.path(), 1, &[]),
+            Err(GourcersError::LayoutTooNew {
+                version: 2,
+                supported: 1,
+                ..
+            })
+        ));
+
+        std::fs::write(temp.path().join("VERSION"), "two").unwrap();
+        assert!(matches!(
+            check_with(temp.path(), 1, &[]),
+            Err(GourcersError::InvalidLayoutVersion { .. })
+        ));
+    }
+}
@@ -0,0 +1,215 @@
+//! An outro with a leaderboard of the top contributors, appended to the end of rendered videos.
+//!
+//! Contributors are ranked by their number of commits in the rendered log, where a commit is every
+//! entry by the same author at the same time, and ties are broken by the number of distinct files
+//! they touched. The leaderboard is drawn with ffmpeg's `drawtext` filter, so ffmpeg must be built
+//! with `libfreetype` and `fontconfig`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    path::Path,
+};
+
+use crate::{
+    config::RenderProfile,
+    error::{GourcersError, Result},
+    ffmpeg::{self, TextCard, DEFAULT_PRESET},
+    log::LogEntry,
+    Context,
+};
+
+/// How long the leaderboard is shown for, in seconds.
+const SECONDS: f64 = 6.0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contributor {
+    pub author: String,
+    pub commits: usize,
+    /// The number of distinct files the contributor added, modified or deleted.
+    pub files: usize,
+}
+
+/// The top `n` contributors in a log's contents.
+#[must_use]
+pub fn rank(contents: &str, n: usize) -> Vec<Contributor> {
+    let mut commits = HashMap::<&str, HashSet<u64>>::new();
+    let mut files = HashMap::<&str, HashSet<&str>>::new();
+
+    for entry in contents.lines().filter_map(LogEntry::parse) {
+        commits
+            .entry(entry.author)
+            .or_default()
+            .insert(entry.timestamp);
+        // the colour field isn't part of the file
+        let path = entry
+            .path
+            .rsplit_once('|')
+            .filter(|(_, colour)| colour.len() == 6)
+            .map_or(entry.path, |(path, _)| path);
+        files.entry(entry.author).or_default().insert(path);
+    }
+
+    let mut contributors = commits
+        .into_iter()
+        .map(|(author, timestamps)| Contributor {
+            author: author.to_string(),
+            commits: timestamps.len(),
+            files: files.get(author).map_or(0, HashSet::len),
+        })
+        .collect::<Vec<_>>();
+    contributors.sort_by(|a, b| {
+        (b.commits, b.files)
+            .cmp(&(a.commits, a.files))
+            .then_with(|| a.author.cmp(&b.author))
+    });
+    contributors.truncate(n);
+
+    contributors
+}
+
+/// The text of the leaderboard, with aligned columns for a monospace font.
+#[must_use]
+pub fn text(contributors: &[Contributor]) -> String {
+    let name_width = contributors
+        .iter()
+        .map(|contributor| contributor.author.chars().count())
+        .max()
+        .unwrap_or_default();
+    let commits_width = contributors
+        .iter()
+        .map(|contributor| contributor.commits.to_string().len())
+        .max()
+        .unwrap_or_default();
+    let files_width = contributors
+        .iter()
+        .map(|contributor| contributor.files.to_string().len())
+        .max()
+        .unwrap_or_default();
+
+    let mut text = "Top contributors\n".to_string();
+    for (x, contributor) in contributors.iter().enumerate() {
+        write!(
+            text,
+            "\n{:>2}. {:<name_width$}  {:>commits_width$} commits  {:>files_width$} files",
+            x + 1,
+            contributor.author,
+            contributor.commits,
+            contributor.files
+        )
+        .unwrap();
+    }
+
+    text
+}
+
+/// Append a leaderboard of the top `n` contributors in `log` to the video rendered with `profile`.
+pub fn append(cx: &Context, profile: &RenderProfile, log: &Path, n: usize) -> Result<()> {
+    let contents = std::fs::read_to_string(log).map_err(GourcersError::io(format!(
+        "failed to read {}",
+        log.display()
+    )))?;
+    let contributors = rank(&contents, n);
+    if contributors.is_empty() {
+        return Ok(());
+    }
+
+    let dir = cx.data_dir.outro_dir(&profile.name);
+    std::fs::create_dir_all(&dir).map_err(GourcersError::io("failed to create outro directory"))?;
+
+    let text_file = dir.join("leaderboard.txt");
+    std::fs::write(&text_file, text(&contributors)).map_err(GourcersError::io(format!(
+        "failed to write {}",
+        text_file.display()
+    )))?;
+
+    let background = cx.background_for(Some(profile));
+
+    let format = profile.format.unwrap_or(cx.format);
+    let extra_args = cx.ffmpeg_output_args_for(Some(profile));
+    let card = dir.join(format!("leaderboard.{}", format.extension()));
+    ffmpeg::render_card(
+        &TextCard {
+            text_file: &text_file,
+            resolution: profile.resolution.as_deref().unwrap_or(&cx.resolution),
+            framerate: profile.framerate.unwrap_or(cx.framerate),
+            background: &background,
+            seconds: SECONDS,
+        },
+        format,
+        profile.ffmpeg_preset.as_deref().unwrap_or(DEFAULT_PRESET),
+        &extra_args,
+        &card,
+    )?;
+
+    // the concat list resolves relative paths against its own directory
+    let video = std::path::absolute(&profile.output).map_err(GourcersError::io(format!(
+        "failed to resolve {}",
+        profile.output.display()
+    )))?;
+    let joined = video.with_extension(format!("leaderboard.{}", format.extension()));
+    debug!(profile = %profile.name, "appending a leaderboard of {} contributors", contributors.len());
+    ffmpeg::concat(
+        &[video.clone(), card],
+        &dir.join("videos.txt"),
+        format,
+        &extra_args,
+        &joined,
+    )?;
+
+    std::fs::rename(&joined, &video).map_err(GourcersError::io(format!(
+        "failed to replace {}",
+        video.display()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank() {
+        let log = "100|alice|A|/r/a\n100|alice|A|/r/b\n200|bob|M|/r/a|FF0000\n300|alice|M|/r/a\n\
+                   400|bob|M|/r/a\n500|carol|A|/r/c\n";
+
+        assert_eq!(
+            rank(log, 2),
+            [
+                Contributor {
+                    author: "alice".into(),
+                    commits: 2,
+                    files: 2
+                },
+                Contributor {
+                    author: "bob".into(),
+                    commits: 2,
+                    files: 1
+                }
+            ]
+        );
+        assert_eq!(rank(log, 10).len(), 3);
+    }
+
+    #[test]
+    fn test_text() {
+        let contributors = [
+            Contributor {
+                author: "alice".into(),
+                commits: 120,
+                files: 3,
+            },
+            Contributor {
+                author: "bob".into(),
+                commits: 7,
+                files: 45,
+            },
+        ];
+
+        assert_eq!(
+            text(&contributors),
+            "Top contributors\n\
+             \n 1. alice  120 commits   3 files\
+             \n 2. bob      7 commits  45 files"
+        );
+    }
+}
@@ -0,0 +1,296 @@
+//! A git backend which uses libgit2 instead of shelling out to `git`.
+//!
+//! This backend reports clone and fetch progress per object and surfaces structured errors.
+
+use std::path::{Path, PathBuf};
+
+use git2::{
+    build::{CheckoutBuilder, RepoBuilder},
+    Cred, CredentialType, Direction, FetchOptions, FetchPrune, ProxyOptions, Remote,
+    RemoteCallbacks, Repository, ResetType,
+};
+use thiserror::Error;
+
+use crate::{github::UpdateStrategy, network::NetworkOptions, progress::Progress};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("failed to clone {url} into {path}")]
+    Clone {
+        url: String,
+        path: PathBuf,
+        #[source]
+        source: git2::Error,
+    },
+    #[error("failed to open existing repository at {path}")]
+    Open {
+        path: PathBuf,
+        #[source]
+        source: git2::Error,
+    },
+    #[error("failed to fetch from origin")]
+    Fetch(#[source] git2::Error),
+    #[error("failed to update the working tree to the fetched commit")]
+    Update(#[source] git2::Error),
+    #[error("origin did not report a default branch")]
+    NoDefaultBranch,
+    #[error("local branch has diverged from origin and cannot be fast-forwarded")]
+    Diverged,
+}
+
+/// Clone or update the repo at `url` into `repo_dir`.
+pub fn fetch_repo(
+    repo_dir: &Path,
+    url: &str,
+    strategy: UpdateStrategy,
+    network: &NetworkOptions,
+    progress: &Progress,
+) -> Result<(), Error> {
+    let sub_bar = progress.sub_bar();
+
+    let res = if repo_dir.exists() {
+        trace!(url = url, "updating with libgit2");
+        update(repo_dir, strategy, network, &sub_bar)
+    } else {
+        trace!(url = url, "cloning with libgit2");
+        RepoBuilder::new()
+            .fetch_options(fetch_options(network, &sub_bar))
+            .clone(url, repo_dir)
+            .map(|_| ())
+            .map_err(|source| Error::Clone {
+                url: url.to_string(),
+                path: repo_dir.to_path_buf(),
+                source,
+            })
+    };
+
+    sub_bar.finish_and_clear();
+    res
+}
+
+fn update(
+    repo_dir: &Path,
+    strategy: UpdateStrategy,
+    network: &NetworkOptions,
+    progress: &Progress,
+) -> Result<(), Error> {
+    let git_repo = Repository::open(repo_dir).map_err(|source| Error::Open {
+        path: repo_dir.to_path_buf(),
+        source,
+    })?;
+
+    let mut remote = git_repo.find_remote("origin").map_err(Error::Fetch)?;
+    remote
+        .fetch::<&str>(&[], Some(&mut fetch_options(network, progress)), None)
+        .map_err(Error::Fetch)?;
+
+    match strategy {
+        UpdateStrategy::Pull => fast_forward(&git_repo),
+        UpdateStrategy::Reset => reset_to_default_branch(&git_repo, &mut remote, network),
+        UpdateStrategy::FetchOnly => Ok(()),
+    }
+}
+
+fn fast_forward(git_repo: &Repository) -> Result<(), Error> {
+    let fetch_head = git_repo
+        .find_reference("FETCH_HEAD")
+        .and_then(|head| git_repo.reference_to_annotated_commit(&head))
+        .map_err(Error::Update)?;
+
+    let (analysis, _) = git_repo
+        .merge_analysis(&[&fetch_head])
+        .map_err(Error::Update)?;
+
+    if analysis.is_up_to_date() {
+        return Ok(());
+    }
+
+    if !analysis.is_fast_forward() {
+        return Err(Error::Diverged);
+    }
+
+    let mut head = git_repo.head().map_err(Error::Update)?;
+    head.set_target(fetch_head.id(), "gourcers: fast-forward")
+        .map_err(Error::Update)?;
+    git_repo
+        .checkout_head(Some(CheckoutBuilder::new().force()))
+        .map_err(Error::Update)?;
+
+    Ok(())
+}
+
+/// Hard reset to the remote's current default branch, which may have been renamed.
+fn reset_to_default_branch(
+    git_repo: &Repository,
+    remote: &mut Remote<'_>,
+    network: &NetworkOptions,
+) -> Result<(), Error> {
+    let connection = remote
+        .connect_auth(
+            Direction::Fetch,
+            Some(callbacks(network)),
+            Some(proxy_options(network)),
+        )
+        .map_err(Error::Fetch)?;
+    let default_branch = connection.default_branch().map_err(Error::Fetch)?;
+    drop(connection);
+
+    let branch = default_branch
+        .as_str()
+        .and_then(|branch| branch.strip_prefix("refs/heads/"))
+        .ok_or(Error::NoDefaultBranch)?;
+
+    let target = git_repo
+        .revparse_single(&format!("refs/remotes/origin/{branch}"))
+        .map_err(Error::Update)?;
+
+    git_repo
+        .reset(
+            &target,
+            ResetType::Hard,
+            Some(CheckoutBuilder::new().force()),
+        )
+        .map_err(Error::Update)
+}
+
+/// Callbacks which authenticate SSH remotes using `--ssh-key`, or the SSH agent if it isn't given,
+/// and HTTPS remotes using git's credential helpers.
+fn callbacks(network: &NetworkOptions) -> RemoteCallbacks<'_> {
+    let mut callbacks = RemoteCallbacks::new();
+
+    let mut tried = CredentialType::empty();
+    callbacks.credentials(move |url, username, allowed| {
+        credential(network, &mut tried, url, username, allowed)
+    });
+
+    callbacks
+}
+
+/// The credential to offer for `url`, of a type which is `allowed` and hasn't been `tried` yet.
+///
+/// libgit2 asks again after every rejected credential, so offering the same one again would loop
+/// forever. Each type is only offered once, and the clone fails once they have all been rejected.
+fn credential(
+    network: &NetworkOptions,
+    tried: &mut CredentialType,
+    url: &str,
+    username: Option<&str>,
+    allowed: CredentialType,
+) -> Result<Cred, git2::Error> {
+    let untried = allowed & !*tried;
+
+    if untried.contains(CredentialType::USERNAME) {
+        *tried |= CredentialType::USERNAME;
+        return Cred::username(username.unwrap_or("git"));
+    }
+
+    if untried.contains(CredentialType::SSH_KEY) {
+        *tried |= CredentialType::SSH_KEY;
+        let username = username.unwrap_or("git");
+        return match &network.ssh_key {
+            Some(key) => Cred::ssh_key(username, None, key, None),
+            None => Cred::ssh_key_from_agent(username),
+        };
+    }
+
+    if untried.contains(CredentialType::USER_PASS_PLAINTEXT) {
+        *tried |= CredentialType::USER_PASS_PLAINTEXT;
+        let config = git2::Config::open_default()?;
+        return Cred::credential_helper(&config, url, username);
+    }
+
+    if untried.contains(CredentialType::DEFAULT) {
+        *tried |= CredentialType::DEFAULT;
+        return Cred::default();
+    }
+
+    Err(git2::Error::from_str(&format!(
+        "no credentials were accepted for {url}"
+    )))
+}
+
+fn fetch_options<'a>(network: &'a NetworkOptions, progress: &'a Progress) -> FetchOptions<'a> {
+    let mut callbacks = callbacks(network);
+
+    callbacks.transfer_progress(|stats| {
+        if stats.received_objects() < stats.total_objects() {
+            progress.set_message("Receiving objects");
+            progress.set_position(percent(stats.received_objects(), stats.total_objects()));
+        } else if stats.total_deltas() > 0 {
+            progress.set_message("Resolving deltas");
+            progress.set_position(percent(stats.indexed_deltas(), stats.total_deltas()));
+        }
+        true
+    });
+
+    let mut options = FetchOptions::new();
+    options
+        .remote_callbacks(callbacks)
+        .proxy_options(proxy_options(network))
+        .prune(FetchPrune::On);
+    options
+}
+
+/// Use `--proxy` if it is given, no proxy with `--no-proxy`, and the proxy from git's config or
+/// the environment otherwise.
+fn proxy_options(network: &NetworkOptions) -> ProxyOptions<'_> {
+    let mut options = ProxyOptions::new();
+    match &network.proxy {
+        Some(proxy) => {
+            options.url(proxy.as_str());
+        }
+        None if !network.no_proxy => {
+            options.auto();
+        }
+        None => {}
+    }
+    options
+}
+
+fn percent(done: usize, total: usize) -> u64 {
+    (done * 100 / total.max(1)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credential() {
+        let network = NetworkOptions {
+            ssh_key: Some(PathBuf::from("id_ed25519")),
+            ..NetworkOptions::default()
+        };
+        let url = "git@github.com:campbellcole/gourcers.git";
+
+        // a rejected key isn't offered again
+        let mut tried = CredentialType::empty();
+        let cred = credential(
+            &network,
+            &mut tried,
+            url,
+            Some("git"),
+            CredentialType::SSH_KEY,
+        );
+        assert_eq!(cred.unwrap().credtype(), CredentialType::SSH_KEY.bits());
+        assert!(credential(
+            &network,
+            &mut tried,
+            url,
+            Some("git"),
+            CredentialType::SSH_KEY
+        )
+        .is_err());
+
+        // remotes which don't accept keys don't get one
+        let mut tried = CredentialType::empty();
+        let cred = credential(
+            &network,
+            &mut tried,
+            "https://github.com/a/b.git",
+            None,
+            CredentialType::DEFAULT,
+        );
+        assert_eq!(cred.unwrap().credtype(), CredentialType::DEFAULT.bits());
+    }
+}
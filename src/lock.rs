@@ -0,0 +1,92 @@
+//! An advisory lock on the data directory, so concurrent runs don't corrupt each other's clones
+//! and logs.
+//!
+//! The lock is held on `gourcers.lock` in the data directory for as long as the process runs, and
+//! is released by the OS when it exits, even if it crashes. The file contains the process ID of
+//! the holder, which is only used in messages.
+
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::{Read, Seek, Write},
+    path::Path,
+};
+
+use crate::error::{GourcersError, Result};
+
+/// The name of the lock file in the data directory.
+pub const FILE_NAME: &str = "gourcers.lock";
+
+/// A held lock on a data directory, which is released when dropped.
+#[derive(Debug)]
+pub struct DataDirLock {
+    _file: File,
+}
+
+impl DataDirLock {
+    /// Take the lock on the data directory at `dir`. If another process holds it, fail, or with
+    /// `wait`, call `waiting` with the holder's process ID and block until it is released.
+    pub fn acquire(dir: &Path, wait: bool, waiting: impl FnOnce(Option<u32>)) -> Result<Self> {
+        let path = dir.join(FILE_NAME);
+        let io_err = || GourcersError::io(format!("failed to lock {}", path.display()));
+
+        // not truncated, so the holder's process ID stays readable until the lock is taken
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(io_err())?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = holder(&mut file);
+                if !wait {
+                    return Err(GourcersError::Locked {
+                        path: dir.to_path_buf(),
+                        pid: holder,
+                    });
+                }
+                waiting(holder);
+                file.lock().map_err(io_err())?;
+            }
+            Err(TryLockError::Error(e)) => return Err(io_err()(e)),
+        }
+
+        file.set_len(0).map_err(io_err())?;
+        file.rewind().map_err(io_err())?;
+        writeln!(file, "{}", std::process::id()).map_err(io_err())?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+/// The process ID written by the holder of the lock, if it could be read.
+fn holder(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let temp = TempDir::new().unwrap();
+
+        let lock = DataDirLock::acquire(temp.path(), false, |_| {}).unwrap();
+
+        match DataDirLock::acquire(temp.path(), false, |_| {}) {
+            Err(GourcersError::Locked { pid, .. }) => assert_eq!(pid, Some(std::process::id())),
+            other => panic!("expected the data directory to be locked: {other:?}"),
+        }
+
+        drop(lock);
+        DataDirLock::acquire(temp.path(), false, |_| {}).unwrap();
+    }
+}
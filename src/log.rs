@@ -0,0 +1,408 @@
+//! Parsing gource's custom log format.
+//!
+//! Each line is `timestamp|author|type|path`, optionally followed by `|colour`. Fields are split
+//! from the left, so a `|` in a path stays part of the path instead of shifting the other fields.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+};
+
+use clap::ValueEnum;
+
+use crate::github::{Repo, RepoKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogEntry<'a> {
+    pub timestamp: u64,
+    pub author: &'a str,
+    /// `A`, `M` or `D` for added, modified or deleted.
+    pub kind: &'a str,
+    /// The path, including the colour field if there is one.
+    pub path: &'a str,
+}
+
+impl<'a> LogEntry<'a> {
+    /// Parse a log line, or `None` if it is malformed.
+    #[must_use]
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let mut fields = line.splitn(4, '|');
+
+        let timestamp = fields.next()?.parse().ok()?;
+        let author = fields.next()?;
+        let kind = fields
+            .next()
+            .filter(|kind| matches!(*kind, "A" | "M" | "D"))?;
+        let path = fields.next()?;
+
+        Some(Self {
+            timestamp,
+            author,
+            kind,
+            path,
+        })
+    }
+
+    /// The top-level directory of the path. This is the repo the entry is from, or its owner with
+    /// [`TreeLayout::OwnerRepo`], unless the tree is flat.
+    #[must_use]
+    pub fn repo(&self) -> &'a str {
+        self.path
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .unwrap_or_default()
+    }
+}
+
+impl Display for LogEntry<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}|{}|{}|{}",
+            self.timestamp, self.author, self.kind, self.path
+        )
+    }
+}
+
+/// How the files of each repo are arranged in the combined tree.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TreeLayout {
+    /// Put each repo's files under a directory named after the repo.
+    #[default]
+    Repo,
+    /// Put each repo's files under its owner's directory, so repos with the same name don't
+    /// share a directory.
+    #[value(name = "owner/repo")]
+    OwnerRepo,
+    /// Merge the files of every repo into a single tree.
+    Flat,
+}
+
+impl TreeLayout {
+    /// The directory the repo's files are placed under, which is empty for a flat tree. Gists are
+    /// placed in a `gists` directory.
+    #[must_use]
+    pub fn prefix(self, repo: &Repo) -> String {
+        match self {
+            Self::Repo if repo.kind == RepoKind::Gist => format!("/gists/{}", repo.name),
+            Self::Repo => format!("/{}", repo.name),
+            Self::OwnerRepo => format!("/{}", repo.full_name()),
+            Self::Flat => String::new(),
+        }
+    }
+}
+
+/// Prefix every path in a log generated by gource with `prefix`, which should start with `/`.
+/// Malformed lines are dropped.
+#[must_use]
+pub fn prefix_paths(log: &str, prefix: &str) -> String {
+    let mut prefixed = String::with_capacity(log.len() + log.lines().count() * prefix.len());
+
+    for line in log.lines() {
+        let Some(entry) = LogEntry::parse(line) else {
+            warn!("dropping malformed log line: {line}");
+            continue;
+        };
+
+        let separator = if entry.path.starts_with('/') { "" } else { "/" };
+        let path = format!("{prefix}{separator}{}", entry.path);
+
+        prefixed.push_str(
+            &LogEntry {
+                path: &path,
+                ..entry
+            }
+            .to_string(),
+        );
+        prefixed.push('\n');
+    }
+
+    prefixed
+}
+
+/// Move every file nested in more than `max_depth` directories into its ancestor directory at
+/// that depth. Malformed lines are dropped.
+#[must_use]
+pub fn limit_depth(log: &str, max_depth: usize) -> String {
+    let mut limited = String::with_capacity(log.len());
+
+    for line in log.lines() {
+        let Some(entry) = LogEntry::parse(line) else {
+            warn!("dropping malformed log line: {line}");
+            continue;
+        };
+
+        let components = entry
+            .path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .collect::<Vec<_>>();
+        let path = match components.split_last() {
+            Some((name, dirs)) if dirs.len() > max_depth => {
+                format!("/{}", [&dirs[..max_depth], &[*name]].concat().join("/"))
+            }
+            _ => entry.path.to_string(),
+        };
+
+        limited.push_str(
+            &LogEntry {
+                path: &path,
+                ..entry
+            }
+            .to_string(),
+        );
+        limited.push('\n');
+    }
+
+    limited
+}
+
+/// The entry with its path relative to the parent of `dir`, if it is in `dir`, so rendering only
+/// these entries zooms in on `dir`. `dir` is a path like `/group/owner/repo/src`.
+#[must_use]
+pub fn focus_entry(line: &str, dir: &str) -> Option<String> {
+    let dir = dir.trim_matches('/');
+    let entry = LogEntry::parse(line)?;
+    let relative = entry
+        .path
+        .strip_prefix('/')?
+        .strip_prefix(dir)?
+        .strip_prefix('/')?;
+
+    let name = dir.rsplit('/').next().unwrap_or(dir);
+    let path = format!("/{name}/{relative}");
+    Some(
+        LogEntry {
+            path: &path,
+            ..entry
+        }
+        .to_string(),
+    )
+}
+
+/// The number of commits in a log. Gource writes a line for every file a commit changed, so
+/// consecutive lines with the same timestamp and author are counted as one commit.
+#[must_use]
+pub fn count_commits(log: &str) -> usize {
+    let mut commits = 0;
+    let mut last = None;
+
+    for entry in log.lines().filter_map(LogEntry::parse) {
+        let commit = (entry.timestamp, entry.author);
+        if last != Some(commit) {
+            commits += 1;
+            last = Some(commit);
+        }
+    }
+
+    commits
+}
+
+/// Move every entry to the start of its author's burst of commits, where a burst is every commit
+/// within `window` seconds of its first, so the burst shows up as a single event. The log must be
+/// in chronological order, and is kept that way. Malformed lines are dropped.
+#[must_use]
+pub fn coalesce(log: &str, window: u64) -> String {
+    let mut bursts = HashMap::<&str, u64>::new();
+    let mut entries = Vec::new();
+
+    for line in log.lines() {
+        let Some(entry) = LogEntry::parse(line) else {
+            warn!("dropping malformed log line: {line}");
+            continue;
+        };
+
+        let start = bursts.entry(entry.author).or_insert(entry.timestamp);
+        if !(*start..=start.saturating_add(window)).contains(&entry.timestamp) {
+            *start = entry.timestamp;
+        }
+
+        entries.push(LogEntry {
+            timestamp: *start,
+            ..entry
+        });
+    }
+
+    // entries moved back can end up before other authors' entries; the sort is stable
+    entries.sort_by_key(|entry| entry.timestamp);
+
+    let mut coalesced = String::with_capacity(log.len());
+    for entry in entries {
+        coalesced.push_str(&entry.to_string());
+        coalesced.push('\n');
+    }
+
+    coalesced
+}
+
+/// Replace the author of every entry which has a replacement in `authors`. Malformed lines are
+/// kept as they are.
+#[must_use]
+pub fn rename_authors<S: std::hash::BuildHasher>(
+    log: &str,
+    authors: &HashMap<String, String, S>,
+) -> String {
+    let mut renamed = String::with_capacity(log.len());
+
+    for line in log.lines() {
+        match LogEntry::parse(line) {
+            Some(entry) if authors.contains_key(entry.author) => {
+                let entry = LogEntry {
+                    author: &authors[entry.author],
+                    ..entry
+                };
+                renamed.push_str(&entry.to_string());
+            }
+            _ => renamed.push_str(line),
+        }
+        renamed.push('\n');
+    }
+
+    renamed
+}
+
+/// Color the entries of files with an extension in `colors`, which is keyed by extension without
+/// the leading dot, ignoring case. The longest matching extension wins, so `d.ts` can be colored
+/// apart from `ts`. Entries which already have a colour are left alone.
+#[must_use]
+pub fn color_extensions(log: &str, colors: &BTreeMap<String, String>) -> String {
+    let mut colors = colors
+        .iter()
+        .map(|(extension, color)| (format!(".{}", extension.to_lowercase()), color))
+        .collect::<Vec<_>>();
+    colors.sort_by_key(|(extension, _)| std::cmp::Reverse(extension.len()));
+
+    let mut colored = String::with_capacity(log.len());
+
+    for line in log.lines() {
+        colored.push_str(line);
+
+        if let Some(entry) = LogEntry::parse(line) {
+            let name = entry
+                .path
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_lowercase();
+            let color = colors
+                .iter()
+                .find(|(extension, _)| name.ends_with(extension.as_str()));
+
+            if let Some((_, color)) = color {
+                if !lazy_regex::regex_is_match!(r"\|[0-9A-Fa-f]{6}$", line) {
+                    colored.push('|');
+                    colored.push_str(color);
+                }
+            }
+        }
+
+        colored.push('\n');
+    }
+
+    colored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entry() {
+        let entry = LogEntry::parse("1700000000|Jane Doe|M|/src/a|b.rs").unwrap();
+        assert_eq!(entry.timestamp, 1_700_000_000);
+        assert_eq!(entry.author, "Jane Doe");
+        assert_eq!(entry.kind, "M");
+        assert_eq!(entry.path, "/src/a|b.rs");
+
+        assert_eq!(LogEntry::parse("soon|x|A|/a"), None);
+        assert_eq!(LogEntry::parse("100|x|X|/a"), None);
+        assert_eq!(LogEntry::parse("100|x|A"), None);
+    }
+
+    #[test]
+    fn test_prefix_paths() {
+        assert_eq!(
+            prefix_paths(
+                "100|x|A|/src/a|b.rs\n200|y|D|README.md\nbad line\n",
+                "/repo"
+            ),
+            "100|x|A|/repo/src/a|b.rs\n200|y|D|/repo/README.md\n"
+        );
+        assert_eq!(
+            prefix_paths("200|y|D|README.md\n", ""),
+            "200|y|D|/README.md\n"
+        );
+    }
+
+    #[test]
+    fn test_color_extensions() {
+        let colors = BTreeMap::from([
+            ("rs".to_string(), "DEA584".to_string()),
+            ("ts".to_string(), "3178C6".to_string()),
+            ("d.ts".to_string(), "888888".to_string()),
+        ]);
+
+        assert_eq!(
+            color_extensions(
+                "100|x|A|/r/main.RS\n100|x|A|/r/a.ts\n100|x|A|/r/a.d.ts\n100|x|M|/r/b.rs|00FF00\n\
+                 100|x|A|/r/rs\n",
+                &colors
+            ),
+            "100|x|A|/r/main.RS|DEA584\n100|x|A|/r/a.ts|3178C6\n100|x|A|/r/a.d.ts|888888\n\
+             100|x|M|/r/b.rs|00FF00\n100|x|A|/r/rs\n"
+        );
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let log = "100|x|A|/a\n130|y|A|/b\n150|x|M|/a\n160|x|A|/c\n300|x|M|/c\nbad line\n";
+        assert_eq!(
+            coalesce(log, 60),
+            "100|x|A|/a\n100|x|M|/a\n100|x|A|/c\n130|y|A|/b\n300|x|M|/c\n"
+        );
+        assert_eq!(coalesce(log, 0), prefix_paths(log, ""));
+    }
+
+    #[test]
+    fn test_limit_depth() {
+        assert_eq!(
+            limit_depth("100|x|A|/a/b/c/d.rs\n100|x|A|/a/e.rs\n200|y|M|f.rs\n", 1),
+            "100|x|A|/a/d.rs\n100|x|A|/a/e.rs\n200|y|M|f.rs\n"
+        );
+        assert_eq!(limit_depth("100|x|A|/a/b/c.rs\n", 0), "100|x|A|/c.rs\n");
+    }
+
+    #[test]
+    fn test_focus_entry() {
+        assert_eq!(
+            focus_entry("100|x|A|/work/me/api/src/main.rs", "/work/me/api/src/").as_deref(),
+            Some("100|x|A|/src/main.rs")
+        );
+        assert_eq!(
+            focus_entry("100|x|A|/work/me/api/lib.rs", "work").as_deref(),
+            Some("100|x|A|/work/me/api/lib.rs")
+        );
+        assert_eq!(
+            focus_entry("100|x|A|/work/me/api2/lib.rs", "/work/me/api"),
+            None
+        );
+        assert_eq!(focus_entry("100|x|A|/oss/lib.rs", "/work"), None);
+    }
+
+    #[test]
+    fn test_count_commits() {
+        let log = "100|x|A|/a\n100|x|A|/b\n100|y|M|/a\n200|x|M|/a\nbad line\n200|x|D|/b\n";
+        assert_eq!(count_commits(log), 3);
+        assert_eq!(count_commits(""), 0);
+    }
+
+    #[test]
+    fn test_rename_authors() {
+        let authors = HashMap::from([("Jane Doe".to_string(), "janedoe".to_string())]);
+        assert_eq!(
+            rename_authors("100|Jane Doe|A|/a\n200|x|M|/a\n", &authors),
+            "100|janedoe|A|/a\n200|x|M|/a\n"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! Reading and writing the per-repo gource logs, which may be stored gzip-compressed.
+//!
+//! Logs are always referred to by their uncompressed path, e.g. `gource/owner__repo.txt`. With
+//! `compress_logs` in the config file, they are written to `gource/owner__repo.txt.gz` instead.
+//! Either form is read, so changing the setting doesn't invalidate existing logs; the next write
+//! replaces one form with the other.
+//!
+//! Every write also writes the [`LogStats`] of the log next to it.
+
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::log_stats::{self, LogStats};
+
+/// The path of the compressed form of the log at `path`.
+#[must_use]
+pub fn compressed(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/// Read the log at `path`, decompressing it if it is stored compressed.
+pub fn read(path: &Path) -> io::Result<String> {
+    let mut contents = String::new();
+    open(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Whether the log at `path` has no entries, without reading all of it.
+pub fn is_empty(path: &Path) -> io::Result<bool> {
+    let mut byte = [0];
+    Ok(open(path)?.read(&mut byte)? == 0)
+}
+
+/// Write the log at `path`, compressed if `compress` is set, and remove the other form. The stats
+/// of the log are written too, with `head` as the commit it was generated from, or the commit
+/// recorded before if it is `None`, since rewriting a log doesn't change where it came from.
+pub fn write(path: &Path, contents: &str, compress: bool, head: Option<&str>) -> io::Result<()> {
+    let head = match head {
+        Some(head) => Some(head.to_string()),
+        None => LogStats::read(path)
+            .ok()
+            .flatten()
+            .and_then(|stats| stats.head),
+    };
+
+    let (target, other) = if compress {
+        (compressed(path), path.to_path_buf())
+    } else {
+        (path.to_path_buf(), compressed(path))
+    };
+
+    let file = File::create(&target)?;
+    if compress {
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        encoder.write_all(contents.as_bytes())?;
+        encoder.finish()?;
+    } else {
+        let mut file = file;
+        file.write_all(contents.as_bytes())?;
+    }
+    remove_file(&other)?;
+
+    let stats = serde_json::to_string(&LogStats {
+        head,
+        ..LogStats::of(contents)
+    })?;
+    std::fs::write(log_stats::path(path), stats)
+}
+
+/// Remove the log at `path` in both forms and its stats, if they exist.
+pub fn remove(path: &Path) -> io::Result<()> {
+    remove_file(path)?;
+    remove_file(&compressed(path))?;
+    remove_file(&log_stats::path(path))
+}
+
+fn open(path: &Path) -> io::Result<Box<dyn Read>> {
+    match File::open(path) {
+        Ok(file) => Ok(Box::new(BufReader::new(file))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let file = File::open(compressed(path)).map_err(|_| e)?;
+            Ok(Box::new(GzDecoder::new(BufReader::new(file))))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn remove_file(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("owner__repo.txt");
+        let log = "1|alice|A|/repo/a.rs\n2|bob|M|/repo/a.rs\n";
+
+        write(&path, log, true, Some("abc123")).unwrap();
+        assert!(!path.exists());
+        assert!(compressed(&path).exists());
+        assert_eq!(read(&path).unwrap(), log);
+        assert!(!is_empty(&path).unwrap());
+
+        // switching the setting replaces the compressed log, and rewriting keeps the head
+        write(&path, "", false, None).unwrap();
+        assert!(!compressed(&path).exists());
+        assert!(is_empty(&path).unwrap());
+        assert_eq!(
+            LogStats::read(&path).unwrap(),
+            Some(LogStats {
+                head: Some("abc123".into()),
+                ..LogStats::default()
+            })
+        );
+
+        remove(&path).unwrap();
+        assert!(!path.exists() && !compressed(&path).exists());
+        assert!(!log_stats::path(&path).exists());
+        assert_eq!(read(&path).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
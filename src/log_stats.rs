@@ -0,0 +1,185 @@
+//! Summaries of the per-repo gource logs, stored next to them.
+//!
+//! Whenever a repo's log is written, its number of commits, first and last timestamps and authors
+//! are written to `gource/{repo}.stats.json`, so they can be looked up without reading the log,
+//! which can be gigabytes for big monorepos. The commit the clone was at when the log was generated
+//! is recorded too, to detect logs which are out of date with their clone.
+
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    calendar,
+    error::{GourcersError, Result},
+    log::{self, LogEntry},
+};
+
+/// The suffix of the stats files in the gource log directory.
+pub const SUFFIX: &str = ".stats.json";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogStats {
+    pub commits: usize,
+    /// The timestamp of the first entry, if there are any.
+    pub first: Option<u64>,
+    /// The timestamp of the last entry, if there are any.
+    pub last: Option<u64>,
+    pub authors: BTreeSet<String>,
+    /// The `HEAD` commit of the clone the log was generated from, if it is known.
+    #[serde(default)]
+    pub head: Option<String>,
+}
+
+impl LogStats {
+    #[must_use]
+    pub fn of(log: &str) -> Self {
+        let mut stats = Self {
+            commits: log::count_commits(log),
+            ..Self::default()
+        };
+
+        for entry in log.lines().filter_map(LogEntry::parse) {
+            stats.first = Some(
+                stats
+                    .first
+                    .map_or(entry.timestamp, |t| t.min(entry.timestamp)),
+            );
+            stats.last = Some(
+                stats
+                    .last
+                    .map_or(entry.timestamp, |t| t.max(entry.timestamp)),
+            );
+            if !stats.authors.contains(entry.author) {
+                stats.authors.insert(entry.author.to_string());
+            }
+        }
+
+        stats
+    }
+
+    /// Read the stats of the log at `log_path`, or `None` if they haven't been written.
+    pub fn read(log_path: &Path) -> Result<Option<Self>> {
+        let path = path(log_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = std::fs::read_to_string(&path).map_err(GourcersError::io(format!(
+            "failed to read {}",
+            path.display()
+        )))?;
+        let stats = serde_json::from_str(&contents).map_err(GourcersError::json(format!(
+            "failed to parse {}",
+            path.display()
+        )))?;
+
+        Ok(Some(stats))
+    }
+}
+
+/// The path of the stats of the log at `log_path`, e.g. `gource/owner__repo.stats.json` for
+/// `gource/owner__repo.txt`.
+#[must_use]
+pub fn path(log_path: &Path) -> PathBuf {
+    log_path.with_extension(&SUFFIX[1..])
+}
+
+/// A table of the stats of each log, with a line of totals.
+#[must_use]
+pub fn report(stats: &[(String, LogStats)]) -> String {
+    let mut report = String::new();
+    let mut line = |s: String| {
+        writeln!(report, "{s}").expect("writing to a String cannot fail");
+    };
+
+    line(format!(
+        "{:<40}{:>10}{:>12}{:>12}{:>9}",
+        "repo", "commits", "first", "last", "authors"
+    ));
+    for (name, stats) in stats {
+        line(format!(
+            "{name:<40}{:>10}{:>12}{:>12}{:>9}",
+            stats.commits,
+            date(stats.first),
+            date(stats.last),
+            stats.authors.len()
+        ));
+    }
+
+    let total = stats
+        .iter()
+        .fold(LogStats::default(), |total, (_, stats)| LogStats {
+            commits: total.commits + stats.commits,
+            first: total.first.into_iter().chain(stats.first).min(),
+            last: total.last.into_iter().chain(stats.last).max(),
+            authors: &total.authors | &stats.authors,
+            head: None,
+        });
+    line(format!(
+        "{:<40}{:>10}{:>12}{:>12}{:>9}",
+        "total",
+        total.commits,
+        date(total.first),
+        date(total.last),
+        total.authors.len()
+    ));
+
+    report
+}
+
+fn date(timestamp: Option<u64>) -> String {
+    timestamp.map_or_else(
+        || "-".to_string(),
+        |timestamp| {
+            let (year, month, day) = calendar::date(timestamp.try_into().unwrap_or(i64::MAX));
+            format!("{year}-{month:02}-{day:02}")
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let log = "\
+1672531200|alice|A|/repo/a.rs
+1672531200|alice|A|/repo/b.rs
+1675209600|bob|M|/repo/a.rs
+1673000000|alice|D|/repo/b.rs
+";
+        let stats = LogStats::of(log);
+        assert_eq!(stats.commits, 3);
+        assert_eq!(stats.first, Some(1_672_531_200));
+        assert_eq!(stats.last, Some(1_675_209_600));
+        assert_eq!(
+            stats.authors,
+            BTreeSet::from(["alice".into(), "bob".into()])
+        );
+
+        assert_eq!(LogStats::of(""), LogStats::default());
+
+        let report = report(&[
+            ("me/repo".into(), stats),
+            ("me/empty".into(), LogStats::default()),
+        ]);
+        let rows = report
+            .lines()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(rows[1], ["me/repo", "3", "2023-01-01", "2023-02-01", "2"]);
+        assert_eq!(rows[2], ["me/empty", "0", "-", "-", "0"]);
+        assert_eq!(rows[3], ["total", "3", "2023-01-01", "2023-02-01", "2"]);
+
+        assert_eq!(
+            path(Path::new("gource/me__repo.txt")),
+            Path::new("gource/me__repo.stats.json")
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Naming authors after their GitHub logins with `--use-github-logins`.
+//!
+//! Gource names authors after the name in each commit, which can differ between machines and from
+//! the author's GitHub login. The login for each author email is looked up by fetching one of their
+//! commits from the API, and cached in `logins.json` in the data directory, including emails which
+//! don't belong to any account.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{GourcersError, Result},
+    github::{self, Repo, RepoKind},
+    log, log_file,
+    progress::Progress,
+    Context,
+};
+
+/// The login of each author email, or `None` if it doesn't belong to a GitHub account.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LoginCache {
+    pub emails: BTreeMap<String, Option<String>>,
+}
+
+impl LoginCache {
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = std::fs::read_to_string(path).map_err(GourcersError::io(format!(
+            "failed to read {}",
+            path.display()
+        )))?;
+        let cache = serde_json::from_str(&contents).map_err(GourcersError::json(format!(
+            "failed to parse {}",
+            path.display()
+        )))?;
+
+        Ok(Some(cache))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(GourcersError::json("failed to serialize login cache"))?;
+        std::fs::write(path, contents).map_err(GourcersError::io(format!(
+            "failed to write {}",
+            path.display()
+        )))
+    }
+}
+
+/// An author of a repo, with their most recent commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Author {
+    name: String,
+    email: String,
+    sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Commit {
+    author: Option<github::Owner>,
+}
+
+/// Rewrite the author names in the gource logs of the repos to their GitHub logins, where they
+/// have one.
+pub async fn apply(cx: &Context, repos: &[Repo], progress: &Progress) -> Result<()> {
+    let path = cx.data_dir.logins();
+    let mut cache = LoginCache::read(&path)?.unwrap_or_default();
+    let client = github::client(cx)?;
+
+    for repo in repos {
+        progress.set_message(repo.full_name());
+
+        let mut logins = HashMap::new();
+        for author in authors(cx, repo).await? {
+            let login = match cache.emails.get(&author.email) {
+                Some(login) => login.clone(),
+                // gists and wikis can't be looked up, but their authors might be known already
+                None if repo.kind != RepoKind::Repo => None,
+                None => {
+                    let login = lookup(cx, &client, repo, &author.sha).await?;
+                    cache.emails.insert(author.email.clone(), login.clone());
+                    login
+                }
+            };
+
+            if let Some(login) = login {
+                // the log's author names have already been sanitized
+                logins
+                    .entry(cx.sanitize.apply(&author.name))
+                    .or_insert(login);
+            }
+        }
+
+        let log_path = cx.data_dir.gource_log(repo);
+        let gource_log = log_file::read(&log_path).map_err(GourcersError::io(format!(
+            "failed to read gource log for {}",
+            repo.full_name()
+        )))?;
+        log_file::write(
+            &log_path,
+            &log::rename_authors(&gource_log, &logins),
+            cx.config.compress_logs,
+            None,
+        )
+        .map_err(GourcersError::io(format!(
+            "failed to write gource log for {}",
+            repo.full_name()
+        )))?;
+
+        progress.inc(1);
+    }
+
+    cache.write(&path)
+}
+
+/// The authors of a repo, from newest to oldest, using the same names as gource.
+async fn authors(cx: &Context, repo: &Repo) -> Result<Vec<Author>> {
+    let output = tokio::process::Command::new("git")
+        .arg("-C")
+        .arg(cx.data_dir.repo_dir(repo))
+        .args(["log", "--format=%aN%x00%aE%x00%H"])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(GourcersError::io(format!(
+            "failed to run git log for {}",
+            repo.full_name()
+        )))?;
+
+    if !output.status.success() {
+        return Err(GourcersError::Git {
+            repo: repo.full_name(),
+            subcommand: "log".into(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(parse_authors(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse the output of `git log --format=%aN%x00%aE%x00%H`, keeping the first commit of each
+/// name and email.
+fn parse_authors(output: &str) -> Vec<Author> {
+    let mut authors = Vec::<Author>::new();
+
+    for line in output.lines() {
+        let mut fields = line.splitn(3, '\0');
+        let (Some(name), Some(email), Some(sha)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+
+        if !authors
+            .iter()
+            .any(|author| author.name == name && author.email == email)
+        {
+            authors.push(Author {
+                name: name.into(),
+                email: email.into(),
+                sha: sha.into(),
+            });
+        }
+    }
+
+    authors
+}
+
+/// Look up the login of the author of a commit, or `None` if the commit's email doesn't belong
+/// to an account.
+async fn lookup(cx: &Context, client: &Client, repo: &Repo, sha: &str) -> Result<Option<String>> {
+    let url = format!("{}/repos/{}/commits/{sha}", cx.api_url, repo.full_name());
+    debug!(url = url, "looking up commit author");
+
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .map_err(GourcersError::network("failed to execute request"))?;
+
+    // commits which were never pushed can't be looked up
+    if matches!(
+        response.status(),
+        StatusCode::NOT_FOUND | StatusCode::UNPROCESSABLE_ENTITY
+    ) {
+        return Ok(None);
+    }
+
+    let commit: Commit = response
+        .error_for_status()
+        .map_err(GourcersError::network("request failed"))?
+        .json()
+        .await
+        .map_err(GourcersError::network("failed to read response"))?;
+
+    Ok(commit.author.map(|author| author.login))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_authors() {
+        let output = "Jane\0jane@example.com\0c3\nJane\0jane@example.com\0c2\nJane\0jane@work.example\0c1\nbad line\n";
+
+        assert_eq!(
+            parse_authors(output),
+            [
+                Author {
+                    name: "Jane".into(),
+                    email: "jane@example.com".into(),
+                    sha: "c3".into(),
+                },
+                Author {
+                    name: "Jane".into(),
+                    email: "jane@work.example".into(),
+                    sha: "c1".into(),
+                },
+            ]
+        );
+    }
+}
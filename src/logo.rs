@@ -0,0 +1,186 @@
+//! A logo or watermark placed in a corner of the video.
+//!
+//! Gource can draw PNG logos itself with `--logo`, but can't scale them, and positions them with
+//! `--logo-offset`, measured from the bottom right corner. Logos which gource can't draw as they
+//! are, because they are too large for the resolution or aren't PNGs, are scaled and overlaid by
+//! ffmpeg instead, which is only possible when rendering to a video file.
+
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use clap::ValueEnum;
+
+use crate::error::{GourcersError, Result};
+
+/// The distance between the logo and the edges of the video, in pixels.
+const MARGIN: u32 = 20;
+
+/// The largest fraction of the video's width and height the logo can cover.
+const MAX_FRACTION: u32 = 4;
+
+/// Options for the logo.
+#[derive(Debug, Clone, Default, clap::Args)]
+#[clap(next_help_heading = "Logo options")]
+pub struct LogoOptions {
+    /// An image to show in a corner of the video, e.g. a company logo.
+    ///
+    /// PNG logos which fit are drawn by gource. Larger logos and other formats are scaled down to
+    /// at most a quarter of the video and overlaid with ffmpeg, which only works when rendering
+    /// render profiles.
+    #[clap(long, value_name = "IMAGE")]
+    pub logo: Option<PathBuf>,
+    /// The corner to place the logo in.
+    #[clap(long, value_enum, default_value_t, requires = "logo")]
+    pub logo_position: LogoPosition,
+}
+
+impl LogoOptions {
+    /// The logo, if one was given.
+    pub fn load(&self) -> Result<Option<Logo>> {
+        self.logo
+            .clone()
+            .map(|path| Logo::load(path, self.logo_position))
+            .transpose()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogoPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+#[derive(Debug, Clone)]
+pub struct Logo {
+    pub path: PathBuf,
+    pub position: LogoPosition,
+    /// The width and height of the image, if it is a PNG.
+    pub size: Option<(u32, u32)>,
+}
+
+impl Logo {
+    pub fn load(path: PathBuf, position: LogoPosition) -> Result<Self> {
+        let file = File::open(&path).map_err(GourcersError::io(format!(
+            "failed to open logo {}",
+            path.display()
+        )))?;
+
+        // anything png can't read is left to ffmpeg
+        let size = png::Decoder::new(BufReader::new(file))
+            .read_info()
+            .ok()
+            .map(|reader| (reader.info().width, reader.info().height));
+
+        Ok(Self {
+            path,
+            position,
+            size,
+        })
+    }
+
+    /// The arguments which make gource draw the logo at `resolution`, or `None` if it has to be
+    /// overlaid by ffmpeg instead.
+    #[must_use]
+    pub fn gource_args(&self, resolution: &str) -> Option<Vec<String>> {
+        let (width, height) = self.size?;
+        let (video_width, video_height) = parse_resolution(resolution)?;
+        if width > video_width / MAX_FRACTION || height > video_height / MAX_FRACTION {
+            return None;
+        }
+
+        // gource measures the offset from the bottom right corner to the logo's bottom right
+        let far_x = video_width - width - MARGIN;
+        let far_y = video_height - height - MARGIN;
+        let (x, y) = match self.position {
+            LogoPosition::TopLeft => (far_x, far_y),
+            LogoPosition::TopRight => (MARGIN, far_y),
+            LogoPosition::BottomLeft => (far_x, MARGIN),
+            LogoPosition::BottomRight => (MARGIN, MARGIN),
+        };
+
+        Some(vec![
+            "--logo".to_string(),
+            self.path.display().to_string(),
+            "--logo-offset".to_string(),
+            format!("{x}x{y}"),
+        ])
+    }
+
+    /// A `-filter_complex` graph which scales the logo, given as the second input, to fit a video
+    /// at `resolution` and overlays it on the first input.
+    #[must_use]
+    pub fn ffmpeg_filter(&self, resolution: &str) -> Option<String> {
+        let (video_width, video_height) = parse_resolution(resolution)?;
+        let (max_width, max_height) = (video_width / MAX_FRACTION, video_height / MAX_FRACTION);
+
+        let x = match self.position {
+            LogoPosition::TopLeft | LogoPosition::BottomLeft => format!("{MARGIN}"),
+            LogoPosition::TopRight | LogoPosition::BottomRight => format!("W-w-{MARGIN}"),
+        };
+        let y = match self.position {
+            LogoPosition::TopLeft | LogoPosition::TopRight => format!("{MARGIN}"),
+            LogoPosition::BottomLeft | LogoPosition::BottomRight => format!("H-h-{MARGIN}"),
+        };
+
+        Some(format!(
+            "[1:v]scale=w='min(iw,{max_width})':h='min(ih,{max_height})':force_original_aspect_ratio=decrease[logo];[0:v][logo]overlay={x}:{y}"
+        ))
+    }
+}
+
+fn parse_resolution(resolution: &str) -> Option<(u32, u32)> {
+    let (width, height) = resolution.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logo(position: LogoPosition, size: Option<(u32, u32)>) -> Logo {
+        Logo {
+            path: "logo.png".into(),
+            position,
+            size,
+        }
+    }
+
+    #[test]
+    fn test_gource_args() {
+        assert_eq!(
+            logo(LogoPosition::BottomRight, Some((200, 100))).gource_args("1920x1080"),
+            Some(
+                ["--logo", "logo.png", "--logo-offset", "20x20"]
+                    .map(String::from)
+                    .to_vec()
+            )
+        );
+        assert_eq!(
+            logo(LogoPosition::TopLeft, Some((200, 100)))
+                .gource_args("1920x1080")
+                .unwrap()[3],
+            "1700x960"
+        );
+
+        // too large, or not a PNG
+        assert_eq!(
+            logo(LogoPosition::BottomRight, Some((1000, 100))).gource_args("1920x1080"),
+            None
+        );
+        assert_eq!(
+            logo(LogoPosition::BottomRight, None).gource_args("1920x1080"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ffmpeg_filter() {
+        let filter = logo(LogoPosition::TopRight, None)
+            .ffmpeg_filter("1920x1080")
+            .unwrap();
+        assert!(filter.contains("min(iw,480)"));
+        assert!(filter.ends_with("overlay=W-w-20:20"));
+    }
+}
@@ -2,38 +2,143 @@
 #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
 
 use std::{
+    collections::{HashMap, HashSet},
+    io::IsTerminal,
     path::{Path, PathBuf},
-    time::Duration,
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
-use clap::Parser;
+use aspect::Aspect;
+use bench::{BenchArgs, Phase, Timings};
+use chapters::ChapterBy;
+use clap::{Parser, Subcommand};
 use color_eyre::{
-    eyre::{Result, WrapErr},
+    eyre::{eyre, Report, Result, WrapErr},
     Section,
 };
+use compare::CompareArgs;
+use config::{Config, RenderProfile};
 use console::style;
 use dialoguer::{theme::ColorfulTheme, Confirm};
-use github::Repo;
-use include::RuleSet;
-use indicatif::{ProgressBar, ProgressStyle};
+use error::GourcersError;
+use eviction::ByteSize;
+use ffmpeg::{Format, FramePosition};
+use github::{GitBackend, Repo, RepoKind, UpdateStrategy};
+use gource::GourceOptions;
+use groups::Groups;
+use headless::Headless;
+use history::Since;
+use lazy_regex::regex;
+use lock::DataDirLock;
+use log::{LogEntry, TreeLayout};
+use log_stats::LogStats;
+use logo::{Logo, LogoOptions};
+use network::NetworkOptions;
+use path_filter::{FilterPreset, PathFilter};
+use progress::Progress;
+use publish::Destination;
+use rules::{Precedence, RuleSet};
+use sanitize::Sanitize;
+use secret::Secret;
+use sort::SortBackendKind;
+use sorted_log::SortedLogState;
+use source::RepoSource;
+use split::SplitBy;
+use teams::Team;
 use temp_dir::TempDir;
+use theme::Theme;
+use top::TopBy;
+use tracing::Instrument;
 use tracing_subscriber::prelude::*;
+use watch::{Interval, WatchArgs};
 
 #[macro_use]
 extern crate tracing;
 
+pub mod aspect;
+pub mod avatars;
+pub mod bench;
+pub mod calendar;
+pub mod captions;
+pub mod chapters;
+pub mod checkpoint;
+pub mod compare;
+pub mod config;
+pub mod doctor;
+#[cfg(test)]
+pub mod e2e;
+pub mod error;
+pub mod eviction;
+pub mod ffmpeg;
 pub mod github;
 pub mod gource;
-pub mod include;
+pub mod groups;
+pub mod headless;
+pub mod history;
+pub mod http_cache;
+pub mod layout;
+pub mod leaderboard;
+#[cfg(feature = "libgit2")]
+pub mod libgit2;
+pub mod lock;
+pub mod log;
+pub mod log_file;
+pub mod log_stats;
+pub mod logins;
+pub mod logo;
+pub mod metadata;
+#[cfg(test)]
+pub mod mock_server;
+pub mod network;
+pub mod otel;
+pub mod parallel;
+pub mod path_filter;
+pub mod progress;
+#[cfg(unix)]
+pub mod progress_socket;
+pub mod publish;
+pub mod pulls;
+pub mod releases;
+pub mod rules;
+pub mod sanitize;
+pub mod secret;
+pub mod shorts;
+pub mod sort;
+pub mod sorted_log;
+pub mod source;
+pub mod speed;
+pub mod split;
+pub mod teams;
+pub mod template;
+pub mod theme;
+pub mod timeline;
+pub mod top;
+pub mod watch;
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Parser)]
 #[clap(version, about, long_about = None)]
 pub struct Cli {
     /// Your personal access token for GitHub.
     ///
-    /// This token must have the `repo` scope.
-    #[clap(short, long, env = "GITHUB_TOKEN")]
-    pub token: String,
+    /// This token must have the `repo` scope, which is checked before listing repos, or be a
+    /// fine-grained token with read access to the contents and metadata of your repos. A warning
+    /// is printed when it expires within a week. It isn't needed with `--from-log` or
+    /// `--local-repos`.
+    #[clap(
+        short,
+        long,
+        env = "GITHUB_TOKEN",
+        hide_env_values = true,
+        required_unless_present_any = ["from_log", "local_repos"]
+    )]
+    pub token: Option<Secret>,
+    /// The base URL of the GitHub API.
+    ///
+    /// For GitHub Enterprise Server, this is usually `https://<hostname>/api/v3`.
+    #[clap(long, env = "GITHUB_API_URL", default_value = github::DEFAULT_API_URL)]
+    pub api_url: String,
     /// The directory to store the cloned repos and gource logs.
     ///
     /// If left blank, a temporary directory will be created and removed after finishing.
@@ -45,25 +150,420 @@ pub struct Cli {
     /// Silently allow using a temporary data directory instead of prompting for confirmation.
     #[clap(short = 'y', long)]
     pub temp: bool,
+    /// Keep the temporary data directory when the run fails and print its path, so it can be
+    /// inspected or passed to `-d` to resume. Pass `false` to always remove it.
+    #[clap(long, default_value_t = true, action = clap::ArgAction::Set, value_name = "BOOL")]
+    pub keep_temp_on_failure: bool,
+    /// Wait for another gourcers process using the same data directory to finish, instead of
+    /// failing.
+    #[clap(long)]
+    pub wait_for_lock: bool,
+    /// The most disk space the clones in the data directory may use, like `50G`.
+    ///
+    /// When the clones use more, the least recently used ones are deleted after generating the
+    /// logs. Their gource logs are kept unless `--evict-logs` is given.
+    #[clap(long, value_name = "SIZE")]
+    pub max_cache_size: Option<ByteSize>,
+    /// Also delete the gource logs of clones evicted by `--max-cache-size`.
+    #[clap(long, requires = "max_cache_size")]
+    pub evict_logs: bool,
     /// Skip cloning/pulling repos and assume they are already present in the data directory.
+    ///
+    /// Fails before generating any logs if a selected repo hasn't been cloned.
     #[clap(long)]
     pub skip_clone: bool,
+    /// The implementation used to clone and pull repos.
+    ///
+    /// The `libgit2` backend is only available when built with the `libgit2` feature.
+    #[clap(long, value_enum, default_value_t)]
+    pub git_backend: GitBackend,
+    /// How existing clones are brought up to date.
+    #[clap(long, value_enum, default_value_t)]
+    pub update_strategy: UpdateStrategy,
+    /// How many times to retry repos which failed to clone or pull, after all other repos are done.
+    #[clap(long, default_value_t = 2)]
+    pub clone_retries: u32,
+    /// Skip repos which still fail to clone or pull after retrying, instead of stopping.
+    #[clap(long)]
+    pub keep_going: bool,
+    /// Include repos without any commits, which are skipped by default.
+    #[clap(long)]
+    pub include_empty: bool,
+    /// Leave merge commits out of the logs.
+    ///
+    /// Merges show up as bursts of activity by whoever merged, on files other people wrote.
+    #[clap(long)]
+    pub no_merges: bool,
+    /// Show commits by the same author within this many seconds of each other as a single event.
+    ///
+    /// This reduces the stutter from many small commits in a row, like stacked PRs.
+    #[clap(long, value_name = "SECONDS")]
+    pub coalesce_window: Option<u64>,
+    /// Show every merged pull request as a file in a `PRs` directory of its repo, added by its
+    /// author when it was merged.
+    ///
+    /// Pull requests are listed with the API, once for every 100 closed pull requests of each
+    /// repo.
+    #[clap(long)]
+    pub pull_requests: bool,
+    /// Show a caption for every tag which looks like a version, e.g. `v1.2.0`, when it was
+    /// created.
+    ///
+    /// The captions are added to the ones given with `--captions`.
+    #[clap(long, conflicts_with = "from_log")]
+    pub mark_releases: bool,
+    /// Only visualize the history since this date, e.g. `2020-01-01`.
+    ///
+    /// Repos which haven't been pushed to since are left out, and earlier log entries are dropped.
+    /// With the `cli` git backend, new clones also only fetch the commits made since.
+    #[clap(long, value_name = "DATE")]
+    pub history_since: Option<Since>,
+    /// Name authors after their GitHub logins instead of the names in their commits.
+    ///
+    /// Logins are looked up with the API once for each author's email, and cached in the data
+    /// directory.
+    #[clap(long)]
+    pub use_github_logins: bool,
+    /// Color each author's files after their GitHub team, for repos owned by organizations.
+    ///
+    /// Authors are matched to team members by login, so this needs `--use-github-logins`.
+    #[clap(long, requires = "use_github_logins")]
+    pub color_by_team: bool,
+    /// Show an avatar on each author's bubble.
+    ///
+    /// Authors named after their GitHub login with `--use-github-logins` get their GitHub avatar,
+    /// and everyone else gets a generated identicon.
+    #[clap(long)]
+    pub avatars: bool,
+    /// How long downloaded avatars are used before they are downloaded again.
+    #[clap(long, default_value = "7d")]
+    pub avatar_max_age: Interval,
+    /// Also visualize the wikis of the selected repos, under `{repo}.wiki/`.
+    #[clap(long)]
+    pub include_wikis: bool,
+    /// Also visualize your gists, under `gists/`.
+    #[clap(long)]
+    pub include_gists: bool,
+    /// Skip repos with fewer than this many commits.
+    #[clap(long, value_name = "N")]
+    pub min_commits: Option<usize>,
+    /// Only keep the N most active repos, ranked by `--by`.
+    #[clap(long, value_name = "N")]
+    pub top: Option<usize>,
+    /// How repos are ranked by `--top`.
+    ///
+    /// Ranking by commits needs every selected repo to be cloned first, while the other rankings
+    /// only clone the repos which are kept.
+    #[clap(long, value_enum, default_value_t, requires = "top")]
+    pub by: TopBy,
+    /// The number of repos to clone and generate logs for at the same time.
+    ///
+    /// Defaults to the number of available CPUs.
+    #[clap(short, long)]
+    pub jobs: Option<usize>,
+    /// How the combined log is sorted.
+    ///
+    /// The `qsv` backend sorts on disk, for when the combined log doesn't fit in memory. It
+    /// requires `qsv` to be installed.
+    #[clap(long, value_enum, default_value_t)]
+    pub sort_backend: SortBackendKind,
+    /// How author names and paths in the logs are cleaned up.
+    ///
+    /// Use `ascii` if your gource font can't display some of the characters in your repos.
+    #[clap(long, value_enum, default_value_t)]
+    pub sanitize: Sanitize,
+    /// How the files of each repo are arranged in the visualization.
+    #[clap(long, value_enum, default_value_t)]
+    pub tree_layout: TreeLayout,
+    /// Only render the files in this directory of the visualization, e.g. `/work` for a group or
+    /// `/owner/repo/src`, zooming in on it.
+    #[clap(long, value_name = "DIR")]
+    pub focus: Option<String>,
+    /// Place repos in named folders at the top of the tree, as listed in the given file.
+    ///
+    /// Each line is `group: <name> => <patterns>`, e.g. `group: work => acme/*, acme-labs/*`.
+    #[clap(long, value_name = "FILE")]
+    pub groups: Option<PathBuf>,
+    /// Read the repos to choose from from the JSON printed by `gh repo list --json`, or from
+    /// stdin if this is `-`, instead of listing them with the API.
+    ///
+    /// The JSON must include `nameWithOwner`, and should include `sshUrl` and `diskUsage`.
+    #[clap(long, value_name = "FILE")]
+    pub repos_json: Option<PathBuf>,
+    /// Choose from the git repos in the subdirectories of this directory, instead of listing them
+    /// with the API.
+    ///
+    /// The repos are cloned into the data directory like any other, and are owned by the
+    /// directory, so `~/code/gourcers` becomes `code/gourcers`.
+    #[clap(long, value_name = "DIR", conflicts_with = "repos_json")]
+    pub local_repos: Option<PathBuf>,
+    /// Write the repos which end up in the video, after all filtering, to this file as JSON, so
+    /// other tools can consume exactly the same set.
+    #[clap(long, value_name = "FILE")]
+    pub export_selection: Option<PathBuf>,
     /// Include any repos matching the given selectors. Can be applied multiple times.
     #[clap(short, long)]
     pub include: Vec<String>,
+    /// Exclude any repos matching the given selectors. Can be applied multiple times.
+    ///
+    /// This is the same as an `--include` with a `!` in front, which shells may try to expand.
+    /// Without any include rules, every other repo is included.
+    #[clap(short = 'x', long)]
+    pub exclude: Vec<String>,
     /// Include any repos matching the given selectors from the given file.
+    ///
+    /// Rules in `{data_dir}/gourcers.rules` are always loaded first, if the file exists.
     #[clap(short = 'f', long)]
     pub include_file: Option<PathBuf>,
-    /// Extra arguments to pass to gource.
+    /// A gource custom log to merge into the sorted log, e.g. activity from outside of git. Can be
+    /// applied multiple times.
+    ///
+    /// Logs in `{data_dir}/gource/extra/` are always merged.
+    #[clap(long, value_name = "FILE")]
+    pub extra_log: Vec<PathBuf>,
+    /// Render this gource custom log instead of listing, cloning and combining any repos.
+    ///
+    /// Only the options for rendering are used, so gourcers works as a renderer for any gource
+    /// log. The log must already be sorted by timestamp.
+    #[clap(long, value_name = "FILE")]
+    pub from_log: Option<PathBuf>,
+    /// Only show files with these extensions, separated by commas, e.g. `rs,toml,md`.
+    #[clap(long, value_name = "EXTENSIONS", value_delimiter = ',')]
+    pub only_extensions: Vec<String>,
+    /// Hide files with these extensions, separated by commas, e.g. `lock,min.js,map`.
+    ///
+    /// Use this to keep generated files, lockfiles and bundles from dominating the tree.
+    #[clap(long, value_name = "EXTENSIONS", value_delimiter = ',')]
+    pub skip_extensions: Vec<String>,
+    /// Hide the files matched by built-in presets, separated by commas.
+    #[clap(long, value_name = "PRESETS", value_enum, value_delimiter = ',')]
+    pub filter_preset: Vec<FilterPreset>,
+    /// Ignore case in every rule, as if each selector was written with `/i`.
+    ///
+    /// Owners are always compared ignoring case.
+    #[clap(long)]
+    pub ci_rules: bool,
+    /// How include and exclude rules are combined.
+    ///
+    /// With `exclude-wins`, a repo is included if any include rule matches it and no exclude rule
+    /// does. With `first-match`, the first rule which matches a repo decides, and with `ordered`,
+    /// the last one does.
+    ///
+    /// Overrides the `# mode:` header of rule files. Defaults to `exclude-wins`.
+    #[clap(long, value_enum)]
+    pub rule_precedence: Option<Precedence>,
+    /// Extra arguments to pass to gource, split like a shell would.
+    ///
+    /// The resulting command will look like `gource -{resolution} {gource options} {gource_args}
+    /// {gource_args_extra} {data_dir}/sorted.txt`.
+    ///
+    /// Defaults to `gource_args` from the config file, or else `--hide root -a 1 -s 1 -c 4 --key
+    /// --multi-sampling`. Using `--hide root` is highly recommended.
+    #[clap(long, env = "GOURCERS_GOURCE_ARGS")]
+    pub gource_args: Option<String>,
+    /// Arguments to pass to gource after `--gource-args`, to add to the defaults instead of
+    /// replacing them.
+    #[clap(long)]
+    pub gource_args_extra: Option<String>,
+    /// Extra arguments to pass to ffmpeg when writing rendered videos, split like a shell would.
+    ///
+    /// They are placed just before the output file, e.g. `-metadata title="2024"`. Defaults to
+    /// `ffmpeg_args` from the config file.
+    #[clap(long, env = "GOURCERS_FFMPEG_ARGS")]
+    pub ffmpeg_args: Option<String>,
+    /// Arguments to pass to ffmpeg after `--ffmpeg-args`, to add to the defaults instead of
+    /// replacing them.
+    #[clap(long)]
+    pub ffmpeg_args_extra: Option<String>,
+    /// The resolution of the gource window and of rendered videos, unless a render profile sets
+    /// its own.
+    #[clap(long, default_value = "1920x1080")]
+    pub resolution: String,
+    /// Match the resolution, and the resolution of every render profile, to an aspect ratio.
+    ///
+    /// The shorter side of the resolution is kept, so `--aspect 9:16` turns 1920x1080 into
+    /// 1080x1920 for vertical clips. Rendered videos are scaled and padded to exactly the matched
+    /// resolution.
+    #[clap(long, value_enum)]
+    pub aspect: Option<Aspect>,
+    /// The framerate of rendered videos, unless a render profile sets its own.
+    ///
+    /// Gource only supports 25, 30 and 60 frames per second.
+    #[clap(long, default_value_t = 60)]
+    pub framerate: u32,
+    /// Path to a TOML config file.
     ///
-    /// The resulting command will look like `gource {gource_args} {data_dir}/sorted.txt`.
+    /// If the config file defines render profiles, each profile is rendered to a video file with
+    /// ffmpeg instead of opening gource in a window.
+    #[clap(short, long)]
+    pub config: Option<PathBuf>,
+    /// Render a video file with ffmpeg to this path, like a render profile with the global
+    /// settings, instead of opening gource in a window.
+    ///
+    /// The path can contain `{date}`, `{repos}` and `{rules}`, which are replaced with the date,
+    /// the number of repos and a digest of the rules, e.g. `gource-{date}-{repos}x.mp4`. The same
+    /// placeholders can be used in the `output` of render profiles.
+    #[clap(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+    /// Overwrite videos which already exist instead of asking first.
+    ///
+    /// Without this, rendering stops before anything is done if it would overwrite a video, unless
+    /// the overwrite is confirmed at a prompt. `update` and `watch` always replace their video.
+    #[clap(long, visible_alias = "overwrite")]
+    pub force: bool,
+    /// Render all profiles from the config file at the same time instead of one after another.
+    #[clap(long)]
+    pub parallel_renders: bool,
+    /// The format of rendered videos, unless a render profile sets its own.
+    ///
+    /// GIF and APNG are rendered at 15 frames per second, for embedding short clips in READMEs
+    /// and chat.
+    #[clap(long, value_enum, default_value_t)]
+    pub format: Format,
+    /// Vary the speed of rendered videos with the amount of activity.
+    ///
+    /// Busy periods are slowed down and periods without commits are skipped. Each period is
+    /// rendered separately and the pieces are joined with ffmpeg, so this only applies when
+    /// rendering to video files.
+    #[clap(long)]
+    pub auto_speed: bool,
+    /// Render each year, quarter or month of history to its own video and join them with ffmpeg.
+    ///
+    /// Chunks are independent of each other, so long histories can be rendered faster with
+    /// `--split-jobs`. This only applies when rendering to video files.
+    #[clap(long, value_enum, value_name = "PERIOD", conflicts_with = "auto_speed")]
+    pub split_by: Option<SplitBy>,
+    /// How many chunks to render at once with `--split-by` or `--checkpoint-every`.
+    #[clap(long, default_value_t = 1)]
+    pub split_jobs: usize,
+    /// Render each period of this much history (e.g. `90d`) to its own video and join them with
+    /// ffmpeg.
     ///
-    /// Using `--hide root` is highly recommended.
+    /// Chunks which were already rendered with the same settings are reused, so a render which
+    /// crashed or was interrupted picks up where it left off. `--split-by` and `--auto-speed` do
+    /// the same for their own chunks.
     #[clap(
         long,
-        default_value = "--hide root -a 1 -s 1 -c 4 --key --multi-sampling -1920x1080"
+        value_name = "INTERVAL",
+        conflicts_with_all = ["auto_speed", "split_by"]
     )]
-    pub gource_args: String,
+    pub checkpoint_every: Option<Interval>,
+    /// Add chapter markers to rendered videos at the start of every year or month, or where each
+    /// repo first appears.
+    ///
+    /// The chapters are timed with `--seconds-per-day` and `--auto-skip-seconds`, so they can't be
+    /// combined with `--auto-speed`. Only MP4 and `WebM` videos have chapters.
+    #[clap(
+        long,
+        value_enum,
+        value_name = "BOUNDARY",
+        conflicts_with = "auto_speed"
+    )]
+    pub chapters: Option<ChapterBy>,
+    /// Append a leaderboard of the top N contributors by commits, and the files they touched, to
+    /// the end of rendered videos.
+    ///
+    /// The leaderboard is drawn with ffmpeg's `drawtext` filter, which needs an ffmpeg built with
+    /// `libfreetype` and `fontconfig`.
+    #[clap(long, value_name = "N")]
+    pub outro_leaderboard: Option<usize>,
+    /// Cut the busiest part of rendered videos, with the most commits, to a vertical 9:16 clip of
+    /// this length, e.g. `60s`, titled with `--title`.
+    ///
+    /// The clip is written next to each video, with `.short` before the extension.
+    #[clap(long, value_name = "LENGTH", conflicts_with = "auto_speed")]
+    pub shorts_clip: Option<Interval>,
+    /// Extract a frame from the rendered video to the given image file.
+    ///
+    /// When rendering several profiles, the frame is taken from the first one.
+    #[clap(long, value_name = "IMAGE")]
+    pub thumbnail: Option<PathBuf>,
+    /// Where in the video to take the thumbnail from, as a percentage (`40%`) or in seconds
+    /// (`12.5`).
+    #[clap(long, default_value = "40%")]
+    pub thumbnail_at: FramePosition,
+    /// A comment to store in the metadata of rendered videos, along with the `--title`.
+    #[clap(long)]
+    pub comment: Option<String>,
+    /// Upload the rendered video once it is done and print its URL.
+    ///
+    /// Either `youtube`, which uploads an unlisted video, or `s3://bucket/key`. Credentials are
+    /// read from the environment; see the README for details.
+    #[clap(long, value_name = "DESTINATION")]
+    pub upload: Option<Destination>,
+    /// Render without a display by running gource through `xvfb-run` or SDL's offscreen driver.
+    #[clap(long)]
+    pub headless: bool,
+    /// Never prompt for input and print plain log lines instead of progress bars.
+    ///
+    /// This is implied when stdin is not a terminal. Prompts become errors explaining which
+    /// argument to pass instead.
+    #[clap(long)]
+    pub non_interactive: bool,
+    /// Print timestamped log lines instead of progress bars.
+    ///
+    /// This is implied when the `CI` environment variable is set to `true`.
+    #[clap(long)]
+    pub no_progress: bool,
+    /// Export tracing spans for each phase and repo to an OpenTelemetry collector.
+    ///
+    /// This is the base URL of the collector's OTLP/HTTP receiver, e.g. `http://localhost:4318`.
+    #[clap(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT", value_name = "URL")]
+    pub otel_endpoint: Option<String>,
+    /// Serve progress events as lines of JSON on a Unix socket at this path.
+    ///
+    /// Each event has a `type`: `step` when a step starts, `item` when work on a repo starts,
+    /// `progress` with the `position`, `length` and `percent` of the current step, `message`, and
+    /// finally `done` or `error`.
+    #[cfg(unix)]
+    #[clap(long, value_name = "PATH")]
+    pub progress_socket: Option<PathBuf>,
+    #[clap(flatten)]
+    pub network: NetworkOptions,
+    #[clap(flatten)]
+    pub logo: LogoOptions,
+    /// Color gource with a theme: `dark`, `light`, `solarized`, `dracula`, or one defined in the
+    /// config file.
+    ///
+    /// Colors given with other options, like `--background-color`, take precedence.
+    #[clap(long, value_name = "NAME")]
+    pub theme: Option<String>,
+    #[clap(flatten)]
+    pub gource_options: GourceOptions,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Render the repos selected by two rule files side by side.
+    ///
+    /// Each rule file gets its own sorted log and gource instance, and the two videos are placed
+    /// next to each other with ffmpeg. Use this for "team A vs team B" retrospectives.
+    Compare(CompareArgs),
+    /// Pull new commits and re-render only if there was new activity since the last run.
+    ///
+    /// Only new log entries are merged into the existing sorted log. This is meant to be run from
+    /// cron with a persistent data directory.
+    Update,
+    /// Run `update` repeatedly, waiting between runs.
+    Watch(WatchArgs),
+    /// Render like normal, timing every step, and print which steps and repos were slowest.
+    ///
+    /// Use this to tune `--jobs`, the update strategy and render presets.
+    Bench(BenchArgs),
+    /// Print the number of commits, date range and number of authors of each repo's log in the
+    /// data directory, without fetching or cloning anything.
+    Stats,
+    /// Open the sorted log from the last run in an interactive gource window, without fetching
+    /// anything or writing a video.
+    ///
+    /// Use this to tune the gource options before a long render. Options which make gource write
+    /// frames, such as `-o`, are left out.
+    Preview,
+    /// Check the external tools, the token, SSH access, ffmpeg's encoders, rendering without a
+    /// display and the data directory, and print whether each check passed.
+    Doctor,
 }
 
 #[derive(Debug)]
@@ -104,7 +604,12 @@ impl OutputDir {
 
     #[must_use]
     pub fn repo_dir(&self, repo: &Repo) -> PathBuf {
-        self.repos_dir().join(repo.full_name_path_friendly())
+        self.repo_dir_by_name(&repo.full_name())
+    }
+
+    #[must_use]
+    pub fn repo_dir_by_name(&self, full_name: &str) -> PathBuf {
+        self.repos_dir().join(github::path_friendly(full_name))
     }
 
     #[must_use]
@@ -112,236 +617,1960 @@ impl OutputDir {
         self.path().join("gource")
     }
 
+    /// Custom logs which are merged into the sorted log along with the logs of the repos.
+    #[must_use]
+    pub fn extra_logs_dir(&self) -> PathBuf {
+        self.gource_dir().join("extra")
+    }
+
     #[must_use]
     pub fn gource_log(&self, repo: &Repo) -> PathBuf {
+        self.gource_log_by_name(&repo.full_name())
+    }
+
+    #[must_use]
+    pub fn gource_log_by_name(&self, full_name: &str) -> PathBuf {
         self.gource_dir()
-            .join(format!("{}.txt", repo.full_name_path_friendly()))
+            .join(format!("{}.txt", github::path_friendly(full_name)))
+    }
+
+    #[must_use]
+    pub fn api_cache_dir(&self) -> PathBuf {
+        self.path().join("api-cache")
+    }
+
+    #[must_use]
+    pub fn metadata_dir(&self) -> PathBuf {
+        self.path().join("metadata")
+    }
+
+    #[must_use]
+    pub fn metadata_file(&self, repo: &Repo) -> PathBuf {
+        self.metadata_dir().join(format!("{}.json", repo.id))
     }
 
     #[must_use]
     pub fn sorted_log(&self) -> PathBuf {
         self.path().join("sorted.txt")
     }
+
+    /// The captions written with `--mark-releases`.
+    #[must_use]
+    pub fn release_captions(&self) -> PathBuf {
+        self.path().join("captions.txt")
+    }
+
+    /// The part of the sorted log rendered with `--focus`.
+    #[must_use]
+    pub fn focused_log(&self) -> PathBuf {
+        self.path().join("focused.txt")
+    }
+
+    #[must_use]
+    pub fn avatars_dir(&self) -> PathBuf {
+        self.path().join("avatars")
+    }
+
+    #[must_use]
+    pub fn logins(&self) -> PathBuf {
+        self.path().join("logins.json")
+    }
+
+    /// When each clone was last used, for `--max-cache-size`.
+    #[must_use]
+    pub fn usage(&self) -> PathBuf {
+        self.path().join("usage.json")
+    }
+
+    #[must_use]
+    pub fn sorted_log_state(&self) -> PathBuf {
+        self.path().join("sorted.json")
+    }
+
+    #[must_use]
+    pub fn segments_dir(&self, profile: &str) -> PathBuf {
+        self.path()
+            .join("segments")
+            .join(github::path_friendly(profile))
+    }
+
+    /// The title file of the clips cut with `--shorts-clip` for a render profile.
+    #[must_use]
+    pub fn shorts_dir(&self, profile: &str) -> PathBuf {
+        self.path()
+            .join("shorts")
+            .join(github::path_friendly(profile))
+    }
+
+    /// The leaderboard appended with `--outro-leaderboard`, and the video it was rendered to.
+    #[must_use]
+    pub fn outro_dir(&self, profile: &str) -> PathBuf {
+        self.path()
+            .join("outro")
+            .join(github::path_friendly(profile))
+    }
+
+    /// Rules which are loaded automatically if the file exists.
+    #[must_use]
+    pub fn rules(&self) -> PathBuf {
+        self.path().join("gourcers.rules")
+    }
+
+    #[must_use]
+    pub fn compare_dir(&self) -> PathBuf {
+        self.path().join("compare")
+    }
+
+    #[must_use]
+    pub fn compare_log(&self, side: &str) -> PathBuf {
+        self.compare_dir().join(format!("{side}.txt"))
+    }
+
+    #[must_use]
+    pub fn compare_video(&self, side: &str) -> PathBuf {
+        self.compare_dir().join(format!("{side}.mp4"))
+    }
 }
 
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Debug)]
 pub struct Context {
-    pub token: String,
+    pub token: Secret,
+    pub api_url: String,
     pub data_dir: OutputDir,
+    /// Held for as long as the data directory is in use.
+    pub data_dir_lock: DataDirLock,
+    pub max_cache_size: Option<ByteSize>,
+    pub evict_logs: bool,
     pub skip_clone: bool,
+    pub git_backend: GitBackend,
+    pub update_strategy: UpdateStrategy,
+    pub network: NetworkOptions,
+    pub clone_retries: u32,
+    pub keep_going: bool,
+    pub include_empty: bool,
+    pub no_merges: bool,
+    pub coalesce_window: Option<u64>,
+    pub history_since: Option<Since>,
+    pub use_github_logins: bool,
+    pub color_by_team: bool,
+    pub avatars: bool,
+    pub avatar_max_age: Interval,
+    pub include_wikis: bool,
+    pub include_gists: bool,
+    pub min_commits: Option<usize>,
+    pub top: Option<usize>,
+    pub top_by: TopBy,
+    pub jobs: usize,
+    pub sort_backend: SortBackendKind,
+    pub sanitize: Sanitize,
+    pub tree_layout: TreeLayout,
+    pub focus: Option<String>,
+    pub groups: Groups,
+    pub repos_json: Option<PathBuf>,
+    pub local_repos: Option<PathBuf>,
+    pub export_selection: Option<PathBuf>,
     pub includes: Option<RuleSet>,
+    pub extra_logs: Vec<PathBuf>,
+    pub from_log: Option<PathBuf>,
+    pub only_extensions: Vec<String>,
+    pub skip_extensions: Vec<String>,
+    pub filter_presets: Vec<FilterPreset>,
+    pub ci_rules: bool,
+    pub rule_precedence: Option<Precedence>,
+    pub gource_options: Vec<String>,
+    /// The gource caption file, which is also written as subtitles for rendered videos.
+    pub captions: Option<PathBuf>,
+    /// The caption file given with `--captions`, which the release captions are added to.
+    pub user_captions: Option<PathBuf>,
+    pub mark_releases: bool,
+    pub pull_requests: bool,
+    /// The title and comment stored in the metadata of rendered videos.
+    pub title: Option<String>,
+    pub comment: Option<String>,
+    pub logo: Option<Logo>,
     pub gource_args: Vec<String>,
+    pub gource_args_extra: Vec<String>,
+    pub ffmpeg_args: Vec<String>,
+    pub ffmpeg_args_extra: Vec<String>,
+    pub resolution: String,
+    pub aspect: Option<Aspect>,
+    pub framerate: u32,
+    pub config: Config,
+    pub parallel_renders: bool,
+    pub format: Format,
+    pub auto_speed: bool,
+    pub split_by: Option<SplitBy>,
+    pub chapters: Option<ChapterBy>,
+    pub outro_leaderboard: Option<usize>,
+    pub shorts_clip: Option<Interval>,
+    pub split_jobs: usize,
+    pub checkpoint_every: Option<Interval>,
+    pub thumbnail: Option<PathBuf>,
+    pub thumbnail_at: FramePosition,
+    pub upload: Option<Destination>,
+    pub headless: Option<Headless>,
+    pub progress_bars: bool,
+    /// Whether the user can be prompted.
+    pub interactive: bool,
+    pub force: bool,
+    pub keep_temp_on_failure: bool,
+    /// How long each step took, which `bench` reports.
+    pub timings: Timings,
 }
 
 impl Context {
-    pub fn from_cli(cli: Cli) -> Result<Self> {
-        let data_dir = cli.data_dir.map_or_else(
-            || -> Result<OutputDir> {
-                if !cli.temp {
-                    eprintln!("{}: {}", style("WARNING").red().bright().bold(), style("No --data-dir specified!").dim());
-                    eprintln!("{}: {}\n", style("WARNING").red().bright().bold(), style("A temporary data directory will be created and removed after finishing. You probably don't want this.").dim());
-
-                    let confirm = Confirm::with_theme(&ColorfulTheme::default())
-                        .with_prompt("Are you sure you want to use a temporary data directory?")
-                        .interact()
-                        .wrap_err("failed to prompt for temporary data directory")?;
-
-                    if !confirm {
-                        eprintln!(
-                            "{}",
-                            style("Refusing to use a temporary data directory.").red()
-                        );
-                        std::process::exit(1);
-                    }
-                }
-                let temp = TempDir::new()
-                    .wrap_err("failed to create a temporary directory")
-                    .suggestion("use -d to specify a data directory")?;
-                Ok(OutputDir::Temp(temp))
-            },
-            |dir| Ok(OutputDir::Specified(dir)),
-        )?;
+    /// The arguments to pass to gource after the resolution, for a render profile or for the
+    /// gource window.
+    #[must_use]
+    pub fn gource_args_for(&self, profile: Option<&RenderProfile>) -> Vec<String> {
+        let args = profile
+            .and_then(|profile| profile.gource_args.as_ref())
+            .unwrap_or(&self.gource_args);
 
-        data_dir.create()?;
+        let avatars = if self.avatars {
+            vec![
+                "--user-image-dir".to_string(),
+                self.data_dir.avatars_dir().display().to_string(),
+            ]
+        } else {
+            Vec::new()
+        };
 
-        let mut includes = None;
+        [
+            &avatars,
+            &self.gource_options,
+            args,
+            &self.gource_args_extra,
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+    }
 
-        if let Some(includes_file) = &cli.include_file {
-            let includes_str = std::fs::read_to_string(includes_file).wrap_err_with(|| {
-                format!("failed to read includes file {}", includes_file.display())
-            })?;
-            let includes_file = includes_str.parse::<RuleSet>().wrap_err_with(|| {
-                format!("failed to parse includes file {}", includes_file.display())
-            })?;
-            includes = Some(includes_file);
-        }
+    /// The arguments to pass to ffmpeg before the output file, for a render profile or for a video
+    /// which isn't rendered from a profile.
+    #[must_use]
+    pub fn ffmpeg_args_for(&self, profile: Option<&RenderProfile>) -> Vec<String> {
+        let args = profile
+            .and_then(|profile| profile.ffmpeg_args.as_ref())
+            .unwrap_or(&self.ffmpeg_args);
 
-        if !cli.include.is_empty() {
-            let includes_str = cli.include.join("\n");
-            let includes_file = includes_str
-                .parse::<RuleSet>()
-                .wrap_err("failed to parse command line includes")?;
-            if let Some(includes) = &mut includes {
-                includes.merge(includes_file);
-            } else {
-                includes = Some(includes_file);
-            }
-        }
+        [args, &self.ffmpeg_args_extra]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    }
 
-        let gource_args = cli
-            .gource_args
-            .split_whitespace()
-            .map(ToString::to_string)
-            .collect();
+    /// The `-metadata` arguments which tag rendered videos with their title and comment, when they
+    /// were rendered, and which version of gourcers rendered them from which rules.
+    #[must_use]
+    pub fn metadata_args(&self) -> Vec<String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
 
-        let cx = Context {
-            token: cli.token,
-            data_dir,
-            skip_clone: cli.skip_clone,
-            includes,
-            gource_args,
+        let version = env!("CARGO_PKG_VERSION");
+        let description = if self.from_log.is_some() {
+            format!("Rendered by gourcers {version}")
+        } else {
+            format!(
+                "Rendered by gourcers {version} from the repos matching {}",
+                self.rules_summary()
+            )
         };
 
-        Ok(cx)
-    }
-}
+        let mut tags = vec![
+            (
+                "creation_time",
+                ffmpeg::creation_time(i64::try_from(now).unwrap_or_default()),
+            ),
+            ("description", description),
+        ];
+        if let Some(title) = &self.title {
+            tags.push(("title", title.clone()));
+        }
+        if let Some(comment) = &self.comment {
+            tags.push(("comment", comment.clone()));
+        }
 
-const NUM_STEPS: usize = 5;
+        ffmpeg::metadata_args(&tags)
+    }
 
-macro_rules! status {
-    ($step_idx:literal, $icon:literal, $($args:tt)*) => {
-        eprintln!(
-            "{} {} {}",
-            ::console::style(
-                format!("[{}/{}]", $step_idx, NUM_STEPS)
-            ).bold().dim(),
-            ::emojis::get_by_shortcode($icon).unwrap(),
-            format!($($args)*)
-        )
-    };
-}
+    /// The rules which select the repos on one line, as [`RuleSet::summary`] writes them.
+    #[must_use]
+    pub fn rules_summary(&self) -> String {
+        self.includes
+            .as_ref()
+            .map_or_else(|| "*:*".to_string(), RuleSet::summary)
+    }
 
-fn main() -> Result<()> {
-    dotenvy::dotenv().ok();
+    /// The values of the placeholders in outputs, for `repos` repos.
+    #[must_use]
+    pub fn template_values(&self, repos: usize) -> template::Values {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
 
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::fmt::layer()
-                .with_file(true)
-                .with_line_number(true)
-                .with_target(false),
+        template::Values::new(
+            i64::try_from(now).unwrap_or_default(),
+            repos,
+            &self.rules_summary(),
         )
-        .with(tracing_subscriber::EnvFilter::from_default_env())
-        .with(tracing_error::ErrorLayer::default())
-        .init();
+    }
 
-    color_eyre::install()?;
+    /// The render profiles, with the placeholders in their outputs replaced for `repos` repos.
+    pub fn expanded_profiles(&self, repos: usize) -> Result<Vec<RenderProfile>> {
+        let values = self.template_values(repos);
 
-    let cli = Cli::parse();
-    trace!("parsed args: {cli:?}");
+        self.config
+            .profiles
+            .iter()
+            .map(|profile| {
+                Ok(RenderProfile {
+                    output: template::expand(&profile.output, &values)?,
+                    ..profile.clone()
+                })
+            })
+            .collect()
+    }
 
-    let cx = Context::from_cli(cli)?;
-    trace!("context: {cx:?}");
+    /// The source of the repos to choose from. `progress` shows the progress of listing them.
+    #[must_use]
+    pub fn repo_source<'a>(&'a self, progress: &'a Progress) -> Box<dyn RepoSource + 'a> {
+        if let Some(path) = &self.repos_json {
+            Box::new(source::RepoList { path: path.clone() })
+        } else if let Some(dir) = &self.local_repos {
+            Box::new(source::LocalDirs { dir: dir.clone() })
+        } else {
+            Box::new(source::GitHub { cx: self, progress })
+        }
+    }
 
-    let determinate_style = ProgressStyle::with_template(
-        "{elapsed:.magenta.bold} {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
-    )
-    .wrap_err("failed to create progress style")
-    .unwrap()
-    .progress_chars("▓▒░");
+    /// The background color of the video for a render profile or for the gource window, as a hex
+    /// code. This is the last one set by the gource arguments, or else gource's default.
+    #[must_use]
+    pub fn background_for(&self, profile: Option<&RenderProfile>) -> String {
+        self.gource_args_for(profile)
+            .windows(2)
+            .rev()
+            .find(|pair| matches!(pair[0].as_str(), "-b" | "--background-colour"))
+            .map_or_else(
+                || gource::DEFAULT_BACKGROUND.to_string(),
+                |pair| pair[1].clone(),
+            )
+    }
 
-    let indeterminate_style = ProgressStyle::default_spinner()
-        .template("{elapsed:.magenta.bold} {spinner:.green} {msg}")
-        .wrap_err("failed to create progress style")
-        .unwrap();
+    /// The arguments to pass to ffmpeg before the output file of a finished video, which are the
+    /// metadata followed by the ones from [`Context::ffmpeg_args_for`], so those can override it.
+    #[must_use]
+    pub fn ffmpeg_output_args_for(&self, profile: Option<&RenderProfile>) -> Vec<String> {
+        [self.metadata_args(), self.ffmpeg_args_for(profile)].concat()
+    }
 
-    status!(1, "mag", "Fetching repos from GitHub API");
+    /// The sorted log of every repo, which is the one in the data directory unless `--from-log`
+    /// is given.
+    #[must_use]
+    pub fn sorted_log(&self) -> PathBuf {
+        self.from_log
+            .clone()
+            .unwrap_or_else(|| self.data_dir.sorted_log())
+    }
 
-    let fetch_progress = ProgressBar::new(1);
-    fetch_progress.set_style(indeterminate_style.clone());
-    fetch_progress.enable_steady_tick(Duration::from_millis(200));
+    /// The log to render, which is only the focused part of the sorted log with `--focus`.
+    #[must_use]
+    pub fn render_log(&self) -> PathBuf {
+        if self.focus.is_some() {
+            self.data_dir.focused_log()
+        } else {
+            self.sorted_log()
+        }
+    }
 
-    let mut repos = github::list_repos(&cx, &fetch_progress).wrap_err("failed to list repos")?;
-    let initial_len = repos.len();
-    trace!("fetched {} repos: {repos:?}", initial_len);
+    /// Which files to keep in the log of a repo, from the overrides for the repo or the options.
+    #[must_use]
+    pub fn path_filter_for(&self, repo: &Repo) -> PathFilter {
+        let overrides = self.config.overrides_for(&repo.full_name());
 
-    if let Some(includes) = &cx.includes {
-        includes.apply(&mut repos);
+        PathFilter::new(
+            overrides
+                .and_then(|overrides| overrides.only_extensions.as_deref())
+                .unwrap_or(&self.only_extensions),
+            overrides
+                .and_then(|overrides| overrides.skip_extensions.as_deref())
+                .unwrap_or(&self.skip_extensions),
+            overrides
+                .and_then(|overrides| overrides.filter_presets.as_deref())
+                .unwrap_or(&self.filter_presets),
+        )
     }
 
-    trace!("filtered to {} repos: {repos:?}", repos.len());
-    debug!("filtering removed {} repos", initial_len - repos.len());
+    pub fn from_cli(cli: Cli) -> Result<Self> {
+        let interactive = !cli.non_interactive && std::io::stdin().is_terminal();
 
-    fetch_progress.finish();
+        let data_dir = choose_data_dir(cli.data_dir.clone(), cli.temp, interactive)?;
+        let data_dir_lock = open_data_dir(&data_dir, cli.wait_for_lock)?;
 
-    status!(
-        2,
-        "arrow_double_down",
-        "Cloning and/or pulling repos{}",
-        if cx.skip_clone { " (skipped)" } else { "" }
-    );
+        let includes = read_includes(&cli, &data_dir)?;
+        let groups = read_groups(cli.groups.as_deref())?;
 
-    if !cx.skip_clone {
-        let clone_progress = ProgressBar::new(repos.len() as u64);
-        clone_progress.set_style(determinate_style.clone());
+        let config = load_config(&cli)?;
 
-        debug!("cloning/pulling {} repos", repos.len());
+        let headless = cli.headless.then(Headless::detect).transpose().suggestion(
+            "install xvfb (which provides `xvfb-run`) or an EGL implementation such as mesa",
+        )?;
 
-        for repo in &repos {
-            clone_progress.set_message(repo.full_name());
-            github::fetch_repo(&cx, repo)
-                .wrap_err_with(|| format!("failed to fetch repo {}", repo.full_name()))?;
-            clone_progress.inc(1);
-        }
+        let (gource_args, ffmpeg_args) = base_args(&cli, &config)?;
 
-        clone_progress.finish();
-    }
+        let resolution = fit_resolution(&cli)?;
+        validate_framerate(cli.framerate)?;
+        validate_profiles(&config)?;
 
-    status!(3, "factory", "Generating gource logs");
+        let api_url = validate_api_url(&cli.api_url)?;
 
-    let gource_progress = ProgressBar::new(repos.len() as u64);
-    gource_progress.set_style(determinate_style.clone());
+        let (gource_options, captions) = gource_options(&cli, &config, &data_dir)?;
 
-    if !cx.data_dir.gource_dir().exists() {
-        trace!(
-            "creating gource log directory: {}",
-            cx.data_dir.gource_dir().display()
-        );
-        std::fs::create_dir(cx.data_dir.gource_dir())
-            .wrap_err("failed to create gource log directory")?;
+        let cx = Context {
+            token: cli.token.unwrap_or_default(),
+            api_url,
+            data_dir,
+            data_dir_lock,
+            max_cache_size: cli.max_cache_size,
+            evict_logs: cli.evict_logs,
+            skip_clone: cli.skip_clone,
+            git_backend: cli.git_backend,
+            update_strategy: cli.update_strategy,
+            network: cli.network,
+            clone_retries: cli.clone_retries,
+            keep_going: cli.keep_going,
+            include_empty: cli.include_empty,
+            no_merges: cli.no_merges,
+            coalesce_window: cli.coalesce_window,
+            history_since: cli.history_since,
+            use_github_logins: cli.use_github_logins,
+            color_by_team: cli.color_by_team,
+            avatars: cli.avatars,
+            avatar_max_age: cli.avatar_max_age,
+            include_wikis: cli.include_wikis,
+            include_gists: cli.include_gists,
+            min_commits: cli.min_commits,
+            top: cli.top,
+            top_by: cli.by,
+            jobs: cli.jobs.unwrap_or_else(default_jobs),
+            sort_backend: cli.sort_backend,
+            sanitize: cli.sanitize,
+            tree_layout: cli.tree_layout,
+            focus: cli.focus,
+            groups,
+            repos_json: cli.repos_json,
+            local_repos: cli.local_repos,
+            export_selection: cli.export_selection,
+            includes,
+            extra_logs: cli.extra_log,
+            from_log: cli.from_log,
+            only_extensions: cli.only_extensions,
+            skip_extensions: cli.skip_extensions,
+            filter_presets: cli.filter_preset,
+            ci_rules: cli.ci_rules,
+            rule_precedence: cli.rule_precedence,
+            gource_options,
+            captions,
+            user_captions: cli.gource_options.captions.clone(),
+            mark_releases: cli.mark_releases,
+            pull_requests: cli.pull_requests,
+            title: cli.gource_options.title.clone(),
+            comment: cli.comment,
+            logo: cli.logo.load()?,
+            gource_args,
+            gource_args_extra: split_args(
+                "--gource-args-extra",
+                cli.gource_args_extra.as_deref().unwrap_or_default(),
+            )?,
+            ffmpeg_args,
+            ffmpeg_args_extra: split_args(
+                "--ffmpeg-args-extra",
+                cli.ffmpeg_args_extra.as_deref().unwrap_or_default(),
+            )?,
+            resolution,
+            aspect: cli.aspect,
+            framerate: cli.framerate,
+            config,
+            parallel_renders: cli.parallel_renders,
+            format: cli.format,
+            auto_speed: cli.auto_speed,
+            split_by: cli.split_by,
+            chapters: cli.chapters,
+            outro_leaderboard: cli.outro_leaderboard,
+            shorts_clip: cli.shorts_clip,
+            split_jobs: cli.split_jobs,
+            checkpoint_every: cli.checkpoint_every,
+            thumbnail: cli.thumbnail,
+            thumbnail_at: cli.thumbnail_at,
+            upload: cli.upload,
+            headless,
+            progress_bars: interactive && !cli.no_progress && !is_ci(),
+            interactive,
+            force: cli.force,
+            keep_temp_on_failure: cli.keep_temp_on_failure,
+            timings: Timings::default(),
+        };
+
+        Ok(cx)
     }
+}
 
-    debug!("generating gource logs for {} repos", repos.len());
-    for repo in &repos {
-        gource_progress.set_message(repo.full_name());
-        gource::generate_gource_log(&cx, repo)
-            .wrap_err_with(|| format!("failed to generate gource log for {}", repo.full_name()))?;
-        gource_progress.inc(1);
+/// The gource arguments for the theme and the gource options, and the caption file gource reads.
+/// With `--mark-releases`, gource reads the release captions, which include the ones given with
+/// `--captions`.
+fn gource_options(
+    cli: &Cli,
+    config: &Config,
+    data_dir: &OutputDir,
+) -> Result<(Vec<String>, Option<PathBuf>)> {
+    let mut options = cli.gource_options.clone();
+    if cli.mark_releases {
+        options.captions = Some(data_dir.release_captions());
     }
 
-    gource_progress.finish();
+    Ok((
+        [theme_args(cli.theme.as_deref(), config)?, options.args()].concat(),
+        options.captions,
+    ))
+}
 
-    status!(4, "construction", "Combining and sorting logs");
+/// The gource arguments for `--theme`, which come before the other options so those take
+/// precedence.
+fn theme_args(name: Option<&str>, config: &Config) -> Result<Vec<String>> {
+    let Some(name) = name else {
+        return Ok(Vec::new());
+    };
 
-    // this step is too fast for a progress bar
-    debug!("combining and sorting logs");
-    gource::combine_and_sort_logs(&cx, &repos).wrap_err("failed to combine and sort logs")?;
+    let theme = Theme::find(name, config).ok_or_else(|| eyre!("unknown theme: {name}"));
+    let themes = theme::BUILTIN
+        .iter()
+        .copied()
+        .chain(config.themes.keys().map(String::as_str))
+        .collect::<Vec<_>>();
 
-    status!(5, "rocket", "Running gource");
+    Ok(theme
+        .suggestion(format!("the available themes are {}", themes.join(", ")))?
+        .args())
+}
 
-    let gource_progress = ProgressBar::new(1);
-    gource_progress.set_style(indeterminate_style.clone());
-    gource_progress.enable_steady_tick(Duration::from_millis(200));
+/// The number of jobs to run at once when `--jobs` isn't given, which is the number of CPUs.
+fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+}
 
-    debug!("running gource");
-    gource::generate_gource_video(&cx).wrap_err("failed to run gource")?;
+/// The resolution, matched to the aspect ratio given with `--aspect`.
+fn fit_resolution(cli: &Cli) -> Result<String> {
+    validate_resolution(&cli.resolution)?;
 
-    gource_progress.finish();
+    Ok(cli
+        .aspect
+        .and_then(|aspect| aspect.fit(&cli.resolution))
+        .unwrap_or_else(|| cli.resolution.clone()))
+}
 
-    eprintln!(
-        "      {} Done!",
-        ::emojis::get_by_shortcode("tada").unwrap()
-    );
+fn validate_resolution(resolution: &str) -> Result<()> {
+    if !regex!(r"^\d+x\d+$").is_match(resolution) {
+        return Err(eyre!("invalid resolution: {resolution}"))
+            .suggestion("resolutions look like 1920x1080");
+    }
 
     Ok(())
 }
+
+/// The gource and ffmpeg arguments from the options, or else from the config file.
+fn base_args(cli: &Cli, config: &Config) -> Result<(Vec<String>, Vec<String>)> {
+    let gource_args = match cli.gource_args.as_deref() {
+        Some(args) => split_args("--gource-args", args)?,
+        None => config
+            .gource_args
+            .clone()
+            .map_or_else(|| split_args("--gource-args", gource::DEFAULT_ARGS), Ok)?,
+    };
+    let ffmpeg_args = match cli.ffmpeg_args.as_deref() {
+        Some(args) => split_args("--ffmpeg-args", args)?,
+        None => config.ffmpeg_args.clone().unwrap_or_default(),
+    };
+
+    Ok((gource_args, ffmpeg_args))
+}
+
+/// The API URL without a trailing slash, if it is valid.
+fn validate_api_url(api_url: &str) -> Result<String> {
+    let api_url = api_url.trim_end_matches('/').to_string();
+    reqwest::Url::parse(&api_url)
+        .wrap_err_with(|| format!("invalid API URL: {api_url}"))
+        .suggestion("the API URL should look like https://github.example.com/api/v3")?;
+
+    Ok(api_url)
+}
+
+fn validate_framerate(framerate: u32) -> Result<()> {
+    if !gource::FRAMERATES.contains(&framerate) {
+        return Err(eyre!("unsupported framerate: {framerate}"))
+            .suggestion("gource only supports 25, 30 and 60 frames per second");
+    }
+
+    Ok(())
+}
+
+/// Load the config file, if one was given, with the profile for `--output`.
+fn load_config(cli: &Cli) -> Result<Config> {
+    let mut config = cli
+        .config
+        .as_deref()
+        .map(Config::load)
+        .transpose()?
+        .unwrap_or_default();
+    add_output_profile(&mut config, cli.output.clone())?;
+
+    if let Some(aspect) = cli.aspect {
+        for profile in &mut config.profiles {
+            // invalid resolutions are left for validate_profiles to report
+            if let Some(resolution) = profile
+                .resolution
+                .as_deref()
+                .and_then(|resolution| aspect.fit(resolution))
+            {
+                profile.resolution = Some(resolution);
+            }
+        }
+    }
+
+    Ok(config)
+}
+
+/// Render the video given with `--output` like a profile which only sets the output file.
+fn add_output_profile(config: &mut Config, output: Option<PathBuf>) -> Result<()> {
+    let Some(output) = output else {
+        return Ok(());
+    };
+
+    if !config.profiles.is_empty() {
+        return Err(eyre!("--output can't be used with render profiles"))
+            .suggestion("set the output of each profile in the config file instead");
+    }
+
+    config.profiles.push(RenderProfile {
+        name: "output".to_string(),
+        resolution: None,
+        framerate: None,
+        gource_args: None,
+        ffmpeg_preset: None,
+        ffmpeg_args: None,
+        format: None,
+        output,
+    });
+
+    Ok(())
+}
+
+fn validate_profiles(config: &Config) -> Result<()> {
+    let values = template::Values::new(0, 0, "");
+
+    for profile in &config.profiles {
+        template::expand(&profile.output, &values)
+            .wrap_err_with(|| format!("invalid output for render profile {}", profile.name))?;
+        if let Some(resolution) = &profile.resolution {
+            validate_resolution(resolution)
+                .wrap_err_with(|| format!("invalid render profile {}", profile.name))?;
+        }
+        if let Some(framerate) = profile.framerate {
+            validate_framerate(framerate)
+                .wrap_err_with(|| format!("invalid render profile {}", profile.name))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Split the value of an argument list option like a shell would.
+fn split_args(option: &str, args: &str) -> Result<Vec<String>> {
+    shell_words::split(args)
+        .wrap_err_with(|| format!("failed to parse {option}"))
+        .suggestion(format!("check that every quote in {option} is closed"))
+}
+
+/// The data directory given with `--data-dir`, or else a temporary one if the user allows it.
+fn choose_data_dir(data_dir: Option<PathBuf>, temp: bool, interactive: bool) -> Result<OutputDir> {
+    data_dir.map_or_else(
+        || -> Result<OutputDir> {
+            if !temp && !interactive {
+                return Err(eyre!("no --data-dir specified"))
+                    .suggestion("use -d to specify a data directory")
+                    .suggestion("use -y to allow a temporary data directory");
+            }
+
+            if !temp {
+                eprintln!("{}: {}", style("WARNING").red().bright().bold(), style("No --data-dir specified!").dim());
+                eprintln!("{}: {}\n", style("WARNING").red().bright().bold(), style("A temporary data directory will be created and removed after finishing. You probably don't want this.").dim());
+
+                let confirm = Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Are you sure you want to use a temporary data directory?")
+                    .interact()
+                    .wrap_err("failed to prompt for temporary data directory")?;
+
+                if !confirm {
+                    eprintln!(
+                        "{}",
+                        style("Refusing to use a temporary data directory.").red()
+                    );
+                    std::process::exit(1);
+                }
+            }
+            let temp = TempDir::new()
+                .wrap_err("failed to create a temporary directory")
+                .suggestion("use -d to specify a data directory")?;
+            Ok(OutputDir::Temp(temp))
+        },
+        |dir| Ok(OutputDir::Specified(dir)),
+    )
+}
+
+/// Create, lock and migrate the data directory.
+fn open_data_dir(data_dir: &OutputDir, wait_for_lock: bool) -> Result<DataDirLock> {
+    data_dir.create()?;
+
+    let lock = DataDirLock::acquire(data_dir.path(), wait_for_lock, |pid| {
+        eprintln!(
+            "{}: waiting for another gourcers process{} to finish using {}",
+            style("INFO").cyan().bold(),
+            pid.map(|pid| format!(" (PID {pid})")).unwrap_or_default(),
+            data_dir.path().display()
+        );
+    })
+    .wrap_err("failed to lock the data directory")
+    .suggestion("pass --wait-for-lock to wait for the other process to finish")?;
+
+    layout::check(data_dir.path())
+        .wrap_err("failed to check the data directory")
+        .suggestion("upgrade gourcers, or delete the data directory and run again")?;
+
+    Ok(lock)
+}
+
+/// Combine the rules from `{data_dir}/gourcers.rules`, `--include-file` and `--include`, in that
+/// order, if there are any.
+fn read_includes(cli: &Cli, data_dir: &OutputDir) -> Result<Option<RuleSet>> {
+    let ignore_case = cli.ci_rules;
+    let precedence = cli.rule_precedence;
+    let mut includes: Option<RuleSet> = None;
+
+    let default_rules = data_dir.rules();
+    let default_rules = default_rules.exists().then_some(default_rules);
+
+    for file in default_rules.iter().chain(&cli.include_file) {
+        debug!("reading rules from {}", file.display());
+        let rules = read_rules(file, ignore_case, precedence)?;
+        if let Some(includes) = &mut includes {
+            includes.merge(rules);
+        } else {
+            includes = Some(rules);
+        }
+    }
+
+    let mut excludes = cli
+        .exclude
+        .iter()
+        .map(|selector| format!("!{}", selector.trim_start_matches('!')))
+        .collect::<Vec<_>>();
+    if !excludes.is_empty() && includes.is_none() && cli.include.is_empty() {
+        // like without any rules, everything which isn't excluded is included
+        excludes.insert(0, "*:*".into());
+    }
+
+    for (origin, lines) in [("--include", &cli.include), ("--exclude", &excludes)] {
+        if lines.is_empty() {
+            continue;
+        }
+
+        let mut rules =
+            RuleSet::parse_with_origin(&lines.join("\n"), origin).map_err(|source| {
+                GourcersError::RuleParse {
+                    origin: "the command line".into(),
+                    source,
+                }
+            })?;
+        if ignore_case {
+            rules.ignore_case();
+        }
+        if let Some(includes) = &mut includes {
+            includes.merge(rules);
+        } else {
+            if let Some(precedence) = precedence {
+                rules.set_precedence(precedence);
+            }
+            includes = Some(rules);
+        }
+    }
+
+    Ok(includes)
+}
+
+/// The groups from `--groups`, which are empty if it isn't given.
+fn read_groups(path: Option<&Path>) -> Result<Groups> {
+    let Some(path) = path else {
+        return Ok(Groups::default());
+    };
+
+    let groups = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("failed to read groups file {}", path.display()))?;
+    let groups = Groups::parse(&groups).map_err(|source| GourcersError::GroupsParse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+
+    Ok(groups)
+}
+
+/// Read a rules file. `precedence` overrides the mode set in the file, if any.
+fn read_rules(path: &Path, ignore_case: bool, precedence: Option<Precedence>) -> Result<RuleSet> {
+    let rules = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("failed to read includes file {}", path.display()))?;
+
+    let origin = path.display().to_string();
+    let mut rules = RuleSet::parse_with_origin(&rules, &origin)
+        .map_err(|source| GourcersError::RuleParse { origin, source })?;
+
+    if ignore_case {
+        rules.ignore_case();
+    }
+    if let Some(precedence) = precedence {
+        rules.set_precedence(precedence);
+    }
+
+    Ok(rules)
+}
+
+fn is_ci() -> bool {
+    std::env::var("CI").is_ok_and(|ci| ci == "true" || ci == "1")
+}
+
+const NUM_STEPS: usize = 5;
+
+macro_rules! status {
+    ($cx:expr, $step_idx:literal, $icon:literal, $($args:tt)*) => {{
+        let name = format!($($args)*);
+        #[cfg(unix)]
+        $crate::progress_socket::emit(|| $crate::progress_socket::Event::Step {
+            step: $step_idx,
+            steps: NUM_STEPS,
+            name: name.clone(),
+        });
+        eprintln!(
+            "{}{} {} {}",
+            if $cx.progress_bars {
+                String::new()
+            } else {
+                format!("{} ", $crate::progress::timestamp())
+            },
+            ::console::style(
+                format!("[{}/{}]", $step_idx, NUM_STEPS)
+            ).bold().dim(),
+            ::emojis::get_by_shortcode($icon).unwrap(),
+            name
+        )
+    }};
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenvy::dotenv().ok();
+
+    let cli = Cli::parse();
+
+    let (otel_layer, exporter) = cli
+        .otel_endpoint
+        .as_deref()
+        .map(|endpoint| otel::new(endpoint, &cli.network))
+        .transpose()
+        .wrap_err("failed to set up span export")?
+        .map_or((None, None), |(layer, exporter)| {
+            (Some(layer), Some(exporter))
+        });
+
+    // the environment filter only applies to the terminal, so spans are always exported
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_file(true)
+                .with_line_number(true)
+                .with_target(false)
+                .with_filter(tracing_subscriber::EnvFilter::from_default_env()),
+        )
+        .with(
+            tracing_error::ErrorLayer::default()
+                .with_filter(tracing_subscriber::EnvFilter::from_default_env()),
+        )
+        .with(otel_layer.map(|layer| {
+            layer.with_filter(
+                tracing_subscriber::filter::Targets::new()
+                    .with_target(env!("CARGO_CRATE_NAME"), tracing::Level::INFO),
+            )
+        }))
+        .init();
+
+    color_eyre::install()?;
+
+    progress::start_clock();
+
+    trace!("parsed args: {cli:?}");
+
+    let token = cli.token.clone().unwrap_or_default();
+
+    #[cfg(unix)]
+    if let Some(path) = &cli.progress_socket {
+        progress_socket::serve(path)
+            .wrap_err_with(|| format!("failed to serve progress events on {}", path.display()))?;
+    }
+
+    let exporter = exporter.map(Arc::new);
+    let export_task = exporter.as_ref().map(otel::Exporter::spawn);
+
+    let result = run(cli).instrument(info_span!("run")).await;
+
+    if let Some(task) = export_task {
+        task.abort();
+    }
+
+    #[cfg(unix)]
+    {
+        progress_socket::emit(|| match &result {
+            Ok(()) => progress_socket::Event::Done,
+            Err(report) => progress_socket::Event::Error {
+                message: token.redact(&format!("{report:#}")),
+            },
+        });
+        progress_socket::close();
+    }
+
+    if let Err(report) = result {
+        // the token should never end up in an error, but if a subprocess or library echoes it
+        // back, make sure it isn't printed
+        let text = format!("{report:?}");
+        let redacted = token.redact(&text);
+        if redacted != text || exporter.is_some() {
+            // reports keep the spans they were created in open, so the report has to be dropped
+            // before those spans can be exported
+            drop(report);
+            if let Some(exporter) = &exporter {
+                exporter.export_or_warn().await;
+            }
+            eprintln!("Error: {redacted}");
+            std::process::exit(1);
+        }
+        return Err(report);
+    }
+
+    if let Some(exporter) = &exporter {
+        exporter.export_or_warn().await;
+    }
+
+    Ok(())
+}
+
+async fn run(mut cli: Cli) -> Result<()> {
+    let command = cli.command.take();
+
+    if cli.from_log.is_some()
+        && command
+            .as_ref()
+            .is_some_and(|c| !matches!(c, Command::Preview))
+    {
+        return Err(eyre!(
+            "--from-log can only be used without a subcommand or with `preview`"
+        ));
+    }
+
+    let cx = Context::from_cli(cli)?;
+    trace!("context: {cx:?}");
+
+    if let Err(report) = run_command(&cx, command).await {
+        if cx.keep_temp_on_failure {
+            keep_temp_dir(cx);
+        }
+        return Err(report);
+    }
+
+    if cx.progress_bars {
+        eprintln!(
+            "      {} Done!",
+            ::emojis::get_by_shortcode("tada").unwrap()
+        );
+    } else {
+        eprintln!("{} Done!", progress::timestamp());
+    }
+
+    Ok(())
+}
+
+/// Run the subcommand, or render if there isn't one, and publish the video.
+async fn run_command(cx: &Context, command: Option<Command>) -> Result<()> {
+    let video = match command {
+        Some(Command::Compare(args)) => compare(cx, &args).await?,
+        Some(Command::Update) => update(cx).await?,
+        Some(Command::Watch(args)) => watch(cx, &args).await,
+        Some(Command::Bench(args)) => bench(cx, &args).await?,
+        Some(Command::Stats) => {
+            stats(cx)?;
+            None
+        }
+        Some(Command::Preview) => {
+            preview(cx)?;
+            None
+        }
+        Some(Command::Doctor) => {
+            doctor(cx).await?;
+            None
+        }
+        None => render(cx).await?,
+    };
+
+    publish(cx, video.as_deref()).await
+}
+
+/// Run every check and print whether it passed, failing if any check failed.
+async fn doctor(cx: &Context) -> Result<()> {
+    let checks = doctor::run(cx).await;
+
+    for check in &checks {
+        let mark = match check.status {
+            doctor::Status::Pass => style("✔").green(),
+            doctor::Status::Warn => style("!").yellow(),
+            doctor::Status::Fail => style("✘").red(),
+        };
+        println!(
+            "{} {}: {}",
+            mark.bold(),
+            style(&check.name).bold(),
+            check.detail
+        );
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == doctor::Status::Fail)
+        .count();
+    if failed > 0 {
+        return Err(eyre!("{failed} of {} checks failed", checks.len()));
+    }
+
+    Ok(())
+}
+
+/// Keep the temporary data directory of a failed run instead of removing it.
+fn keep_temp_dir(cx: Context) {
+    if let OutputDir::Temp(temp) = cx.data_dir {
+        eprintln!(
+            "{}: the temporary data directory was kept at {}, pass it to -d to resume",
+            style("INFO").cyan().bold(),
+            temp.path().display()
+        );
+        temp.leak();
+    }
+}
+
+/// Extract a thumbnail from and upload the rendered video, if requested.
+async fn publish(cx: &Context, video: Option<&Path>) -> Result<()> {
+    if let Some(thumbnail) = &cx.thumbnail {
+        if let Some(video) = &video {
+            debug!("extracting thumbnail from {}", video.display());
+            ffmpeg::extract_frame(video, cx.thumbnail_at, thumbnail)
+                .wrap_err("failed to extract thumbnail")?;
+        } else {
+            warn!("--thumbnail only applies when rendering to a video file");
+        }
+    }
+
+    if let Some(destination) = &cx.upload {
+        if let Some(video) = &video {
+            let upload_progress = Progress::spinner(!cx.progress_bars);
+            upload_progress.set_message(format!("Uploading to {destination}"));
+
+            let url = publish::publish(destination, video, &cx.network)
+                .await
+                .wrap_err_with(|| format!("failed to upload to {destination}"))?;
+
+            upload_progress.finish_and_clear();
+            println!("{url}");
+        } else {
+            warn!("--upload only applies when rendering to a video file");
+        }
+    }
+
+    Ok(())
+}
+
+async fn watch(cx: &Context, args: &WatchArgs) -> ! {
+    loop {
+        let res = match update(cx).await {
+            Ok(video) => publish(cx, video.as_deref()).await,
+            Err(e) => Err(e),
+        };
+
+        // keep watching after failures, which are often temporary network problems
+        if let Err(report) = res {
+            eprintln!(
+                "{}: update failed: {}",
+                style("ERROR").red().bold(),
+                cx.token.redact(&format!("{report:?}"))
+            );
+        }
+
+        eprintln!(
+            "{} Waiting {} until the next update",
+            progress::timestamp(),
+            args.interval
+        );
+        tokio::time::sleep(args.interval.0).await;
+    }
+}
+
+/// Append new log entries to the sorted log and re-render if there were any.
+async fn update(cx: &Context) -> Result<Option<PathBuf>> {
+    let repos = prepare(cx, |_| true).await?;
+
+    status!(cx, 4, "construction", "Merging new log entries");
+
+    let added = sorted_log::update(cx, &repos).wrap_err("failed to update the sorted log")?;
+    debug!("added {added} log entries");
+
+    if added == 0 {
+        status!(cx, 5, "zzz", "No new activity, skipping render");
+        return Ok(None);
+    }
+
+    if cx.avatars {
+        avatars::prepare(cx)
+            .await
+            .wrap_err("failed to prepare avatars")?;
+    }
+
+    render_sorted(cx)
+}
+
+/// Bring the sorted log up to date with every repo and render it.
+async fn render(cx: &Context) -> Result<Option<PathBuf>> {
+    if let Some(log) = &cx.from_log {
+        if !log.is_file() {
+            return Err(eyre!("--from-log {} doesn't exist", log.display()));
+        }
+    }
+
+    // outputs which don't depend on the repos are checked before anything is done
+    check_overwrite(cx, &profile_outputs(cx, false)?)?;
+
+    if cx.from_log.is_none() {
+        let repos = prepare(cx, |_| true).await?;
+        combine_and_sort(cx, &repos)?;
+    }
+
+    check_overwrite(cx, &profile_outputs(cx, true)?)?;
+
+    if cx.avatars {
+        avatars::prepare(cx)
+            .await
+            .wrap_err("failed to prepare avatars")?;
+    }
+
+    let started = Instant::now();
+    let video = info_span!("render").in_scope(|| render_sorted(cx))?;
+    cx.timings.phase(Phase::Render, started.elapsed());
+
+    Ok(video)
+}
+
+/// Bring the sorted log up to date with every repo.
+fn combine_and_sort(cx: &Context, repos: &[Repo]) -> Result<()> {
+    status!(cx, 4, "construction", "Combining and sorting logs");
+
+    let started = Instant::now();
+
+    // this step is too fast for a progress bar
+    debug!("combining and sorting logs");
+    info_span!("sort")
+        .in_scope(|| sorted_log::update(cx, repos))
+        .wrap_err("failed to combine and sort logs")?;
+
+    cx.timings.phase(Phase::Sort, started.elapsed());
+
+    Ok(())
+}
+
+/// Render, or only prepare and sort the logs, and print how long each step took.
+async fn bench(cx: &Context, args: &BenchArgs) -> Result<Option<PathBuf>> {
+    let video = if args.skip_render {
+        let repos = prepare(cx, |_| true).await?;
+        combine_and_sort(cx, &repos)?;
+        None
+    } else {
+        render(cx).await?
+    };
+
+    println!("{}", cx.timings.report(args.slowest));
+
+    Ok(video)
+}
+
+/// Print the stats of every log in the data directory.
+fn stats(cx: &Context) -> Result<()> {
+    let gource_dir = cx.data_dir.gource_dir();
+    if !gource_dir.exists() {
+        return Err(eyre!(
+            "there are no logs in {}",
+            cx.data_dir.path().display()
+        ))
+        .suggestion("run gourcers with this data directory first");
+    }
+
+    let mut stats = Vec::new();
+    let mut stale = Vec::new();
+    for entry in std::fs::read_dir(&gource_dir).wrap_err("failed to read gource log directory")? {
+        let path = entry
+            .wrap_err("failed to read gource log directory")?
+            .path();
+        let Some(name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(log_stats::SUFFIX))
+        else {
+            continue;
+        };
+
+        let full_name = name.replacen("__", "/", 1);
+        let log_stats = LogStats::read(&path.with_file_name(format!("{name}.txt")))
+            .wrap_err_with(|| format!("failed to read the stats of {full_name}"))?
+            .unwrap_or_default();
+
+        let repo_dir = cx.data_dir.repo_dir_by_name(&full_name);
+        if log_stats.head.is_some()
+            && repo_dir.exists()
+            && gource::clone_head(&repo_dir) != log_stats.head
+        {
+            stale.push(full_name.clone());
+        }
+
+        stats.push((full_name, log_stats));
+    }
+    stats.sort_by(|(a, _), (b, _)| a.cmp(b));
+    stale.sort();
+
+    print!("{}", log_stats::report(&stats));
+
+    if !stale.is_empty() {
+        eprintln!(
+            "{}: the clones of {} repos moved on since their logs were generated, run gourcers again to regenerate them: {}",
+            style("WARNING").yellow().bold(),
+            stale.len(),
+            stale.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Open the sorted log in a gource window.
+fn preview(cx: &Context) -> Result<()> {
+    let log = cx.sorted_log();
+    if !log.is_file() {
+        return Err(eyre!("there is no sorted log at {}", log.display()))
+            .suggestion("run gourcers with this data directory first, or pass --from-log");
+    }
+
+    focus(cx)?;
+
+    gource::preview(cx).wrap_err("failed to run gource")
+}
+
+/// Write the focused part of the sorted log for `--focus`.
+fn focus(cx: &Context) -> Result<()> {
+    let Some(dir) = &cx.focus else {
+        return Ok(());
+    };
+
+    let written = sorted_log::write_focused(&cx.sorted_log(), &cx.data_dir.focused_log(), dir)
+        .wrap_err("failed to write the focused log")?;
+    if written == 0 {
+        return Err(eyre!("no files in the sorted log are in --focus {dir}")).suggestion(
+            "the directory should start with the group, owner or repo, like the tree in the video",
+        );
+    }
+
+    debug!("focused on {written} entries in {dir}");
+    Ok(())
+}
+
+/// Render the sorted log, returning the path of the first video file written, if any.
+fn render_sorted(cx: &Context) -> Result<Option<PathBuf>> {
+    let plain = !cx.progress_bars;
+
+    focus(cx)?;
+
+    if cx.config.profiles.is_empty() {
+        if cx.auto_speed {
+            warn!("--auto-speed only applies to render profiles and will be ignored");
+        }
+        if cx.split_by.is_some() || cx.checkpoint_every.is_some() {
+            warn!("--split-by and --checkpoint-every only apply to render profiles and will be ignored");
+        }
+
+        status!(cx, 5, "rocket", "Running gource");
+
+        let gource_progress = Progress::spinner(plain);
+
+        debug!("running gource");
+        gource::generate_gource_video(cx).wrap_err("failed to run gource")?;
+
+        gource_progress.finish();
+    } else {
+        status!(
+            cx,
+            5,
+            "rocket",
+            "Rendering {} profiles{}",
+            cx.config.profiles.len(),
+            if cx.parallel_renders {
+                " in parallel"
+            } else {
+                ""
+            }
+        );
+
+        let profiles = cx.expanded_profiles(repo_count(cx)?)?;
+        let render_progress = Progress::bar(profiles.len() as u64, plain);
+
+        gource::render_profiles(cx, &profiles, &render_progress)?;
+
+        render_progress.finish();
+
+        return Ok(profiles.first().map(|profile| profile.output.clone()));
+    }
+
+    Ok(None)
+}
+
+/// The outputs of the render profiles which use `{repos}`, which can only be worked out once the
+/// logs are sorted, or else of the ones which don't.
+fn profile_outputs(cx: &Context, uses_repos: bool) -> Result<Vec<PathBuf>> {
+    let templates = cx
+        .config
+        .profiles
+        .iter()
+        .map(|profile| &profile.output)
+        .filter(|output| output.to_string_lossy().contains("{repos}") == uses_repos)
+        .collect::<Vec<_>>();
+
+    if templates.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let values = cx.template_values(if uses_repos { repo_count(cx)? } else { 0 });
+    templates
+        .into_iter()
+        .map(|output| Ok(template::expand(output, &values)?))
+        .collect()
+}
+
+/// Make sure existing videos are only overwritten with `--force` or once the user confirms it.
+fn check_overwrite(cx: &Context, outputs: &[PathBuf]) -> Result<()> {
+    let existing = outputs
+        .iter()
+        .filter(|output| output.exists())
+        .collect::<Vec<_>>();
+
+    if cx.force || existing.is_empty() {
+        return Ok(());
+    }
+
+    if cx.interactive {
+        for output in &existing {
+            eprintln!(
+                "{}: {} already exists",
+                style("WARNING").yellow().bold(),
+                output.display()
+            );
+        }
+
+        let confirm = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt("Overwrite the existing videos?")
+            .default(false)
+            .interact()
+            .wrap_err("failed to prompt for overwriting videos")?;
+
+        if confirm {
+            return Ok(());
+        }
+    }
+
+    let mut report = eyre!("rendering would overwrite existing videos");
+    for output in existing {
+        report = report.section(output.display().to_string());
+    }
+    Err(report
+        .suggestion("pass --force to overwrite them")
+        .suggestion(
+            "add a placeholder such as {date} to the output to keep the video of every run",
+        ))
+}
+
+/// The number of repos in the log being rendered, for the `{repos}` placeholder.
+fn repo_count(cx: &Context) -> Result<usize> {
+    if cx.from_log.is_none() {
+        if let Some(state) = SortedLogState::read(&cx.data_dir.sorted_log_state())? {
+            return Ok(state.repos.len());
+        }
+    }
+
+    // logs from elsewhere don't have a state, so count their top-level directories instead
+    let contents =
+        std::fs::read_to_string(cx.sorted_log()).wrap_err("failed to read the sorted log")?;
+    let repos = contents
+        .lines()
+        .filter_map(LogEntry::parse)
+        .map(|entry| entry.repo())
+        .collect::<HashSet<_>>();
+
+    Ok(repos.len())
+}
+
+/// Render the comparison, returning the path of the video file written.
+async fn compare(cx: &Context, args: &CompareArgs) -> Result<Option<PathBuf>> {
+    let plain = !cx.progress_bars;
+
+    let [left, right] = args.include_files.as_slice() else {
+        return Err(eyre!("compare needs exactly two rule files"))
+            .suggestion("pass --include-file twice, once for each side");
+    };
+    check_overwrite(cx, &[args.output(cx.format)])?;
+    let rule_sets = [
+        read_rules(left, cx.ci_rules, cx.rule_precedence)?,
+        read_rules(right, cx.ci_rules, cx.rule_precedence)?,
+    ];
+
+    let repos = prepare(cx, |repo| {
+        rule_sets.iter().any(|rules| rules.test(repo).keep())
+    })
+    .await?;
+
+    status!(cx, 4, "construction", "Combining and sorting logs");
+
+    let compare_dir = cx.data_dir.compare_dir();
+    if !compare_dir.exists() {
+        trace!("creating compare directory: {}", compare_dir.display());
+        std::fs::create_dir(&compare_dir).wrap_err("failed to create compare directory")?;
+    }
+
+    for (side, rules) in compare::SIDES.iter().zip(&rule_sets) {
+        let mut side_repos = repos.clone();
+        rules.apply(&mut side_repos);
+        debug!("{side} side has {} repos", side_repos.len());
+
+        if side_repos.is_empty() {
+            warn!("no repos matched the {side} rule file");
+        }
+
+        gource::combine_and_sort_logs(cx, &side_repos, &cx.data_dir.compare_log(side))
+            .wrap_err_with(|| format!("failed to combine and sort logs for the {side} side"))?;
+    }
+
+    status!(cx, 5, "rocket", "Rendering comparison");
+
+    let render_progress = Progress::bar(compare::SIDES.len() as u64 + 1, plain);
+
+    let output = compare::render(cx, args, &render_progress)?;
+
+    render_progress.finish();
+
+    Ok(Some(output))
+}
+
+/// List, filter, clone and generate logs for every repo which passes the include rules and
+/// `select`. This is steps 1 to 3 of every command.
+async fn prepare(cx: &Context, select: impl Fn(&Repo) -> bool) -> Result<Vec<Repo>> {
+    let (mut repos, teams) = list(cx, select).instrument(info_span!("api")).await?;
+
+    fetch(cx, &mut repos)
+        .instrument(info_span!("clone"))
+        .await?;
+
+    generate_logs(cx, &mut repos, &teams)
+        .instrument(info_span!("logs"))
+        .await?;
+
+    eviction::record(cx, &repos).wrap_err("failed to record repo usage")?;
+    if let Some(max) = cx.max_cache_size {
+        evict(cx, &repos, max)?;
+    }
+
+    if let Some(path) = &cx.export_selection {
+        github::write_repos(path, &repos).wrap_err("failed to export the selected repos")?;
+    }
+
+    Ok(repos)
+}
+
+/// Delete the least recently used clones which aren't in `repos` until the clones fit in `max`.
+fn evict(cx: &Context, repos: &[Repo], max: ByteSize) -> Result<()> {
+    let evicted = eviction::evict(cx, repos, max, cx.evict_logs)
+        .wrap_err("failed to evict clones from the data directory")?;
+
+    if !evicted.repos.is_empty() {
+        eprintln!(
+            "{}: evicted {} least recently used clones, freeing {}",
+            style("INFO").cyan().bold(),
+            evicted.repos.len(),
+            evicted.freed
+        );
+    }
+    if evicted.remaining > max {
+        eprintln!(
+            "{}: the clones of the selected repos alone use {}, more than --max-cache-size {max}",
+            style("WARNING").yellow().bold(),
+            evicted.remaining
+        );
+    }
+
+    Ok(())
+}
+
+/// List the repos and select the ones to visualize. This is step 1.
+async fn list(cx: &Context, select: impl Fn(&Repo) -> bool) -> Result<(Vec<Repo>, Vec<Team>)> {
+    status!(cx, 1, "mag", "Listing repos");
+
+    let started = Instant::now();
+
+    let fetch_progress = Progress::spinner(!cx.progress_bars);
+
+    let source = cx.repo_source(&fetch_progress);
+    if source.uses_token() {
+        check_token(cx).await?;
+    }
+    let mut repos = source::list(source.as_ref())
+        .await
+        .wrap_err("failed to list repos")?;
+    trace!("fetched {} repos: {repos:?}", repos.len());
+
+    let uses_teams = cx.includes.as_ref().is_some_and(RuleSet::uses_teams);
+    let teams = if uses_teams || cx.color_by_team {
+        teams::fetch(cx, &mut repos, cx.color_by_team, &fetch_progress)
+            .await
+            .wrap_err("failed to fetch teams")?
+    } else {
+        Vec::new()
+    };
+
+    fetch_progress.finish();
+
+    select_repos(cx, &mut repos, select);
+
+    add_wikis_and_gists(cx, &mut repos).await?;
+
+    cx.timings.phase(Phase::Api, started.elapsed());
+
+    Ok((repos, teams))
+}
+
+/// Make sure the token can list private repos, since a token without the `repo` scope silently
+/// lists only public repos.
+async fn check_token(cx: &Context) -> Result<()> {
+    let info = github::token_info(cx)
+        .await
+        .wrap_err("failed to check the token")
+        .suggestion("check that the token is valid and hasn't expired")?;
+    trace!("token info: {info:?}");
+
+    if !info.has_repo_scope() {
+        return Err(eyre!(
+            "the token of {} doesn't have the repo scope, so private repos can't be listed",
+            info.login
+        ))
+        .section(match info.scopes.unwrap_or_default().as_slice() {
+            [] => "The token has no scopes".to_string(),
+            scopes => format!("The token has the scopes: {}", scopes.join(", ")),
+        })
+        .suggestion("create a classic token with the repo scope")
+        .suggestion(
+            "or create a fine-grained token with read access to the contents and metadata of \
+             your repos",
+        );
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    if let Some(warning) = info.expiry_warning(i64::try_from(now).unwrap_or_default()) {
+        eprintln!("{}: {warning}", style("WARNING").yellow().bold());
+    }
+
+    Ok(())
+}
+
+/// Clone or pull the repos. This is step 2.
+async fn fetch(cx: &Context, repos: &mut Vec<Repo>) -> Result<()> {
+    status!(
+        cx,
+        2,
+        "arrow_double_down",
+        "Cloning and/or pulling repos{}",
+        if cx.skip_clone { " (skipped)" } else { "" }
+    );
+
+    let started = Instant::now();
+
+    metadata::detect_renames(&cx.data_dir, repos).wrap_err("failed to update repo metadata")?;
+
+    if cx.skip_clone {
+        check_clones(cx, repos)?;
+    } else {
+        check_bandwidth_limit(cx)?;
+        check_shallow_clones(cx);
+        fetch_all(cx, repos).await?;
+    }
+
+    cx.timings.phase(Phase::Clone, started.elapsed());
+
+    Ok(())
+}
+
+/// Make sure every repo has been cloned before, since `--skip-clone` won't clone them. Wikis which
+/// weren't cloned are dropped instead, since wikis without any pages can't be cloned.
+fn check_clones(cx: &Context, repos: &mut Vec<Repo>) -> Result<()> {
+    let mut missing = Vec::new();
+    repos.retain(|repo| {
+        let cloned = cx.data_dir.repo_dir(repo).exists();
+        if !cloned && repo.kind != RepoKind::Wiki {
+            missing.push(repo.full_name());
+        }
+        cloned
+    });
+
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut report = eyre!(
+        "{} selected repos haven't been cloned into {}",
+        missing.len(),
+        cx.data_dir.repos_dir().display()
+    );
+    for full_name in missing {
+        report = report.section(full_name);
+    }
+    Err(report
+        .suggestion("drop --skip-clone to clone them, or change the rules so they aren't selected"))
+}
+
+/// Make sure `--clone-bandwidth-limit` can be applied.
+fn check_bandwidth_limit(cx: &Context) -> Result<()> {
+    if cx.network.clone_bandwidth_limit.is_none() {
+        return Ok(());
+    }
+
+    if cx.git_backend != GitBackend::Cli {
+        eprintln!(
+            "{}: --clone-bandwidth-limit is only applied by the cli git backend",
+            style("WARNING").yellow().bold()
+        );
+    } else if headless::find_executable("trickle").is_none() {
+        return Err(eyre!(
+            "--clone-bandwidth-limit needs `trickle`, which was not found"
+        ))
+        .suggestion("install trickle with your package manager");
+    }
+
+    Ok(())
+}
+
+/// Warn that `--history-since` clones the whole history with backends which can't make shallow
+/// clones.
+fn check_shallow_clones(cx: &Context) {
+    if cx.history_since.is_some() && cx.git_backend != GitBackend::Cli {
+        eprintln!(
+            "{}: --history-since only makes shallow clones with the cli git backend, so the whole \
+             history of new clones is fetched",
+            style("WARNING").yellow().bold()
+        );
+    }
+}
+
+/// Generate the gource logs of the repos, and drop the repos whose logs don't pass the filters.
+/// This is step 3.
+async fn generate_logs(cx: &Context, repos: &mut Vec<Repo>, teams: &[Team]) -> Result<()> {
+    let plain = !cx.progress_bars;
+
+    status!(cx, 3, "factory", "Generating gource logs");
+
+    let started = Instant::now();
+
+    let gource_progress = Progress::bar(repos.len() as u64, plain);
+
+    if !cx.data_dir.gource_dir().exists() {
+        trace!(
+            "creating gource log directory: {}",
+            cx.data_dir.gource_dir().display()
+        );
+        std::fs::create_dir(cx.data_dir.gource_dir())
+            .wrap_err("failed to create gource log directory")?;
+    }
+
+    debug!("generating gource logs for {} repos", repos.len());
+    parallel::for_each(
+        repos,
+        cx.jobs,
+        &gource_progress,
+        Repo::full_name,
+        async |repo, _| {
+            let started = Instant::now();
+            let result = gource::generate_gource_log(cx, repo)
+                .await
+                .wrap_err_with(|| {
+                    format!("failed to generate gource log for {}", repo.full_name())
+                });
+            cx.timings
+                .repo(Phase::LogGen, repo.full_name(), started.elapsed());
+            result
+        },
+    )
+    .await?;
+
+    gource_progress.finish();
+
+    if cx.pull_requests {
+        let pulls_progress = Progress::spinner(plain);
+        pulls::add_to_logs(cx, repos, &pulls_progress)
+            .await
+            .wrap_err("failed to add pull requests to the logs")?;
+        pulls_progress.finish();
+    }
+
+    if cx.use_github_logins {
+        let logins_progress = Progress::bar(repos.len() as u64, plain);
+        logins::apply(cx, repos, &logins_progress)
+            .await
+            .wrap_err("failed to resolve GitHub logins")?;
+        logins_progress.finish();
+    }
+
+    if cx.color_by_team {
+        teams::color_logs(cx, repos, teams).wrap_err("failed to color logs by team")?;
+    }
+
+    select_logs(cx, repos)?;
+
+    if cx.mark_releases {
+        releases::write_captions(cx, repos)
+            .await
+            .wrap_err("failed to write the release captions")?;
+    }
+
+    cx.timings.phase(Phase::LogGen, started.elapsed());
+
+    Ok(())
+}
+
+/// Apply the rules and every other filter which only needs what the API reported about the
+/// repos.
+fn select_repos(cx: &Context, repos: &mut Vec<Repo>, select: impl Fn(&Repo) -> bool) {
+    let initial_len = repos.len();
+
+    let mut unused_rules = Vec::new();
+    if let Some(includes) = &cx.includes {
+        unused_rules = includes.unused(repos);
+        includes.apply(repos);
+    }
+    repos.retain(select);
+
+    // the API reports a size of 0 for repos without any commits
+    let mut empty = Vec::new();
+    if !cx.include_empty {
+        repos.retain(|repo| {
+            if repo.size == 0 {
+                empty.push(repo.full_name());
+            }
+            repo.size > 0
+        });
+    }
+
+    if let Some(since) = cx.history_since {
+        repos.retain(|repo| since.pushed_since(repo));
+    }
+
+    trace!("filtered to {} repos: {repos:?}", repos.len());
+    debug!("filtering removed {} repos", initial_len - repos.len());
+
+    for rule in unused_rules {
+        let source = rule
+            .entry
+            .source
+            .as_ref()
+            .map(|source| format!(" on {source}"))
+            .unwrap_or_default();
+        eprintln!(
+            "{}: rule `{rule}`{source} matched nothing",
+            style("WARNING").yellow().bold(),
+        );
+    }
+    report_empty(&empty);
+
+    if let Some(n) = cx.top {
+        match cx.top_by {
+            TopBy::Commits => {}
+            TopBy::RecentActivity => top::keep_top(repos, n, |repo| repo.pushed_at.clone()),
+            TopBy::Stars => top::keep_top(repos, n, |repo| repo.stargazers_count),
+        }
+    }
+}
+
+/// Apply the filters which need the gource logs.
+fn select_logs(cx: &Context, repos: &mut Vec<Repo>) -> Result<()> {
+    // the size reported by the API can lag behind, so also check what gource found
+    if !cx.include_empty {
+        drop_empty_logs(cx, repos);
+    }
+
+    if let Some(min_commits) = cx.min_commits {
+        drop_small_repos(cx, repos, min_commits)?;
+    }
+
+    if let Some(n) = cx.top.filter(|_| cx.top_by.needs_logs()) {
+        let counts = repos
+            .iter()
+            .map(|repo| Ok((repo.full_name(), gource::count_commits(cx, repo)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+        top::keep_top(repos, n, |repo| counts.get(&repo.full_name()).copied());
+    }
+
+    Ok(())
+}
+
+/// Add the wikis of the repos with `--include-wikis`, and the user's gists with `--include-gists`.
+async fn add_wikis_and_gists(cx: &Context, repos: &mut Vec<Repo>) -> Result<()> {
+    if cx.include_wikis {
+        let wikis = repos
+            .iter()
+            .filter(|repo| repo.has_wiki)
+            .map(Repo::wiki)
+            .collect::<Vec<_>>();
+        debug!("including {} wikis", wikis.len());
+        repos.extend(wikis);
+    }
+
+    if cx.include_gists {
+        let progress = Progress::spinner(!cx.progress_bars);
+        let gists = github::list_gists(cx, &progress)
+            .await
+            .wrap_err("failed to list gists")?;
+        progress.finish();
+        debug!("including {} gists", gists.len());
+        repos.extend(gists);
+    }
+
+    Ok(())
+}
+
+/// Clone or pull the repos. Repos which fail are removed with a warning if `--keep-going` was
+/// given.
+async fn fetch_all(cx: &Context, repos: &mut Vec<Repo>) -> Result<()> {
+    debug!("cloning/pulling {} repos", repos.len());
+
+    let (wiki_failures, failures): (Vec<_>, Vec<_>) = github::fetch_repos(cx, repos)
+        .await
+        .into_iter()
+        .partition(|(repo, _)| repo.kind == RepoKind::Wiki);
+
+    // wikis without any pages can't be cloned, which isn't an error
+    let missing_wikis = wiki_failures
+        .into_iter()
+        .map(|(repo, e)| {
+            debug!("failed to fetch {}: {:#}", repo.full_name(), Report::new(e));
+            repo.full_name()
+        })
+        .collect::<Vec<_>>();
+
+    let mut failed = Vec::new();
+    if !failures.is_empty() {
+        failed = failures
+            .iter()
+            .map(|(repo, _)| repo.full_name())
+            .collect::<Vec<_>>();
+
+        if !cx.keep_going {
+            let mut report = eyre!("failed to fetch {} repos", failures.len());
+            for (_, e) in failures {
+                report = report.section(format!("{:#}", Report::new(e)));
+            }
+            return Err(report.suggestion("use --keep-going to skip repos which fail to fetch"));
+        }
+
+        for (name, (_, e)) in failed.iter().zip(failures) {
+            eprintln!(
+                "{}: skipping {name}: {:#}",
+                style("WARNING").yellow().bold(),
+                Report::new(e)
+            );
+        }
+    }
+
+    repos.retain(|repo| {
+        let full_name = repo.full_name();
+        !failed.contains(&full_name) && !missing_wikis.contains(&full_name)
+    });
+
+    Ok(())
+}
+
+/// Remove repos whose gource log is empty.
+fn drop_empty_logs(cx: &Context, repos: &mut Vec<Repo>) {
+    let mut empty = Vec::new();
+    repos.retain(|repo| {
+        let log_empty = log_file::is_empty(&cx.data_dir.gource_log(repo)).unwrap_or_default();
+        if log_empty {
+            empty.push(repo.full_name());
+        }
+        !log_empty
+    });
+    report_empty(&empty);
+}
+
+/// Remove repos with fewer than `min_commits` commits in their gource log.
+fn drop_small_repos(cx: &Context, repos: &mut Vec<Repo>, min_commits: usize) -> Result<()> {
+    let counts = repos
+        .iter()
+        .map(|repo| gource::count_commits(cx, repo))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut counts = counts.into_iter();
+    repos.retain(|repo| {
+        let commits = counts.next().unwrap_or_default();
+        if commits < min_commits {
+            eprintln!(
+                "{}: skipping {}: only {commits} of the {min_commits} commits needed",
+                style("INFO").cyan().bold(),
+                repo.full_name()
+            );
+        }
+        commits >= min_commits
+    });
+
+    Ok(())
+}
+
+fn report_empty(empty: &[String]) {
+    for name in empty {
+        eprintln!(
+            "{}: skipping {name} because it has no commits",
+            style("INFO").cyan().bold()
+        );
+    }
+    if !empty.is_empty() {
+        eprintln!(
+            "{}: use --include-empty to include empty repos",
+            style("INFO").cyan().bold()
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Per-repo metadata stored in the data directory, keyed by GitHub's numeric repo ID.
+//!
+//! The ID stays the same when a repo is renamed or transferred, which lets us move the existing
+//! clone and log to the new name instead of cloning the repo again.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{GourcersError, Result},
+    github::{Repo, RepoKind},
+    log_file, log_stats, OutputDir,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepoMetadata {
+    pub id: u64,
+    pub full_name: String,
+}
+
+impl RepoMetadata {
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = std::fs::read_to_string(path).map_err(GourcersError::io(format!(
+            "failed to read {}",
+            path.display()
+        )))?;
+        let metadata = serde_json::from_str(&contents).map_err(GourcersError::json(format!(
+            "failed to parse {}",
+            path.display()
+        )))?;
+
+        Ok(Some(metadata))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(GourcersError::json("failed to serialize metadata"))?;
+        std::fs::write(path, contents).map_err(GourcersError::io(format!(
+            "failed to write {}",
+            path.display()
+        )))
+    }
+}
+
+/// Move the clones and logs of any repos whose full name changed since the last run, then record
+/// the current name of every repo. Wikis and gists are skipped, since they don't have IDs of their
+/// own.
+pub fn detect_renames(data_dir: &OutputDir, repos: &[Repo]) -> Result<()> {
+    let metadata_dir = data_dir.metadata_dir();
+    if !metadata_dir.exists() {
+        trace!("creating metadata directory: {}", metadata_dir.display());
+        std::fs::create_dir_all(&metadata_dir)
+            .map_err(GourcersError::io("failed to create metadata directory"))?;
+    }
+
+    for repo in repos.iter().filter(|repo| repo.kind == RepoKind::Repo) {
+        let path = data_dir.metadata_file(repo);
+        let full_name = repo.full_name();
+
+        if let Some(previous) = RepoMetadata::read(&path)? {
+            if previous.full_name != full_name {
+                info!("repo {} was renamed to {full_name}", previous.full_name);
+                rename(
+                    &data_dir.repo_dir_by_name(&previous.full_name),
+                    &data_dir.repo_dir(repo),
+                )?;
+                let (from, to) = (
+                    data_dir.gource_log_by_name(&previous.full_name),
+                    data_dir.gource_log(repo),
+                );
+                rename(&from, &to)?;
+                rename(&log_file::compressed(&from), &log_file::compressed(&to))?;
+                rename(&log_stats::path(&from), &log_stats::path(&to))?;
+            }
+        }
+
+        RepoMetadata {
+            id: repo.id,
+            full_name,
+        }
+        .write(&path)?;
+    }
+
+    Ok(())
+}
+
+/// Rename `from` to `to` unless `from` is missing or `to` already exists.
+fn rename(from: &Path, to: &Path) -> Result<()> {
+    if !from.exists() || to.exists() {
+        return Ok(());
+    }
+
+    debug!("moving {} to {}", from.display(), to.display());
+    std::fs::rename(from, to).map_err(GourcersError::io(format!(
+        "failed to move {} to {}",
+        from.display(),
+        to.display()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+    use crate::github::Owner;
+
+    fn repo(id: u64, owner: &str, name: &str, kind: RepoKind) -> Repo {
+        Repo {
+            id,
+            name: name.into(),
+            owner: Owner {
+                login: owner.into(),
+                account_type: "User".into(),
+            },
+            kind,
+            ..Repo::default()
+        }
+    }
+
+    /// The clone, log, compressed log and stats of a repo.
+    fn paths(data_dir: &OutputDir, full_name: &str) -> [std::path::PathBuf; 4] {
+        let log = data_dir.gource_log_by_name(full_name);
+        [
+            data_dir.repo_dir_by_name(full_name),
+            log_file::compressed(&log),
+            log_stats::path(&log),
+            log,
+        ]
+    }
+
+    fn create(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn record(data_dir: &OutputDir, repo: &Repo, full_name: &str) {
+        std::fs::create_dir_all(data_dir.metadata_dir()).unwrap();
+        RepoMetadata {
+            id: repo.id,
+            full_name: full_name.into(),
+        }
+        .write(&data_dir.metadata_file(repo))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_detect_renames() {
+        let temp = TempDir::new().unwrap();
+        let data_dir = OutputDir::Specified(temp.path().to_path_buf());
+
+        // renamed, with everything moved and the metadata rewritten
+        let renamed = repo(1, "alice", "new", RepoKind::Repo);
+        record(&data_dir, &renamed, "alice/old");
+        for path in paths(&data_dir, "alice/old") {
+            create(&path, "old");
+        }
+
+        // renamed onto a name which is already used, so nothing is overwritten
+        let taken = repo(2, "alice", "taken", RepoKind::Repo);
+        record(&data_dir, &taken, "alice/previous");
+        create(&paths(&data_dir, "alice/previous")[3], "previous");
+        create(&paths(&data_dir, "alice/taken")[3], "taken");
+
+        // renamed, but its files are gone
+        let missing = repo(3, "alice", "missing", RepoKind::Repo);
+        record(&data_dir, &missing, "alice/gone");
+
+        // wikis share the ID of their repo, so they would look like a rename of it
+        let wiki = repo(1, "alice", "new.wiki", RepoKind::Wiki);
+
+        detect_renames(
+            &data_dir,
+            &[renamed.clone(), taken.clone(), missing.clone(), wiki],
+        )
+        .unwrap();
+
+        for (from, to) in paths(&data_dir, "alice/old")
+            .iter()
+            .zip(paths(&data_dir, "alice/new"))
+        {
+            assert!(!from.exists());
+            assert!(to.exists());
+        }
+        let metadata = RepoMetadata::read(&data_dir.metadata_file(&renamed))
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata.full_name, "alice/new");
+
+        let [_, _, _, previous] = paths(&data_dir, "alice/previous");
+        let [_, _, _, taken_log] = paths(&data_dir, "alice/taken");
+        assert_eq!(std::fs::read_to_string(previous).unwrap(), "previous");
+        assert_eq!(std::fs::read_to_string(taken_log).unwrap(), "taken");
+
+        assert!(paths(&data_dir, "alice/missing")
+            .iter()
+            .all(|path| !path.exists()));
+        assert_eq!(
+            RepoMetadata::read(&data_dir.metadata_file(&missing))
+                .unwrap()
+                .unwrap()
+                .full_name,
+            "alice/missing"
+        );
+
+        assert!(paths(&data_dir, "alice/new.wiki")
+            .iter()
+            .all(|path| !path.exists()));
+    }
+}
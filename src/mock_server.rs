@@ -0,0 +1,158 @@
+//! A minimal HTTP server which answers with canned responses, so the API client can be tested
+//! offline.
+//!
+//! Each path is given a list of responses, which are sent in order, repeating the last one once
+//! the others have been sent. `{url}` in the headers and bodies is replaced with the server's URL,
+//! so pagination links can point back at it. Every request is recorded for inspection.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl MockResponse {
+    /// A `200 OK` response with a JSON body.
+    #[must_use]
+    pub fn json(body: &str) -> Self {
+        Self {
+            status: 200,
+            headers: vec![("Content-Type".into(), "application/json".into())],
+            body: body.to_string(),
+        }
+    }
+
+    /// A response with the given status and an empty body.
+    #[must_use]
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// A request the server received.
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    /// The path and query.
+    pub path: String,
+    /// The headers, with lowercase names.
+    pub headers: HashMap<String, String>,
+}
+
+pub struct MockServer {
+    url: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockServer {
+    /// Start a server on a free local port, answering requests for each path with its responses.
+    /// Other paths are answered with `404 Not Found`.
+    #[must_use]
+    pub fn start(routes: Vec<(&str, Vec<MockResponse>)>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let routes = routes
+            .into_iter()
+            .map(|(path, responses)| (path.to_string(), responses))
+            .collect::<HashMap<_, _>>();
+        let routes = Arc::new(Mutex::new(routes));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        // the thread stops with the test process, since the listener is never closed
+        std::thread::spawn({
+            let url = url.clone();
+            let requests = requests.clone();
+            move || {
+                for stream in listener.incoming().map_while(Result::ok) {
+                    handle(&stream, &url, &routes, &requests);
+                }
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    /// The URL of the server, without a trailing slash.
+    #[must_use]
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The requests the server has received, in order.
+    #[must_use]
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn handle(
+    stream: &TcpStream,
+    url: &str,
+    routes: &Mutex<HashMap<String, Vec<MockResponse>>>,
+    requests: &Mutex<Vec<MockRequest>>,
+) {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+
+    let mut headers = HashMap::new();
+    let mut line = String::new();
+    while reader.read_line(&mut line).is_ok_and(|read| read > 0) {
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.insert(name.to_lowercase(), value.trim().to_string());
+        line.clear();
+    }
+
+    let response = {
+        let mut routes = routes.lock().unwrap();
+        match routes.get_mut(&path) {
+            Some(responses) if responses.len() > 1 => responses.remove(0),
+            Some(responses) => responses[0].clone(),
+            None => MockResponse::status(404),
+        }
+    };
+    requests.lock().unwrap().push(MockRequest { path, headers });
+
+    let body = response.body.replace("{url}", url);
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        body.len()
+    );
+    for (name, value) in &response.headers {
+        head.extend([name, ": ", &value.replace("{url}", url), "\r\n"]);
+    }
+
+    let mut stream = stream;
+    stream
+        .write_all(format!("{head}\r\n{body}").as_bytes())
+        .ok();
+}
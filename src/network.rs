@@ -0,0 +1,246 @@
+//! How gourcers connects to GitHub, both for API requests and for the git commands which clone and
+//! pull repos.
+
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+use std::path::Path;
+use std::path::PathBuf;
+
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+use reqwest::Certificate;
+use reqwest::{ClientBuilder, Proxy, Url};
+use tokio::process::Command;
+
+use crate::{
+    error::{GourcersError, Result},
+    eviction::ByteSize,
+};
+
+/// Options for the connections to the API and the git remotes.
+#[derive(Debug, Clone, Default, clap::Args)]
+#[clap(next_help_heading = "Network options")]
+pub struct NetworkOptions {
+    /// The SSH private key to clone and pull with, instead of the SSH agent's default identity.
+    #[clap(long, value_name = "PATH", conflicts_with = "ssh_command")]
+    pub ssh_key: Option<PathBuf>,
+    /// The command git uses to connect over SSH, passed to it as `GIT_SSH_COMMAND`.
+    ///
+    /// Only used by the `cli` git backend.
+    #[clap(long, value_name = "COMMAND")]
+    pub ssh_command: Option<String>,
+    /// The proxy to send API requests, uploads, exported spans and HTTP(S) git traffic through,
+    /// e.g. `http://proxy.example.com:3128`.
+    ///
+    /// Without this, the `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY` environment variables are
+    /// used.
+    #[clap(long, value_name = "URL", conflicts_with = "no_proxy")]
+    pub proxy: Option<Url>,
+    /// Connect directly, ignoring any proxy set in the environment.
+    #[clap(long)]
+    pub no_proxy: bool,
+    /// A PEM file with CA certificates to trust, for networks which intercept TLS.
+    ///
+    /// API requests, uploads and exported spans trust these in addition to the built-in CAs. HTTPS
+    /// git traffic with the `cli` git backend trusts only these, since git replaces its CA bundle
+    /// with the file, so it should be a whole bundle when git also connects to other hosts.
+    #[clap(long, value_name = "PEM", env = "SSL_CERT_FILE")]
+    pub ca_cert: Option<PathBuf>,
+    /// Limit the download rate of all clones and pulls together, e.g. `2M` for 2 MiB per second.
+    ///
+    /// The limit is shared evenly between the `--jobs` concurrent clones. This runs git under
+    /// `trickle`, which must be installed, and is only used by the `cli` git backend.
+    #[clap(long, value_name = "RATE", value_parser = parse_rate)]
+    pub clone_bandwidth_limit: Option<ByteSize>,
+}
+
+impl NetworkOptions {
+    /// Apply the options to a client builder.
+    pub fn client_builder(&self, builder: ClientBuilder) -> Result<ClientBuilder> {
+        #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+        let builder = match &self.ca_cert {
+            Some(path) => read_certificates(path)?
+                .into_iter()
+                .fold(builder, ClientBuilder::add_root_certificate),
+            None => builder,
+        };
+
+        if let Some(proxy) = &self.proxy {
+            let proxy =
+                Proxy::all(proxy.clone()).map_err(GourcersError::network("invalid proxy URL"))?;
+            return Ok(builder.proxy(proxy));
+        }
+
+        if self.no_proxy {
+            return Ok(builder.no_proxy());
+        }
+
+        Ok(builder)
+    }
+
+    /// The value of `GIT_SSH_COMMAND` for the git commands which clone and pull repos, if it
+    /// needs to be set.
+    #[must_use]
+    pub fn git_ssh_command(&self) -> Option<String> {
+        self.ssh_command.clone().or_else(|| {
+            self.ssh_key.as_ref().map(|key| {
+                format!(
+                    "ssh -i {} -o IdentitiesOnly=yes",
+                    shell_words::quote(&key.to_string_lossy())
+                )
+            })
+        })
+    }
+
+    /// A git command with the options applied, which is one of `jobs` running concurrently.
+    #[must_use]
+    pub fn git_command(&self, jobs: usize) -> Command {
+        let mut cmd = match self.clone_bandwidth_limit {
+            Some(limit) => {
+                let mut cmd = Command::new("trickle");
+                cmd.args(["-s", "-d", &kib_per_job(limit, jobs).to_string(), "git"]);
+                cmd
+            }
+            None => Command::new("git"),
+        };
+
+        cmd.args(self.git_config_args()).envs(self.git_env());
+        cmd
+    }
+
+    /// The arguments to pass to git before the subcommand, as `-c` options.
+    #[must_use]
+    pub fn git_config_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(proxy) = &self.proxy {
+            args.extend(["-c".to_string(), format!("http.proxy={proxy}")]);
+        } else if self.no_proxy {
+            // an empty proxy makes git's curl ignore the proxy environment variables
+            args.extend(["-c".to_string(), "http.proxy=".to_string()]);
+        }
+
+        args
+    }
+
+    /// The environment variables to set for git.
+    #[must_use]
+    pub fn git_env(&self) -> Vec<(&'static str, String)> {
+        let mut env = Vec::new();
+
+        if let Some(ssh_command) = self.git_ssh_command() {
+            env.push(("GIT_SSH_COMMAND", ssh_command));
+        }
+        if let Some(ca_cert) = &self.ca_cert {
+            env.push(("GIT_SSL_CAINFO", ca_cert.to_string_lossy().into_owned()));
+        }
+
+        env
+    }
+}
+
+/// The download rate in KiB per second for each of `jobs` concurrent git commands.
+fn kib_per_job(limit: ByteSize, jobs: usize) -> u64 {
+    (limit.0 / 1024 / jobs.max(1) as u64).max(1)
+}
+
+/// Parse a rate like `2M` or `500K/s`.
+fn parse_rate(s: &str) -> std::result::Result<ByteSize, String> {
+    let s = s.trim();
+    s.strip_suffix("/s").unwrap_or(s).parse()
+}
+
+#[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+/// Read every certificate in a PEM file, which is often a whole bundle like with `SSL_CERT_FILE`.
+fn read_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let pem = std::fs::read(path).map_err(GourcersError::io(format!(
+        "failed to read CA certificates {}",
+        path.display()
+    )))?;
+
+    // reqwest's `Certificate::from_pem_bundle` hands DER to native-tls, which expects PEM, so the
+    // bundle is split into one PEM block per certificate here instead
+    let pem = String::from_utf8_lossy(&pem);
+    let certificates = pem
+        .split_inclusive("-----END CERTIFICATE-----")
+        .filter_map(|block| {
+            block
+                .find("-----BEGIN CERTIFICATE-----")
+                .map(|start| &block[start..])
+        })
+        .map(|block| Certificate::from_pem(block.as_bytes()))
+        .collect::<reqwest::Result<Vec<_>>>()
+        .map_err(GourcersError::network(format!(
+            "failed to parse CA certificates {}",
+            path.display()
+        )))?;
+
+    if certificates.is_empty() {
+        return Err(GourcersError::io(format!(
+            "failed to read CA certificates {}",
+            path.display()
+        ))(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "there are no certificates in the file",
+        )));
+    }
+
+    Ok(certificates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_git_options() {
+        let options = NetworkOptions {
+            ssh_key: Some("/home/me/.ssh/id work".into()),
+            proxy: Some("http://proxy.example.com:3128".parse().unwrap()),
+            ..NetworkOptions::default()
+        };
+        assert_eq!(
+            options.git_env(),
+            [(
+                "GIT_SSH_COMMAND",
+                "ssh -i '/home/me/.ssh/id work' -o IdentitiesOnly=yes".to_string()
+            )]
+        );
+        assert_eq!(
+            options.git_config_args(),
+            ["-c", "http.proxy=http://proxy.example.com:3128/"]
+        );
+
+        let options = NetworkOptions {
+            no_proxy: true,
+            ..NetworkOptions::default()
+        };
+        assert!(options.git_env().is_empty());
+        assert_eq!(options.git_config_args(), ["-c", "http.proxy="]);
+        assert!(NetworkOptions::default().git_config_args().is_empty());
+
+        let options = NetworkOptions {
+            ca_cert: Some("/etc/ssl/corp.pem".into()),
+            ..NetworkOptions::default()
+        };
+        assert_eq!(
+            options.git_env(),
+            [("GIT_SSL_CAINFO", "/etc/ssl/corp.pem".to_string())]
+        );
+    }
+
+    #[cfg(any(feature = "rustls-tls", feature = "native-tls"))]
+    #[test]
+    fn test_read_certificates() {
+        let bundle = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/certs/bundle.pem");
+        assert_eq!(read_certificates(&bundle).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_bandwidth_limit() {
+        assert_eq!(parse_rate("2M/s"), Ok(ByteSize(2 << 20)));
+        assert_eq!(parse_rate("500K"), Ok(ByteSize(500 << 10)));
+        assert!(parse_rate("fast").is_err());
+
+        assert_eq!(kib_per_job(ByteSize(2 << 20), 4), 512);
+        assert_eq!(kib_per_job(ByteSize(100), 8), 1);
+    }
+}
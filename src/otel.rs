@@ -0,0 +1,353 @@
+//! Exporting tracing spans to an OpenTelemetry collector for `--otel-endpoint`.
+//!
+//! Finished spans are collected by [`OtelLayer`] and sent by [`Exporter`] every few seconds, and
+//! once more when the run ends, as OTLP/HTTP JSON. Every phase of a run has a span, with a span
+//! per repo below it for cloning and generating logs.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use color_eyre::Report;
+use console::style;
+use reqwest::Client;
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::{
+    error::{GourcersError, Result},
+    network::NetworkOptions,
+};
+
+/// How often finished spans are sent during a run.
+pub const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A span which has finished, ready to be exported.
+#[derive(Debug, Clone)]
+struct FinishedSpan {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_id: Option<[u8; 8]>,
+    name: &'static str,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(String, Value)>,
+}
+
+/// What is kept in a span's extensions until it closes.
+struct OpenSpan {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_id: Option<[u8; 8]>,
+    start: SystemTime,
+    attributes: Vec<(String, Value)>,
+}
+
+type Finished = Arc<Mutex<Vec<FinishedSpan>>>;
+
+/// A layer which collects finished spans for the [`Exporter`] it was created with.
+pub struct OtelLayer {
+    finished: Finished,
+}
+
+/// Sends the spans collected by an [`OtelLayer`] to a collector.
+#[derive(Debug)]
+pub struct Exporter {
+    client: Client,
+    url: String,
+    finished: Finished,
+}
+
+/// Create a layer and the exporter for the spans it collects. `endpoint` is the base URL of the
+/// collector's OTLP/HTTP receiver, e.g. `http://localhost:4318`, which is connected to with the
+/// `network` options.
+pub fn new(endpoint: &str, network: &NetworkOptions) -> Result<(OtelLayer, Exporter)> {
+    let client = network
+        .client_builder(Client::builder())?
+        .build()
+        .map_err(GourcersError::network("failed to build reqwest client"))?;
+    let finished = Finished::default();
+
+    let endpoint = endpoint.trim_end_matches('/');
+    let url = if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{endpoint}/v1/traces")
+    };
+
+    Ok((
+        OtelLayer {
+            finished: finished.clone(),
+        },
+        Exporter {
+            client,
+            url,
+            finished,
+        },
+    ))
+}
+
+impl Exporter {
+    /// Send every span which finished since the last export.
+    pub async fn export(&self) -> Result<()> {
+        let spans = std::mem::take(&mut *self.finished.lock().unwrap());
+        if spans.is_empty() {
+            return Ok(());
+        }
+
+        trace!("exporting {} spans to {}", spans.len(), self.url);
+
+        self.client
+            .post(&self.url)
+            .json(&request(&spans))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(GourcersError::network(format!(
+                "failed to export spans to {}",
+                self.url
+            )))?;
+
+        Ok(())
+    }
+
+    /// Export finished spans every [`EXPORT_INTERVAL`] until the returned task is aborted.
+    #[must_use]
+    pub fn spawn(self: &Arc<Self>) -> JoinHandle<()> {
+        let exporter = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(EXPORT_INTERVAL).await;
+                exporter.export_or_warn().await;
+            }
+        })
+    }
+
+    /// Export finished spans, printing a warning if that fails, since the run itself is fine.
+    pub async fn export_or_warn(&self) {
+        if let Err(e) = self.export().await {
+            eprintln!("{}: {:#}", style("WARNING").yellow().bold(), Report::new(e));
+        }
+    }
+}
+
+impl<S> Layer<S> for OtelLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<OpenSpan>()
+                .map(|open| (open.trace_id, open.span_id))
+        });
+
+        let mut visitor = AttributeVisitor(Vec::new());
+        attrs.record(&mut visitor);
+
+        let (trace_id, parent_id) = match parent {
+            Some((trace_id, parent_id)) => (trace_id, Some(parent_id)),
+            None => (random_id(), None),
+        };
+
+        span.extensions_mut().insert(OpenSpan {
+            trace_id,
+            span_id: random_id(),
+            parent_id,
+            start: SystemTime::now(),
+            attributes: visitor.0,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut extensions = span.extensions_mut();
+        if let Some(open) = extensions.get_mut::<OpenSpan>() {
+            let mut visitor = AttributeVisitor(std::mem::take(&mut open.attributes));
+            values.record(&mut visitor);
+            open.attributes = visitor.0;
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+
+        let Some(open) = span.extensions_mut().remove::<OpenSpan>() else {
+            return;
+        };
+
+        self.finished.lock().unwrap().push(FinishedSpan {
+            trace_id: open.trace_id,
+            span_id: open.span_id,
+            parent_id: open.parent_id,
+            name: span.name(),
+            start: open.start,
+            end: SystemTime::now(),
+            attributes: open.attributes,
+        });
+    }
+}
+
+struct AttributeVisitor(Vec<(String, Value)>);
+
+impl Visit for AttributeVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0
+            .push((field.name().to_string(), json!({ "stringValue": value })));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        // 64-bit integers are strings in OTLP JSON
+        self.0.push((
+            field.name().to_string(),
+            json!({ "intValue": value.to_string() }),
+        ));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push((
+            field.name().to_string(),
+            json!({ "intValue": value.to_string() }),
+        ));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0
+            .push((field.name().to_string(), json!({ "boolValue": value })));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+}
+
+/// The body of an OTLP/HTTP JSON export request for the spans.
+fn request(spans: &[FinishedSpan]) -> Value {
+    let spans = spans
+        .iter()
+        .map(|span| {
+            let mut value = json!({
+                "traceId": hex::encode(span.trace_id),
+                "spanId": hex::encode(span.span_id),
+                "name": span.name,
+                // SPAN_KIND_INTERNAL
+                "kind": 1,
+                "startTimeUnixNano": nanos(span.start),
+                "endTimeUnixNano": nanos(span.end),
+                "attributes": span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| json!({ "key": key, "value": value }))
+                    .collect::<Vec<_>>(),
+            });
+            if let Some(parent_id) = span.parent_id {
+                value["parentSpanId"] = hex::encode(parent_id).into();
+            }
+            value
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": "gourcers" } },
+                    {
+                        "key": "service.version",
+                        "value": { "stringValue": env!("CARGO_PKG_VERSION") }
+                    },
+                ],
+            },
+            "scopeSpans": [{
+                "scope": { "name": "gourcers" },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// A random ID, which doesn't need to be cryptographically secure.
+fn random_id<const N: usize>() -> [u8; N] {
+    let mut id = [0; N];
+    for chunk in id.chunks_mut(8) {
+        // every RandomState is seeded differently
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        );
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes()[..chunk.len()]);
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_collects_spans() {
+        let (layer, exporter) = new("http://localhost:4318/", &NetworkOptions::default()).unwrap();
+        assert_eq!(exporter.url, "http://localhost:4318/v1/traces");
+
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let phase = tracing::info_span!("clone");
+            phase.in_scope(|| {
+                tracing::info_span!("fetch", repo = "me/repo", attempt = 1).in_scope(|| {});
+            });
+        });
+
+        let spans = exporter.finished.lock().unwrap().clone();
+        let [fetch, clone] = spans.as_slice() else {
+            panic!("expected two spans: {spans:?}");
+        };
+
+        assert_eq!(fetch.name, "fetch");
+        assert_eq!(clone.name, "clone");
+        assert_eq!(fetch.trace_id, clone.trace_id);
+        assert_eq!(fetch.parent_id, Some(clone.span_id));
+        assert_eq!(clone.parent_id, None);
+
+        let body = request(&spans);
+        let fetch = &body["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(fetch["parentSpanId"], hex::encode(clone.span_id));
+        assert_eq!(
+            fetch["attributes"],
+            json!([
+                { "key": "repo", "value": { "stringValue": "me/repo" } },
+                { "key": "attempt", "value": { "intValue": "1" } },
+            ])
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Running a step for many repos at once.
+//!
+//! Workers are futures polled concurrently on the current task, so `f` must not block. CPU-heavy
+//! or blocking work should be moved onto a blocking thread with [`tokio::task::spawn_blocking`].
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Mutex,
+};
+
+use futures_util::future::join_all;
+
+use crate::progress::Progress;
+
+/// Run `f` on every item using up to `jobs` concurrent workers.
+///
+/// Each worker gets its own bar below `progress` showing the item it is processing, labelled with
+/// `label`. After the first failure no new items are started, and the first error is returned
+/// once every worker has stopped.
+pub async fn for_each<T, E, L, F>(
+    items: &[T],
+    jobs: usize,
+    progress: &Progress,
+    label: L,
+    f: F,
+) -> Result<(), E>
+where
+    L: Fn(&T) -> String,
+    F: AsyncFn(&T, &Progress) -> Result<(), E>,
+{
+    match run(items, jobs, progress, label, f, true)
+        .await
+        .into_iter()
+        .next()
+    {
+        Some((_, e)) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Like [`for_each`], but keeps going after failures and returns every failed item with its error.
+pub async fn for_each_collect<'a, T, E, L, F>(
+    items: &'a [T],
+    jobs: usize,
+    progress: &Progress,
+    label: L,
+    f: F,
+) -> Vec<(&'a T, E)>
+where
+    L: Fn(&T) -> String,
+    F: AsyncFn(&T, &Progress) -> Result<(), E>,
+{
+    run(items, jobs, progress, label, f, false)
+        .await
+        .into_iter()
+        .map(|(idx, e)| (&items[idx], e))
+        .collect()
+}
+
+/// Returns the index and error of every failed item, in order.
+async fn run<T, E, L, F>(
+    items: &[T],
+    jobs: usize,
+    progress: &Progress,
+    label: L,
+    f: F,
+    fail_fast: bool,
+) -> Vec<(usize, E)>
+where
+    L: Fn(&T) -> String,
+    F: AsyncFn(&T, &Progress) -> Result<(), E>,
+{
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let failures = Mutex::new(Vec::new());
+    let jobs = jobs.clamp(1, items.len().max(1));
+
+    let work = async || {
+        let worker = progress.worker();
+
+        while !(fail_fast && failed.load(Ordering::Relaxed)) {
+            let idx = next.fetch_add(1, Ordering::Relaxed);
+            let Some(item) = items.get(idx) else {
+                break;
+            };
+
+            worker.set_message(label(item));
+
+            if let Err(e) = f(item, &worker).await {
+                failed.store(true, Ordering::Relaxed);
+                failures.lock().unwrap().push((idx, e));
+            }
+
+            progress.inc(1);
+        }
+
+        worker.finish_and_clear();
+    };
+
+    join_all((0..jobs).map(|_| work())).await;
+
+    let mut failures = failures.into_inner().unwrap();
+    failures.sort_by_key(|(idx, _)| *idx);
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::{bail, Report};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_for_each_visits_every_item() {
+        let items = (0..50).collect::<Vec<_>>();
+        let seen = Mutex::new(Vec::new());
+        let progress = Progress::bar(items.len() as u64, true);
+
+        for_each::<_, Report, _, _>(
+            &items,
+            4,
+            &progress,
+            ToString::to_string,
+            async |item, _| {
+                tokio::task::yield_now().await;
+                seen.lock().unwrap().push(*item);
+                Ok(())
+            },
+        )
+        .await
+        .unwrap();
+
+        let mut seen = seen.into_inner().unwrap();
+        seen.sort_unstable();
+        assert_eq!(seen, items);
+    }
+
+    #[tokio::test]
+    async fn test_for_each_returns_error() {
+        let items = (0..10).collect::<Vec<_>>();
+        let progress = Progress::bar(items.len() as u64, true);
+
+        let res = for_each(
+            &items,
+            3,
+            &progress,
+            ToString::to_string,
+            async |item, _| {
+                if *item == 5 {
+                    bail!("item {item} failed");
+                }
+                Ok(())
+            },
+        )
+        .await;
+
+        assert_eq!(res.unwrap_err().to_string(), "item 5 failed");
+    }
+
+    #[tokio::test]
+    async fn test_for_each_collect_keeps_going() {
+        let items = (0..10).collect::<Vec<_>>();
+        let progress = Progress::bar(items.len() as u64, true);
+
+        let failures = for_each_collect(
+            &items,
+            3,
+            &progress,
+            ToString::to_string,
+            async |item, _| {
+                if item % 4 == 0 {
+                    bail!("item {item} failed");
+                }
+                Ok(())
+            },
+        )
+        .await;
+
+        let failed = failures.iter().map(|(item, _)| **item).collect::<Vec<_>>();
+        assert_eq!(failed, vec![0, 4, 8]);
+    }
+}
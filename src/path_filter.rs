@@ -0,0 +1,203 @@
+//! Dropping files from the logs by their extensions or by built-in presets, so generated files,
+//! lockfiles and vendored code don't dominate the tree.
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::log::LogEntry;
+
+/// A built-in set of paths to hide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterPreset {
+    /// Lockfiles, build output, vendored dependencies and generated or minified files.
+    Codegen,
+}
+
+impl FilterPreset {
+    /// The patterns of the paths to hide. A pattern ending in `/` matches a directory anywhere
+    /// in the path, and any other pattern matches file names, where `*` matches anything.
+    #[must_use]
+    pub fn patterns(self) -> &'static [&'static str] {
+        match self {
+            Self::Codegen => &[
+                "Cargo.lock",
+                "package-lock.json",
+                "npm-shrinkwrap.json",
+                "yarn.lock",
+                "pnpm-lock.yaml",
+                "bun.lockb",
+                "go.sum",
+                "poetry.lock",
+                "Pipfile.lock",
+                "uv.lock",
+                "Gemfile.lock",
+                "composer.lock",
+                "flake.lock",
+                "dist/",
+                "build/",
+                "vendor/",
+                "node_modules/",
+                "*.generated.*",
+                "*.min.js",
+                "*.min.css",
+                "*.map",
+            ],
+        }
+    }
+}
+
+/// Which files to keep in the logs.
+#[derive(Debug, Default, Clone)]
+pub struct PathFilter {
+    /// Every extension with a leading dot, in lowercase.
+    only: Vec<String>,
+    skip: Vec<String>,
+    patterns: Vec<&'static str>,
+}
+
+impl PathFilter {
+    /// Keep only the files with one of the `only` extensions, if there are any, and none of the
+    /// files with one of the `skip` extensions or matching the presets. Extensions can have
+    /// several parts, like `min.js`.
+    #[must_use]
+    pub fn new(only: &[String], skip: &[String], presets: &[FilterPreset]) -> Self {
+        let normalize = |extensions: &[String]| {
+            extensions
+                .iter()
+                .map(|extension| extension.trim().trim_start_matches('.'))
+                .filter(|extension| !extension.is_empty())
+                .map(|extension| format!(".{}", extension.to_lowercase()))
+                .collect()
+        };
+
+        Self {
+            only: normalize(only),
+            skip: normalize(skip),
+            patterns: presets
+                .iter()
+                .flat_map(|preset| preset.patterns())
+                .copied()
+                .collect(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.only.is_empty() && self.skip.is_empty() && self.patterns.is_empty()
+    }
+
+    /// Whether the file at `path` is kept.
+    #[must_use]
+    pub fn keeps(&self, path: &str) -> bool {
+        let (dirs, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let lowercase = name.to_lowercase();
+        let has = |extensions: &[String]| {
+            extensions.iter().any(|extension| {
+                lowercase.len() > extension.len() && lowercase.ends_with(extension)
+            })
+        };
+
+        let matches_preset = self
+            .patterns
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('/') {
+                Some(dir) => dirs.split('/').any(|component| component == dir),
+                None => glob_match(pattern, name),
+            });
+
+        (self.only.is_empty() || has(&self.only)) && !has(&self.skip) && !matches_preset
+    }
+
+    /// Drop the entries for files which aren't kept. Malformed lines are kept as they are.
+    #[must_use]
+    pub fn apply(&self, log: &str) -> String {
+        if self.is_empty() {
+            return log.to_string();
+        }
+
+        let mut filtered = String::with_capacity(log.len());
+        for line in log.lines() {
+            if LogEntry::parse(line).is_none_or(|entry| self.keeps(entry.path)) {
+                filtered.push_str(line);
+                filtered.push('\n');
+            }
+        }
+
+        filtered
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` matches any number of characters.
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts = parts.collect::<Vec<_>>();
+    let Some(last) = parts.pop() else {
+        // no `*` at all
+        return rest.is_empty();
+    };
+
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_extensions() {
+        let filter = PathFilter::new(&strings(&["rs", ".JS"]), &strings(&["min.js"]), &[]);
+        assert!(filter.keeps("/src/main.rs"));
+        assert!(filter.keeps("/web/App.js"));
+        assert!(!filter.keeps("/web/app.min.js"));
+        assert!(!filter.keeps("/Cargo.lock"));
+        assert!(!filter.keeps("/.rs"));
+
+        let filter = PathFilter::new(&[], &strings(&["lock", "map"]), &[]);
+        assert_eq!(
+            filter.apply("100|x|A|/Cargo.lock\n100|x|A|/src/main.rs\n200|x|M|/app.js.map\n"),
+            "100|x|A|/src/main.rs\n"
+        );
+        assert!(PathFilter::new(&strings(&[" "]), &[], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_codegen_preset() {
+        let filter = PathFilter::new(&[], &[], &[FilterPreset::Codegen]);
+        assert!(!filter.keeps("/Cargo.lock"));
+        assert!(!filter.keeps("/web/package-lock.json"));
+        assert!(!filter.keeps("/web/dist/index.html"));
+        assert!(!filter.keeps("/api/types.generated.ts"));
+        assert!(!filter.keeps("/node_modules/left-pad/index.js"));
+        assert!(filter.keeps("/src/main.rs"));
+        assert!(filter.keeps("/src/build.rs"));
+        assert!(filter.keeps("/distance/lock.rs"));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.generated.*", "api.generated.ts"));
+        assert!(!glob_match("*.generated.*", "generated.ts"));
+        assert!(glob_match("*.min.js", "app.min.js"));
+        assert!(glob_match("go.sum", "go.sum"));
+        assert!(!glob_match("go.sum", "go.sum.bak"));
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(!glob_match("a*b*c", "acb"));
+    }
+}
@@ -0,0 +1,275 @@
+//! Progress reporting which degrades to plain log lines when progress bars are unwanted.
+
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, LazyLock,
+    },
+    time::{Duration, Instant},
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+#[cfg(unix)]
+use crate::progress_socket::{self, Event};
+
+static START: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Start the clock used for plain log line timestamps.
+pub fn start_clock() {
+    LazyLock::force(&START);
+}
+
+/// The time since [`start_clock`] was called, formatted for plain log lines.
+#[must_use]
+pub fn timestamp() -> String {
+    format_elapsed(START.elapsed())
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    format!(
+        "[{:02}:{:02}:{:02}]",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn bar_style() -> ProgressStyle {
+    ProgressStyle::with_template(
+        "{elapsed:.magenta.bold} {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
+    )
+    .expect("failed to create progress style")
+    .progress_chars("▓▒░")
+}
+
+fn spinner_style() -> ProgressStyle {
+    ProgressStyle::default_spinner()
+        .template("{elapsed:.magenta.bold} {spinner:.green} {msg}")
+        .expect("failed to create progress style")
+}
+
+fn multi(plain: bool) -> MultiProgress {
+    if plain {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Progress {
+    bar: ProgressBar,
+    multi: MultiProgress,
+    /// Print each message as a log line, because the bars are hidden.
+    echo: bool,
+    /// The number of items started so far, shared with worker bars, used to number log lines.
+    started: Arc<AtomicU64>,
+    /// The total number of items, if known.
+    len: Option<u64>,
+    kind: Kind,
+}
+
+/// What a bar is used for, which decides what is sent to `--progress-socket`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// The bar of a step, whose progress and messages are sent.
+    Step,
+    /// A worker's bar, whose messages are the items it starts on.
+    Worker,
+    /// Progress within a single item, which isn't sent.
+    Sub,
+}
+
+impl Progress {
+    /// Create a progress bar for a task with a known number of steps.
+    #[must_use]
+    pub fn bar(len: u64, plain: bool) -> Self {
+        let multi = multi(plain);
+        let bar = multi.add(ProgressBar::new(len));
+
+        if !plain {
+            bar.set_style(bar_style());
+        }
+
+        Self {
+            bar,
+            multi,
+            echo: plain,
+            started: Arc::default(),
+            len: Some(len),
+            kind: Kind::Step,
+        }
+    }
+
+    /// Create a spinner for a task with an unknown number of steps.
+    #[must_use]
+    pub fn spinner(plain: bool) -> Self {
+        let multi = multi(plain);
+        let bar = multi.add(ProgressBar::new_spinner());
+
+        if !plain {
+            bar.set_style(spinner_style());
+            bar.enable_steady_tick(Duration::from_millis(200));
+        }
+
+        Self {
+            bar,
+            multi,
+            echo: plain,
+            started: Arc::default(),
+            len: None,
+            kind: Kind::Step,
+        }
+    }
+
+    /// Create a spinner below this bar which shows the item a single worker is processing.
+    ///
+    /// Messages on worker bars are numbered as items of this bar when printed as log lines.
+    #[must_use]
+    pub fn worker(&self) -> Self {
+        let bar = self.multi.add(ProgressBar::new_spinner());
+
+        bar.set_style(
+            ProgressStyle::default_spinner()
+                .template("         {spinner:.green} {msg}")
+                .expect("failed to create progress style"),
+        );
+        bar.enable_steady_tick(Duration::from_millis(200));
+
+        Self {
+            bar,
+            multi: self.multi.clone(),
+            echo: self.echo,
+            started: self.started.clone(),
+            len: self.len,
+            kind: Kind::Worker,
+        }
+    }
+
+    /// Create a percentage bar nested under this one, for progress within a single step.
+    ///
+    /// Messages on sub-bars are never printed as log lines. Call [`Progress::finish_and_clear`]
+    /// when the step is done.
+    #[must_use]
+    pub fn sub_bar(&self) -> Self {
+        let bar = self.multi.insert_after(&self.bar, ProgressBar::new(100));
+
+        bar.set_style(
+            ProgressStyle::with_template("           {bar:38.green/white} {pos:>3}% {msg}")
+                .expect("failed to create progress style")
+                .progress_chars("▓▒░"),
+        );
+
+        Self {
+            bar,
+            multi: self.multi.clone(),
+            echo: false,
+            started: Arc::default(),
+            len: None,
+            kind: Kind::Sub,
+        }
+    }
+
+    /// Set the message shown next to the bar. In plain mode, the message is printed as a line.
+    pub fn set_message(&self, msg: impl Into<Cow<'static, str>>) {
+        let msg = msg.into();
+
+        if self.echo {
+            match self.len {
+                Some(len) => {
+                    let n = self.started.fetch_add(1, Ordering::Relaxed) + 1;
+                    eprintln!("{} [{n}/{len}] {msg}", timestamp());
+                }
+                None => eprintln!("{} {msg}", timestamp()),
+            }
+        }
+
+        #[cfg(unix)]
+        match self.kind {
+            Kind::Step => progress_socket::emit(|| Event::Message {
+                message: msg.to_string(),
+            }),
+            Kind::Worker => progress_socket::emit(|| Event::Item {
+                item: msg.to_string(),
+            }),
+            Kind::Sub => {}
+        }
+
+        self.bar.set_message(msg);
+    }
+
+    /// Print a line above the bars, or as a timestamped log line in plain mode.
+    pub fn println(&self, msg: impl AsRef<str>) {
+        if self.echo {
+            eprintln!("{} {}", timestamp(), msg.as_ref());
+        } else {
+            self.multi.println(msg).ok();
+        }
+    }
+
+    /// Turn a spinner into a bar once the number of steps is known.
+    pub fn set_length(&self, len: u64) {
+        if self.bar.length().is_none() && !self.multi.is_hidden() {
+            self.bar.set_style(bar_style());
+        }
+        self.bar.set_length(len);
+        self.emit_progress();
+    }
+
+    pub fn set_position(&self, pos: u64) {
+        self.bar.set_position(pos);
+        self.emit_progress();
+    }
+
+    pub fn inc(&self, delta: u64) {
+        self.bar.inc(delta);
+        self.emit_progress();
+    }
+
+    /// Send the progress of a step's bar to `--progress-socket`.
+    fn emit_progress(&self) {
+        #[cfg(unix)]
+        if self.kind == Kind::Step {
+            progress_socket::emit(|| {
+                let position = self.bar.position();
+                let length = self.bar.length();
+                #[allow(clippy::cast_precision_loss)]
+                let percent = length
+                    .filter(|length| *length > 0)
+                    .map(|length| position as f64 / length as f64 * 100.0);
+                Event::Progress {
+                    position,
+                    length,
+                    percent,
+                }
+            });
+        }
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish();
+    }
+
+    pub fn finish_and_clear(&self) {
+        self.bar.finish_and_clear();
+        self.multi.remove(&self.bar);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_secs(0)), "[00:00:00]");
+        assert_eq!(format_elapsed(Duration::from_secs(83)), "[00:01:23]");
+        assert_eq!(
+            format_elapsed(Duration::from_secs(3 * 3600 + 61)),
+            "[03:01:01]"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Machine-readable progress events on a Unix socket for `--progress-socket`.
+//!
+//! Every client which connects gets one JSON object per line for each step, item and change in
+//! progress, so wrappers can show their own progress without parsing the terminal output. A
+//! client which connects late first gets the event for the current step.
+
+use std::{
+    io::{self, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+/// How long a write to a client may block before the client is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+static SOCKET: OnceLock<Socket> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A new step of the run started.
+    Step {
+        step: usize,
+        steps: usize,
+        name: String,
+    },
+    /// A worker started on an item of the current step, usually a repo.
+    Item {
+        item: String,
+    },
+    /// The progress of the current step changed.
+    Progress {
+        position: u64,
+        length: Option<u64>,
+        percent: Option<f64>,
+    },
+    /// A message about the current step.
+    Message {
+        message: String,
+    },
+    Done,
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug)]
+struct Socket {
+    path: PathBuf,
+    clients: Mutex<Vec<UnixStream>>,
+    step: Mutex<Option<String>>,
+}
+
+/// Listen on a Unix socket at `path` and send every event to the clients which connect to it.
+/// A stale socket left at `path` by an earlier run is replaced.
+pub fn serve(path: &Path) -> io::Result<()> {
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    let socket = SOCKET.get_or_init(|| Socket {
+        path: path.to_path_buf(),
+        clients: Mutex::default(),
+        step: Mutex::default(),
+    });
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() {
+                continue;
+            }
+
+            // hold the lock so the step can't change before the client is added
+            let step = socket.step.lock().unwrap();
+            if let Some(line) = &*step {
+                if stream.write_all(line.as_bytes()).is_err() {
+                    continue;
+                }
+            }
+            socket.clients.lock().unwrap().push(stream);
+        }
+    });
+
+    Ok(())
+}
+
+/// Send an event to every connected client, if the socket is being served. The event is only
+/// created if it is sent.
+pub fn emit(event: impl FnOnce() -> Event) {
+    let Some(socket) = SOCKET.get() else {
+        return;
+    };
+
+    let event = event();
+    let mut line = serde_json::to_string(&event).expect("events can always be serialized");
+    line.push('\n');
+
+    let mut step = socket.step.lock().unwrap();
+    if matches!(event, Event::Step { .. }) {
+        *step = Some(line.clone());
+    }
+
+    // clients which can't keep up or went away are dropped
+    socket
+        .clients
+        .lock()
+        .unwrap()
+        .retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+}
+
+/// Remove the socket file, if the socket is being served.
+pub fn close() {
+    if let Some(socket) = SOCKET.get() {
+        std::fs::remove_file(&socket.path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        assert_eq!(
+            serde_json::to_string(&Event::Step {
+                step: 2,
+                steps: 5,
+                name: "Cloning and/or pulling repos".into(),
+            })
+            .unwrap(),
+            r#"{"type":"step","step":2,"steps":5,"name":"Cloning and/or pulling repos"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Event::Progress {
+                position: 3,
+                length: Some(4),
+                percent: Some(75.0),
+            })
+            .unwrap(),
+            r#"{"type":"progress","position":3,"length":4,"percent":75.0}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Event::Done).unwrap(),
+            r#"{"type":"done"}"#
+        );
+    }
+}
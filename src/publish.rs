@@ -0,0 +1,410 @@
+//! Uploading the rendered video once it is done, for teams which automate their videos.
+//!
+//! Credentials are read from the environment:
+//! - YouTube: `YOUTUBE_CLIENT_ID`, `YOUTUBE_CLIENT_SECRET` and `YOUTUBE_REFRESH_TOKEN`, from an
+//!   OAuth client with the `youtube.upload` scope
+//! - S3: `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and optionally `AWS_SESSION_TOKEN`,
+//!   `AWS_REGION` and `AWS_ENDPOINT_URL` for S3-compatible services
+
+#![allow(clippy::doc_markdown)]
+
+use std::{
+    fmt::{Display, Write},
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use reqwest::{header::LOCATION, Body, Client};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    calendar,
+    error::{GourcersError, Result},
+    network::NetworkOptions,
+};
+
+const YOUTUBE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const YOUTUBE_UPLOAD_URL: &str =
+    "https://www.googleapis.com/upload/youtube/v3/videos?uploadType=resumable&part=snippet,status";
+
+/// Where to upload the rendered video.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    /// An unlisted video on the YouTube channel the OAuth credentials belong to.
+    Youtube,
+    /// An object in an S3 bucket.
+    S3 { bucket: String, key: String },
+}
+
+impl FromStr for Destination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "youtube" {
+            return Ok(Self::Youtube);
+        }
+
+        let Some(path) = s.strip_prefix("s3://") else {
+            return Err(format!("expected `youtube` or `s3://bucket/key`: {s}"));
+        };
+
+        match path.split_once('/') {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok(Self::S3 {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            }),
+            _ => Err(format!(
+                "S3 destinations must include a bucket and key: {s}"
+            )),
+        }
+    }
+}
+
+impl Display for Destination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Youtube => write!(f, "YouTube"),
+            Self::S3 { bucket, key } => write!(f, "s3://{bucket}/{key}"),
+        }
+    }
+}
+
+/// Upload the video, returning the URL it can be found at.
+pub async fn publish(
+    destination: &Destination,
+    video: &Path,
+    network: &NetworkOptions,
+) -> Result<String> {
+    let client = network
+        .client_builder(Client::builder())?
+        .user_agent("gourcers-ng")
+        .build()
+        .map_err(GourcersError::network("failed to build reqwest client"))?;
+
+    match destination {
+        Destination::Youtube => upload_youtube(&client, video).await,
+        Destination::S3 { bucket, key } => upload_s3(&client, video, bucket, key).await,
+    }
+}
+
+fn env(name: &'static str) -> Result<String> {
+    std::env::var(name).map_err(|_| GourcersError::MissingCredential(name))
+}
+
+async fn open(video: &Path) -> Result<(tokio::fs::File, u64)> {
+    let file = tokio::fs::File::open(video)
+        .await
+        .map_err(GourcersError::io(format!(
+            "failed to open {}",
+            video.display()
+        )))?;
+    let len = file
+        .metadata()
+        .await
+        .map_err(GourcersError::io(format!(
+            "failed to read {}",
+            video.display()
+        )))?
+        .len();
+
+    Ok((file, len))
+}
+
+async fn upload_youtube(client: &Client, video: &Path) -> Result<String> {
+    #[derive(Deserialize)]
+    struct Token {
+        access_token: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Video {
+        id: String,
+    }
+
+    let token: Token = client
+        .post(YOUTUBE_TOKEN_URL)
+        .form(&[
+            ("client_id", env("YOUTUBE_CLIENT_ID")?),
+            ("client_secret", env("YOUTUBE_CLIENT_SECRET")?),
+            ("refresh_token", env("YOUTUBE_REFRESH_TOKEN")?),
+            ("grant_type", "refresh_token".into()),
+        ])
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(GourcersError::network(
+            "failed to get a YouTube access token",
+        ))?
+        .json()
+        .await
+        .map_err(GourcersError::network(
+            "failed to read YouTube access token",
+        ))?;
+
+    let (file, len) = open(video).await?;
+
+    let title = video
+        .file_stem()
+        .map_or_else(|| "gourcers".into(), |stem| stem.to_string_lossy());
+
+    // resumable uploads start with a request for the URL to send the video to
+    let session = client
+        .post(YOUTUBE_UPLOAD_URL)
+        .bearer_auth(&token.access_token)
+        .header("X-Upload-Content-Type", "video/*")
+        .header("X-Upload-Content-Length", len)
+        .json(&serde_json::json!({
+            "snippet": { "title": title },
+            "status": { "privacyStatus": "unlisted" },
+        }))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(GourcersError::network("failed to start YouTube upload"))?;
+
+    let upload_url = session
+        .headers()
+        .get(LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| GourcersError::Publish("YouTube did not return an upload URL".into()))?
+        .to_string();
+
+    let uploaded: Video = client
+        .put(upload_url)
+        .bearer_auth(&token.access_token)
+        .header("Content-Length", len)
+        .body(Body::from(file))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(GourcersError::network("failed to upload video to YouTube"))?
+        .json()
+        .await
+        .map_err(GourcersError::network(
+            "failed to read YouTube upload response",
+        ))?;
+
+    Ok(format!("https://youtu.be/{}", uploaded.id))
+}
+
+async fn upload_s3(client: &Client, video: &Path, bucket: &str, key: &str) -> Result<String> {
+    let access_key = env("AWS_ACCESS_KEY_ID")?;
+    let secret_key = env("AWS_SECRET_ACCESS_KEY")?;
+    let session_token = std::env::var("AWS_SESSION_TOKEN").ok();
+    let region = std::env::var("AWS_REGION")
+        .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+        .unwrap_or_else(|_| "us-east-1".into());
+
+    let key = uri_encode(key);
+
+    // custom endpoints are usually S3-compatible services, which don't all support
+    // virtual-hosted buckets
+    let (host, uri, url) = if let Ok(endpoint) = std::env::var("AWS_ENDPOINT_URL") {
+        let endpoint = endpoint.trim_end_matches('/');
+        let host = endpoint
+            .split_once("://")
+            .map_or(endpoint, |(_, host)| host)
+            .to_string();
+        let uri = format!("/{bucket}/{key}");
+        let url = format!("{endpoint}{uri}");
+        (host, uri, url)
+    } else {
+        let host = format!("{bucket}.s3.{region}.amazonaws.com");
+        let uri = format!("/{key}");
+        let url = format!("https://{host}{uri}");
+        (host, uri, url)
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before 1970")
+        .as_secs();
+    let (date, timestamp) = amz_date(now);
+
+    let mut headers = vec![
+        ("host", host),
+        ("x-amz-content-sha256", "UNSIGNED-PAYLOAD".to_string()),
+        ("x-amz-date", timestamp.clone()),
+    ];
+    if let Some(token) = session_token {
+        headers.push(("x-amz-security-token", token));
+    }
+
+    let canonical = canonical_request("PUT", &uri, &headers, "UNSIGNED-PAYLOAD");
+    let scope = format!("{date}/{region}/s3/aws4_request");
+    let signature = signature(&secret_key, &date, &region, &timestamp, &canonical);
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let (file, len) = open(video).await?;
+
+    let mut request = client
+        .put(&url)
+        .header(
+            "Authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={access_key}/{scope}, SignedHeaders={signed_headers}, Signature={signature}"
+            ),
+        )
+        .header("Content-Length", len)
+        .body(Body::from(file));
+
+    for (name, value) in &headers {
+        if *name != "host" {
+            request = request.header(*name, value);
+        }
+    }
+
+    request
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(GourcersError::network("failed to upload video to S3"))?;
+
+    Ok(url)
+}
+
+/// Percent-encode everything except unreserved characters and `/`, as S3 expects in paths.
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Build an AWS Signature Version 4 canonical request without a query string. `headers` must be
+/// sorted by name and have lowercase names.
+fn canonical_request(
+    method: &str,
+    uri: &str,
+    headers: &[(&str, String)],
+    payload_hash: &str,
+) -> String {
+    let mut canonical_headers = String::new();
+    for (name, value) in headers {
+        writeln!(canonical_headers, "{name}:{}", value.trim())
+            .expect("writing to a String cannot fail");
+    }
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    format!("{method}\n{uri}\n\n{canonical_headers}\n{signed_headers}\n{payload_hash}")
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Sign a canonical request for S3 with AWS Signature Version 4.
+fn signature(
+    secret_key: &str,
+    date: &str,
+    region: &str,
+    timestamp: &str,
+    canonical: &str,
+) -> String {
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{timestamp}\n{date}/{region}/s3/aws4_request\n{}",
+        hex::encode(Sha256::digest(canonical.as_bytes()))
+    );
+
+    let key = hmac(format!("AWS4{secret_key}").as_bytes(), date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, "s3");
+    let key = hmac(&key, "aws4_request");
+
+    hex::encode(hmac(&key, &string_to_sign))
+}
+
+/// Format a Unix timestamp as the date (`20130524`) and time (`20130524T000000Z`) AWS expects.
+fn amz_date(secs: u64) -> (String, String) {
+    let (year, month, day) = calendar::date(i64::try_from(secs).expect("timestamp is in range"));
+    let secs_of_day = secs % 86_400;
+
+    let date = format!("{year:04}{month:02}{day:02}");
+    let time = format!(
+        "{date}T{:02}{:02}{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    );
+
+    (date, time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_destination() {
+        assert_eq!("youtube".parse(), Ok(Destination::Youtube));
+        assert_eq!(
+            "s3://retros/2024/week-1.mp4".parse(),
+            Ok(Destination::S3 {
+                bucket: "retros".into(),
+                key: "2024/week-1.mp4".into(),
+            })
+        );
+        assert!("s3://retros".parse::<Destination>().is_err());
+        assert!("vimeo".parse::<Destination>().is_err());
+    }
+
+    #[test]
+    fn test_amz_date() {
+        assert_eq!(
+            amz_date(1_369_353_600),
+            ("20130524".into(), "20130524T000000Z".into())
+        );
+        assert_eq!(
+            amz_date(951_782_400 + 3661),
+            ("20000229".into(), "20000229T010101Z".into())
+        );
+    }
+
+    #[test]
+    fn test_signature() {
+        // the GET Object example from the S3 Signature Version 4 documentation
+        let headers = [
+            ("host", "examplebucket.s3.amazonaws.com".to_string()),
+            ("range", "bytes=0-9".to_string()),
+            (
+                "x-amz-content-sha256",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string(),
+            ),
+            ("x-amz-date", "20130524T000000Z".to_string()),
+        ];
+        let canonical = canonical_request(
+            "GET",
+            "/test.txt",
+            &headers,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        );
+
+        assert_eq!(
+            signature(
+                "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY",
+                "20130524",
+                "us-east-1",
+                "20130524T000000Z",
+                &canonical
+            ),
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+        );
+    }
+}
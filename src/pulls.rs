@@ -0,0 +1,174 @@
+//! Showing merged pull requests alongside commits.
+//!
+//! Every merged pull request becomes a file in a `PRs` directory of its repo, added by the pull
+//! request's author when it was merged, so review activity shows up in the video. Gource colors
+//! the files by extension, so they are named `<number>.pr` to stand out from the repo's own files.
+
+use lazy_regex::regex;
+use serde::Deserialize;
+
+use crate::{
+    calendar,
+    error::{GourcersError, Result},
+    github::{self, Owner, Repo, RepoKind},
+    history::Since,
+    http_cache::ResponseCache,
+    log::LogEntry,
+    log_file,
+    progress::Progress,
+    Context,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullRequest {
+    pub number: u64,
+    pub user: Option<Owner>,
+    /// When the pull request was merged, as an ISO 8601 timestamp, or `None` if it was closed
+    /// without merging.
+    pub merged_at: Option<String>,
+}
+
+/// Add the merged pull requests of each repo to its gource log.
+pub async fn add_to_logs(cx: &Context, repos: &[Repo], progress: &Progress) -> Result<()> {
+    let client = github::client(cx)?;
+    let cache = ResponseCache::new(cx.data_dir.api_cache_dir());
+
+    // wikis and gists don't have pull requests
+    for repo in repos.iter().filter(|repo| repo.kind == RepoKind::Repo) {
+        let url = format!(
+            "{}/repos/{}/pulls?state=closed&per_page=100",
+            cx.api_url,
+            repo.full_name()
+        );
+        let pulls: Vec<PullRequest> = github::list_pages(
+            &client,
+            &cache,
+            url,
+            &format!("{}'s pull requests", repo.full_name()),
+            progress,
+        )
+        .await?;
+
+        let prefix = cx.groups.prefix(repo) + &cx.tree_layout.prefix(repo);
+        let entries = entries(&pulls, &prefix, cx.history_since);
+        debug!(repo = %repo.full_name(), "adding {} merged pull requests", entries.len());
+
+        let path = cx.data_dir.gource_log(repo);
+        let log = log_file::read(&path).map_err(GourcersError::io(format!(
+            "failed to read gource log for {}",
+            repo.full_name()
+        )))?;
+        log_file::write(&path, &merge(&log, &entries), cx.config.compress_logs, None).map_err(
+            GourcersError::io(format!(
+                "failed to write gource log for {}",
+                repo.full_name()
+            )),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The log entries for the merged pull requests, under `prefix`, leaving out the ones merged
+/// before `since`.
+#[must_use]
+pub fn entries(pulls: &[PullRequest], prefix: &str, since: Option<Since>) -> Vec<String> {
+    pulls
+        .iter()
+        .filter_map(|pull| {
+            let timestamp = parse_timestamp(pull.merged_at.as_deref()?)?;
+            if since.is_some_and(|since| timestamp < since.0) {
+                return None;
+            }
+            let author = pull.user.as_ref().map_or("ghost", |user| &user.login);
+
+            Some(format!(
+                "{timestamp}|{author}|A|{prefix}/PRs/{}.pr",
+                pull.number
+            ))
+        })
+        .collect()
+}
+
+/// Insert the entries into the log, keeping it in chronological order.
+#[must_use]
+pub fn merge(log: &str, entries: &[String]) -> String {
+    let mut lines = log
+        .lines()
+        .chain(entries.iter().map(String::as_str))
+        .filter_map(|line| LogEntry::parse(line).map(|entry| (entry.timestamp, line)))
+        .collect::<Vec<_>>();
+    // the sort is stable, so entries at the same time as commits come after them
+    lines.sort_by_key(|&(timestamp, _)| timestamp);
+
+    let mut merged =
+        String::with_capacity(log.len() + entries.iter().map(String::len).sum::<usize>());
+    for (_, line) in lines {
+        merged.push_str(line);
+        merged.push('\n');
+    }
+
+    merged
+}
+
+/// Parse an ISO 8601 timestamp in UTC like the API returns, e.g. `2024-03-01T12:00:00Z`.
+fn parse_timestamp(timestamp: &str) -> Option<i64> {
+    let captures =
+        regex!(r"^(\d{4})-(\d{2})-(\d{2})T(\d{2}):(\d{2}):(\d{2})Z$").captures(timestamp)?;
+    let number = |x: usize| captures[x].parse::<i64>().ok();
+
+    Some(
+        calendar::from_date(number(1)?, number(2)?, number(3)?)
+            + number(4)? * 3600
+            + number(5)? * 60
+            + number(6)?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries() {
+        let pull = |number, merged_at: Option<&str>| PullRequest {
+            number,
+            user: Some(Owner {
+                login: "octocat".into(),
+                account_type: "User".into(),
+            }),
+            merged_at: merged_at.map(ToString::to_string),
+        };
+        let pulls = [
+            pull(1, Some("2019-12-31T23:59:59Z")),
+            pull(2, None),
+            pull(3, Some("2024-03-01T12:00:00Z")),
+        ];
+
+        assert_eq!(
+            entries(&pulls, "/repo", None),
+            [
+                "1577836799|octocat|A|/repo/PRs/1.pr",
+                "1709294400|octocat|A|/repo/PRs/3.pr"
+            ]
+        );
+        assert_eq!(
+            entries(&pulls, "/repo", Some(Since(1_577_836_800))),
+            ["1709294400|octocat|A|/repo/PRs/3.pr"]
+        );
+    }
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(
+            merge(
+                "100|a|A|/repo/x\n300|a|M|/repo/x\n",
+                &[
+                    "300|b|A|/repo/PRs/2.pr".into(),
+                    "200|b|A|/repo/PRs/1.pr".into()
+                ]
+            ),
+            "100|a|A|/repo/x\n200|b|A|/repo/PRs/1.pr\n300|a|M|/repo/x\n300|b|A|/repo/PRs/2.pr\n"
+        );
+    }
+}
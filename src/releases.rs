@@ -0,0 +1,113 @@
+//! Captions for releases, so version milestones appear while the video plays.
+//!
+//! Every tag which looks like a version, e.g. `v1.2.0` or `release-2.0`, becomes a caption at the
+//! time it was created, which is the tagger date of annotated tags and the commit date of
+//! lightweight tags. The captions are merged with the ones given with `--captions`.
+
+use lazy_regex::regex;
+
+use crate::{
+    captions::{self, Caption},
+    error::{GourcersError, Result},
+    github::{Repo, RepoKind},
+    Context,
+};
+
+/// The arguments to `git tag` which list every tag as `timestamp|name`.
+const TAG_ARGS: &[&str] = &[
+    "tag",
+    "--list",
+    "--format=%(creatordate:unix)|%(refname:short)",
+];
+
+/// Write the captions for the releases of `repos` to the caption file gource reads.
+pub async fn write_captions(cx: &Context, repos: &[Repo]) -> Result<()> {
+    let mut captions = match &cx.user_captions {
+        Some(path) => captions::parse(&std::fs::read_to_string(path).map_err(
+            GourcersError::io(format!("failed to read {}", path.display())),
+        )?),
+        None => Vec::new(),
+    };
+
+    for repo in repos.iter().filter(|repo| repo.kind == RepoKind::Repo) {
+        let tags = list_tags(cx, repo).await?;
+        captions.extend(
+            releases(&tags)
+                .into_iter()
+                .filter(|caption| {
+                    cx.history_since
+                        .is_none_or(|since| caption.timestamp >= since.0)
+                })
+                .map(|caption| Caption {
+                    text: format!("{} {}", repo.name, caption.text),
+                    ..caption
+                }),
+        );
+    }
+    captions.sort_by_key(|caption| caption.timestamp);
+
+    let path = cx.data_dir.release_captions();
+    debug!("writing {} captions to {}", captions.len(), path.display());
+    std::fs::write(&path, captions::to_file(&captions)).map_err(GourcersError::io(format!(
+        "failed to write {}",
+        path.display()
+    )))
+}
+
+/// The output of `git tag` for the repo's clone.
+async fn list_tags(cx: &Context, repo: &Repo) -> Result<String> {
+    let output = tokio::process::Command::new("git")
+        .arg("-C")
+        .arg(cx.data_dir.repo_dir(repo))
+        .args(TAG_ARGS)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(GourcersError::io(format!(
+            "failed to run git tag for {}",
+            repo.full_name()
+        )))?;
+
+    if !output.status.success() {
+        return Err(GourcersError::Git {
+            repo: repo.full_name(),
+            subcommand: "tag".into(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The tags listed by `git tag` which look like versions, as captions with the tag's name.
+#[must_use]
+pub fn releases(tags: &str) -> Vec<Caption> {
+    captions::parse(tags)
+        .into_iter()
+        .filter(|caption| regex!(r"\d+\.\d+").is_match(&caption.text))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_releases() {
+        let tags = "1700000000|v1.2.0\n1600000000|release-0.9\n1650000000|nightly\n|v2.0\n";
+
+        assert_eq!(
+            releases(tags),
+            [
+                Caption {
+                    timestamp: 1_600_000_000,
+                    text: "release-0.9".into()
+                },
+                Caption {
+                    timestamp: 1_700_000_000,
+                    text: "v1.2.0".into()
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,840 @@
+//! A file format which describes a set of rules which decide which repos to include and ignore.
+//!
+//! The format is a selector, followed by the name of the value in the given selector.
+//!
+//! The selector can be one of:
+//! - `*`: matches all repos
+//! - `owner`: the owner of the repo
+//! - `name`: the name of the repo
+//! - `full_name`: the full name of the repo, which is the owner and name separated by a slash
+//! - `is_fork`: whether the repo is a fork
+//! - `public`: whether the repo is public
+//! - `team`: a team with access to the repo, either its slug or `org/slug`
+//!
+//! The value is a string which is matched against the value of the selector. Several values can be
+//! given separated by commas, which is the same as writing the rule once for each value.
+//!
+//! Writing `/*` after an owner, e.g. `owner:rust-lang/*`, also lists that owner's public repos, so
+//! repos which the token's account has no access to can be included too.
+//!
+//! Owners are always compared ignoring case, like GitHub does. Adding `/i` to the selector, e.g.
+//! `name/i:rust`, ignores case for the whole value.
+//!
+//! How include and exclude rules combine is decided by the [`Precedence`]. By default, a repo is
+//! kept if any include matches it and no exclude does, regardless of the order of the rules. A
+//! different precedence can be chosen with a `# mode: <precedence>` comment before the first rule,
+//! e.g. `# mode: ordered` to let later rules override earlier ones like in a gitignore file. When
+//! several rules files are combined, the mode of the last one with a header is used.
+//!
+//! Examples:
+//! - `*:*`
+//! - `name:rust`
+//! - `!owner:rust-lang`
+//! - `full_name:rust-lang/rust`
+//! - `is_fork:true`
+//! - `public:false`
+//! - `!name:test,playground,scratch`
+//! - `full_name/i:Rust-Lang/Rust`
+//! - `owner:rust-lang/*`
+
+use std::{fmt::Display, str::FromStr};
+
+use clap::ValueEnum;
+use thiserror::Error;
+
+use crate::github::Repo;
+
+#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Error)]
+pub enum ErrorKind {
+    #[error("Invalid selector: {0:?}")]
+    InvalidSelector(Option<String>),
+    #[error("Value must be a bool: {0}")]
+    InvalidBool(String),
+    #[error("Selector has no value: {0}")]
+    MissingValue(String),
+    #[error("Unknown mode: {0}")]
+    InvalidMode(String),
+}
+
+#[derive(Debug, Error)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub line: usize,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl From<(usize, ErrorKind)> for Error {
+    fn from((line, kind): (usize, ErrorKind)) -> Self {
+        Self { kind, line }
+    }
+}
+
+/// How include and exclude rules are combined.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Precedence {
+    /// The first rule which matches a repo decides whether it is kept.
+    FirstMatch,
+    /// A repo is kept if any include matches it and no exclude does.
+    #[default]
+    ExcludeWins,
+    /// The last rule which matches a repo decides whether it is kept, so later rules override
+    /// earlier ones.
+    Ordered,
+}
+
+/// Whether a rule includes or excludes the repos it matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Include,
+    Exclude,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub action: Action,
+    pub entry: Entry,
+}
+
+impl Rule {
+    #[must_use]
+    pub fn include(entry: Entry) -> Self {
+        Self {
+            action: Action::Include,
+            entry,
+        }
+    }
+
+    #[must_use]
+    pub fn exclude(entry: Entry) -> Self {
+        Self {
+            action: Action::Exclude,
+            entry,
+        }
+    }
+}
+
+/// Writes the rule the way it would be written in a rules file, e.g. `!name:test`.
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.action {
+            Action::Include => write!(f, "{}", self.entry),
+            Action::Exclude => write!(f, "!{}", self.entry),
+        }
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RuleSet {
+    /// The rules in the order they were written.
+    pub(crate) rules: Vec<Rule>,
+    pub(crate) precedence: Precedence,
+    /// Whether the precedence was set by a `# mode:` header or [`RuleSet::set_precedence`],
+    /// rather than being the default.
+    pub(crate) precedence_set: bool,
+}
+
+impl FromStr for RuleSet {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, None)
+    }
+}
+
+impl RuleSet {
+    /// Parse rules read from `origin`, recording where each rule came from so it can be reported
+    /// by [`RuleSet::unused`].
+    pub fn parse_with_origin(s: &str, origin: &str) -> Result<Self, Error> {
+        Self::parse(s, Some(origin))
+    }
+
+    fn parse(s: &str, origin: Option<&str>) -> Result<Self, Error> {
+        let mut rule_set = Self::new();
+
+        for (x, line) in s.lines().enumerate() {
+            let line_number = x + 1;
+            let mut line = line.trim();
+
+            // the mode can only be set in the comments before the first rule
+            if let Some(mode) = line
+                .strip_prefix('#')
+                .and_then(|comment| comment.trim().strip_prefix("mode:"))
+                .filter(|_| rule_set.rules.is_empty())
+            {
+                let mode = mode.trim();
+                rule_set.precedence = Precedence::from_str(mode, true)
+                    .map_err(|_| Error::from((line_number, ErrorKind::InvalidMode(mode.into()))))?;
+                rule_set.precedence_set = true;
+                continue;
+            }
+
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            let exclude = line.starts_with('!');
+            if exclude {
+                line = &line[1..];
+            }
+
+            let mut entries = Entry::parse_list(line).map_err(|e| Error::from((line_number, e)))?;
+
+            if let Some(origin) = origin {
+                for entry in &mut entries {
+                    entry.source = Some(Source {
+                        origin: origin.to_string(),
+                        line: line_number,
+                    });
+                }
+            }
+
+            let rule = if exclude {
+                Rule::exclude
+            } else {
+                Rule::include
+            };
+            rule_set.rules.extend(entries.into_iter().map(rule));
+        }
+
+        Ok(rule_set)
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub enum IncludeResult<'a> {
+    /// The repository was explicitly included and no rules excluded it.
+    Include(&'a Entry),
+    /// The repository was explicitly included but a rule excluded it.
+    Exclude(&'a Entry, &'a Entry),
+    /// No inclusions matched the repository, so it should be excluded.
+    Default,
+}
+
+impl IncludeResult<'_> {
+    #[inline]
+    #[must_use]
+    pub fn keep(&self) -> bool {
+        matches!(self, Self::Include(_))
+    }
+}
+
+impl RuleSet {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            precedence: Precedence::default(),
+            precedence_set: false,
+        }
+    }
+
+    /// Add the rules of `other` after the rules of this set. If `other` sets its precedence, it
+    /// replaces the precedence of this set, so a later file can change the mode of earlier ones.
+    pub fn merge(&mut self, other: Self) {
+        self.rules.extend(other.rules);
+        if other.precedence_set {
+            self.set_precedence(other.precedence);
+        }
+    }
+
+    /// Ignore case in every rule, as if they were all written with `/i`.
+    pub fn ignore_case(&mut self) {
+        for rule in &mut self.rules {
+            rule.entry.ignore_case = true;
+        }
+    }
+
+    pub fn set_precedence(&mut self, precedence: Precedence) {
+        self.precedence = precedence;
+        self.precedence_set = true;
+    }
+
+    pub fn includes(&self) -> impl Iterator<Item = &Entry> {
+        self.with_action(Action::Include)
+    }
+
+    pub fn excludes(&self) -> impl Iterator<Item = &Entry> {
+        self.with_action(Action::Exclude)
+    }
+
+    /// Whether any rule selects repos by team, which needs the teams of each repo to be fetched.
+    #[must_use]
+    pub fn uses_teams(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.entry.selector == Selector::Team)
+    }
+
+    /// The owners whose repos should be listed along with the repos the token has access to,
+    /// from include rules written as `owner:<login>/*`.
+    #[must_use]
+    pub fn listed_owners(&self) -> Vec<&str> {
+        let mut owners = Vec::<&str>::new();
+
+        for entry in self.includes().filter(|entry| entry.list_owner) {
+            if !owners
+                .iter()
+                .any(|owner| owner.eq_ignore_ascii_case(&entry.value))
+            {
+                owners.push(&entry.value);
+            }
+        }
+
+        owners
+    }
+
+    /// The rules on one line, the way they would be written in a rules file, followed by the
+    /// mode if it isn't the default, e.g. `*:*, !owner:alice (mode: ordered)`.
+    #[must_use]
+    pub fn summary(&self) -> String {
+        let rules = self
+            .rules
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        match self.precedence.to_possible_value() {
+            Some(mode) if self.precedence != Precedence::default() => {
+                format!("{rules} (mode: {})", mode.get_name())
+            }
+            _ => rules,
+        }
+    }
+
+    fn with_action(&self, action: Action) -> impl Iterator<Item = &Entry> {
+        self.rules
+            .iter()
+            .filter(move |rule| rule.action == action)
+            .map(|rule| &rule.entry)
+    }
+
+    pub fn apply(&self, repos: &mut Vec<Repo>) {
+        repos.retain(|r| {
+            let res = self.test(r);
+            match res {
+                IncludeResult::Exclude(inclusion, exclusion) => {
+                    debug!(
+                        "excluding repo {}: {} but {}",
+                        r.full_name.as_ref().unwrap_or(&r.name),
+                        inclusion.describe(),
+                        exclusion.describe()
+                    );
+                }
+                IncludeResult::Include(inclusion) => {
+                    debug!(
+                        "including repo {}: {}",
+                        r.full_name.as_ref().unwrap_or(&r.name),
+                        inclusion.describe()
+                    );
+                }
+                IncludeResult::Default => {
+                    debug!(
+                        "ignoring repo {}: no rules matched",
+                        r.full_name.as_ref().unwrap_or(&r.name)
+                    );
+                }
+            }
+            res.keep()
+        });
+    }
+
+    /// The rules which didn't match any of the given repos, which are usually typos. An exclusion
+    /// only counts as matching if the repo was also included, since otherwise it has no effect.
+    #[must_use]
+    pub fn unused(&self, repos: &[Repo]) -> Vec<&Rule> {
+        let included = repos
+            .iter()
+            .filter(|repo| self.includes().any(|entry| entry.matches(repo)))
+            .collect::<Vec<_>>();
+
+        self.rules
+            .iter()
+            .filter(|rule| match rule.action {
+                Action::Include => !repos.iter().any(|repo| rule.entry.matches(repo)),
+                Action::Exclude => !included.iter().any(|repo| rule.entry.matches(repo)),
+            })
+            .collect()
+    }
+
+    /// Returns the entry which matches the given repo, if any.
+    ///
+    /// With [`Precedence::ExcludeWins`], a repo which is included but matches an exclusion is
+    /// ignored. With [`Precedence::FirstMatch`], it is only ignored if the exclusion comes before
+    /// every inclusion which matches it, and with [`Precedence::Ordered`] if it comes after.
+    #[must_use]
+    pub fn test(&self, repo: &Repo) -> IncludeResult<'_> {
+        let Some(inclusion) = self.includes().find(|entry| entry.matches(repo)) else {
+            return IncludeResult::Default;
+        };
+
+        let exclusion = match self.precedence {
+            Precedence::ExcludeWins => self.excludes().find(|entry| entry.matches(repo)),
+            Precedence::FirstMatch => self
+                .rules
+                .iter()
+                .find(|rule| rule.entry.matches(repo))
+                .filter(|rule| rule.action == Action::Exclude)
+                .map(|rule| &rule.entry),
+            Precedence::Ordered => self
+                .rules
+                .iter()
+                .rfind(|rule| rule.entry.matches(repo))
+                .filter(|rule| rule.action == Action::Exclude)
+                .map(|rule| &rule.entry),
+        };
+
+        match exclusion {
+            Some(exclusion) => IncludeResult::Exclude(inclusion, exclusion),
+            None => IncludeResult::Include(inclusion),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub(crate) selector: Selector,
+    pub(crate) value: String,
+    pub(crate) ignore_case: bool,
+    /// Whether the owner's repos are listed too, for rules written as `owner:<login>/*`.
+    pub(crate) list_owner: bool,
+    /// Where the rule was written, if it was read with [`RuleSet::parse_with_origin`].
+    pub(crate) source: Option<Source>,
+}
+
+/// The file and line a rule was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub origin: String,
+    pub line: usize,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {} of {}", self.line, self.origin)
+    }
+}
+
+impl FromStr for Entry {
+    type Err = ErrorKind;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut parts = line.splitn(2, ':');
+
+        let mut selector_part = parts.next();
+
+        let ignore_case = selector_part.is_some_and(|part| part.ends_with("/i"));
+        if ignore_case {
+            selector_part = selector_part.and_then(|part| part.strip_suffix("/i"));
+        }
+
+        let selector = match selector_part {
+            Some("*") => Selector::All,
+            Some("owner") => Selector::Owner,
+            Some("name") => Selector::Name,
+            Some("full_name") => Selector::FullName,
+            Some("is_fork") => Selector::IsFork,
+            Some("public") => Selector::Public,
+            Some("team") => Selector::Team,
+            part => {
+                return Err(ErrorKind::InvalidSelector(part.map(ToString::to_string)));
+            }
+        };
+
+        let value = match parts.next() {
+            Some(value) if !value.is_empty() => value,
+            _ => return Err(ErrorKind::MissingValue(line.to_string())),
+        };
+
+        if matches!(selector, Selector::IsFork | Selector::Public)
+            && value != "true"
+            && value != "false"
+        {
+            return Err(ErrorKind::InvalidBool(value.to_string()));
+        }
+
+        let owner = value
+            .strip_suffix("/*")
+            .filter(|_| selector == Selector::Owner);
+
+        Ok(Entry {
+            ignore_case,
+            list_owner: owner.is_some(),
+            ..Entry::new(selector, &owner.unwrap_or(value))
+        })
+    }
+}
+
+impl Entry {
+    /// Parse a rule with a comma separated list of values into an entry for each value.
+    pub fn parse_list(line: &str) -> Result<Vec<Self>, ErrorKind> {
+        let Some((selector, values)) = line.split_once(':') else {
+            return line.parse().map(|entry| vec![entry]);
+        };
+
+        values
+            .split(',')
+            .map(|value| format!("{selector}:{}", value.trim()).parse())
+            .collect()
+    }
+
+    #[must_use]
+    pub fn describe(&self) -> String {
+        let sel = match self.selector {
+            Selector::All => return "* is enabled".into(),
+            Selector::Owner => "owner",
+            Selector::Name => "name",
+            Selector::FullName => "full_name",
+            Selector::IsFork => "is_fork",
+            Selector::Public => "public",
+            Selector::Team => "team",
+        };
+
+        if self.ignore_case {
+            format!("{} is {:?} ignoring case", sel, self.value)
+        } else {
+            format!("{} is {:?}", sel, self.value)
+        }
+    }
+
+    #[must_use]
+    pub fn new(selector: Selector, value: &impl ToString) -> Self {
+        Self {
+            selector,
+            value: value.to_string(),
+            ignore_case: false,
+            list_owner: false,
+            source: None,
+        }
+    }
+
+    #[must_use]
+    pub fn matches(&self, repo: &Repo) -> bool {
+        let eq = |actual: &str| {
+            if self.ignore_case {
+                actual.eq_ignore_ascii_case(&self.value)
+            } else {
+                actual == self.value
+            }
+        };
+
+        match self.selector {
+            Selector::All => true,
+            // GitHub logins are case-insensitive
+            Selector::Owner => repo.owner.login.eq_ignore_ascii_case(&self.value),
+            Selector::Name => eq(&repo.name),
+            Selector::FullName => {
+                let full_name = repo.full_name();
+                match (full_name.split_once('/'), self.value.split_once('/')) {
+                    (Some((owner, name)), Some((value_owner, value_name))) if !self.ignore_case => {
+                        owner.eq_ignore_ascii_case(value_owner) && name == value_name
+                    }
+                    _ => eq(&full_name),
+                }
+            }
+            Selector::IsFork => repo.fork.to_string() == self.value,
+            Selector::Public => (!repo.private).to_string() == self.value,
+            // team slugs are always lowercase
+            Selector::Team => repo.teams.iter().any(|team| {
+                team.eq_ignore_ascii_case(&self.value)
+                    || team
+                        .split_once('/')
+                        .is_some_and(|(_, slug)| slug.eq_ignore_ascii_case(&self.value))
+            }),
+        }
+    }
+}
+
+/// Writes the rule the way it would be written in a rules file, e.g. `name/i:rust`.
+impl Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sel = match self.selector {
+            Selector::All => "*",
+            Selector::Owner => "owner",
+            Selector::Name => "name",
+            Selector::FullName => "full_name",
+            Selector::IsFork => "is_fork",
+            Selector::Public => "public",
+            Selector::Team => "team",
+        };
+        let suffix = if self.ignore_case { "/i" } else { "" };
+        let listed = if self.list_owner { "/*" } else { "" };
+
+        write!(f, "{sel}{suffix}:{}{listed}", self.value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selector {
+    All,
+    Owner,
+    Name,
+    FullName,
+    IsFork,
+    Public,
+    Team,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rule() {
+        const CASES: &[&str] = &[
+            // valid cases
+            "*:*",
+            "owner:rust-lang",
+            "name:rust",
+            "full_name:rust-lang/rust",
+            "is_fork:true",
+            "public:false",
+            "owner:rust-lang:extra",
+            "owner:spaces are allowed",
+            "name/i:Rust",
+            // invalid cases
+            "invalid",
+            "owner",
+            "owner:",
+            "is_fork:no",
+            "public:yes",
+        ];
+
+        let expected = vec![
+            Ok(Entry::new(Selector::All, &"*")),
+            Ok(Entry::new(Selector::Owner, &"rust-lang")),
+            Ok(Entry::new(Selector::Name, &"rust")),
+            Ok(Entry::new(Selector::FullName, &"rust-lang/rust")),
+            Ok(Entry::new(Selector::IsFork, &"true")),
+            Ok(Entry::new(Selector::Public, &"false")),
+            Ok(Entry::new(Selector::Owner, &"rust-lang:extra")),
+            Ok(Entry::new(Selector::Owner, &"spaces are allowed")),
+            Ok(Entry {
+                ignore_case: true,
+                ..Entry::new(Selector::Name, &"Rust")
+            }),
+            Err(ErrorKind::InvalidSelector(Some("invalid".into()))),
+            Err(ErrorKind::MissingValue("owner".into())),
+            Err(ErrorKind::MissingValue("owner:".into())),
+            Err(ErrorKind::InvalidBool("no".into())),
+            Err(ErrorKind::InvalidBool("yes".into())),
+        ];
+
+        for (case, expected) in CASES.iter().zip(expected) {
+            let actual = case.parse::<Entry>();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_file() {
+        const CONTENTS: &str = r"
+# include all repos
+*:*
+# exclude any private repos
+!public:false
+# exclude forks
+!is_fork:true
+# exclude rust-lang repos
+!owner:rust-lang
+# exclude scratch repos
+!name:test, playground,scratch
+        ";
+
+        let contents = CONTENTS.trim();
+
+        let expected = RuleSet {
+            rules: vec![
+                Rule::include(Entry::new(Selector::All, &"*")),
+                Rule::exclude(Entry::new(Selector::Public, &"false")),
+                Rule::exclude(Entry::new(Selector::IsFork, &"true")),
+                Rule::exclude(Entry::new(Selector::Owner, &"rust-lang")),
+                Rule::exclude(Entry::new(Selector::Name, &"test")),
+                Rule::exclude(Entry::new(Selector::Name, &"playground")),
+                Rule::exclude(Entry::new(Selector::Name, &"scratch")),
+            ],
+            precedence: Precedence::ExcludeWins,
+            precedence_set: false,
+        };
+
+        let actual = contents.parse::<RuleSet>().unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_value_list() {
+        assert_eq!(
+            Entry::parse_list("owner:alice,bob"),
+            Ok(vec![
+                Entry::new(Selector::Owner, &"alice"),
+                Entry::new(Selector::Owner, &"bob"),
+            ])
+        );
+        assert_eq!(
+            Entry::parse_list("owner:alice,"),
+            Err(ErrorKind::MissingValue("owner:".into()))
+        );
+        assert_eq!(
+            Entry::parse_list("is_fork:true,maybe"),
+            Err(ErrorKind::InvalidBool("maybe".into()))
+        );
+    }
+
+    fn repo(owner: &str, name: &str) -> Repo {
+        Repo {
+            id: 1,
+            name: name.into(),
+            full_name: Some(format!("{owner}/{name}")),
+            owner: crate::github::Owner {
+                login: owner.into(),
+                ..crate::github::Owner::default()
+            },
+            size: 1,
+            ..Repo::default()
+        }
+    }
+
+    #[test]
+    fn test_matches_ignoring_case() {
+        let repo = repo("Rust-Lang", "Rust");
+
+        let matches = |rule: &str| rule.parse::<Entry>().unwrap().matches(&repo);
+
+        assert!(matches("owner:rust-lang"));
+        assert!(matches("full_name:rust-lang/Rust"));
+        assert!(!matches("full_name:rust-lang/rust"));
+        assert!(!matches("name:rust"));
+        assert!(matches("name/i:rust"));
+        assert!(matches("full_name/i:rust-lang/rust"));
+    }
+
+    #[test]
+    fn test_unused_rules() {
+        let rules = RuleSet::parse_with_origin(
+            "owner:alice\nname:old-project,tools\n!name/i:TOOLS\n!public:false",
+            "rules.txt",
+        )
+        .unwrap();
+        let repos = [repo("alice", "site"), repo("bob", "tools")];
+
+        let unused = rules
+            .unused(&repos)
+            .iter()
+            .map(|rule| format!("{rule} ({})", rule.entry.source.as_ref().unwrap()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            unused,
+            [
+                "name:old-project (line 2 of rules.txt)",
+                "!public:false (line 4 of rules.txt)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_precedence() {
+        let mut rules = "!name:site\nowner:alice\n!name:tools"
+            .parse::<RuleSet>()
+            .unwrap();
+        let site = repo("alice", "site");
+        let tools = repo("alice", "tools");
+
+        assert!(!rules.test(&site).keep());
+        assert!(!rules.test(&tools).keep());
+
+        rules.set_precedence(Precedence::FirstMatch);
+        assert!(!rules.test(&site).keep());
+        assert!(rules.test(&tools).keep());
+
+        rules.set_precedence(Precedence::Ordered);
+        assert!(rules.test(&site).keep());
+        assert!(!rules.test(&tools).keep());
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        let rules = "# layered rules\n# mode: ordered\n*:*\n!owner:alice\nname:site"
+            .parse::<RuleSet>()
+            .unwrap();
+        assert_eq!(rules.precedence, Precedence::Ordered);
+        assert!(rules.test(&repo("alice", "site")).keep());
+        assert!(!rules.test(&repo("alice", "tools")).keep());
+
+        // only the header sets the mode
+        let rules = "*:*\n# mode: ordered".parse::<RuleSet>().unwrap();
+        assert_eq!(rules.precedence, Precedence::ExcludeWins);
+
+        let error = "# mode: random".parse::<RuleSet>().unwrap_err();
+        assert_eq!(error.line, 1);
+        assert_eq!(error.kind, ErrorKind::InvalidMode("random".into()));
+    }
+
+    #[test]
+    fn test_merge_mode() {
+        let defaults = "# mode: first-match\n*:*".parse::<RuleSet>().unwrap();
+
+        // the later file's header wins
+        let mut rules = defaults.clone();
+        rules.merge("# mode: ordered\n!owner:alice".parse().unwrap());
+        assert_eq!(rules.precedence, Precedence::Ordered);
+        assert_eq!(rules.rules.len(), 2);
+
+        // a file without a header keeps the mode
+        let mut rules = defaults.clone();
+        rules.merge("!owner:alice".parse().unwrap());
+        assert_eq!(rules.precedence, Precedence::FirstMatch);
+    }
+
+    #[test]
+    fn test_summary() {
+        let rules = "# mode: ordered\n*:*\n!owner:alice\nname/i:site,docs"
+            .parse::<RuleSet>()
+            .unwrap();
+        assert_eq!(
+            rules.summary(),
+            "*:*, !owner:alice, name/i:site, name/i:docs (mode: ordered)"
+        );
+        assert_eq!("*:*".parse::<RuleSet>().unwrap().summary(), "*:*");
+    }
+
+    #[test]
+    fn test_listed_owners() {
+        let rules = "owner:rust-lang/*,Rust-Lang/*\nowner:alice\n!owner:bob/*"
+            .parse::<RuleSet>()
+            .unwrap();
+
+        assert_eq!(rules.listed_owners(), ["rust-lang"]);
+        assert_eq!(rules.rules[0].to_string(), "owner:rust-lang/*");
+        assert!(rules.test(&repo("rust-lang", "rust")).keep());
+        assert_eq!(
+            "name:a/*".parse::<Entry>(),
+            Ok(Entry::new(Selector::Name, &"a/*"))
+        );
+    }
+
+    #[test]
+    fn test_team_rules() {
+        let rules = "team:backend\n!team:acme/legacy"
+            .parse::<RuleSet>()
+            .unwrap();
+        assert!(rules.uses_teams());
+
+        let mut repo = repo("acme", "api");
+        assert!(!rules.test(&repo).keep());
+
+        repo.teams = vec!["acme/backend".into()];
+        assert!(rules.test(&repo).keep());
+
+        repo.teams.push("acme/legacy".into());
+        assert!(!rules.test(&repo).keep());
+    }
+}
@@ -0,0 +1,65 @@
+//! Cleaning up author names and paths in generated logs.
+
+use std::fmt::Write;
+
+use clap::ValueEnum;
+
+/// How author names and paths in the logs are cleaned up before rendering.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Sanitize {
+    /// Leave the logs as gource wrote them.
+    None,
+    /// Remove quote characters, keeping everything else, including non-ASCII text.
+    #[default]
+    Quotes,
+    /// Remove quotes and accents, and escape any other non-ASCII characters as `\u{...}`.
+    Ascii,
+}
+
+/// The characters removed from the logs unless sanitizing is disabled.
+const QUOTES: [char; 3] = ['\'', '"', '`'];
+
+impl Sanitize {
+    #[must_use]
+    pub fn apply(self, log: &str) -> String {
+        match self {
+            Self::None => log.to_string(),
+            Self::Quotes => log.chars().filter(|c| !QUOTES.contains(c)).collect(),
+            Self::Ascii => {
+                let log = diacritics::remove_diacritics(log);
+                let mut sanitized = String::with_capacity(log.len());
+
+                for c in log.chars().filter(|c| !QUOTES.contains(c)) {
+                    if c.is_ascii() {
+                        sanitized.push(c);
+                    } else {
+                        write!(sanitized, "\\u{{{:x}}}", u32::from(c))
+                            .expect("writing to a String cannot fail");
+                    }
+                }
+
+                sanitized
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        let log = "100|Zoë \"zo\" O'Brien|A|/repo/日本/café.txt";
+
+        assert_eq!(Sanitize::None.apply(log), log);
+        assert_eq!(
+            Sanitize::Quotes.apply(log),
+            "100|Zoë zo OBrien|A|/repo/日本/café.txt"
+        );
+        assert_eq!(
+            Sanitize::Ascii.apply(log),
+            "100|Zoe zo OBrien|A|/repo/\\u{65e5}\\u{672c}/cafe.txt"
+        );
+    }
+}
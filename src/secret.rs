@@ -0,0 +1,65 @@
+//! A wrapper for sensitive strings such as access tokens.
+
+use std::{convert::Infallible, fmt, str::FromStr};
+
+const REDACTED: &str = "***";
+
+/// A string which is never printed by its [`Debug`](fmt::Debug) implementation.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Access the secret value. Only use this where the value is actually needed.
+    #[must_use]
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Replace every occurrence of the secret in `text`.
+    #[must_use]
+    pub fn redact(&self, text: &str) -> String {
+        if self.0.is_empty() {
+            return text.to_string();
+        }
+
+        text.replace(&self.0, REDACTED)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl FromStr for Secret {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let secret: Secret = "ghp_abc123".parse().unwrap();
+        assert_eq!(format!("{secret:?}"), "***");
+        assert_eq!(secret.expose(), "ghp_abc123");
+    }
+
+    #[test]
+    fn test_redact() {
+        let secret: Secret = "ghp_abc123".parse().unwrap();
+        assert_eq!(
+            secret.redact("https://ghp_abc123@github.com failed: ghp_abc123"),
+            "https://***@github.com failed: ***"
+        );
+
+        let empty: Secret = "".parse().unwrap();
+        assert_eq!(empty.redact("nothing to hide"), "nothing to hide");
+    }
+}
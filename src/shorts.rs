@@ -0,0 +1,108 @@
+//! Vertical "shorts" clips of the busiest part of rendered videos, for sharing on social media.
+//!
+//! The clip starts where the most commits are shown within its length, where a commit is every
+//! entry by the same author at the same time, and is timed with the log's [`Timeline`]. It is cut
+//! from the finished video, cropped around the center to 9:16, and titled with `--title`.
+
+use std::{collections::HashSet, path::Path};
+
+use crate::{
+    config::RenderProfile,
+    error::{GourcersError, Result},
+    ffmpeg::{self, Clip},
+    log::LogEntry,
+    timeline::Timeline,
+    Context,
+};
+
+/// The start of the `length` seconds of video with the most commits, given the video times of the
+/// commits in order. The clip never runs past `duration`, unless the video is shorter than it.
+#[must_use]
+pub fn busiest(times: &[f64], length: f64, duration: f64) -> f64 {
+    let mut best = (0, 0.0);
+    let mut end = 0;
+
+    for (start, &time) in times.iter().enumerate() {
+        end = end.max(start);
+        while end < times.len() && times[end] < time + length {
+            end += 1;
+        }
+        if end - start > best.0 {
+            best = (end - start, time);
+        }
+    }
+
+    best.1.min(duration - length).max(0.0)
+}
+
+/// Cut a clip of the busiest `length` seconds from the video rendered from `log` with `profile`,
+/// next to the video.
+pub fn write(cx: &Context, profile: &RenderProfile, log: &Path, length: f64) -> Result<()> {
+    let contents = std::fs::read_to_string(log).map_err(GourcersError::io(format!(
+        "failed to read {}",
+        log.display()
+    )))?;
+    let timeline = Timeline::from_log(&contents, &cx.gource_args_for(Some(profile)));
+
+    let commits = contents
+        .lines()
+        .filter_map(LogEntry::parse)
+        .filter_map(|entry| Some((i64::try_from(entry.timestamp).ok()?, entry.author)))
+        .collect::<HashSet<_>>();
+    let mut times = commits
+        .into_iter()
+        .map(|(timestamp, _)| timeline.video_time(timestamp))
+        .collect::<Vec<_>>();
+    times.sort_by(f64::total_cmp);
+
+    let video = &profile.output;
+    let start = busiest(&times, length, ffmpeg::duration(video)?);
+
+    let title_file = match &cx.title {
+        Some(title) => {
+            let dir = cx.data_dir.shorts_dir(&profile.name);
+            std::fs::create_dir_all(&dir)
+                .map_err(GourcersError::io("failed to create shorts directory"))?;
+
+            let path = dir.join("title.txt");
+            std::fs::write(&path, title).map_err(GourcersError::io(format!(
+                "failed to write {}",
+                path.display()
+            )))?;
+            Some(path)
+        }
+        None => None,
+    };
+
+    let format = profile.format.unwrap_or(cx.format);
+    let output = video.with_extension(format!("short.{}", format.extension()));
+    debug!(profile = %profile.name, "cutting a {length}s clip at {start:.1}s to {}", output.display());
+    ffmpeg::render_clip(
+        video,
+        &Clip {
+            start,
+            seconds: length,
+            title_file: title_file.as_deref(),
+        },
+        format,
+        &cx.ffmpeg_output_args_for(Some(profile)),
+        &output,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_busiest() {
+        let times = [0.0, 1.0, 20.0, 21.0, 22.0, 23.0, 50.0];
+
+        assert!((busiest(&times, 10.0, 100.0) - 20.0).abs() < f64::EPSILON);
+        // the busiest window would run past the end
+        assert!((busiest(&times, 10.0, 25.0) - 15.0).abs() < f64::EPSILON);
+        // the video is shorter than the clip
+        assert!((busiest(&times, 60.0, 55.0) - 0.0).abs() < f64::EPSILON);
+        assert!((busiest(&[], 10.0, 100.0) - 0.0).abs() < f64::EPSILON);
+    }
+}
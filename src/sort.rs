@@ -0,0 +1,320 @@
+//! Sorting the combined log by timestamp.
+//!
+//! The default backend sorts in memory, which needs the whole combined log to fit in RAM. The
+//! `qsv` backend runs an external merge sort on disk with `qsv extsort` instead.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    error::{GourcersError, Result},
+    log::LogEntry,
+    log_file,
+};
+
+pub trait SortBackend {
+    /// Combine the logs at `inputs` into a single log sorted by timestamp, written to `output`.
+    fn sort(&self, inputs: &[PathBuf], output: &Path) -> Result<()>;
+}
+
+/// The backend used to sort the combined log.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortBackendKind {
+    /// Sort in memory.
+    #[default]
+    Memory,
+    /// Sort on disk with `qsv extsort`, for logs which don't fit in memory.
+    Qsv,
+}
+
+impl SortBackendKind {
+    /// Create the backend. `temp_dir` is where on-disk backends keep their intermediate files.
+    #[must_use]
+    pub fn backend(self, temp_dir: &Path) -> Box<dyn SortBackend> {
+        match self {
+            Self::Memory => Box::new(InMemory),
+            Self::Qsv => Box::new(Qsv {
+                temp_dir: temp_dir.to_path_buf(),
+            }),
+        }
+    }
+}
+
+/// What the sorted log is ordered by: the timestamp, then the top-level directory (usually the
+/// repo the entry is from), the author and the path. Entries with the same timestamp are common
+/// across repos, so the extra fields make the order of the sorted log independent of the order the
+/// logs were read in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SortKey<'a> {
+    pub timestamp: u64,
+    pub repo: &'a str,
+    pub author: &'a str,
+    pub path: &'a str,
+}
+
+impl<'a> SortKey<'a> {
+    /// Parse the key of a log line, or `None` if the line is malformed.
+    #[must_use]
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let entry = LogEntry::parse(line)?;
+
+        Some(Self {
+            timestamp: entry.timestamp,
+            repo: entry.repo(),
+            author: entry.author,
+            path: entry.path,
+        })
+    }
+}
+
+/// The lines of a log with their sort keys, skipping malformed lines with a warning. `origin` is
+/// the file the log was read from.
+pub fn parse_lines<'a>(
+    log: &'a str,
+    origin: &'a Path,
+) -> impl Iterator<Item = (SortKey<'a>, &'a str)> + 'a {
+    log.lines().enumerate().filter_map(move |(x, line)| {
+        let key = SortKey::parse(line);
+        if key.is_none() {
+            warn!(
+                "skipping malformed log line {} in {}: {line}",
+                x + 1,
+                origin.display()
+            );
+        }
+        key.map(|key| (key, line))
+    })
+}
+
+pub struct InMemory;
+
+impl SortBackend for InMemory {
+    fn sort(&self, inputs: &[PathBuf], output: &Path) -> Result<()> {
+        trace!("reading gource logs into memory");
+        let logs = inputs
+            .iter()
+            .map(|input| {
+                log_file::read(input).map_err(GourcersError::io(format!(
+                    "failed to read {}",
+                    input.display()
+                )))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        trace!("sorting combined logs");
+        let mut lines = logs
+            .iter()
+            .zip(inputs)
+            .flat_map(|(log, input)| parse_lines(log, input))
+            .collect::<Vec<_>>();
+
+        // the sort is stable, so entries with the same key keep their order in the repo's log
+        lines.sort_by_key(|(key, _)| *key);
+
+        trace!(sorted_path = ?output, "writing sorted log to disk");
+
+        let file =
+            File::create(output).map_err(GourcersError::io("failed to create sorted log file"))?;
+        let mut writer = BufWriter::new(file);
+
+        for (_, line) in lines {
+            writeln!(writer, "{line}").map_err(GourcersError::io("failed to write sorted log"))?;
+        }
+
+        writer
+            .flush()
+            .map_err(GourcersError::io("failed to write sorted log"))
+    }
+}
+
+/// Sorts with `qsv extsort`, which compares whole lines. Each line is prefixed with its sort key,
+/// with the timestamp zero-padded so it compares correctly as text, and the prefix is removed
+/// again after sorting.
+///
+/// Unlike the in-memory sort, entries with the same key are ordered by the rest of the line. Both
+/// orders only depend on the contents of the logs.
+pub struct Qsv {
+    temp_dir: PathBuf,
+}
+
+/// Wide enough for any `u64`.
+const TIMESTAMP_WIDTH: usize = 20;
+/// Separates the fields of the key prefix. It sorts before any printable character, so a field
+/// which is a prefix of another sorts first, as it does when comparing the key.
+const KEY_SEPARATOR: char = '\x1f';
+
+impl SortBackend for Qsv {
+    fn sort(&self, inputs: &[PathBuf], output: &Path) -> Result<()> {
+        let keyed = output.with_extension("keyed.txt");
+        let sorted = output.with_extension("keyed.sorted.txt");
+
+        let result = write_keyed(inputs, &keyed)
+            .and_then(|()| self.extsort(&keyed, &sorted))
+            .and_then(|()| strip_keys(&sorted, output));
+
+        for path in [keyed, sorted] {
+            if path.exists() {
+                std::fs::remove_file(&path).map_err(GourcersError::io(format!(
+                    "failed to remove {}",
+                    path.display()
+                )))?;
+            }
+        }
+
+        result
+    }
+}
+
+impl Qsv {
+    fn extsort(&self, input: &Path, output: &Path) -> Result<()> {
+        let mut cmd = Command::new("qsv");
+
+        cmd.args(["extsort", "--no-headers", "--tmp-dir"])
+            .arg(&self.temp_dir)
+            .arg(input)
+            .arg(output);
+
+        cmd.stdout(Stdio::null()).stderr(Stdio::piped());
+
+        trace!(command = ?cmd, "sorting combined log with qsv");
+
+        let result = cmd
+            .output()
+            .map_err(GourcersError::io("failed to run qsv"))?;
+
+        if !result.status.success() {
+            return Err(GourcersError::Qsv(
+                String::from_utf8_lossy(&result.stderr).trim().to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Concatenate the logs into `output`, one at a time, with every line prefixed with its key.
+fn write_keyed(inputs: &[PathBuf], output: &Path) -> Result<()> {
+    trace!(keyed_path = ?output, "concatenating gource logs");
+
+    let mut writer = BufWriter::new(
+        File::create(output).map_err(GourcersError::io("failed to create combined log file"))?,
+    );
+
+    for input in inputs {
+        let log = log_file::read(input).map_err(GourcersError::io(format!(
+            "failed to read {}",
+            input.display()
+        )))?;
+
+        for (key, line) in parse_lines(&log, input) {
+            writeln!(
+                writer,
+                "{:0width$}{KEY_SEPARATOR}{}{KEY_SEPARATOR}{}{KEY_SEPARATOR}{}{KEY_SEPARATOR}{line}",
+                key.timestamp,
+                key.repo,
+                key.author,
+                key.path,
+                width = TIMESTAMP_WIDTH
+            )
+            .map_err(GourcersError::io("failed to write combined log"))?;
+        }
+    }
+
+    writer
+        .flush()
+        .map_err(GourcersError::io("failed to write combined log"))
+}
+
+/// Remove the key prefixes added by [`write_keyed`] from every line of `input`.
+fn strip_keys(input: &Path, output: &Path) -> Result<()> {
+    let reader =
+        BufReader::new(File::open(input).map_err(GourcersError::io("failed to open sorted log"))?);
+    let mut writer = BufWriter::new(
+        File::create(output).map_err(GourcersError::io("failed to create sorted log file"))?,
+    );
+
+    for line in reader.lines() {
+        let line = line.map_err(GourcersError::io("failed to read sorted log"))?;
+        let line = line.splitn(5, KEY_SEPARATOR).nth(4).unwrap_or_default();
+        writeln!(writer, "{line}").map_err(GourcersError::io("failed to write sorted log"))?;
+    }
+
+    writer
+        .flush()
+        .map_err(GourcersError::io("failed to write sorted log"))
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_sort_key() {
+        assert_eq!(
+            SortKey::parse("999999999|x|A|/repo/src/main.rs"),
+            Some(SortKey {
+                timestamp: 999_999_999,
+                repo: "repo",
+                author: "x",
+                path: "/repo/src/main.rs",
+            })
+        );
+        assert_eq!(SortKey::parse("soon|x|A|/repo/a"), None);
+        assert_eq!(SortKey::parse("100|x|A"), None);
+    }
+
+    #[test]
+    fn test_in_memory_sort() {
+        let temp = TempDir::new().unwrap();
+        let one = temp.path().join("one.txt");
+        let two = temp.path().join("two.txt");
+        let output = temp.path().join("sorted.txt");
+        std::fs::write(&one, "1000000000|x|A|/one/a\n300|x|M|/one/a\n").unwrap();
+        std::fs::write(
+            &two,
+            "999999999|y|A|/two/b\nbad line\n300|y|A|/two/c\n300|x|A|/two/c\n300|x|A|/two/b\n",
+        )
+        .unwrap();
+
+        InMemory.sort(&[two.clone(), one.clone()], &output).unwrap();
+        let sorted = std::fs::read_to_string(&output).unwrap();
+
+        assert_eq!(
+            sorted,
+            "300|x|M|/one/a\n300|x|A|/two/b\n300|x|A|/two/c\n300|y|A|/two/c\n999999999|y|A|/two/b\n1000000000|x|A|/one/a\n"
+        );
+
+        // the order the logs are read in doesn't matter
+        InMemory.sort(&[one, two], &output).unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), sorted);
+    }
+
+    #[test]
+    fn test_strip_keys() {
+        let temp = TempDir::new().unwrap();
+        let inputs = [temp.path().join("one.txt")];
+        let keyed = temp.path().join("keyed.txt");
+        let output = temp.path().join("sorted.txt");
+        std::fs::write(&inputs[0], "300|x|M|/one/a|#FF0000\n").unwrap();
+
+        write_keyed(&inputs, &keyed).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&keyed).unwrap(),
+            "00000000000000000300\x1fone\x1fx\x1f/one/a|#FF0000\x1f300|x|M|/one/a|#FF0000\n"
+        );
+
+        strip_keys(&keyed, &output).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "300|x|M|/one/a|#FF0000\n"
+        );
+    }
+}
@@ -0,0 +1,390 @@
+//! Incremental updates of the sorted log.
+//!
+//! The last timestamp merged from each repo's log is recorded next to the sorted log. On the next
+//! run only the entries after it are merged into the existing sorted log, instead of sorting every
+//! log again. The sorted log is rebuilt from scratch whenever that wouldn't give the same result:
+//! when a repo was removed from the selection, or when a repo's entries up to its last recorded
+//! timestamp changed (e.g. after a force push, or with a different `--tree-layout`).
+//!
+//! Extra logs are tracked the same way as repos, keyed by `extra:` followed by their path.
+//!
+//! The checksum and line count of the sorted log are recorded too, so a sorted log which was only
+//! partially written, e.g. because of a crash, is rebuilt instead of rendering a truncated video.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{GourcersError, Result},
+    github::Repo,
+    gource, log, log_file,
+    sort::{self, SortKey},
+    Context,
+};
+
+/// What has been merged into the sorted log from each repo, keyed by the repo's full name, or from
+/// each extra log.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SortedLogState {
+    pub repos: BTreeMap<String, RepoState>,
+    /// A SHA-256 digest of the whole sorted log when it was written.
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// The number of lines in the sorted log when it was written.
+    #[serde(default)]
+    pub lines: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepoState {
+    /// The timestamp of the last entry merged.
+    pub last: u64,
+    /// The number of entries merged.
+    pub entries: usize,
+    /// A SHA-256 digest of the entries merged.
+    pub digest: String,
+}
+
+impl SortedLogState {
+    pub fn read(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = std::fs::read_to_string(path).map_err(GourcersError::io(format!(
+            "failed to read {}",
+            path.display()
+        )))?;
+
+        let state = serde_json::from_str(&contents).map_err(GourcersError::json(format!(
+            "failed to parse {}",
+            path.display()
+        )))?;
+
+        Ok(Some(state))
+    }
+
+    /// Whether the sorted log at `path` is the one which was written with this state.
+    pub fn matches(&self, path: &Path) -> Result<bool> {
+        let Some(checksum) = &self.checksum else {
+            return Ok(false);
+        };
+
+        let (actual, lines) = checksum_of(path)?;
+        Ok(actual == *checksum && lines == self.lines)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(GourcersError::json("failed to serialize sorted log state"))?;
+        std::fs::write(path, contents).map_err(GourcersError::io(format!(
+            "failed to write {}",
+            path.display()
+        )))
+    }
+}
+
+/// Bring the sorted log in the data directory up to date with the logs of the given repos and any
+/// extra logs. Returns the number of entries added, which is every entry if the log had to be
+/// rebuilt.
+///
+/// Only one log is held in memory at a time, so this works with any sort backend.
+pub fn update(cx: &Context, repos: &[Repo]) -> Result<usize> {
+    let output = cx.data_dir.sorted_log();
+    let state_path = cx.data_dir.sorted_log_state();
+
+    let mut inputs = repos
+        .iter()
+        .map(|repo| (repo.full_name(), cx.data_dir.gource_log(repo)))
+        .collect::<Vec<_>>();
+    for log in gource::extra_logs(cx)? {
+        inputs.push((format!("extra:{}", log.display()), log));
+    }
+
+    let previous = if output.exists() {
+        match SortedLogState::read(&state_path) {
+            // written by an older version, so the log is rebuilt
+            Err(GourcersError::Json { .. }) => None,
+            result => result?,
+        }
+    } else {
+        None
+    };
+    let previous = match previous {
+        Some(previous) if !previous.matches(&output)? => {
+            warn!("the sorted log doesn't match its recorded checksum and will be rebuilt");
+            None
+        }
+        previous => previous,
+    };
+
+    let selected = inputs.iter().map(|(name, _)| name).collect::<BTreeSet<_>>();
+    let previous = previous.filter(|previous| {
+        let removed = previous.repos.keys().find(|name| !selected.contains(*name));
+        if let Some(removed) = removed {
+            debug!("{removed} is no longer selected");
+        }
+        removed.is_none()
+    });
+
+    let mut new = previous.as_ref().map(|_| Vec::new());
+    let mut state = SortedLogState::default();
+
+    for (full_name, path) in &inputs {
+        let log = log_file::read(path).map_err(GourcersError::io(format!(
+            "failed to read gource log for {full_name}"
+        )))?;
+
+        let lines = if new.is_some() {
+            sort::parse_lines(&log, path).collect::<Vec<_>>()
+        } else {
+            // the sort backend reports malformed lines when rebuilding
+            log.lines()
+                .filter_map(|line| SortKey::parse(line).map(|key| (key, line)))
+                .collect()
+        };
+
+        if let (Some(previous), Some(new_lines)) = (&previous, &mut new) {
+            if let Some(added) = new_entries(previous.repos.get(full_name), &lines) {
+                new_lines.extend(added.map(ToString::to_string));
+            } else {
+                debug!("the merged entries of {full_name} changed");
+                new = None;
+            }
+        }
+
+        if let Some(repo_state) = RepoState::of(&lines) {
+            state.repos.insert(full_name.clone(), repo_state);
+        }
+    }
+
+    let added = if let Some(mut new) = new {
+        debug!("merging {} new entries into the sorted log", new.len());
+        new.sort_by(|a, b| SortKey::parse(a).cmp(&SortKey::parse(b)));
+        merge(&output, &new)?;
+        new.len()
+    } else {
+        debug!("rebuilding the sorted log");
+        let paths = inputs.into_iter().map(|(_, path)| path).collect::<Vec<_>>();
+        gource::combine_and_sort(cx, &paths, &output)?;
+        state.repos.values().map(|repo| repo.entries).sum()
+    };
+
+    let (checksum, lines) = checksum_of(&output)?;
+    state.checksum = Some(checksum);
+    state.lines = lines;
+    state.write(&state_path)?;
+
+    Ok(added)
+}
+
+impl RepoState {
+    /// The state after merging all of a log's lines, or `None` if it has none.
+    fn of(lines: &[(SortKey, &str)]) -> Option<Self> {
+        let last = lines.iter().map(|(key, _)| key.timestamp).max()?;
+
+        Some(Self {
+            last,
+            entries: lines.len(),
+            digest: digest(lines.iter().map(|(_, line)| *line)),
+        })
+    }
+}
+
+fn digest<'a>(lines: impl Iterator<Item = &'a str>) -> String {
+    let mut hasher = Sha256::new();
+    for line in lines {
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// Write the entries of the log at `input` which are in `dir` to `output`, relative to the parent
+/// of `dir`. Returns the number of entries written.
+pub fn write_focused(input: &Path, output: &Path, dir: &str) -> Result<usize> {
+    let reader = BufReader::new(File::open(input).map_err(GourcersError::io(format!(
+        "failed to open {}",
+        input.display()
+    )))?);
+    let file = File::create(output).map_err(GourcersError::io("failed to create focused log"))?;
+    let mut writer = BufWriter::new(file);
+
+    let mut written = 0;
+    for line in reader.lines() {
+        let line = line.map_err(GourcersError::io("failed to read sorted log"))?;
+        if let Some(entry) = log::focus_entry(&line, dir) {
+            writeln!(writer, "{entry}")
+                .map_err(GourcersError::io("failed to write focused log"))?;
+            written += 1;
+        }
+    }
+
+    writer
+        .flush()
+        .map_err(GourcersError::io("failed to write focused log"))?;
+
+    Ok(written)
+}
+
+/// The SHA-256 digest and number of lines of the file at `path`, read a line at a time so the
+/// whole sorted log is never held in memory.
+fn checksum_of(path: &Path) -> Result<(String, usize)> {
+    let err = || GourcersError::io(format!("failed to read {}", path.display()));
+
+    let mut reader = BufReader::new(File::open(path).map_err(err())?);
+    let mut hasher = Sha256::new();
+    let mut lines = 0;
+    let mut line = Vec::new();
+
+    while reader.read_until(b'\n', &mut line).map_err(err())? > 0 {
+        hasher.update(&line);
+        lines += 1;
+        line.clear();
+    }
+
+    Ok((hex::encode(hasher.finalize()), lines))
+}
+
+/// The lines of a log which come after what was previously merged from it, or `None` if the
+/// lines which were merged changed and the sorted log has to be rebuilt instead.
+fn new_entries<'a>(
+    previous: Option<&RepoState>,
+    lines: &'a [(SortKey, &'a str)],
+) -> Option<impl Iterator<Item = &'a str>> {
+    if let Some(previous) = previous {
+        let old = lines
+            .iter()
+            .filter(|(key, _)| key.timestamp <= previous.last)
+            .map(|(_, line)| *line);
+
+        if old.clone().count() != previous.entries || digest(old) != previous.digest {
+            return None;
+        }
+    }
+
+    let last = previous.map(|previous| previous.last);
+    Some(
+        lines
+            .iter()
+            .filter(move |(key, _)| last.is_none_or(|last| key.timestamp > last))
+            .map(|(_, line)| *line),
+    )
+}
+
+/// Merge sorted entries into the sorted log at `path`. Entries already in the log come first when
+/// timestamps are equal.
+fn merge(path: &Path, new: &[String]) -> Result<()> {
+    if new.is_empty() {
+        return Ok(());
+    }
+
+    let existing =
+        BufReader::new(File::open(path).map_err(GourcersError::io("failed to open sorted log"))?);
+
+    let temp_path = path.with_extension("txt.tmp");
+    let file =
+        File::create(&temp_path).map_err(GourcersError::io("failed to create sorted log file"))?;
+    let mut writer = BufWriter::new(file);
+
+    let mut new = new.iter().peekable();
+
+    for line in existing.lines() {
+        let line = line.map_err(GourcersError::io("failed to read sorted log"))?;
+        while let Some(entry) = new.next_if(|entry| SortKey::parse(entry) < SortKey::parse(&line)) {
+            writeln!(writer, "{entry}").map_err(GourcersError::io("failed to write sorted log"))?;
+        }
+        writeln!(writer, "{line}").map_err(GourcersError::io("failed to write sorted log"))?;
+    }
+
+    for entry in new {
+        writeln!(writer, "{entry}").map_err(GourcersError::io("failed to write sorted log"))?;
+    }
+
+    writer
+        .flush()
+        .map_err(GourcersError::io("failed to write sorted log"))?;
+
+    std::fs::rename(&temp_path, path).map_err(GourcersError::io("failed to replace sorted log"))
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    fn parse(log: &str) -> Vec<(SortKey<'_>, &str)> {
+        log.lines()
+            .map(|line| (SortKey::parse(line).unwrap(), line))
+            .collect()
+    }
+
+    #[test]
+    fn test_new_entries() {
+        let previous = RepoState::of(&parse("100|x|A|/one/a\n200|x|M|/one/a\n"));
+
+        let lines = parse("100|x|A|/one/a\n200|x|M|/one/a\n1000|x|M|/one/a\n");
+        assert_eq!(
+            new_entries(previous.as_ref(), &lines).map(Iterator::collect::<Vec<_>>),
+            Some(vec!["1000|x|M|/one/a"])
+        );
+
+        let lines = parse("100|y|A|/two/b\n");
+        assert_eq!(
+            new_entries(None, &lines).map(Iterator::collect::<Vec<_>>),
+            Some(vec!["100|y|A|/two/b"])
+        );
+
+        // rewritten history needs a rebuild
+        let lines = parse("100|x|A|/one/a\n150|x|M|/one/a\n200|x|M|/one/a\n");
+        assert!(new_entries(previous.as_ref(), &lines).is_none());
+        let lines = parse("100|x|A|/one/b\n200|x|M|/one/b\n");
+        assert!(new_entries(previous.as_ref(), &lines).is_none());
+    }
+
+    #[test]
+    fn test_matches() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("sorted.txt");
+        std::fs::write(&path, "10|x|A|/one/a\n20|x|M|/one/a\n").unwrap();
+
+        let (checksum, lines) = checksum_of(&path).unwrap();
+        assert_eq!(lines, 2);
+        let state = SortedLogState {
+            checksum: Some(checksum),
+            lines,
+            ..SortedLogState::default()
+        };
+        assert!(state.matches(&path).unwrap());
+
+        // a partially written log
+        std::fs::write(&path, "10|x|A|/one/a\n").unwrap();
+        assert!(!state.matches(&path).unwrap());
+
+        // written by an older version
+        assert!(!SortedLogState::default().matches(&path).unwrap());
+    }
+
+    #[test]
+    fn test_merge() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("sorted.txt");
+        std::fs::write(&path, "10|x|A|/one/a\n20|x|M|/one/a\n30|x|M|/one/a\n").unwrap();
+
+        let new = ["15|y|A|/two/b", "20|y|M|/two/b", "40|y|M|/two/b"].map(String::from);
+        merge(&path, &new).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "10|x|A|/one/a\n15|y|A|/two/b\n20|x|M|/one/a\n20|y|M|/two/b\n30|x|M|/one/a\n40|y|M|/two/b\n"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Where the repos to choose from come from.
+//!
+//! Every source lists repos and says where to clone them from, so the rest of the pipeline doesn't
+//! depend on which one is used. Repos are listed from the GitHub API by default, from the JSON
+//! printed by `gh repo list` with `--repos-json`, or from the git repos in a directory with
+//! `--local-repos`.
+
+use std::path::{Path, PathBuf};
+
+use futures_util::future::BoxFuture;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{GourcersError, Result},
+    github::{self, Owner, Repo},
+    progress::Progress,
+    Context,
+};
+
+pub trait RepoSource: Send + Sync {
+    /// List every repo the source has.
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Repo>>>;
+
+    /// The URL or path to clone the repo from.
+    fn clone_url(&self, repo: &Repo) -> String;
+
+    /// Whether listing the repos uses the GitHub token, so it should be checked first.
+    fn uses_token(&self) -> bool {
+        false
+    }
+}
+
+/// List the repos of a source, with the URLs to clone them from.
+pub async fn list(source: &dyn RepoSource) -> Result<Vec<Repo>> {
+    let mut repos = source.list().await?;
+    for repo in &mut repos {
+        repo.ssh_url = source.clone_url(repo);
+    }
+
+    Ok(repos)
+}
+
+/// The repos the token has access to, from the GitHub API.
+pub struct GitHub<'a> {
+    pub cx: &'a Context,
+    pub progress: &'a Progress,
+}
+
+impl RepoSource for GitHub<'_> {
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Repo>>> {
+        Box::pin(github::list_repos(self.cx, self.progress))
+    }
+
+    fn clone_url(&self, repo: &Repo) -> String {
+        repo.ssh_url.clone()
+    }
+
+    fn uses_token(&self) -> bool {
+        true
+    }
+}
+
+/// An explicit list of repos, as printed by `gh repo list --json`.
+pub struct RepoList {
+    /// The file with the list, or `-` for stdin.
+    pub path: PathBuf,
+}
+
+impl RepoSource for RepoList {
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Repo>>> {
+        Box::pin(async { github::read_gh_json(&self.path) })
+    }
+
+    fn clone_url(&self, repo: &Repo) -> String {
+        repo.ssh_url.clone()
+    }
+}
+
+/// The git repos in the subdirectories of a directory, which are owned by the directory. Bare
+/// repos are included.
+pub struct LocalDirs {
+    pub dir: PathBuf,
+}
+
+impl RepoSource for LocalDirs {
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Repo>>> {
+        Box::pin(async { self.list_dirs() })
+    }
+
+    fn clone_url(&self, repo: &Repo) -> String {
+        let path = self.dir.join(&repo.name);
+        std::path::absolute(&path)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+}
+
+impl LocalDirs {
+    fn list_dirs(&self) -> Result<Vec<Repo>> {
+        let dir = std::path::absolute(&self.dir).map_err(GourcersError::io(format!(
+            "failed to resolve {}",
+            self.dir.display()
+        )))?;
+        let owner = dir.file_name().map_or_else(
+            || "local".to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+
+        let entries = std::fs::read_dir(&dir).map_err(GourcersError::io(format!(
+            "failed to read {}",
+            dir.display()
+        )))?;
+
+        let mut repos = Vec::new();
+        for entry in entries {
+            let path = entry
+                .map_err(GourcersError::io(format!(
+                    "failed to read {}",
+                    dir.display()
+                )))?
+                .path();
+            if !is_git_repo(&path) {
+                continue;
+            }
+
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            // the ID keys the repo's metadata and usage records, so it is hashed from the path to
+            // stay the same between runs. A moved directory gets a new ID, like a new repo
+            let id = Sha256::digest(path.to_string_lossy().as_bytes());
+
+            repos.push(Repo {
+                id: u64::from_be_bytes(id[..8].try_into().unwrap()),
+                name: name.into_owned(),
+                owner: Owner {
+                    login: owner.clone(),
+                    account_type: String::new(),
+                },
+                // there is no size to read, so leave it to the check of the gource log
+                size: 1,
+                ..Repo::default()
+            });
+        }
+        repos.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(repos)
+    }
+}
+
+/// Whether the directory is a git repo, with a `.git` directory or file, or a bare repo.
+fn is_git_repo(path: &Path) -> bool {
+    path.join(".git").exists() || (path.join("HEAD").is_file() && path.join("objects").is_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockSource(Vec<&'static str>);
+
+    impl RepoSource for MockSource {
+        fn list(&self) -> BoxFuture<'_, Result<Vec<Repo>>> {
+            let repos = self
+                .0
+                .iter()
+                .map(|name| Repo {
+                    name: (*name).to_string(),
+                    ..Repo::default()
+                })
+                .collect();
+            Box::pin(async { Ok(repos) })
+        }
+
+        fn clone_url(&self, repo: &Repo) -> String {
+            format!("https://forge.example/{}.git", repo.name)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list() {
+        let repos = list(&MockSource(vec!["a", "b"])).await.unwrap();
+
+        assert_eq!(
+            repos
+                .iter()
+                .map(|repo| repo.ssh_url.as_str())
+                .collect::<Vec<_>>(),
+            ["https://forge.example/a.git", "https://forge.example/b.git"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_local_dirs() {
+        let temp = temp_dir::TempDir::new().unwrap();
+        for dir in ["b/.git", "a/objects", "not-a-repo"] {
+            std::fs::create_dir_all(temp.child(dir)).unwrap();
+        }
+        std::fs::write(temp.child("a/HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        let source = LocalDirs {
+            dir: temp.path().to_path_buf(),
+        };
+        let repos = list(&source).await.unwrap();
+
+        assert_eq!(
+            repos
+                .iter()
+                .map(|repo| repo.name.as_str())
+                .collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+        assert_eq!(repos[1].ssh_url, temp.child("b").display().to_string());
+        assert_eq!(
+            repos[0].owner.login,
+            temp.path().file_name().unwrap().to_string_lossy()
+        );
+        assert_ne!(repos[0].id, repos[1].id);
+    }
+}
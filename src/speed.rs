@@ -0,0 +1,279 @@
+//! Speed ramping for `--auto-speed`.
+//!
+//! The sorted log is split into segments by commit density. Each segment is rendered with its own
+//! `--seconds-per-day`, so busy periods play slowly and quiet ones quickly, and periods with no
+//! commits at all are left out. The segments are then joined with ffmpeg.
+//!
+//! Gource starts every segment with an empty tree, so each segment's log begins with an add, at
+//! the start of the segment, for every file which existed at that point.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    checkpoint,
+    config::RenderProfile,
+    error::{GourcersError, Result},
+    ffmpeg::{self, Format},
+    log::LogEntry,
+    Context,
+};
+
+const DAY: i64 = 86_400;
+/// The number of days commit density is measured over.
+const WINDOW_DAYS: i64 = 30;
+const MIN_SECONDS_PER_DAY: f64 = 0.05;
+const MAX_SECONDS_PER_DAY: f64 = 1.0;
+/// Speeds are rounded to a multiple of this so neighbouring windows can share a segment.
+const SPEED_STEP: f64 = 0.05;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    /// The first timestamp in the segment.
+    pub start: i64,
+    /// The timestamp after the last one in the segment.
+    pub end: i64,
+    pub seconds_per_day: f64,
+}
+
+/// Render the log in segments of varying speed and join them into the profile's output file.
+pub fn render(cx: &Context, profile: &RenderProfile, log: &Path) -> Result<()> {
+    let contents = std::fs::read_to_string(log).map_err(GourcersError::io(format!(
+        "failed to read {}",
+        log.display()
+    )))?;
+
+    let timestamps = contents
+        .lines()
+        .filter_map(|line| LogEntry::parse(line).and_then(|entry| timestamp(&entry)))
+        .collect::<Vec<_>>();
+
+    let segments = plan(&timestamps);
+    debug!(profile = %profile.name, "rendering {} segments", segments.len());
+
+    let dir = cx.data_dir.segments_dir(&profile.name);
+    std::fs::create_dir_all(&dir)
+        .map_err(GourcersError::io("failed to create segments directory"))?;
+
+    let ranges = segments
+        .iter()
+        .map(|segment| segment.start..segment.end)
+        .collect::<Vec<_>>();
+    let logs = write_segment_logs(&contents, &ranges, &dir)?;
+
+    let mut videos = Vec::with_capacity(segments.len());
+
+    for (x, (segment, log)) in segments.iter().zip(&logs).enumerate() {
+        let video = dir.join(format!("{x}.mp4"));
+        // segments are always MP4 so they can be joined without re-encoding
+        let segment_profile = RenderProfile {
+            format: Some(Format::Mp4),
+            // the extra ffmpeg arguments are only used for the joined video
+            ffmpeg_args: Some(Vec::new()),
+            output: video.clone(),
+            ..profile.clone()
+        };
+
+        trace!(profile = %profile.name, segment = ?segment, "rendering segment");
+        checkpoint::encode(
+            cx,
+            &segment_profile,
+            log,
+            &[
+                "--seconds-per-day".into(),
+                segment.seconds_per_day.to_string(),
+            ],
+        )?;
+
+        videos.push(video);
+    }
+
+    ffmpeg::concat(
+        &videos,
+        &dir.join("segments.txt"),
+        profile.format.unwrap_or(cx.format),
+        &cx.ffmpeg_output_args_for(Some(profile)),
+        &profile.output,
+    )
+}
+
+/// Split the timeline into segments, dropping windows without any commits and merging
+/// neighbouring windows which play at the same speed.
+#[allow(clippy::cast_precision_loss)]
+#[must_use]
+pub fn plan(timestamps: &[i64]) -> Vec<Segment> {
+    let Some(&first) = timestamps.iter().min() else {
+        return Vec::new();
+    };
+
+    let window = WINDOW_DAYS * DAY;
+
+    let mut counts = BTreeMap::<i64, usize>::new();
+    for timestamp in timestamps {
+        *counts
+            .entry((timestamp - first).div_euclid(window))
+            .or_default() += 1;
+    }
+
+    // measure against the 90th percentile so a single huge import doesn't make everything fast
+    let mut sorted = counts.values().copied().collect::<Vec<_>>();
+    sorted.sort_unstable();
+    let reference = sorted[(sorted.len() * 9 / 10).min(sorted.len() - 1)].max(1);
+
+    let mut segments = Vec::<Segment>::new();
+
+    for (idx, count) in counts {
+        let ratio = (count as f64 / reference as f64).min(1.0);
+        let seconds_per_day = ((MAX_SECONDS_PER_DAY * ratio / SPEED_STEP).round() * SPEED_STEP)
+            .max(MIN_SECONDS_PER_DAY);
+
+        let start = first + idx * window;
+        let end = start + window;
+
+        match segments.last_mut() {
+            Some(last)
+                if last.end == start
+                    && (last.seconds_per_day - seconds_per_day).abs() < f64::EPSILON =>
+            {
+                last.end = end;
+            }
+            _ => segments.push(Segment {
+                start,
+                end,
+                seconds_per_day,
+            }),
+        }
+    }
+
+    segments
+}
+
+/// The timestamp of an entry as a signed number, for doing arithmetic on.
+pub(crate) fn timestamp(entry: &LogEntry) -> Option<i64> {
+    i64::try_from(entry.timestamp).ok()
+}
+
+/// Write the log lines in each range of timestamps to its own file in `dir`, each starting with the
+/// files which already existed. Returns the path of each range's log, skipping ranges without any
+/// lines.
+pub(crate) fn write_segment_logs(
+    contents: &str,
+    segments: &[Range<i64>],
+    dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    // the files which exist at the current point in the log, and who last touched them
+    let mut files = BTreeMap::<&str, &str>::new();
+    let mut logs = Vec::with_capacity(segments.len());
+    let mut segments = segments.iter().enumerate().peekable();
+    let mut writer: Option<BufWriter<File>> = None;
+
+    for line in contents.lines() {
+        let Some(entry) = LogEntry::parse(line) else {
+            continue;
+        };
+        let Some(timestamp) = timestamp(&entry) else {
+            continue;
+        };
+
+        while segments
+            .peek()
+            .is_some_and(|(_, segment)| timestamp >= segment.end)
+        {
+            segments.next();
+            if let Some(mut writer) = writer.take() {
+                writer
+                    .flush()
+                    .map_err(GourcersError::io("failed to write segment log"))?;
+            }
+        }
+
+        if let Some(&(x, segment)) = segments.peek() {
+            if timestamp >= segment.start && writer.is_none() {
+                let path = dir.join(format!("{x}.txt"));
+                let file = File::create(&path)
+                    .map_err(GourcersError::io("failed to create segment log"))?;
+                let mut new_writer = BufWriter::new(file);
+
+                for (path, user) in &files {
+                    writeln!(new_writer, "{}|{user}|A|{path}", segment.start)
+                        .map_err(GourcersError::io("failed to write segment log"))?;
+                }
+
+                logs.push(path);
+                writer = Some(new_writer);
+            }
+        }
+
+        if let Some(writer) = &mut writer {
+            writeln!(writer, "{line}").map_err(GourcersError::io("failed to write segment log"))?;
+        }
+
+        if entry.kind == "D" {
+            files.remove(entry.path);
+        } else {
+            files.insert(entry.path, entry.author);
+        }
+    }
+
+    if let Some(mut writer) = writer {
+        writer
+            .flush()
+            .map_err(GourcersError::io("failed to write segment log"))?;
+    }
+
+    Ok(logs)
+}
+
+#[cfg(test)]
+mod tests {
+    use temp_dir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_plan_skips_quiet_windows() {
+        // ten busy days, a year of nothing, then a single commit
+        let mut timestamps = (0..100).map(|x| x * DAY / 10).collect::<Vec<_>>();
+        timestamps.push(400 * DAY);
+
+        let segments = plan(&timestamps);
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].start, 0);
+        assert_eq!(segments[0].end, WINDOW_DAYS * DAY);
+        assert!((segments[0].seconds_per_day - MAX_SECONDS_PER_DAY).abs() < f64::EPSILON);
+        assert_eq!(segments[1].start, 390 * DAY);
+        assert!((segments[1].seconds_per_day - MIN_SECONDS_PER_DAY).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_segment_logs_start_with_existing_files() {
+        let temp = TempDir::new().unwrap();
+        let contents = format!(
+            "0|alice|A|/repo/a\n10|bob|A|/repo/b\n20|alice|D|/repo/a\n{0}|bob|M|/repo/b\n",
+            100 * DAY
+        );
+        let segments = [0..DAY, 90 * DAY..120 * DAY];
+
+        let logs = write_segment_logs(&contents, &segments, temp.path()).unwrap();
+
+        assert_eq!(logs.len(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&logs[0]).unwrap(),
+            "0|alice|A|/repo/a\n10|bob|A|/repo/b\n20|alice|D|/repo/a\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(&logs[1]).unwrap(),
+            format!(
+                "{0}|bob|A|/repo/b\n{1}|bob|M|/repo/b\n",
+                90 * DAY,
+                100 * DAY
+            )
+        );
+    }
+}
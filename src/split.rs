@@ -0,0 +1,219 @@
+//! Chunked rendering for `--split-by` and `--checkpoint-every`.
+//!
+//! The sorted log is split into calendar periods (in UTC) or into periods of a fixed length, each
+//! period is rendered to its own video, and the videos are joined with ffmpeg. Periods are
+//! independent of each other, so several can be rendered at once with `--split-jobs`, and chunks
+//! which were already rendered are reused when a render is run again. Like with `--auto-speed`,
+//! each chunk's log begins with an add for every file which existed at its start.
+//!
+//! The chunk logs are kept in the profile's segments directory, so they can also be rendered on
+//! other machines.
+
+use std::{
+    ops::Range,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    calendar::{month_index, month_start},
+    checkpoint,
+    config::RenderProfile,
+    error::{GourcersError, Result},
+    ffmpeg::{self, Format},
+    log::LogEntry,
+    speed, Context,
+};
+
+/// The calendar periods a log can be split into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SplitBy {
+    Year,
+    Quarter,
+    Month,
+}
+
+impl SplitBy {
+    /// The number of months in each period.
+    fn months(self) -> i64 {
+        match self {
+            Self::Year => 12,
+            Self::Quarter => 3,
+            Self::Month => 1,
+        }
+    }
+
+    /// The periods which contain at least one of the timestamps, in order.
+    #[must_use]
+    pub fn chunks(self, timestamps: &[i64]) -> Vec<Range<i64>> {
+        let months = self.months();
+
+        let mut periods = timestamps
+            .iter()
+            .map(|&timestamp| month_index(timestamp).div_euclid(months))
+            .collect::<Vec<_>>();
+        periods.sort_unstable();
+        periods.dedup();
+
+        periods
+            .into_iter()
+            .map(|period| month_start(period * months)..month_start((period + 1) * months))
+            .collect()
+    }
+}
+
+/// Periods of `length` starting at the first timestamp which contain at least one of the
+/// timestamps, in order.
+#[must_use]
+pub fn every(timestamps: &[i64], length: Duration) -> Vec<Range<i64>> {
+    let Some(&first) = timestamps.iter().min() else {
+        return Vec::new();
+    };
+    let length = i64::try_from(length.as_secs()).unwrap_or(i64::MAX).max(1);
+
+    let mut periods = timestamps
+        .iter()
+        .map(|timestamp| (timestamp - first) / length)
+        .collect::<Vec<_>>();
+    periods.sort_unstable();
+    periods.dedup();
+
+    periods
+        .into_iter()
+        .map(|period| first + period * length..first + (period + 1) * length)
+        .collect()
+}
+
+/// Render the log in the chunks returned by `chunks` for its timestamps, and join them into the
+/// profile's output file.
+pub fn render(
+    cx: &Context,
+    profile: &RenderProfile,
+    log: &Path,
+    chunks: impl FnOnce(&[i64]) -> Vec<Range<i64>>,
+) -> Result<()> {
+    let contents = std::fs::read_to_string(log).map_err(GourcersError::io(format!(
+        "failed to read {}",
+        log.display()
+    )))?;
+
+    let timestamps = contents
+        .lines()
+        .filter_map(|line| LogEntry::parse(line).and_then(|entry| speed::timestamp(&entry)))
+        .collect::<Vec<_>>();
+
+    let chunks = chunks(&timestamps);
+    debug!(profile = %profile.name, "rendering {} chunks", chunks.len());
+
+    let dir = cx.data_dir.segments_dir(&profile.name);
+    std::fs::create_dir_all(&dir)
+        .map_err(GourcersError::io("failed to create segments directory"))?;
+
+    let logs = speed::write_segment_logs(&contents, &chunks, &dir)?;
+    let videos = (0..logs.len())
+        .map(|x| dir.join(format!("{x}.mp4")))
+        .collect::<Vec<_>>();
+
+    let render_chunk = |x: usize| -> Result<()> {
+        // chunks are always MP4 so they can be joined without re-encoding
+        let chunk_profile = RenderProfile {
+            format: Some(Format::Mp4),
+            // the extra ffmpeg arguments are only used for the joined video
+            ffmpeg_args: Some(Vec::new()),
+            output: videos[x].clone(),
+            ..profile.clone()
+        };
+
+        trace!(profile = %profile.name, chunk = x, "rendering chunk");
+        checkpoint::encode(cx, &chunk_profile, &logs[x], &[]).map(drop)
+    };
+
+    // each worker takes the next chunk which hasn't been started yet
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        let handles = (0..cx.split_jobs.clamp(1, logs.len().max(1)))
+            .map(|_| {
+                s.spawn(|| -> Result<()> {
+                    loop {
+                        let x = next.fetch_add(1, Ordering::Relaxed);
+                        if x >= logs.len() {
+                            return Ok(());
+                        }
+                        render_chunk(x)?;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().expect("render thread panicked"))
+    })?;
+
+    ffmpeg::concat(
+        &videos,
+        &dir.join("segments.txt"),
+        profile.format.unwrap_or(cx.format),
+        &cx.ffmpeg_output_args_for(Some(profile)),
+        &profile.output,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::DAY;
+
+    // 2023-01-01, 2023-04-01, 2023-05-01, 2024-01-01 and 2024-03-01 at midnight UTC
+    const JAN_2023: i64 = 1_672_531_200;
+    const APR_2023: i64 = 1_680_307_200;
+    const MAY_2023: i64 = 1_682_899_200;
+    const JAN_2024: i64 = 1_704_067_200;
+    const MAR_2024: i64 = 1_709_251_200;
+
+    #[test]
+    fn test_chunks() {
+        let timestamps = [APR_2023 + 5, JAN_2023 + 10, MAY_2023 + DAY, MAR_2024 + 1];
+
+        assert_eq!(
+            SplitBy::Year.chunks(&timestamps),
+            [
+                JAN_2023..JAN_2024,
+                JAN_2024..month_start(month_index(JAN_2024) + 12)
+            ]
+        );
+        assert_eq!(
+            SplitBy::Quarter.chunks(&timestamps),
+            [
+                JAN_2023..APR_2023,
+                APR_2023..month_start(month_index(APR_2023) + 3),
+                JAN_2024..APR_2023 + 366 * DAY,
+            ]
+        );
+        assert_eq!(
+            SplitBy::Month.chunks(&timestamps),
+            [
+                JAN_2023..JAN_2023 + 31 * DAY,
+                APR_2023..MAY_2023,
+                MAY_2023..MAY_2023 + 31 * DAY,
+                MAR_2024..MAR_2024 + 31 * DAY,
+            ]
+        );
+        assert!(SplitBy::Month.chunks(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_every() {
+        let length = Duration::from_secs(10 * DAY as u64);
+        let timestamps = [5 * DAY, 7 * DAY, 40 * DAY, 16 * DAY];
+
+        assert_eq!(
+            every(&timestamps, length),
+            [5 * DAY..15 * DAY, 15 * DAY..25 * DAY, 35 * DAY..45 * DAY]
+        );
+        assert!(every(&[], length).is_empty());
+    }
+}
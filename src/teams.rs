@@ -0,0 +1,225 @@
+//! Selecting and coloring by GitHub team.
+//!
+//! With `team:` rules, the teams of every organization which owns a listed repo are fetched, along
+//! with the repos each team has access to. With `--color-by-team`, each team's members are fetched
+//! too, and the log entries of every member are colored after their team.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{GourcersError, Result},
+    github::{self, Repo},
+    http_cache::ResponseCache,
+    log::LogEntry,
+    log_file,
+    progress::Progress,
+    Context,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Team {
+    /// The team's full name, as `org/slug`.
+    pub name: String,
+    /// The IDs of the repos the team has access to.
+    pub repos: Vec<u64>,
+    /// The logins of the team's members, if they were fetched.
+    pub members: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiTeam {
+    slug: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiRepo {
+    id: u64,
+}
+
+/// Fetch the teams of every organization which owns one of the repos, and record the teams of
+/// each repo. Members are only fetched if `members` is set.
+pub async fn fetch(
+    cx: &Context,
+    repos: &mut [Repo],
+    members: bool,
+    progress: &Progress,
+) -> Result<Vec<Team>> {
+    let client = github::client(cx)?;
+    let cache = ResponseCache::new(cx.data_dir.api_cache_dir());
+
+    let mut orgs = repos
+        .iter()
+        .filter(|repo| repo.owner.account_type == "Organization")
+        .map(|repo| repo.owner.login.clone())
+        .collect::<Vec<_>>();
+    orgs.sort();
+    orgs.dedup();
+
+    let mut teams = Vec::new();
+
+    for org in orgs {
+        let url = format!("{}/orgs/{org}/teams?per_page=100", cx.api_url);
+        let api_teams: Vec<ApiTeam> =
+            github::list_pages(&client, &cache, url, &format!("{org}'s teams"), progress).await?;
+
+        for team in api_teams {
+            let name = format!("{org}/{}", team.slug);
+            let url = format!(
+                "{}/orgs/{org}/teams/{}/repos?per_page=100",
+                cx.api_url, team.slug
+            );
+            let team_repos: Vec<ApiRepo> =
+                github::list_pages(&client, &cache, url, &format!("{name}'s repos"), progress)
+                    .await?;
+
+            let members = if members {
+                let url = format!(
+                    "{}/orgs/{org}/teams/{}/members?per_page=100",
+                    cx.api_url, team.slug
+                );
+                let members: Vec<github::Owner> = github::list_pages(
+                    &client,
+                    &cache,
+                    url,
+                    &format!("{name}'s members"),
+                    progress,
+                )
+                .await?;
+                members.into_iter().map(|member| member.login).collect()
+            } else {
+                Vec::new()
+            };
+
+            teams.push(Team {
+                name,
+                repos: team_repos.into_iter().map(|repo| repo.id).collect(),
+                members,
+            });
+        }
+    }
+
+    for repo in repos.iter_mut() {
+        repo.teams = teams
+            .iter()
+            .filter(|team| team.repos.contains(&repo.id))
+            .map(|team| team.name.clone())
+            .collect();
+    }
+
+    Ok(teams)
+}
+
+/// The color of a team's entries, as a hex code.
+#[must_use]
+pub fn color(team: &str) -> String {
+    let hash = Sha256::digest(team.as_bytes());
+    hex::encode_upper(&hash[..3])
+}
+
+/// The color of each team member's entries. Members of several teams get the color of the first
+/// team by name.
+#[must_use]
+pub fn member_colors(teams: &[Team]) -> HashMap<String, String> {
+    let mut teams = teams.iter().collect::<Vec<_>>();
+    teams.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut colors = HashMap::new();
+    for team in teams {
+        let color = color(&team.name);
+        for member in &team.members {
+            colors
+                .entry(member.clone())
+                .or_insert_with(|| color.clone());
+        }
+    }
+
+    colors
+}
+
+/// Color the entries of every author in `colors`. Gource reads the color from a field after the
+/// path, so entries which already have one are left alone.
+#[must_use]
+pub fn color_log<S: std::hash::BuildHasher>(
+    log: &str,
+    colors: &HashMap<String, String, S>,
+) -> String {
+    let mut colored = String::with_capacity(log.len());
+
+    for line in log.lines() {
+        colored.push_str(line);
+
+        if let Some(color) = LogEntry::parse(line).and_then(|entry| colors.get(entry.author)) {
+            if !lazy_regex::regex_is_match!(r"\|[0-9A-Fa-f]{6}$", line) {
+                colored.push('|');
+                colored.push_str(color);
+            }
+        }
+
+        colored.push('\n');
+    }
+
+    colored
+}
+
+/// Color the gource logs of the repos after the teams of their authors.
+pub fn color_logs(cx: &Context, repos: &[Repo], teams: &[Team]) -> Result<()> {
+    let colors = member_colors(teams);
+
+    for repo in repos {
+        let path = cx.data_dir.gource_log(repo);
+        let log = log_file::read(&path).map_err(GourcersError::io(format!(
+            "failed to read gource log for {}",
+            repo.full_name()
+        )))?;
+        log_file::write(
+            &path,
+            &color_log(&log, &colors),
+            cx.config.compress_logs,
+            None,
+        )
+        .map_err(GourcersError::io(format!(
+            "failed to write gource log for {}",
+            repo.full_name()
+        )))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_log() {
+        let teams = [
+            Team {
+                name: "acme/backend".into(),
+                repos: Vec::new(),
+                members: vec!["alice".into(), "bob".into()],
+            },
+            Team {
+                name: "acme/admins".into(),
+                repos: Vec::new(),
+                members: vec!["alice".into()],
+            },
+        ];
+
+        let colors = member_colors(&teams);
+        let admins = color("acme/admins");
+        let backend = color("acme/backend");
+        assert_eq!(colors["alice"], admins);
+        assert_eq!(colors["bob"], backend);
+
+        assert_eq!(
+            color_log(
+                "100|bob|A|/a\n200|carol|M|/a\n300|bob|M|/b|00FF00\n",
+                &colors
+            ),
+            format!("100|bob|A|/a|{backend}\n200|carol|M|/a\n300|bob|M|/b|00FF00\n")
+        );
+    }
+}
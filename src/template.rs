@@ -0,0 +1,105 @@
+//! Placeholders in the paths rendered videos are written to, so scheduled runs which render the
+//! same profiles don't overwrite each other's videos.
+//!
+//! The placeholders are:
+//! - `{date}`: the date of the run in UTC, e.g. `2024-03-01`
+//! - `{repos}`: the number of repos in the sorted log
+//! - `{rules}`: the first 8 hex digits of a SHA-256 digest of the rules which selected the repos
+
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::calendar;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("unknown placeholder {{{0}}}, expected {{date}}, {{repos}} or {{rules}}")]
+    UnknownPlaceholder(String),
+    #[error("unclosed {{ in {0}")]
+    Unclosed(String),
+}
+
+/// The values the placeholders are replaced with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Values {
+    pub date: String,
+    pub repos: usize,
+    pub rules: String,
+}
+
+impl Values {
+    /// The values for a run at `timestamp` which rendered `repos` repos selected by the rules in
+    /// `rules`, written like [`crate::rules::RuleSet::summary`].
+    #[must_use]
+    pub fn new(timestamp: i64, repos: usize, rules: &str) -> Self {
+        let (year, month, day) = calendar::date(timestamp);
+
+        Self {
+            date: format!("{year:04}-{month:02}-{day:02}"),
+            repos,
+            rules: hex::encode(&Sha256::digest(rules)[..4]),
+        }
+    }
+}
+
+/// Replace the placeholders in `template`.
+pub fn expand(template: &Path, values: &Values) -> Result<PathBuf, Error> {
+    let template = template.to_string_lossy();
+    let mut expanded = String::with_capacity(template.len());
+
+    let mut rest = template.as_ref();
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+
+        let Some(end) = rest[start..].find('}') else {
+            return Err(Error::Unclosed(template.to_string()));
+        };
+        match &rest[start + 1..start + end] {
+            "date" => expanded.push_str(&values.date),
+            "repos" => expanded.push_str(&values.repos.to_string()),
+            "rules" => expanded.push_str(&values.rules),
+            name => return Err(Error::UnknownPlaceholder(name.to_string())),
+        }
+
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        // 2024-03-01 at noon UTC
+        let values = Values::new(1_709_294_400, 12, "*:*");
+        assert_eq!(values.date, "2024-03-01");
+        assert_eq!(values.rules.len(), 8);
+
+        assert_eq!(
+            expand(Path::new("out/gource-{date}-{repos}x.mp4"), &values),
+            Ok(PathBuf::from("out/gource-2024-03-01-12x.mp4"))
+        );
+        assert_eq!(
+            expand(Path::new("gource.mp4"), &values),
+            Ok(PathBuf::from("gource.mp4"))
+        );
+        assert_eq!(
+            expand(Path::new("gource-{rules}.mp4"), &values),
+            Ok(PathBuf::from(format!("gource-{}.mp4", values.rules)))
+        );
+        assert_eq!(
+            expand(Path::new("gource-{time}.mp4"), &values),
+            Err(Error::UnknownPlaceholder("time".into()))
+        );
+        assert!(matches!(
+            expand(Path::new("gource-{date.mp4"), &values),
+            Err(Error::Unclosed(_))
+        ));
+    }
+}
@@ -0,0 +1,125 @@
+//! Color themes for gource, which set the background, text and highlight colors together.
+//!
+//! Besides the built-in themes, the config file can define its own:
+//! ```toml
+//! [theme.midnight]
+//! background = "0b0e14"
+//! font = "bfbdb6"
+//! highlight = "e6b450"
+//! ```
+
+use serde::{de::Error as _, Deserialize, Deserializer};
+
+use crate::config::Config;
+
+/// The names of the built-in themes.
+pub const BUILTIN: &[&str] = &["dark", "light", "solarized", "dracula"];
+
+/// The colors of a theme, as hex codes. Colors which aren't set keep gource's defaults.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    #[serde(deserialize_with = "deserialize_color")]
+    pub background: Option<String>,
+    /// The color of the date, title, key and user names.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub font: Option<String>,
+    /// The color of directory names.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub dir: Option<String>,
+    /// The color of highlighted users and files.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub highlight: Option<String>,
+    /// The color of selected users and files.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub selection: Option<String>,
+}
+
+impl Theme {
+    /// The built-in theme with the given name.
+    #[must_use]
+    pub fn builtin(name: &str) -> Option<Self> {
+        let [background, font, dir, highlight, selection] = match name {
+            "dark" => ["0D1117", "E6EDF3", "8B949E", "58A6FF", "F78166"],
+            "light" => ["FFFFFF", "24292F", "57606A", "0969DA", "CF222E"],
+            "solarized" => ["002B36", "93A1A1", "586E75", "B58900", "CB4B16"],
+            "dracula" => ["282A36", "F8F8F2", "6272A4", "FF79C6", "50FA7B"],
+            _ => return None,
+        };
+
+        Some(Self {
+            background: Some(background.to_string()),
+            font: Some(font.to_string()),
+            dir: Some(dir.to_string()),
+            highlight: Some(highlight.to_string()),
+            selection: Some(selection.to_string()),
+        })
+    }
+
+    /// The theme with the given name, from the config file or else the built-in themes, so the
+    /// config file can replace a built-in theme.
+    #[must_use]
+    pub fn find(name: &str, config: &Config) -> Option<Self> {
+        config
+            .themes
+            .get(name)
+            .cloned()
+            .or_else(|| Self::builtin(name))
+    }
+
+    /// The gource arguments which set the theme's colors.
+    #[must_use]
+    pub fn args(&self) -> Vec<String> {
+        [
+            ("--background-colour", &self.background),
+            ("--font-colour", &self.font),
+            ("--dir-colour", &self.dir),
+            ("--highlight-colour", &self.highlight),
+            ("--selection-colour", &self.selection),
+        ]
+        .into_iter()
+        .filter_map(|(arg, color)| Some([arg.to_string(), color.clone()?]))
+        .flatten()
+        .collect()
+    }
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<String>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|color| crate::gource::parse_color(&color).map_err(D::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let config: Config = toml::from_str(
+            "[theme.midnight]\nbackground = \"#0b0e14\"\nhighlight = \"e6b450\"\n\n\
+             [theme.light]\nbackground = \"fafafa\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Theme::find("midnight", &config).unwrap().args(),
+            [
+                "--background-colour",
+                "0B0E14",
+                "--highlight-colour",
+                "E6B450"
+            ]
+        );
+        assert_eq!(
+            Theme::find("light", &config).unwrap().background.as_deref(),
+            Some("FAFAFA")
+        );
+        assert_eq!(Theme::find("dracula", &config).unwrap().args().len(), 10);
+        assert_eq!(Theme::find("neon", &config), None);
+
+        assert!(toml::from_str::<Config>("[theme.bad]\nfont = \"white\"\n").is_err());
+    }
+}
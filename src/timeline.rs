@@ -0,0 +1,141 @@
+//! Mapping commit timestamps to times in the rendered video.
+//!
+//! Gource plays `--seconds-per-day` seconds for every day of history, starting at the first entry
+//! in the log, but skips ahead to the next entry once nothing has happened for
+//! `--auto-skip-seconds`. The mapping follows the same rules, so it is only as accurate as gource's
+//! own timing, and doesn't apply to `--auto-speed` renders, whose speed changes between segments.
+
+use crate::log::LogEntry;
+
+const DAY: f64 = 86_400.0;
+
+/// Gource's default `--seconds-per-day`.
+const DEFAULT_SECONDS_PER_DAY: f64 = 10.0;
+
+/// Gource's default `--auto-skip-seconds`.
+const DEFAULT_AUTO_SKIP_SECONDS: f64 = 3.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeline {
+    /// Every distinct timestamp in the log, in order, with the video time gource reaches it at.
+    points: Vec<(i64, f64)>,
+    seconds_per_day: f64,
+    /// The longest gource waits for the next entry, or `None` if it never skips ahead.
+    auto_skip: Option<f64>,
+}
+
+impl Timeline {
+    #[must_use]
+    pub fn new(timestamps: &[i64], seconds_per_day: f64, auto_skip: Option<f64>) -> Self {
+        let mut timestamps = timestamps.to_vec();
+        timestamps.sort_unstable();
+        timestamps.dedup();
+
+        let mut points = Vec::<(i64, f64)>::with_capacity(timestamps.len());
+        for timestamp in timestamps {
+            let time = points.last().map_or(0.0, |&(previous, time)| {
+                time + gap(timestamp - previous, seconds_per_day, auto_skip)
+            });
+            points.push((timestamp, time));
+        }
+
+        Self {
+            points,
+            seconds_per_day,
+            auto_skip,
+        }
+    }
+
+    /// The timeline of a log's contents when rendered with the given gource arguments.
+    #[must_use]
+    pub fn from_log(contents: &str, gource_args: &[String]) -> Self {
+        let timestamps = contents
+            .lines()
+            .filter_map(LogEntry::parse)
+            .filter_map(|entry| i64::try_from(entry.timestamp).ok())
+            .collect::<Vec<_>>();
+
+        let seconds_per_day = last_value(gource_args, &["-s", "--seconds-per-day"])
+            .unwrap_or(DEFAULT_SECONDS_PER_DAY);
+        let auto_skip = if gource_args.iter().any(|arg| arg == "--disable-auto-skip") {
+            None
+        } else {
+            Some(
+                last_value(gource_args, &["-a", "--auto-skip-seconds"])
+                    .unwrap_or(DEFAULT_AUTO_SKIP_SECONDS),
+            )
+        };
+
+        Self::new(&timestamps, seconds_per_day, auto_skip)
+    }
+
+    /// The number of seconds into the video at which gource shows `timestamp`.
+    #[must_use]
+    pub fn video_time(&self, timestamp: i64) -> f64 {
+        let x = self
+            .points
+            .partition_point(|&(point, _)| point <= timestamp);
+        let Some(&(point, time)) = x.checked_sub(1).and_then(|x| self.points.get(x)) else {
+            return 0.0;
+        };
+
+        time + gap(timestamp - point, self.seconds_per_day, self.auto_skip)
+    }
+
+    /// The length of the video in seconds, up to the last entry.
+    #[must_use]
+    pub fn duration(&self) -> f64 {
+        self.points.last().map_or(0.0, |&(_, time)| time)
+    }
+}
+
+/// The video time which passes between two entries `seconds` apart.
+#[allow(clippy::cast_precision_loss)]
+fn gap(seconds: i64, seconds_per_day: f64, auto_skip: Option<f64>) -> f64 {
+    let time = seconds as f64 / DAY * seconds_per_day;
+    auto_skip.map_or(time, |skip| time.min(skip))
+}
+
+/// The value of the last of `names` in `args` which parses as a number.
+pub(crate) fn last_value(args: &[String], names: &[&str]) -> Option<f64> {
+    args.windows(2)
+        .rev()
+        .find(|pair| names.contains(&pair[0].as_str()))
+        .and_then(|pair| pair[1].parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_video_time() {
+        let day = 86_400;
+        let timeline = Timeline::new(&[day, 0, 2 * day, 100 * day], 1.0, Some(3.0));
+
+        assert!((timeline.video_time(0) - 0.0).abs() < f64::EPSILON);
+        assert!((timeline.video_time(day / 2) - 0.5).abs() < f64::EPSILON);
+        assert!((timeline.video_time(2 * day) - 2.0).abs() < f64::EPSILON);
+        // the quiet period is skipped after 3 seconds
+        assert!((timeline.video_time(50 * day) - 5.0).abs() < f64::EPSILON);
+        assert!((timeline.video_time(100 * day) - 5.0).abs() < f64::EPSILON);
+        assert!((timeline.duration() - 5.0).abs() < f64::EPSILON);
+        // before the first entry
+        assert!((timeline.video_time(-day) - 0.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_from_log() {
+        let log = "0|a|A|/x\n86400|a|M|/x\n";
+        let args = ["-s", "1", "--seconds-per-day", "2", "--disable-auto-skip"].map(String::from);
+        let timeline = Timeline::from_log(log, &args);
+
+        assert!((timeline.seconds_per_day - 2.0).abs() < f64::EPSILON);
+        assert_eq!(timeline.auto_skip, None);
+        assert!((timeline.duration() - 2.0).abs() < f64::EPSILON);
+
+        let timeline = Timeline::from_log(log, &[]);
+        assert_eq!(timeline.auto_skip, Some(DEFAULT_AUTO_SKIP_SECONDS));
+        assert!((timeline.duration() - DEFAULT_AUTO_SKIP_SECONDS).abs() < f64::EPSILON);
+    }
+}
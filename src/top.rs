@@ -0,0 +1,67 @@
+//! Keeping only the most active repos with `--top`.
+
+use std::cmp::Reverse;
+
+use clap::ValueEnum;
+
+use crate::github::Repo;
+
+/// How repos are ranked by `--top`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TopBy {
+    /// The number of commits in the repo's gource log.
+    #[default]
+    Commits,
+    /// When the repo was last pushed to.
+    RecentActivity,
+    /// The number of stars the repo has.
+    Stars,
+}
+
+impl TopBy {
+    /// Whether ranking needs the gource logs. Otherwise the repos can be ranked before they are
+    /// cloned.
+    #[must_use]
+    pub fn needs_logs(self) -> bool {
+        self == Self::Commits
+    }
+}
+
+/// Keep the `n` repos with the highest `key`, in their original order. Repos with the same key
+/// are ranked in their original order too.
+pub fn keep_top<K: Ord>(repos: &mut Vec<Repo>, n: usize, key: impl Fn(&Repo) -> K) {
+    let mut ranked = repos.iter().enumerate().collect::<Vec<_>>();
+    ranked.sort_by_key(|(_, repo)| Reverse(key(repo)));
+
+    let mut keep = vec![false; repos.len()];
+    for (x, _) in ranked.into_iter().take(n) {
+        keep[x] = true;
+    }
+
+    let mut keep = keep.into_iter();
+    repos.retain(|_| keep.next().unwrap_or_default());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keep_top() {
+        let mut repos = [("a", 5), ("b", 10), ("c", 5), ("d", 1)]
+            .map(|(name, stars)| Repo {
+                name: name.into(),
+                stargazers_count: stars,
+                ..Repo::default()
+            })
+            .to_vec();
+
+        keep_top(&mut repos, 2, |repo| repo.stargazers_count);
+
+        let names = repos
+            .iter()
+            .map(|repo| repo.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+    }
+}
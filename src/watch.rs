@@ -0,0 +1,76 @@
+//! Running `update` on a schedule, for long-running containers without cron.
+
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+#[derive(Debug, clap::Args)]
+pub struct WatchArgs {
+    /// How long to wait between updates, e.g. `7d`, `12h` or `30m`.
+    #[clap(long, default_value = "7d")]
+    pub interval: Interval,
+}
+
+/// A duration written as a number followed by a unit: `s`, `m`, `h`, `d` or `w`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval(pub Duration);
+
+const UNITS: [(char, u64); 5] = [
+    ('w', 7 * 86_400),
+    ('d', 86_400),
+    ('h', 3600),
+    ('m', 60),
+    ('s', 1),
+];
+
+impl FromStr for Interval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("expected a number followed by s, m, h, d or w: {s}");
+
+        let unit = s.chars().next_back().ok_or_else(err)?;
+        let (_, seconds) = UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .ok_or_else(err)?;
+
+        let value = s[..s.len() - 1].parse::<u64>().map_err(|_| err())?;
+        if value == 0 {
+            return Err(format!("interval must be greater than zero: {s}"));
+        }
+
+        Ok(Self(Duration::from_secs(value * seconds)))
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = self.0.as_secs();
+        let (unit, seconds) = UNITS
+            .iter()
+            .find(|(_, seconds)| secs.is_multiple_of(*seconds))
+            .expect("every duration is a whole number of seconds");
+
+        write!(f, "{}{unit}", secs / seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!("7d".parse(), Ok(Interval(Duration::from_hours(168))));
+        assert_eq!("90m".parse(), Ok(Interval(Duration::from_mins(90))));
+        assert!("0h".parse::<Interval>().is_err());
+        assert!("7".parse::<Interval>().is_err());
+        assert!("d".parse::<Interval>().is_err());
+        assert!("weekly".parse::<Interval>().is_err());
+    }
+
+    #[test]
+    fn test_display_interval() {
+        assert_eq!("14d".parse::<Interval>().unwrap().to_string(), "2w");
+        assert_eq!("90m".parse::<Interval>().unwrap().to_string(), "90m");
+    }
+}